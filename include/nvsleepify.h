@@ -0,0 +1,48 @@
+/* C interface to nvsleepifyd, implemented by libnvsleepify.so (src/ffi.rs). */
+#ifndef NVSLEEPIFY_H
+#define NVSLEEPIFY_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Return codes. The nonzero ones match the org.nvsleepify.Error.* names. */
+#define NVSLEEPIFY_OK 0
+#define NVSLEEPIFY_ERROR_FAILED 1
+#define NVSLEEPIFY_ERROR_BUSY 2
+#define NVSLEEPIFY_ERROR_BLOCKED_BY_PROCESSES 3
+#define NVSLEEPIFY_ERROR_INVALID_ARGS 4
+#define NVSLEEPIFY_ERROR_PERMISSION_DENIED 5
+#define NVSLEEPIFY_ERROR_SLOT_POWER_UNAVAILABLE 6
+#define NVSLEEPIFY_ERROR_MODULES_IN_USE 7
+#define NVSLEEPIFY_ERROR_TIMEOUT 8
+#define NVSLEEPIFY_ERROR_POWER_DOWN_FAILED 9
+#define NVSLEEPIFY_ERROR_DRIVES_DISPLAY 10
+
+typedef struct nvsleepify_subscription nvsleepify_subscription;
+
+/* The strings are only valid during the call. Runs on a background thread;
+ * don't call back into the library from it. */
+typedef void (*nvsleepify_state_cb)(const char *mode, const char *power_state, void *user_data);
+
+/* Saved mode ("Standard", ...) and power state ("D0", "D3cold", "NotFound", ...).
+ * Either out pointer may be NULL. Free the strings with nvsleepify_free_string. */
+int nvsleepify_get_status(char **mode, char **power_state);
+
+/* mode: "standard", "integrated", "optimized" or "vfio". Blocks until the
+ * transition is done. message may be NULL. */
+int nvsleepify_set_mode(const char *mode, char **message);
+
+/* NULL on error; see nvsleepify_last_error. */
+nvsleepify_subscription *nvsleepify_subscribe(nvsleepify_state_cb callback, void *user_data);
+void nvsleepify_unsubscribe(nvsleepify_subscription *subscription);
+
+/* Description of this thread's last error, or NULL. Free with nvsleepify_free_string. */
+char *nvsleepify_last_error(void);
+void nvsleepify_free_string(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,218 @@
+# English messages, built into nvsleepify. A translation lives in
+# locales/<language>/nvsleepify.ftl and may leave messages out; those are
+# shown in English.
+
+## Tray menu
+
+tray-mode-standard = Standard (Always On)
+tray-mode-integrated = Integrated (Force Sleep)
+tray-mode-optimized = Optimized (Auto)
+tray-mode-vfio = VFIO (VM Passthrough)
+tray-keep-awake = Keep Awake
+tray-keep-awake-30m = 30 minutes
+tray-keep-awake-1h = 1 hour
+tray-keep-awake-2h = 2 hours
+tray-cancel-wake = Cancel (back to { $mode })
+tray-blockers = Blocking Processes ({ $count })
+tray-kill = Kill
+tray-kill-retry = Kill and retry sleep
+tray-kill-all = Kill all
+tray-kill-all-sleep = Kill all & sleep
+tray-notifications = Notifications
+tray-quick-settings = Quick Settings
+tray-quit = Quit
+
+## Tray title and tooltip
+
+tray-title-active = GPU Active ({ $count } proc)
+tray-title-suspended = GPU Suspended (D3cold)
+tray-tooltip-mode = Mode: { $mode }
+tray-tooltip-kept-awake = Kept awake: { $hours }h { $minutes }m left
+tray-tooltip-power = Power: { $state }
+tray-tooltip-sensors = Sensors: { $sensors }
+tray-tooltip-processes = Processes using GPU:
+tray-tooltip-error = Error: { $error }
+
+## Tray errors, shown in the tooltip
+
+tray-error-invalid-pid = Invalid PID { $pid }
+tray-error-kill = Kill failed: { $error }
+tray-error-sleep = Sleep failed: { $error }
+tray-error-keep-awake = Keep awake failed: { $error }
+tray-error-set-mode = Set Mode failed: { $error }
+
+## Quick-settings popup
+
+popup-confirm-kill = Switching kills these processes:
+popup-kill-and-switch = Kill and switch
+popup-cancel = Cancel
+
+## Notifications
+
+notify-woke = GPU Woke up (D0)
+notify-suspended = GPU Suspended (D3cold)
+notify-mode-changed = Mode changed to { $mode }
+notify-no-tray = No system tray found, so the nvsleepify icon can't be shown. GPU state changes will be reported as notifications until a tray becomes available (on GNOME, install the AppIndicator extension).
+notify-sleep-blocked = The GPU can't sleep while these use it: { $processes }
+notify-action-kill = Kill & sleep
+notify-action-keep-awake = Keep awake 1h
+notify-action-dismiss = Dismiss
+notify-stays-awake = The Nvidia GPU stays awake
+notify-stays-awake-blocked = It can't sleep while { $processes } use it, which drains the battery.
+notify-stays-awake-failed = Putting it to sleep failed ({ $error }), so it keeps drawing power.
+notify-idles-awake = The Nvidia GPU idles awake
+notify-idles-awake-body = { $description }. { $processes } keep it awake without using it; close them to let it sleep.
+notify-residual-power = The Nvidia GPU may still draw power
+notify-residual-power-body = It reports being asleep, but the battery drain only went from { $before } W to { $after } W. Run `nvsleepify status` for details.
+
+## Command line prompts
+
+confirm-blockers = The following processes are using the Nvidia GPU and may need to be killed to sleep it:
+confirm-proceed = Do you want to proceed?
+confirm-no-terminal = stdin is not a terminal, can't ask for confirmation. Rerun with --yes to proceed.
+confirm-no-terminal-idle = stdin is not a terminal, can't ask for confirmation. Rerun with --yes or --when-idle.
+confirm-no-gui = Built without GUI dialogs, asking on the terminal instead.
+choose-kill = Which processes should be killed? (space to select, enter to confirm)
+choose-sleep = How should the GPU go to sleep?
+choose-sleep-kill = Kill them and sleep now
+choose-sleep-idle = Sleep once they exit
+choose-sleep-abort = Abort
+aborted = Aborted.
+
+## Command line output
+
+ps-none = No processes are using the Nvidia GPU.
+kill-none = No matching processes are using the Nvidia GPU.
+kill-targets = The following processes will be terminated:
+kill-still-running = { $process } is still running
+kill-some-failed = Some processes could not be killed
+set-mode-result = Set mode to { $mode }: { $message }
+set-mode-done = Set mode to { $mode }: { $result } (power state: { $state })
+set-mode-success = Success.
+set-mode-blockers = Processes using Nvidia GPU found:
+set-mode-error = Error: { $error }
+set-mode-aborted = Aborted, mode left unchanged.
+set-mode-failed = Failed to set mode to { $mode }
+wait-reached = GPU reached expected state ({ $state }).
+run-waking = Waking GPU (was in { $mode } mode)...
+run-leaving-awake = Leaving the GPU awake (Standard mode).
+run-restoring = Restoring { $mode } mode...
+run-restore-failed = Error restoring { $mode } mode: { $error }
+run-renew-failed = Failed to keep the GPU awake: { $error }
+run-vfio = The GPU is passed through to a VM (Vfio mode); switch to another mode before running programs on it.
+wake-keeping = Keeping the GPU awake for { $duration }, then returning to the saved mode.
+
+## nvsleepify watch
+
+watch-title = nvsleepify watch (Ctrl+C to exit)
+watch-mode = Mode:        { $mode }
+watch-power = Power State: { $state }
+watch-last-change = Last Change: { $from } -> { $to } ({ $ago } ago)
+watch-no-change = Last Change: None observed
+watch-no-blockers = Blockers:    None
+watch-blockers = Blockers:    { $count }
+watch-pending = Sleep:       Pending until the blockers exit
+
+top-title = nvsleepify top (Ctrl+C to exit)
+top-no-processes = No processes are using the GPU.
+
+## nvsleepify history
+
+history-none = No transitions recorded yet.
+history-time = TIME
+history-transition = TRANSITION
+history-duration = DURATION
+history-trigger = TRIGGER
+history-fault = KERNEL FAULT
+history-message = MESSAGE
+history-asleep-today = Time asleep today: { $asleep } of { $elapsed }
+
+## nvsleepify savings
+
+savings-on-battery = On battery
+savings-time = Time
+savings-draw = Average Draw
+savings-runtime = Runtime Left
+savings-asleep = GPU asleep
+savings-awake = GPU awake
+savings-not-measured = not measured
+savings-saved = Sleeping the GPU saves { $watts } W ({ $percent }% less draw).
+savings-gained = With { $energy } Wh left, that's { $runtime } more runtime.
+savings-no-data = Run on battery with the GPU both asleep and awake for a comparison; each state needs a minute of samples.
+
+## nvsleepify stats
+
+stats-since = Since { $time }:
+stats-sleeps = Sleeps:              { $count }
+stats-wakes = Wakes:               { $count }
+stats-d3cold = Time in D3cold:      { $duration }
+stats-retries = Enforcement retries: { $count }
+stats-failures = Failed transitions:  { $count }
+
+## nvsleepify bench
+
+bench-confirm = Power the GPU off and on { $cycles } times, killing the processes using it?
+bench-running = Running { $cycles } sleep/wake cycles...
+bench-step = Step
+bench-min = Min
+bench-avg = Avg
+bench-max = Max
+bench-whole-cycle = whole cycle
+
+## nvsleepify capabilities and devices
+
+caps-yes = yes
+caps-no = no
+caps-slot-power = Slot Power Control: { $supported }
+caps-bridge-pm = Bridge Runtime PM:  { $supported }
+caps-d3cold = D3cold:             { $supported }
+caps-acpi-off = ACPI _OFF:          { $supported }
+caps-bbswitch = bbswitch:           { $supported }
+caps-nvml = NVML:               { $supported }
+caps-dgpu-disable = dGPU Disable:       { $supported }
+devices-none = No display controllers found.
+devices-managed = (managed)
+devices-ids = IDs:         { $vendor }:{ $device }
+devices-power = Power State: { $state }
+devices-driver = Driver:      { $driver }
+devices-functions = Functions:   { $functions }
+
+## nvsleepify migrate
+
+migrate-none = No envycontrol, supergfxctl or optimus-manager configuration found.
+migrate-found = Found configuration from other GPU switchers:
+migrate-conflicts = conflicts
+migrate-kept = kept
+migrate-will-rename = { $count } conflicting file(s) will be renamed with a { $suffix } suffix.
+migrate-confirm = Move the conflicting files aside?
+migrate-aborted = Aborted, nothing was changed.
+migrate-needs-root = Moving system files requires root; rerun with sudo.
+migrate-moved = Moved { $path } to { $backup }
+migrate-rebuild-initramfs = Run your initramfs tool (e.g. dracut -f or mkinitcpio -P) and reboot if a driver blacklist was removed.
+migrate-disable-tool = Disable { $tool } so it doesn't re-apply its settings: systemctl disable --now { $service }
+migrate-switch-mode = Switch nvsleepify to { $mode } mode?
+
+## nvsleepify desktop, launcher, install and uninstall
+
+desktop-added = Added dGPU action: { $path }
+desktop-removed = Removed dGPU action from { $path }
+desktop-no-action = { $app } has no dGPU action
+desktop-some-failed = Some launcher entries could not be updated
+installed = Installed { $path }
+removed = Removed { $path }
+install-start-daemon = Start the daemon with: systemctl enable --now nvsleepifyd.service
+install-done = nvsleepifyd is enabled and running.
+uninstall-waking = Returning the GPU to Standard mode...
+uninstall-wake-failed = Failed to wake the GPU: { $error }
+uninstall-backups = Files moved aside by nvsleepify migrate:
+uninstall-restore-backups = Move them back?
+uninstall-restored = Restored { $path }
+uninstall-done = nvsleepify is uninstalled; remove the binaries with your package manager or make uninstall.
+
+## nvsleepify report and status without the daemon
+
+report-not-root = Not running as root: the kernel log and some state files are left out.
+report-collecting = Collecting diagnostics...
+report-written = Wrote { $path }. Check it for anything private before attaching it to a bug report.
+status-no-daemon = nvsleepifyd is not running; read-only status from sysfs (best effort).
+status-not-root = Processes of other users holding the GPU are only listed when run as root.
@@ -0,0 +1,518 @@
+use anyhow::{anyhow, Result};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Reads and writes under /sys (and /proc where sysfs points there).
+pub trait SysfsBackend: Send + Sync {
+    fn read(&self, path: &Path) -> io::Result<String>;
+    fn write(&self, path: &Path, value: &str) -> io::Result<()>;
+    /// Full paths of the directory's entries.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+    fn exists(&self, path: &Path) -> bool;
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>;
+    /// The path with symlinks resolved, still relative to the sysfs root.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// A binary attribute such as a device's `config`.
+    fn read_bytes(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.read(path).map(String::into_bytes)
+    }
+
+    /// Writes `bytes` at `offset` into a binary attribute.
+    fn write_at(&self, _path: &Path, _offset: u64, _bytes: &[u8]) -> io::Result<()> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+/// systemctl. `action` is "start", "stop", "enable", "disable", "mask" or "unmask".
+pub trait ServiceManager: Send + Sync {
+    fn run(&self, action: &str, service: &str) -> Result<()>;
+}
+
+/// modprobe, for the nvidia modules in dependency order, and modinfo.
+pub trait ModuleManager: Send + Sync {
+    /// Loads one module with `params` ("name=value") on the command line.
+    fn load(&self, module: &str, params: &[&str]) -> Result<()>;
+    fn unload(&self, modules: &[&str]) -> Result<()>;
+    /// A field of the installed module, e.g. its "license"; None if it
+    /// isn't installed.
+    fn info(&self, module: &str, field: &str) -> Option<String>;
+}
+
+/// Where the transition steps keep what they need across a crash or a
+/// power-off, unless the backends name another directory.
+pub const STATE_DIR: &str = "/var/lib/nvsleepify";
+
+/// What pci, system and the transition steps talk to the machine through.
+#[derive(Clone)]
+pub struct Backends {
+    pub sysfs: Arc<dyn SysfsBackend>,
+    pub services: Arc<dyn ServiceManager>,
+    pub modules: Arc<dyn ModuleManager>,
+    /// The transition journal and saved config space go here.
+    pub state_dir: PathBuf,
+}
+
+impl Default for Backends {
+    fn default() -> Self {
+        Self {
+            sysfs: Arc::new(RealSysfs),
+            services: Arc::new(Systemctl),
+            modules: Arc::new(Modprobe),
+            state_dir: PathBuf::from(STATE_DIR),
+        }
+    }
+}
+
+static BACKENDS: OnceLock<Backends> = OnceLock::new();
+
+thread_local! {
+    /// Set by `scoped`, taking precedence over `BACKENDS` on this thread.
+    static SCOPED: RefCell<Option<Backends>> = const { RefCell::new(None) };
+}
+
+/// Read for `--sysfs-root` by the CLI and the daemon.
+pub const SYSFS_ROOT_ENV: &str = "NVSLEEPIFY_SYSFS_ROOT";
+
+static SYSFS_ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+/// Points every sysfs access at a directory of fixture files laid out like
+/// /sys, e.g. a copy of a reporter's tree. modprobe and systemctl are only
+/// printed, and no processes are listed or killed, since none of them
+/// belong to the fake GPU.
+pub fn use_sysfs_root(root: impl Into<PathBuf>) -> Result<()> {
+    SYSFS_ROOT
+        .set(root.into())
+        .map_err(|_| anyhow!("The sysfs root is already set"))?;
+    install(Backends {
+        sysfs: Arc::new(RealSysfs),
+        services: Arc::new(DryRun),
+        modules: Arc::new(DryRun),
+        ..Backends::default()
+    })
+}
+
+pub fn sysfs_root() -> Option<&'static Path> {
+    SYSFS_ROOT.get().map(PathBuf::as_path)
+}
+
+/// Where `path` (as under /sys) really is, with the sysfs root applied.
+pub fn host_path(path: &Path) -> PathBuf {
+    match sysfs_root() {
+        Some(root) => root.join(path.strip_prefix("/").unwrap_or(path)),
+        None => path.to_path_buf(),
+    }
+}
+
+/// The inverse of `host_path`.
+fn logical_path(path: PathBuf) -> PathBuf {
+    match sysfs_root().and_then(|root| path.strip_prefix(root).ok()) {
+        Some(rest) => Path::new("/").join(rest),
+        None => path,
+    }
+}
+
+/// Replaces the real backends, e.g. with mocks. Must happen before anything
+/// touches the GPU; fails if the backends are already in use.
+pub fn install(backends: Backends) -> Result<()> {
+    BACKENDS
+        .set(backends)
+        .map_err(|_| anyhow!("Backends are already in use"))
+}
+
+/// Undoes `scoped` when dropped.
+pub struct ScopedBackends {
+    previous: Option<Backends>,
+}
+
+impl Drop for ScopedBackends {
+    fn drop(&mut self) {
+        SCOPED.with(|scoped| *scoped.borrow_mut() = self.previous.take());
+    }
+}
+
+/// Uses `backends` on the current thread until the guard is dropped,
+/// whether or not the global ones are in use. Lets each test run the steps
+/// against its own mocks; work done on other threads doesn't see them.
+pub fn scoped(backends: Backends) -> ScopedBackends {
+    let previous = SCOPED.with(|scoped| scoped.borrow_mut().replace(backends));
+    ScopedBackends { previous }
+}
+
+fn backends() -> Backends {
+    SCOPED
+        .with(|scoped| scoped.borrow().clone())
+        .unwrap_or_else(|| BACKENDS.get_or_init(Backends::default).clone())
+}
+
+pub fn sysfs() -> Arc<dyn SysfsBackend> {
+    backends().sysfs
+}
+
+pub fn services() -> Arc<dyn ServiceManager> {
+    backends().services
+}
+
+pub fn modules() -> Arc<dyn ModuleManager> {
+    backends().modules
+}
+
+/// Where the state file `name` is kept.
+pub fn state_path(name: &str) -> PathBuf {
+    backends().state_dir.join(name)
+}
+
+/// A sysfs attribute with surrounding whitespace removed.
+pub fn read_trimmed(path: impl AsRef<Path>) -> Option<String> {
+    sysfs()
+        .read(path.as_ref())
+        .ok()
+        .map(|s| s.trim().to_string())
+}
+
+pub struct RealSysfs;
+
+/// The real filesystem, below the sysfs root if one is set.
+impl SysfsBackend for RealSysfs {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        std::fs::read_to_string(host_path(path))
+    }
+
+    fn write(&self, path: &Path, value: &str) -> io::Result<()> {
+        std::fs::write(host_path(path), value)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        Ok(std::fs::read_dir(host_path(path))?
+            .flatten()
+            .map(|entry| path.join(entry.file_name()))
+            .collect())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        host_path(path).exists()
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        std::fs::read_link(host_path(path))
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        std::fs::canonicalize(host_path(path)).map(logical_path)
+    }
+
+    fn read_bytes(&self, path: &Path) -> io::Result<Vec<u8>> {
+        std::fs::read(host_path(path))
+    }
+
+    fn write_at(&self, path: &Path, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        use std::os::unix::fs::FileExt;
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(host_path(path))?;
+        file.write_all_at(bytes, offset)
+    }
+}
+
+pub struct Systemctl;
+
+impl ServiceManager for Systemctl {
+    fn run(&self, action: &str, service: &str) -> Result<()> {
+        let status = crate::command::status_with_timeout(
+            Command::new("systemctl").arg(action).arg(service),
+        )?;
+        if !status.success() {
+            return Err(anyhow!("systemctl {} {}: {}", action, service, status));
+        }
+        Ok(())
+    }
+}
+
+/// Stands in for modprobe and systemctl on a fake sysfs tree.
+pub struct DryRun;
+
+impl ServiceManager for DryRun {
+    fn run(&self, action: &str, service: &str) -> Result<()> {
+        eprintln!("[sysfs root] would run: systemctl {} {}", action, service);
+        Ok(())
+    }
+}
+
+impl ModuleManager for DryRun {
+    fn load(&self, module: &str, params: &[&str]) -> Result<()> {
+        let args: Vec<&str> = std::iter::once(module)
+            .chain(params.iter().copied())
+            .collect();
+        eprintln!("[sysfs root] would run: modprobe {}", args.join(" "));
+        Ok(())
+    }
+
+    fn unload(&self, modules: &[&str]) -> Result<()> {
+        eprintln!("[sysfs root] would run: modprobe -r {}", modules.join(" "));
+        Ok(())
+    }
+
+    /// The fixture's modules aren't installed here.
+    fn info(&self, _module: &str, _field: &str) -> Option<String> {
+        None
+    }
+}
+
+pub struct Modprobe;
+
+impl ModuleManager for Modprobe {
+    fn load(&self, module: &str, params: &[&str]) -> Result<()> {
+        let status =
+            crate::command::status_with_timeout(Command::new("modprobe").arg(module).args(params))?;
+        if !status.success() {
+            return Err(anyhow!("modprobe {} failed: {}", module, status));
+        }
+        Ok(())
+    }
+
+    fn unload(&self, modules: &[&str]) -> Result<()> {
+        let status =
+            crate::command::status_with_timeout(Command::new("modprobe").arg("-r").args(modules))?;
+        if !status.success() {
+            return Err(anyhow!(
+                "modprobe -r {} failed: {}",
+                modules.join(" "),
+                status
+            ));
+        }
+        Ok(())
+    }
+
+    fn info(&self, module: &str, field: &str) -> Option<String> {
+        let output = crate::command::output_with_timeout(
+            Command::new("modinfo").args(["-F", field, module]),
+        )
+        .ok()?;
+        let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        (output.status.success() && !value.is_empty()).then_some(value)
+    }
+}
+
+/// An in-memory sysfs. Directories exist implicitly wherever a file is below
+/// them; writes to missing files fail like they would on sysfs.
+#[derive(Default)]
+pub struct MockSysfs {
+    files: Mutex<BTreeMap<PathBuf, String>>,
+    writes: Mutex<Vec<(PathBuf, String)>>,
+}
+
+impl MockSysfs {
+    /// Creates or replaces a file.
+    pub fn set(&self, path: impl Into<PathBuf>, value: &str) {
+        self.files
+            .lock()
+            .unwrap()
+            .insert(path.into(), value.to_string());
+    }
+
+    /// Removes a file, or a directory with everything below it.
+    pub fn remove(&self, path: impl AsRef<Path>) {
+        let path = path.as_ref();
+        self.files
+            .lock()
+            .unwrap()
+            .retain(|file, _| !file.starts_with(path));
+    }
+
+    pub fn get(&self, path: impl AsRef<Path>) -> Option<String> {
+        self.files.lock().unwrap().get(path.as_ref()).cloned()
+    }
+
+    /// Every write so far, in order.
+    pub fn writes(&self) -> Vec<(PathBuf, String)> {
+        self.writes.lock().unwrap().clone()
+    }
+}
+
+impl SysfsBackend for MockSysfs {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        self.get(path)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    }
+
+    fn write(&self, path: &Path, value: &str) -> io::Result<()> {
+        let mut files = self.files.lock().unwrap();
+        let Some(file) = files.get_mut(path) else {
+            return Err(io::Error::from(io::ErrorKind::NotFound));
+        };
+        *file = value.to_string();
+        self.writes
+            .lock()
+            .unwrap()
+            .push((path.to_path_buf(), value.to_string()));
+        Ok(())
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let entries: BTreeSet<PathBuf> = self
+            .files
+            .lock()
+            .unwrap()
+            .keys()
+            .filter_map(|file| {
+                let rest = file.strip_prefix(path).ok()?;
+                let first = rest.components().next()?;
+                Some(path.join(first))
+            })
+            .collect();
+        if entries.is_empty() {
+            return Err(io::Error::from(io::ErrorKind::NotFound));
+        }
+        Ok(entries.into_iter().collect())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.files
+            .lock()
+            .unwrap()
+            .keys()
+            .any(|file| file.starts_with(path))
+    }
+
+    /// The mock has no symlinks.
+    fn read_link(&self, _path: &Path) -> io::Result<PathBuf> {
+        Err(io::Error::from(io::ErrorKind::NotFound))
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        Ok(path.to_path_buf())
+    }
+}
+
+/// Records systemctl calls; `fail` makes the named services' calls fail.
+#[derive(Default)]
+pub struct MockServices {
+    pub calls: Mutex<Vec<(String, String)>>,
+    pub fail: Mutex<BTreeSet<String>>,
+}
+
+impl ServiceManager for MockServices {
+    fn run(&self, action: &str, service: &str) -> Result<()> {
+        self.calls
+            .lock()
+            .unwrap()
+            .push((action.to_string(), service.to_string()));
+        if self.fail.lock().unwrap().contains(service) {
+            return Err(anyhow!("systemctl {} {}: mock failure", action, service));
+        }
+        Ok(())
+    }
+}
+
+/// Loads and unloads modules in a `MockSysfs`, as `/sys/module/<name>`
+/// entries, so code checking sysfs for them sees the change. Records the
+/// parameters each got. Unloading fails while `busy` is set, like
+/// modprobe -r on a module in use.
+pub struct MockModules {
+    sysfs: Arc<MockSysfs>,
+    pub params: Mutex<BTreeMap<String, Vec<String>>>,
+    pub busy: Mutex<bool>,
+    /// modinfo fields by module and field name; modules without any count
+    /// as not installed.
+    pub installed: Mutex<BTreeMap<(String, String), String>>,
+}
+
+impl MockModules {
+    pub fn new(sysfs: Arc<MockSysfs>) -> Self {
+        Self {
+            sysfs,
+            params: Mutex::default(),
+            busy: Mutex::default(),
+            installed: Mutex::default(),
+        }
+    }
+
+    fn dir(module: &str) -> PathBuf {
+        Path::new("/sys/module").join(module)
+    }
+
+    pub fn is_loaded(&self, module: &str) -> bool {
+        self.sysfs.exists(&Self::dir(module))
+    }
+}
+
+impl ModuleManager for MockModules {
+    fn load(&self, module: &str, params: &[&str]) -> Result<()> {
+        self.sysfs.set(Self::dir(module).join("refcnt"), "0");
+        self.params.lock().unwrap().insert(
+            module.to_string(),
+            params.iter().map(|p| p.to_string()).collect(),
+        );
+        Ok(())
+    }
+
+    fn unload(&self, modules: &[&str]) -> Result<()> {
+        if *self.busy.lock().unwrap() {
+            return Err(anyhow!(
+                "modprobe -r {}: module is in use",
+                modules.join(" ")
+            ));
+        }
+        for module in modules {
+            self.sysfs.remove(Self::dir(module));
+        }
+        Ok(())
+    }
+
+    fn info(&self, module: &str, field: &str) -> Option<String> {
+        self.installed
+            .lock()
+            .unwrap()
+            .get(&(module.to_string(), field.to_string()))
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_modules_show_up_in_sysfs() {
+        let sysfs = Arc::new(MockSysfs::default());
+        let modules = MockModules::new(sysfs.clone());
+        modules
+            .load("nvidia", &["NVreg_EnableGpuFirmware=0"])
+            .unwrap();
+        assert!(sysfs.exists(Path::new("/sys/module/nvidia")));
+
+        *modules.busy.lock().unwrap() = true;
+        assert!(modules.unload(&["nvidia"]).is_err());
+        *modules.busy.lock().unwrap() = false;
+        modules.unload(&["nvidia"]).unwrap();
+        assert!(!modules.is_loaded("nvidia"));
+        assert!(!sysfs.exists(Path::new("/sys/module/nvidia")));
+    }
+
+    #[test]
+    fn scoped_backends_end_with_the_guard() {
+        let sysfs = Arc::new(MockSysfs::default());
+        sysfs.set("/sys/bus/pci/rescan", "");
+        let guard = scoped(Backends {
+            sysfs: sysfs.clone(),
+            services: Arc::new(MockServices::default()),
+            modules: Arc::new(MockModules::new(sysfs.clone())),
+            state_dir: PathBuf::from("/nonexistent"),
+        });
+        super::sysfs()
+            .write(Path::new("/sys/bus/pci/rescan"), "1")
+            .unwrap();
+        assert_eq!(sysfs.get("/sys/bus/pci/rescan").as_deref(), Some("1"));
+        assert_eq!(
+            state_path("transition.json"),
+            Path::new("/nonexistent/transition.json")
+        );
+
+        drop(guard);
+        assert!(SCOPED.with(|scoped| scoped.borrow().is_none()));
+    }
+}
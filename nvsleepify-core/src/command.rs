@@ -0,0 +1,91 @@
+use anyhow::Result;
+use std::io::Read;
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_COMMAND_TIMEOUT_SECS: u64 = 30;
+static COMMAND_TIMEOUT_SECS: AtomicU64 = AtomicU64::new(DEFAULT_COMMAND_TIMEOUT_SECS);
+
+/// How long external commands (modprobe, systemctl, lsof, ...) may run
+/// before they are killed.
+pub fn set_command_timeout(secs: u64) {
+    COMMAND_TIMEOUT_SECS.store(secs.max(1), Ordering::Relaxed);
+}
+
+fn describe_command(cmd: &Command) -> String {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| arg.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A command killed for running past the command timeout.
+#[derive(Debug)]
+pub struct CommandTimeout {
+    pub command: String,
+    pub secs: u64,
+}
+
+impl std::fmt::Display for CommandTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`{}` timed out after {}s", self.command, self.secs)
+    }
+}
+
+impl std::error::Error for CommandTimeout {}
+
+/// Waits for `child`, killing it once the command timeout runs out.
+fn wait_with_timeout(mut child: Child, cmd: &Command) -> Result<ExitStatus> {
+    let secs = COMMAND_TIMEOUT_SECS.load(Ordering::Relaxed);
+    let deadline = Instant::now() + Duration::from_secs(secs);
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            // A process stuck in the kernel (modprobe -r on a wedged driver)
+            // only dies once the call returns; reap it whenever that is.
+            std::thread::spawn(move || {
+                let _ = child.wait();
+            });
+            return Err(CommandTimeout {
+                command: describe_command(cmd),
+                secs,
+            }
+            .into());
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Like `Command::status`, but gives up after the command timeout.
+pub fn status_with_timeout(cmd: &mut Command) -> Result<ExitStatus> {
+    let child = cmd.spawn()?;
+    wait_with_timeout(child, cmd)
+}
+
+/// Like `Command::output`, but gives up after the command timeout.
+pub fn output_with_timeout(cmd: &mut Command) -> Result<Output> {
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    // Drained on threads so a chatty command can't block on a full pipe.
+    let drain = |pipe: Option<Box<dyn Read + Send>>| {
+        std::thread::spawn(move || {
+            let mut buf = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut buf);
+            }
+            buf
+        })
+    };
+    let stdout = drain(child.stdout.take().map(|p| Box::new(p) as _));
+    let stderr = drain(child.stderr.take().map(|p| Box::new(p) as _));
+    let status = wait_with_timeout(child, cmd)?;
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
@@ -0,0 +1,589 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Where the tray applet keeps its settings, relative to the user's config directory.
+const TRAY_CONFIG_FILE: &str = "nvsleepify/tray.toml";
+/// System-wide daemon settings.
+pub const DAEMON_CONFIG_FILE: &str = "/etc/nvsleepify/nvsleepifyd.toml";
+
+static DAEMON_CONFIG_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Reads the daemon settings from `path` instead of `DAEMON_CONFIG_FILE`
+/// (`nvsleepifyd --config`).
+pub fn use_daemon_config(path: impl Into<PathBuf>) -> Result<()> {
+    DAEMON_CONFIG_PATH
+        .set(path.into())
+        .map_err(|_| anyhow::anyhow!("The daemon config path is already set"))
+}
+
+pub fn daemon_config_path() -> &'static Path {
+    DAEMON_CONFIG_PATH
+        .get()
+        .map(PathBuf::as_path)
+        .unwrap_or(Path::new(DAEMON_CONFIG_FILE))
+}
+
+/// Daemon settings. Every section is optional; a missing file means defaults.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    pub metrics: MetricsConfig,
+    pub compat: CompatConfig,
+    pub conflicts: ConflictsConfig,
+    pub xorg: XorgConfig,
+    pub commands: CommandsConfig,
+    pub access: AccessConfig,
+    pub modules: ModulesConfig,
+    pub profiles: ProfilesConfig,
+    pub restore: RestoreConfig,
+    pub socket: SocketConfig,
+    pub processes: ProcessesConfig,
+    pub notifications: NotificationsConfig,
+    pub server: ServerConfig,
+    pub display_manager: DisplayManagerConfig,
+    pub idle: IdleConfig,
+    pub persistence: PersistenceConfig,
+    pub sleep: SleepConfig,
+    pub wake_locks: WakeLocksConfig,
+    pub faults: FaultsConfig,
+    pub platform: PlatformConfig,
+}
+
+/// Prometheus metrics export, off unless one of the outputs is set.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Address to serve /metrics on, e.g. "127.0.0.1:9577".
+    pub listen: Option<String>,
+    /// File to keep updated for node_exporter's textfile collector.
+    pub textfile: Option<PathBuf>,
+}
+
+/// Compatibility interfaces for frontends written for other tools.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompatConfig {
+    /// Serve `org.supergfxctl.Daemon` so supergfxctl frontends can switch modes.
+    pub supergfxd: bool,
+}
+
+/// Other GPU switchers nvsleepify should run alongside instead of pausing for.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConflictsConfig {
+    /// "optimus-manager", "system76-power", "supergfxd" or "bumblebee".
+    pub acknowledged: Vec<String>,
+}
+
+/// Xorg configuration managed per mode, off by default.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct XorgConfig {
+    /// Write an xorg.conf.d snippet in Standard mode and remove it otherwise.
+    pub generate: bool,
+    /// Render the desktop on the dGPU instead of only driving its outputs.
+    pub dgpu_primary: bool,
+}
+
+/// Limits on the external commands run during transitions.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CommandsConfig {
+    /// Seconds before a hung modprobe, systemctl or lsof is killed.
+    pub timeout: u64,
+}
+
+/// Which kernel driver the GPU is managed with.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum KernelDriver {
+    /// Whichever is bound to the GPU, loaded or installed.
+    #[default]
+    Auto,
+    Nvidia,
+    /// The open-source driver: one module, no nvidia-* services.
+    Nouveau,
+}
+
+/// Which nvidia modules a wake loads.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ModuleSet {
+    /// nvidia, nvidia_uvm, nvidia_modeset and nvidia_drm.
+    #[default]
+    Full,
+    /// Only nvidia and nvidia_uvm, for CUDA on a GPU that drives no display.
+    /// Nothing in the display stack holds them, so unloading never fights it.
+    Compute,
+}
+
+impl ModuleSet {
+    /// In load order.
+    pub fn modules(&self) -> &'static [&'static str] {
+        match self {
+            ModuleSet::Full => &["nvidia", "nvidia_uvm", "nvidia_modeset", "nvidia_drm"],
+            ModuleSet::Compute => &["nvidia", "nvidia_uvm"],
+        }
+    }
+}
+
+/// Kernel module handling on wake.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModulesConfig {
+    pub driver: KernelDriver,
+    pub load: ModuleSet,
+    /// Parameters passed to modprobe per module, e.g.
+    /// `nvidia = "NVreg_DynamicPowerManagement=0x02"`.
+    pub options: BTreeMap<String, String>,
+}
+
+impl ModulesConfig {
+    pub const fn new() -> Self {
+        Self {
+            driver: KernelDriver::Auto,
+            load: ModuleSet::Full,
+            options: BTreeMap::new(),
+        }
+    }
+
+    /// The configured parameters for `module`. Each must be `name=value`,
+    /// since anything else would be taken as another module or a flag.
+    pub fn params(&self, module: &str) -> Result<Vec<&str>> {
+        let Some(options) = self.options.get(module) else {
+            return Ok(Vec::new());
+        };
+        let params: Vec<&str> = options.split_whitespace().collect();
+        if let Some(bad) = params.iter().find(
+            |p| !matches!(p.split_once('='), Some((k, _)) if !k.is_empty() && !k.starts_with('-')),
+        ) {
+            return Err(anyhow::anyhow!(
+                "Invalid option '{}' for {} in [modules.options]; expected name=value",
+                bad,
+                module
+            ));
+        }
+        Ok(params)
+    }
+}
+
+/// Modes picked by power source, e.g. Standard when docked and Integrated
+/// on battery. Modes are named like on the command line.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProfilesConfig {
+    /// Mode on AC power that isn't one of the docks.
+    pub ac: Option<String>,
+    pub battery: Option<String>,
+    /// Power supply name (under /sys/class/power_supply) to mode.
+    pub docks: BTreeMap<String, String>,
+    /// Remember the mode last set in each context and switch back to it.
+    pub remember: bool,
+}
+
+/// What startup does when the saved mode has the GPU off the nvidia driver
+/// but processes are using it, e.g. after an unclean shutdown.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Reconcile {
+    /// Switch to Standard mode and leave the GPU to its users.
+    PreferHardware,
+    /// Enforce the saved mode, killing the processes.
+    PreferState,
+    /// Keep the saved mode, but only sleep once the processes exit.
+    #[default]
+    AskLater,
+}
+
+/// What a sleep may do with processes holding the GPU.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum KillPolicy {
+    /// Never kill them; the sleep fails, or waits for them in Integrated mode.
+    Never,
+    /// Only kill them when the client asked to (`SetModeWithOptions` with
+    /// `kill_procs`, which the CLI and tray pass after confirming).
+    Ask,
+    /// Kill them whenever a mode calls for it, monitor enforcement included.
+    #[default]
+    Always,
+}
+
+impl std::fmt::Display for KillPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KillPolicy::Never => write!(f, "never"),
+            KillPolicy::Ask => write!(f, "ask"),
+            KillPolicy::Always => write!(f, "always"),
+        }
+    }
+}
+
+/// Processes holding the GPU.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProcessesConfig {
+    pub kill: KillPolicy,
+}
+
+/// Desktop notifications the daemon sends to graphical sessions.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationsConfig {
+    /// Tell the user when the monitor can't put the GPU to sleep.
+    pub sleep_blocked: bool,
+    /// Seconds between two such notifications at least.
+    pub interval: u64,
+    /// Tell the user when a sleep saved too little power (`[sleep] min_saving`).
+    pub residual_power: bool,
+}
+
+impl Default for NotificationsConfig {
+    fn default() -> Self {
+        Self {
+            sleep_blocked: true,
+            interval: 30 * 60,
+            residual_power: true,
+        }
+    }
+}
+
+/// Headless GPU servers: no display stack to look after, and in Integrated
+/// mode the card idles between jobs instead of being put to sleep at once.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub enabled: bool,
+    /// Seconds an awake GPU must go unused before it's put back to sleep.
+    pub idle_after: u64,
+    /// Run nvidia-persistenced while the GPU is awake.
+    pub persistenced: bool,
+}
+
+impl ServerConfig {
+    pub const fn new() -> Self {
+        Self {
+            enabled: false,
+            idle_after: 5 * 60,
+            persistenced: true,
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The display manager, whose login screen can hold the GPU.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplayManagerConfig {
+    /// Restart it around a sleep when only its login screen keeps the GPU
+    /// awake and nobody is logged in graphically.
+    pub restart: bool,
+}
+
+/// Kernel reports about the GPU: Xid errors, falling off the bus, PCIe AER.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct FaultsConfig {
+    /// Follow /dev/kmsg and record them.
+    pub watch: bool,
+    /// Power-cycle the GPU after a fatal one.
+    pub recover: bool,
+}
+
+impl Default for FaultsConfig {
+    fn default() -> Self {
+        Self {
+            watch: true,
+            recover: false,
+        }
+    }
+}
+
+/// Telling processes that merely hold the awake GPU from ones that use it,
+/// by its utilization.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdleConfig {
+    /// Seconds the GPU must stay at or below `utilization` before the
+    /// processes still holding it count as idle; 0 turns this off.
+    pub after: u64,
+    /// Utilization in percent the GPU counts as idle at.
+    pub utilization: u32,
+}
+
+impl IdleConfig {
+    pub const fn new() -> Self {
+        Self {
+            after: 10 * 60,
+            utilization: 2,
+        }
+    }
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Persistence mode, which keeps the GPU out of D3 for as long as it's on.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct PersistenceConfig {
+    /// Set it on each wake instead of leaving it to nvidia-persistenced:
+    /// off in Optimized mode and for temporary wakes in Integrated mode.
+    pub manage: bool,
+    /// Whether it's on in Standard mode.
+    pub standard: bool,
+}
+
+impl PersistenceConfig {
+    pub const fn new() -> Self {
+        Self {
+            manage: true,
+            standard: true,
+        }
+    }
+}
+
+impl Default for PersistenceConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether sleeps cut the GPU's power with the firmware's dGPU switch
+/// (ASUS `dgpu_disable` and the like) instead of its slot.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DgpuDisable {
+    /// Use the switch when there is one.
+    #[default]
+    Auto,
+    /// Always switch the slot, e.g. while another tool owns the switch.
+    Never,
+}
+
+/// Firmware switches that power the dGPU off more thoroughly than its slot.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlatformConfig {
+    pub dgpu_disable: DgpuDisable,
+    /// The switch's sysfs attribute where it isn't found by itself, e.g. one
+    /// from a Lenovo Legion module. Writing "1" must cut the dGPU's power
+    /// and "0" give it back.
+    pub path: Option<PathBuf>,
+    /// Let setting Integrated mode switch a display MUX wired to the dGPU
+    /// back to hybrid. Needs a reboot to take effect.
+    pub switch_mux: bool,
+}
+
+impl PlatformConfig {
+    pub const fn new() -> Self {
+        Self {
+            dgpu_disable: DgpuDisable::Auto,
+            path: None,
+            switch_mux: false,
+        }
+    }
+}
+
+/// One way of putting the GPU to sleep, from least to most invasive.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SleepStage {
+    /// Keep the driver loaded and let runtime PM suspend the GPU.
+    RuntimeSuspend,
+    /// Unload the driver and let runtime PM suspend the bare device.
+    Unbind,
+    /// Unload the driver and cut the slot's power.
+    PowerOff,
+}
+
+impl std::fmt::Display for SleepStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SleepStage::RuntimeSuspend => write!(f, "runtime-suspend"),
+            SleepStage::Unbind => write!(f, "unbind"),
+            SleepStage::PowerOff => write!(f, "power-off"),
+        }
+    }
+}
+
+/// How sleeps escalate when the GPU doesn't power down.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SleepConfig {
+    /// Tried in order until the GPU reaches D3cold or leaves the bus.
+    pub strategy: Vec<SleepStage>,
+    /// Seconds `runtime-suspend` and `unbind` wait for D3cold before the
+    /// next stage.
+    pub timeout: u64,
+    /// Watts a sleep on battery should save at least; less suggests the
+    /// card still draws power. 0 turns the check off.
+    pub min_saving: f64,
+    /// Save the config space headers of the GPU's functions before powering
+    /// the slot off and write back what changed after the rescan, for
+    /// firmware that loses the BARs.
+    pub restore_config_space: bool,
+}
+
+impl Default for SleepConfig {
+    fn default() -> Self {
+        Self {
+            strategy: vec![SleepStage::PowerOff],
+            timeout: 10,
+            min_saving: 1.5,
+            restore_config_space: false,
+        }
+    }
+}
+
+/// Wake locks held on behalf of systemd units.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct WakeLocksConfig {
+    /// Units that keep the GPU awake while they run, e.g. "ollama.service".
+    pub units: Vec<String>,
+}
+
+/// Restoring the saved mode at startup.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RestoreConfig {
+    pub reconcile: Reconcile,
+}
+
+/// JSON-RPC on a Unix socket, for systems without a system bus. Off unless
+/// `path` is set.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SocketConfig {
+    /// e.g. "/run/nvsleepify.sock"
+    pub path: Option<PathBuf>,
+    /// Permission bits of the socket file; connecting needs write access.
+    pub mode: u32,
+    /// Group owning the socket file, root's if unset.
+    pub group: Option<String>,
+}
+
+impl Default for SocketConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            mode: 0o660,
+            group: None,
+        }
+    }
+}
+
+/// Who may change the mode, wake or kill processes over D-Bus.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessConfig {
+    /// Only root and members of this group (e.g. "nvsleepify") may call the
+    /// state-changing methods. Unset leaves them open to everyone.
+    pub group: Option<String>,
+}
+
+impl Default for CommandsConfig {
+    fn default() -> Self {
+        Self {
+            timeout: crate::command::DEFAULT_COMMAND_TIMEOUT_SECS,
+        }
+    }
+}
+
+impl DaemonConfig {
+    pub fn load() -> Result<Self> {
+        Self::load_from(daemon_config_path())
+    }
+
+    pub fn load_from(path: &std::path::Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => toml::from_str(&content)
+                .map_err(|e| anyhow::anyhow!("Invalid {}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(anyhow::anyhow!("Failed to read {}: {}", path.display(), e)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IconStyle {
+    /// Theme icons if installed, embedded ones otherwise.
+    #[default]
+    Auto,
+    Theme,
+    Embedded,
+}
+
+/// Per-user tray applet preferences. Missing keys take their defaults.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TrayConfig {
+    pub notifications: bool,
+    /// Seconds between refreshes while the daemon's change signals are available.
+    pub poll_interval: u64,
+    /// Ask before switching to Integrated mode kills blocking processes.
+    pub confirm_kill: bool,
+    pub icon_style: IconStyle,
+}
+
+impl Default for TrayConfig {
+    fn default() -> Self {
+        Self {
+            notifications: true,
+            poll_interval: 30,
+            confirm_kill: true,
+            icon_style: IconStyle::Auto,
+        }
+    }
+}
+
+fn config_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+}
+
+impl TrayConfig {
+    pub fn path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join(TRAY_CONFIG_FILE))
+    }
+
+    /// Loads the saved settings. A missing file gives the defaults; an unreadable
+    /// one is reported and ignored so the tray still starts.
+    pub fn load() -> Self {
+        let Some(path) = Self::path() else {
+            return Self::default();
+        };
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(_) => return Self::default(),
+        };
+        toml::from_str(&content).unwrap_or_else(|e| {
+            eprintln!("Ignoring invalid {}: {}", path.display(), e);
+            Self::default()
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path().ok_or_else(|| anyhow::anyhow!("No config directory"))?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
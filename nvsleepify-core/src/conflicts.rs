@@ -0,0 +1,92 @@
+use crate::system;
+use std::path::Path;
+use std::process::Command;
+
+/// Other GPU switchers that actively manage the dGPU. Running alongside them,
+/// nvsleepify would flip the GPU back and forth against them.
+/// (name used in the config, systemd unit, process name)
+const SWITCHERS: [(&str, &str, &str); 4] = [
+    (
+        "optimus-manager",
+        "optimus-manager.service",
+        "optimus-manager-daemon",
+    ),
+    ("system76-power", "system76-power.service", "system76-power"),
+    ("supergfxd", "supergfxd.service", "supergfxd"),
+    ("bumblebee", "bumblebeed.service", "bumblebeed"),
+];
+
+/// A conflicting switcher found on the system.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub tool: &'static str,
+    /// What gave it away, e.g. "supergfxd.service is active".
+    pub evidence: String,
+}
+
+fn unit_active(unit: &str) -> bool {
+    system::status_with_timeout(Command::new("systemctl").args(["is-active", "--quiet", unit]))
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+/// Whether a process named `name` runs, either directly or as a script
+/// (python3 /usr/bin/optimus-manager-daemon).
+fn process_running(name: &str) -> bool {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return false;
+    };
+    entries.flatten().any(|entry| {
+        let Ok(cmdline) = std::fs::read(entry.path().join("cmdline")) else {
+            return false;
+        };
+        cmdline
+            .split(|&b| b == 0)
+            .take(2)
+            .filter_map(|arg| std::str::from_utf8(arg).ok())
+            .any(|arg| Path::new(arg).file_name().is_some_and(|f| f == name))
+    })
+}
+
+/// Finds switchers that are currently running.
+pub fn detect() -> Vec<Conflict> {
+    SWITCHERS
+        .iter()
+        .filter_map(|&(tool, unit, process)| {
+            let evidence = if unit_active(unit) {
+                format!("{} is active", unit)
+            } else if process_running(process) {
+                format!("{} is running", process)
+            } else {
+                return None;
+            };
+            Some(Conflict { tool, evidence })
+        })
+        .collect()
+}
+
+/// Running switchers that the config doesn't acknowledge.
+pub fn unacknowledged(acknowledged: &[String]) -> Vec<Conflict> {
+    detect()
+        .into_iter()
+        .filter(|c| !acknowledged.iter().any(|a| a == c.tool))
+        .collect()
+}
+
+/// Explains why enforcement is paused and how to resume it.
+pub fn describe(conflicts: &[Conflict]) -> String {
+    let found: Vec<String> = conflicts
+        .iter()
+        .map(|c| format!("{} ({})", c.tool, c.evidence))
+        .collect();
+    let names: Vec<String> = conflicts
+        .iter()
+        .map(|c| format!("\"{}\"", c.tool))
+        .collect();
+    format!(
+        "Another GPU switcher is running: {}. nvsleepify won't enforce its mode while it does. Stop and disable it, or add `acknowledged = [{}]` under [conflicts] in {} to run both anyway.",
+        found.join(", "),
+        names.join(", "),
+        crate::config::daemon_config_path().display()
+    )
+}
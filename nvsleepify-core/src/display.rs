@@ -0,0 +1,196 @@
+use crate::backend;
+use crate::pci::PciDevice;
+use crate::platform::{self, Mux};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Where debugfs lists each DRM device's open files, by card number or (on
+/// newer kernels) by PCI address. Only readable by root.
+const DRI_DEBUG_DIR: &str = "/sys/kernel/debug/dri";
+
+/// Connector types of a laptop's own panel.
+const INTERNAL_CONNECTORS: [&str; 3] = ["eDP", "LVDS", "DSI"];
+
+/// A sign that the session is displayed through the Nvidia GPU, so powering
+/// it down or handing it to a VM would take the screen with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Evidence {
+    /// The firmware initialized it as the boot display: a dGPU-only machine,
+    /// or a MUX switched to the dGPU.
+    BootVga,
+    /// The laptop's display MUX wires the built-in panel to it.
+    Mux,
+    /// No other GPU has a driver bound, so nothing would be left to drive a
+    /// screen.
+    OnlyGpu,
+    /// A connector of the GPU lights a display, e.g. "eDP-1". `internal` for
+    /// the laptop's own panel.
+    Connector { name: String, internal: bool },
+    /// A display server is DRM master on the GPU's primary node, i.e. it
+    /// drives the GPU's outputs.
+    DisplayServer { command: String, pid: String },
+}
+
+impl fmt::Display for Evidence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Evidence::BootVga => write!(f, "it is the boot display (boot_vga)"),
+            Evidence::Mux => write!(f, "the display MUX wires the built-in panel to it"),
+            Evidence::OnlyGpu => write!(f, "no other GPU is active"),
+            Evidence::Connector {
+                name,
+                internal: true,
+            } => write!(f, "it drives the built-in panel ({})", name),
+            Evidence::Connector { name, .. } => write!(f, "a monitor on {} is lit by it", name),
+            Evidence::DisplayServer { command, pid } => {
+                write!(f, "{} (PID {}) renders the session on it", command, pid)
+            }
+        }
+    }
+}
+
+/// The GPU's primary DRM node in sysfs, e.g. .../drm/card1.
+fn primary_card(gpu: &PciDevice) -> Option<PathBuf> {
+    backend::sysfs()
+        .read_dir(&gpu.path.join("drm"))
+        .ok()?
+        .into_iter()
+        .find(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("card"))
+        })
+}
+
+/// Enabled connectors with a display attached, e.g. "card1-eDP-1" as "eDP-1".
+fn lit_connectors(card: &Path) -> Vec<Evidence> {
+    let Some(card_name) = card.file_name().and_then(|name| name.to_str()) else {
+        return Vec::new();
+    };
+    let prefix = format!("{}-", card_name);
+    let Ok(entries) = backend::sysfs().read_dir(card) else {
+        return Vec::new();
+    };
+    entries
+        .iter()
+        .filter_map(|entry| {
+            let name = entry.file_name()?.to_str()?.strip_prefix(&prefix)?;
+            let connected = backend::read_trimmed(entry.join("status"))? == "connected";
+            let enabled = backend::read_trimmed(entry.join("enabled"))? == "enabled";
+            (connected && enabled).then(|| Evidence::Connector {
+                name: name.to_string(),
+                internal: INTERNAL_CONNECTORS
+                    .iter()
+                    .any(|kind| name.starts_with(&format!("{}-", kind))),
+            })
+        })
+        .collect()
+}
+
+/// Display servers holding DRM master on the card, from debugfs' client
+/// list (`command tgid dev master a uid magic`). Empty without debugfs.
+fn display_servers(gpu: &PciDevice, card: &Path) -> Vec<Evidence> {
+    let minor = card
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.strip_prefix("card"))
+        .unwrap_or_default();
+    let clients = [minor, gpu.address.as_str()].iter().find_map(|dir| {
+        backend::sysfs()
+            .read(&Path::new(DRI_DEBUG_DIR).join(dir).join("clients"))
+            .ok()
+    });
+    let Some(clients) = clients else {
+        return Vec::new();
+    };
+    clients
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [command, pid, _dev, "y", ..] => Some(Evidence::DisplayServer {
+                    command: command.to_string(),
+                    pid: pid.to_string(),
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Why the session would go dark without `gpu`. Empty when it's displayed
+/// through another GPU, which is the usual hybrid (Optimus) setup.
+pub fn check(gpu: &PciDevice) -> Vec<Evidence> {
+    let mut evidence = Vec::new();
+    if backend::read_trimmed(gpu.path.join("boot_vga")).as_deref() == Some("1") {
+        evidence.push(Evidence::BootVga);
+    }
+    if platform::mux() == Some(Mux::Discrete) {
+        evidence.push(Evidence::Mux);
+    }
+    let other_gpu = PciDevice::find_display_devices()
+        .iter()
+        .any(|device| device.address != gpu.address && device.driver().is_some());
+    if !other_gpu {
+        evidence.push(Evidence::OnlyGpu);
+    }
+    if let Some(card) = primary_card(gpu) {
+        evidence.extend(lit_connectors(&card));
+        evidence.extend(display_servers(gpu, &card));
+    }
+    evidence
+}
+
+/// Why the GPU has to stay on, and what the user can do about it.
+pub fn advice(evidence: &[Evidence]) -> String {
+    let reasons: Vec<String> = evidence.iter().map(|e| e.to_string()).collect();
+    let mut steps = Vec::new();
+    let internal = evidence
+        .iter()
+        .any(|e| matches!(e, Evidence::Connector { internal: true, .. }));
+    if evidence.contains(&Evidence::Mux) && platform::pending_mux() == Some(Mux::Hybrid) {
+        steps.push("reboot, which finishes switching the MUX to hybrid");
+    } else if evidence.contains(&Evidence::Mux) {
+        steps.push(
+            "switch the MUX to hybrid (`switch_mux = true` under [platform] lets setting \
+             Integrated mode do it) and reboot, so the integrated GPU drives the panel",
+        );
+    } else if internal || evidence.contains(&Evidence::BootVga) {
+        steps.push(
+            "switch the GPU mode to hybrid (Optimus/MSHybrid) in the firmware setup or the \
+             vendor's tool and reboot, so the integrated GPU drives the panel",
+        );
+    }
+    if evidence.iter().any(|e| {
+        matches!(
+            e,
+            Evidence::Connector {
+                internal: false,
+                ..
+            }
+        )
+    }) {
+        steps.push("unplug the monitors connected to the Nvidia GPU's outputs");
+    }
+    if evidence.contains(&Evidence::OnlyGpu) {
+        steps
+            .push("enable the integrated GPU in the firmware setup and make sure its driver loads");
+    }
+    if evidence
+        .iter()
+        .any(|e| matches!(e, Evidence::DisplayServer { .. }))
+    {
+        steps.push(
+            "log in to a session running on the integrated GPU (e.g. undo `prime-select \
+             nvidia` or a PrimaryGPU option in the Xorg configuration, then log out and in)",
+        );
+    }
+    format!(
+        "The session is displayed through the Nvidia GPU: {}. Powering it down would \
+         black out the screen. To use the GPU on demand instead, {}. `nvsleepify set \
+         --force` overrides this check, e.g. on a machine without a screen.",
+        reasons.join("; "),
+        steps.join(", or ")
+    )
+}
@@ -0,0 +1,80 @@
+use crate::protocol::{ProcessInfo, ProcessList};
+use crate::system;
+use anyhow::{anyhow, Result};
+use std::path::Path;
+use std::process::Command;
+
+/// The alias systemd gives whichever display manager is enabled.
+const DISPLAY_MANAGER_UNIT: &str = "display-manager.service";
+const ALIAS_PATH: &str = "/etc/systemd/system/display-manager.service";
+
+/// Users login screens run as: GDM's (older and per-greeter ones), SDDM's and
+/// LightDM's.
+const GREETER_USERS: [&str; 4] = ["gdm", "sddm", "lightdm", "gdm-greeter"];
+
+/// Whether `proc` belongs to a display manager's login screen, e.g.
+/// `sddm-greeter`, or GDM's gnome-shell and Xwayland running as `gdm`.
+pub fn is_greeter(proc: &ProcessInfo) -> bool {
+    if proc.name.contains("greeter") {
+        return true;
+    }
+    let user = system::get_process_details(&proc.pid).user;
+    GREETER_USERS.contains(&user.as_str()) || user.starts_with("gdm-greeter")
+}
+
+/// The login screen's processes among `procs`.
+pub fn greeters(procs: &[ProcessInfo]) -> ProcessList {
+    procs.iter().filter(|p| is_greeter(p)).cloned().collect()
+}
+
+/// The enabled display manager's unit, e.g. "gdm.service", going by the
+/// display-manager.service alias.
+pub fn unit() -> Option<String> {
+    let target = std::fs::read_link(ALIAS_PATH).ok()?;
+    Some(target.file_name()?.to_string_lossy().into_owned())
+}
+
+/// What the user can do about a login screen holding the GPU.
+pub fn advice(greeters: &[ProcessInfo]) -> String {
+    let names: Vec<String> = greeters.iter().map(|p| p.to_string()).collect();
+    let unit = unit().unwrap_or_else(|| DISPLAY_MANAGER_UNIT.to_string());
+    format!(
+        "The login screen holds the GPU ({}). Killing it would only start it again; \
+         restart the display manager while the GPU sleeps instead: log out, switch to a \
+         text console and run `sudo systemctl stop {unit} && sudo nvsleepify set integrated \
+         && sudo systemctl start {unit}`, or set `[display_manager] restart = true`",
+        names.join(", "),
+        unit = unit
+    )
+}
+
+fn systemctl(action: &str) -> Result<()> {
+    let status = system::status_with_timeout(
+        Command::new("systemctl")
+            .arg(action)
+            .arg(DISPLAY_MANAGER_UNIT),
+    )?;
+    if !status.success() {
+        return Err(anyhow!(
+            "systemctl {} {} failed",
+            action,
+            DISPLAY_MANAGER_UNIT
+        ));
+    }
+    Ok(())
+}
+
+/// Stops the display manager, runs `sleep` while the login screen is gone and
+/// starts it again, so it comes back on the iGPU alone. The display manager
+/// is started again even when `sleep` fails.
+pub fn restart_around<T, E: From<anyhow::Error>>(
+    sleep: impl FnOnce() -> Result<T, E>,
+) -> Result<T, E> {
+    if !Path::new(ALIAS_PATH).exists() {
+        return Err(anyhow!("No display manager is enabled").into());
+    }
+    systemctl("stop")?;
+    let result = sleep();
+    systemctl("start")?;
+    result
+}
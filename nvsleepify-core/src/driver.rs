@@ -0,0 +1,199 @@
+use crate::backend;
+use crate::config::KernelDriver;
+use crate::pci::PciDevice;
+use colored::*;
+use std::fmt;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Exists only while the nvidia module is loaded.
+const MODULE_VERSION: &str = "/sys/module/nvidia/version";
+const PROC_VERSION: &str = "/proc/driver/nvidia/version";
+/// Bit 0 turns GSP firmware on in the proprietary modules; the open ones
+/// always use it.
+const GSP_PARAM: &str = "/sys/module/nvidia/parameters/NVreg_EnableGpuFirmware";
+const GSP_PARAM_NAME: &str = "NVreg_EnableGpuFirmware";
+
+/// nouveau is a single module, DRM driver included.
+pub const NOUVEAU_MODULE: &str = "nouveau";
+
+/// The driver whose modules were last unloaded, so the wake loads it again
+/// while nothing is bound or loaded to tell.
+static UNLOADED: Mutex<Option<Driver>> = Mutex::new(None);
+
+/// How long GSP firmware gets to shut down after the modules unload, before
+/// the slot loses power. Cutting it off mid-teardown can leave the next wake
+/// with a GPU that fails to initialize.
+pub const GSP_SETTLE: Duration = Duration::from_secs(2);
+
+/// Which kernel modules the nvidia driver comes as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flavor {
+    Proprietary,
+    /// The open-gpu-kernel-modules, which run the driver on the GPU's GSP.
+    Open,
+}
+
+impl fmt::Display for Flavor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Flavor::Proprietary => write!(f, "proprietary"),
+            Flavor::Open => write!(f, "open"),
+        }
+    }
+}
+
+/// The kernel driver the GPU runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Driver {
+    /// NVIDIA's, proprietary or open: several modules and the nvidia-*
+    /// services.
+    Nvidia,
+    Nouveau,
+}
+
+impl fmt::Display for Driver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Driver::Nvidia => write!(f, "nvidia"),
+            Driver::Nouveau => write!(f, "{}", NOUVEAU_MODULE),
+        }
+    }
+}
+
+/// The driver to unload and load: the configured one, else the one bound to
+/// the GPU, else the one last unloaded, else the one loaded. With none of
+/// those, nouveau only if the nvidia modules aren't installed.
+pub fn current(configured: KernelDriver) -> Driver {
+    match configured {
+        KernelDriver::Nvidia => return Driver::Nvidia,
+        KernelDriver::Nouveau => return Driver::Nouveau,
+        KernelDriver::Auto => {}
+    }
+    let bound = PciDevice::find_nvidia_gpu()
+        .ok()
+        .and_then(|gpu| gpu.driver());
+    match bound.as_deref() {
+        Some("nvidia") => return Driver::Nvidia,
+        Some(NOUVEAU_MODULE) => return Driver::Nouveau,
+        _ => {}
+    }
+    if let Some(driver) = *UNLOADED.lock().unwrap() {
+        return driver;
+    }
+    let loaded = |module: &str| backend::sysfs().exists(&Path::new("/sys/module").join(module));
+    if loaded("nvidia") {
+        Driver::Nvidia
+    } else if loaded(NOUVEAU_MODULE) || (installed().is_none() && nouveau_installed()) {
+        Driver::Nouveau
+    } else {
+        Driver::Nvidia
+    }
+}
+
+/// Called once `driver`'s modules are unloaded.
+pub fn remember_unloaded(driver: Driver) {
+    *UNLOADED.lock().unwrap() = Some(driver);
+}
+
+fn nouveau_installed() -> bool {
+    backend::modules()
+        .info(NOUVEAU_MODULE, "filename")
+        .is_some()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DriverInfo {
+    pub flavor: Flavor,
+    pub version: Option<String>,
+    /// Read from the running module; otherwise from the installed one.
+    pub loaded: bool,
+    /// Whether GSP firmware runs the GPU. Only known while loaded; the
+    /// installed proprietary modules pick it per GPU.
+    pub gsp: bool,
+}
+
+impl fmt::Display for DriverInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.flavor)?;
+        if let Some(version) = &self.version {
+            write!(f, " {}", version)?;
+        }
+        if self.gsp {
+            write!(f, ", GSP firmware")?;
+        }
+        if !self.loaded {
+            write!(f, " (installed, not loaded)")?;
+        }
+        Ok(())
+    }
+}
+
+/// The loaded nvidia module's flavor, or the installed one's while the GPU
+/// sleeps. None when neither can be found.
+pub fn detect() -> Option<DriverInfo> {
+    loaded().or_else(installed)
+}
+
+/// The running module, from sysfs and /proc/driver/nvidia.
+pub fn loaded() -> Option<DriverInfo> {
+    let version = backend::read_trimmed(MODULE_VERSION)?;
+    let banner = backend::read_trimmed(PROC_VERSION).unwrap_or_default();
+    let flavor = if banner.contains("Open Kernel Module") {
+        Flavor::Open
+    } else {
+        Flavor::Proprietary
+    };
+    let firmware_bit = backend::read_trimmed(GSP_PARAM)
+        .and_then(|value| value.parse::<u32>().ok())
+        .is_some_and(|value| value & 1 == 1);
+    Some(DriverInfo {
+        flavor,
+        version: Some(version),
+        loaded: true,
+        gsp: flavor == Flavor::Open || firmware_bit,
+    })
+}
+
+/// The module modprobe would load, going by its license: the open modules
+/// are "Dual MIT/GPL", the proprietary ones "NVIDIA".
+fn installed() -> Option<DriverInfo> {
+    let field = |name: &str| backend::modules().info("nvidia", name);
+    let license = field("license")?;
+    let flavor = if license.contains("GPL") {
+        Flavor::Open
+    } else {
+        Flavor::Proprietary
+    };
+    Some(DriverInfo {
+        flavor,
+        version: field("version"),
+        loaded: false,
+        gsp: flavor == Flavor::Open,
+    })
+}
+
+/// Drops module parameters the flavor rejects: the open modules can't run
+/// without GSP firmware, so turning it off only makes the load fail.
+pub fn filter_params<'a>(flavor: Flavor, module: &str, params: Vec<&'a str>) -> Vec<&'a str> {
+    if flavor != Flavor::Open || module != "nvidia" {
+        return params;
+    }
+    params
+        .into_iter()
+        .filter(|param| {
+            let disables_gsp = param
+                .split_once('=')
+                .is_some_and(|(name, value)| name == GSP_PARAM_NAME && value.trim() == "0");
+            if disables_gsp {
+                eprintln!(
+                    "{} Ignoring {} for the open nvidia modules, which require GSP firmware",
+                    "WARN:".yellow(),
+                    param
+                );
+            }
+            !disables_gsp
+        })
+        .collect()
+}
@@ -0,0 +1,172 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{Mutex, OnceLock};
+use tokio::sync::broadcast;
+
+/// How many entries the daemon keeps in memory for `nvsleepify logs`.
+const CAPACITY: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Info,
+    Warn,
+    Error,
+}
+
+impl std::fmt::Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Level::Info => write!(f, "INFO"),
+            Level::Warn => write!(f, "WARN"),
+            Level::Error => write!(f, "ERROR"),
+        }
+    }
+}
+
+impl std::str::FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "INFO" => Ok(Level::Info),
+            "WARN" => Ok(Level::Warn),
+            "ERROR" => Ok(Level::Error),
+            _ => Err(format!("Unknown log level: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    pub timestamp: u64,
+    pub level: Level,
+    pub message: String,
+}
+
+impl LogEntry {
+    /// D-Bus representation: (unix_timestamp, level, message)
+    pub fn to_tuple(&self) -> (u64, String, String) {
+        (self.timestamp, self.level.to_string(), self.message.clone())
+    }
+
+    /// The inverse of `to_tuple`; unknown levels read as Info.
+    pub fn from_tuple((timestamp, level, message): (u64, String, String)) -> Self {
+        Self {
+            timestamp,
+            level: level.parse().unwrap_or(Level::Info),
+            message,
+        }
+    }
+}
+
+/// Least severe level that is printed; everything is still kept for
+/// `nvsleepify logs`.
+static PRINT_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+/// Prefix printed lines with the time and level, for output that doesn't go
+/// to the journal.
+static TIMESTAMPS: AtomicBool = AtomicBool::new(false);
+
+pub fn set_print_level(level: Level) {
+    PRINT_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn set_timestamps(enabled: bool) {
+    TIMESTAMPS.store(enabled, Ordering::Relaxed);
+}
+
+fn buffer() -> &'static Mutex<VecDeque<LogEntry>> {
+    static BUFFER: OnceLock<Mutex<VecDeque<LogEntry>>> = OnceLock::new();
+    BUFFER.get_or_init(|| Mutex::new(VecDeque::with_capacity(CAPACITY)))
+}
+
+fn sender() -> &'static broadcast::Sender<LogEntry> {
+    static SENDER: OnceLock<broadcast::Sender<LogEntry>> = OnceLock::new();
+    SENDER.get_or_init(|| broadcast::channel(64).0)
+}
+
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Formats a unix timestamp as local "YYYY-MM-DD HH:MM:SS".
+pub fn format_timestamp(timestamp: u64) -> String {
+    let t = timestamp as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&t, &mut tm) }.is_null() {
+        return timestamp.to_string();
+    }
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+/// Prints the message (for the journal) and records it in the in-memory history.
+/// A message identical to the most recent entry is only printed, so the monitor
+/// loop retrying the same action doesn't flush the history.
+pub fn record(level: Level, message: impl Into<String>) {
+    let entry = LogEntry {
+        timestamp: unix_now(),
+        level,
+        message: message.into(),
+    };
+    if level as u8 >= PRINT_LEVEL.load(Ordering::Relaxed) {
+        let line = if TIMESTAMPS.load(Ordering::Relaxed) {
+            format!(
+                "[{}] {:<5} {}",
+                format_timestamp(entry.timestamp),
+                level.to_string(),
+                entry.message
+            )
+        } else {
+            entry.message.clone()
+        };
+        match level {
+            Level::Info => println!("{}", line),
+            Level::Warn | Level::Error => eprintln!("{}", line),
+        }
+    }
+
+    {
+        let mut buf = buffer().lock().unwrap();
+        if let Some(last) = buf.back() {
+            if last.level == entry.level && last.message == entry.message {
+                return;
+            }
+        }
+        if buf.len() == CAPACITY {
+            buf.pop_front();
+        }
+        buf.push_back(entry.clone());
+    }
+    // No receivers is fine: nobody is following the log.
+    let _ = sender().send(entry);
+}
+
+pub fn info(message: impl Into<String>) {
+    record(Level::Info, message);
+}
+
+pub fn warn(message: impl Into<String>) {
+    record(Level::Warn, message);
+}
+
+pub fn error(message: impl Into<String>) {
+    record(Level::Error, message);
+}
+
+pub fn entries() -> Vec<LogEntry> {
+    buffer().lock().unwrap().iter().cloned().collect()
+}
+
+pub fn subscribe() -> broadcast::Receiver<LogEntry> {
+    sender().subscribe()
+}
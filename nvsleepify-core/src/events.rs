@@ -0,0 +1,120 @@
+use crate::eventlog::{self, unix_now, Level};
+use std::collections::VecDeque;
+use std::sync::{Mutex, OnceLock};
+use tokio::sync::broadcast;
+
+/// How many events the daemon keeps in memory for `nvsleepify events`.
+const CAPACITY: usize = 100;
+
+/// What an event is about, so consumers can filter without parsing messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// A mode was requested.
+    Mode,
+    /// The GPU's power state changed.
+    Transition,
+    /// The monitor put the GPU back into the state the mode calls for.
+    Enforcement,
+    /// The daemon decided to act (or not) on its own: Optimized mode reacting
+    /// to the charger, temporary wakes, conflicting switchers.
+    Policy,
+    Failure,
+}
+
+impl std::fmt::Display for EventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventKind::Mode => write!(f, "mode"),
+            EventKind::Transition => write!(f, "transition"),
+            EventKind::Enforcement => write!(f, "enforcement"),
+            EventKind::Policy => write!(f, "policy"),
+            EventKind::Failure => write!(f, "failure"),
+        }
+    }
+}
+
+impl std::str::FromStr for EventKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mode" => Ok(EventKind::Mode),
+            "transition" => Ok(EventKind::Transition),
+            "enforcement" => Ok(EventKind::Enforcement),
+            "policy" => Ok(EventKind::Policy),
+            "failure" => Ok(EventKind::Failure),
+            _ => Err(format!("Unknown event kind: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub timestamp: u64,
+    pub kind: EventKind,
+    pub message: String,
+}
+
+impl Event {
+    /// D-Bus representation: (unix_timestamp, kind, message)
+    pub fn to_tuple(&self) -> (u64, String, String) {
+        (self.timestamp, self.kind.to_string(), self.message.clone())
+    }
+
+    /// The inverse of `to_tuple`; unknown kinds read as Policy.
+    pub fn from_tuple((timestamp, kind, message): (u64, String, String)) -> Self {
+        Self {
+            timestamp,
+            kind: kind.parse().unwrap_or(EventKind::Policy),
+            message,
+        }
+    }
+}
+
+fn buffer() -> &'static Mutex<VecDeque<Event>> {
+    static BUFFER: OnceLock<Mutex<VecDeque<Event>>> = OnceLock::new();
+    BUFFER.get_or_init(|| Mutex::new(VecDeque::with_capacity(CAPACITY)))
+}
+
+fn sender() -> &'static broadcast::Sender<Event> {
+    static SENDER: OnceLock<broadcast::Sender<Event>> = OnceLock::new();
+    SENDER.get_or_init(|| broadcast::channel(64).0)
+}
+
+/// Records a notable event, also writing it to the event log. Like the event
+/// log, a repeat of the most recent event isn't recorded again.
+pub fn emit(kind: EventKind, message: impl Into<String>) {
+    let message = message.into();
+    let level = match kind {
+        EventKind::Failure => Level::Error,
+        _ => Level::Info,
+    };
+    eventlog::record(level, message.clone());
+
+    let event = Event {
+        timestamp: unix_now(),
+        kind,
+        message,
+    };
+    {
+        let mut buf = buffer().lock().unwrap();
+        if let Some(last) = buf.back() {
+            if last.kind == event.kind && last.message == event.message {
+                return;
+            }
+        }
+        if buf.len() == CAPACITY {
+            buf.pop_front();
+        }
+        buf.push_back(event.clone());
+    }
+    let _ = sender().send(event);
+}
+
+pub fn recent() -> Vec<Event> {
+    buffer().lock().unwrap().iter().cloned().collect()
+}
+
+pub fn subscribe() -> broadcast::Receiver<Event> {
+    sender().subscribe()
+}
@@ -0,0 +1,237 @@
+use crate::protocol::PciAddress;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const FAULTS_FILE: &str = "/var/lib/nvsleepify/faults.jsonl";
+/// Once the file grows past this many records it is trimmed back to it.
+const MAX_RECORDS: usize = 200;
+/// The kernel's ring buffer, one record per read.
+const KMSG: &str = "/dev/kmsg";
+
+/// Xid codes after which the GPU has to be reset: 79 fallen off the bus, 119
+/// and 120 GSP firmware timeouts and errors, 154 recovery action required.
+const FATAL_XIDS: [u32; 4] = [79, 119, 120, 154];
+
+/// A kernel report about the managed GPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultKind {
+    /// An NVRM Xid error with its code.
+    Xid(u32),
+    /// "GPU has fallen off the bus": the card stopped answering.
+    FellOffBus,
+    /// A PCIe AER report; `fatal` for uncorrectable fatal errors.
+    Aer { fatal: bool },
+}
+
+impl FaultKind {
+    /// Whether the GPU is unusable until it's reset.
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            FaultKind::Xid(code) => FATAL_XIDS.contains(code),
+            FaultKind::FellOffBus => true,
+            FaultKind::Aer { fatal } => *fatal,
+        }
+    }
+}
+
+impl fmt::Display for FaultKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FaultKind::Xid(code) => write!(f, "Xid {}", code),
+            FaultKind::FellOffBus => write!(f, "fell off the bus"),
+            FaultKind::Aer { fatal: true } => write!(f, "fatal PCIe error"),
+            FaultKind::Aer { fatal: false } => write!(f, "PCIe error"),
+        }
+    }
+}
+
+/// The kernel message about `address` in `message`, if it reports a fault.
+/// NVRM names the GPU "PCI:0000:01:00" without the function, so any function
+/// of the slot matches.
+pub fn classify(message: &str, address: &PciAddress) -> Option<FaultKind> {
+    if !message.contains(address.slot()) {
+        return None;
+    }
+    if let Some((_, rest)) = message.split_once("NVRM: Xid (") {
+        // "NVRM: Xid (PCI:0000:01:00): 79, pid=..., ..."
+        let code = rest.split_once("): ")?.1;
+        let code = code.split(|c: char| !c.is_ascii_digit()).next()?;
+        return code.parse().ok().map(FaultKind::Xid);
+    }
+    if message.contains("fallen off the bus") {
+        return Some(FaultKind::FellOffBus);
+    }
+    // One "PCIe Bus Error: severity=..." line per AER report; the lines
+    // around it are details.
+    if message.contains("PCIe Bus Error") {
+        return Some(FaultKind::Aer {
+            fatal: message.contains("Fatal"),
+        });
+    }
+    None
+}
+
+/// A persisted kernel fault.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FaultRecord {
+    pub timestamp: u64,
+    /// e.g. "Xid 79" or "fell off the bus".
+    pub kind: String,
+    /// The kernel message.
+    pub message: String,
+}
+
+impl FaultRecord {
+    /// D-Bus representation: (unix_timestamp, kind, message)
+    pub fn to_tuple(&self) -> (u64, String, String) {
+        (self.timestamp, self.kind.clone(), self.message.clone())
+    }
+
+    /// The inverse of `to_tuple`.
+    pub fn from_tuple((timestamp, kind, message): (u64, String, String)) -> Self {
+        Self {
+            timestamp,
+            kind,
+            message,
+        }
+    }
+}
+
+pub fn append(record: &FaultRecord) -> Result<()> {
+    let path = Path::new(FAULTS_FILE);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{}", serde_json::to_string(record)?)?;
+    drop(file);
+
+    // Trim in batches, as the transition history does.
+    let records = load_recent(usize::MAX);
+    if records.len() > MAX_RECORDS + MAX_RECORDS / 10 {
+        let mut content = String::new();
+        for record in &records[records.len() - MAX_RECORDS..] {
+            content.push_str(&serde_json::to_string(record)?);
+            content.push('\n');
+        }
+        std::fs::write(path, content)?;
+    }
+    Ok(())
+}
+
+/// The most recent `limit` records, oldest first. Unreadable lines are skipped.
+pub fn load_recent(limit: usize) -> Vec<FaultRecord> {
+    let mut records: Vec<FaultRecord> = std::fs::read_to_string(FAULTS_FILE)
+        .map(|content| {
+            content
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    if records.len() > limit {
+        records.drain(..records.len() - limit);
+    }
+    records
+}
+
+/// Calls `on_message` with each kernel message logged from now on, until
+/// /dev/kmsg can't be read. Blocks; run it on its own thread. Needs
+/// CAP_SYSLOG where `kernel.dmesg_restrict` is set.
+pub fn follow_kmsg(mut on_message: impl FnMut(&str)) -> Result<()> {
+    let mut kmsg = std::fs::File::open(KMSG)?;
+    // Only what's logged from now on; older faults were handled back then.
+    kmsg.seek(SeekFrom::End(0))?;
+    let mut buf = vec![0; 8192];
+    loop {
+        let len = match kmsg.read(&mut buf) {
+            Ok(len) => len,
+            // Records were overwritten before being read; carry on with the next.
+            Err(e) if e.raw_os_error() == Some(libc::EPIPE) => continue,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        // "priority,sequence,timestamp,flags;message", then " KEY=value" lines.
+        let record = String::from_utf8_lossy(&buf[..len]);
+        if let Some((_, message)) = record.lines().next().and_then(|l| l.split_once(';')) {
+            on_message(message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel_messages_are_classified() {
+        let gpu: PciAddress = "0000:01:00.0".parse().unwrap();
+        let cases = [
+            (
+                "NVRM: Xid (PCI:0000:01:00): 79, pid=1234, name=Xorg, GPU has fallen off the bus.",
+                Some(FaultKind::Xid(79)),
+            ),
+            (
+                "NVRM: Xid (PCI:0000:01:00): 119, pid=2211, name=kwin_wayland, Timeout waiting for RPC from GSP!",
+                Some(FaultKind::Xid(119)),
+            ),
+            (
+                "NVRM: Xid (PCI:0000:01:00): 13, Graphics SM Warp Exception on (GPC 0, TPC 1)",
+                Some(FaultKind::Xid(13)),
+            ),
+            (
+                "NVRM: GPU at PCI:0000:01:00: GPU-6a1d3b2c has fallen off the bus.",
+                Some(FaultKind::FellOffBus),
+            ),
+            (
+                "nvidia 0000:01:00.0: PCIe Bus Error: severity=Uncorrected (Fatal), type=Transaction Layer, (Requester ID)",
+                Some(FaultKind::Aer { fatal: true }),
+            ),
+            (
+                "nvidia 0000:01:00.0: PCIe Bus Error: severity=Corrected, type=Physical Layer, (Receiver ID)",
+                Some(FaultKind::Aer { fatal: false }),
+            ),
+            // Another device's faults and unrelated messages.
+            ("NVRM: Xid (PCI:0000:02:00): 79, pid=1234, name=Xorg", None),
+            (
+                "nvidia 0000:01:00.0: enabling device (0000 -> 0003)",
+                None,
+            ),
+            ("usb 3-2: new high-speed USB device number 4 using xhci_hcd", None),
+            // A mangled Xid line with no code.
+            ("NVRM: Xid (PCI:0000:01:00): pid=1234", None),
+        ];
+        for (message, expected) in cases {
+            assert_eq!(classify(message, &gpu), expected, "{}", message);
+        }
+    }
+
+    #[test]
+    fn other_functions_of_the_slot_match() {
+        let audio: PciAddress = "0000:01:00.1".parse().unwrap();
+        assert_eq!(
+            classify(
+                "NVRM: Xid (PCI:0000:01:00): 154, GPU recovery action changed",
+                &audio
+            ),
+            Some(FaultKind::Xid(154))
+        );
+    }
+
+    #[test]
+    fn fatal_faults_need_a_reset() {
+        for code in FATAL_XIDS {
+            assert!(FaultKind::Xid(code).is_fatal());
+        }
+        assert!(!FaultKind::Xid(13).is_fatal());
+        assert!(FaultKind::FellOffBus.is_fatal());
+        assert!(FaultKind::Aer { fatal: true }.is_fatal());
+        assert!(!FaultKind::Aer { fatal: false }.is_fatal());
+    }
+}
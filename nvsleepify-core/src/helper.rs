@@ -0,0 +1,284 @@
+use crate::backend;
+use crate::config::DaemonConfig;
+use crate::pci::{self, PciDevice};
+use crate::platform::{self, Mux};
+use crate::protocol::{PciAddress, ProcessList};
+use crate::system::{self, CommandTimeout};
+use crate::transition::Step;
+use crate::xorg;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::ffi::CString;
+use std::io::{BufRead, BufReader, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+/// Where nvsleepify-helper listens. Only root and the nvsleepify group may
+/// connect (see nvsleepify-helper.socket).
+pub const SOCKET_PATH: &str = "/run/nvsleepify/helper.sock";
+
+/// The account the unprivileged daemon runs as. The socket's group also
+/// grants access, so peers are checked by UID as well.
+pub const DAEMON_USER: &str = "nvsleepify";
+
+/// Set when the daemon runs unprivileged; privileged operations are then
+/// sent to the helper instead of being done in-process.
+static SOCKET: OnceLock<PathBuf> = OnceLock::new();
+
+/// Serializes the requests that change something, so two connections can't
+/// interleave modprobe and sysfs writes.
+static CHANGING: Mutex<()> = Mutex::new(());
+
+/// Everything the daemon needs root for. Kept to operations whose arguments
+/// the helper can check itself, so a compromised daemon can't turn it into a
+/// general-purpose root shell.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Request {
+    /// A transition step on the Nvidia GPU at `address`.
+    Step {
+        step: Step,
+        address: Option<PciAddress>,
+    },
+    /// Processes with /dev/nvidia* or the given /dev/dri nodes open.
+    ListProcesses { nodes: Vec<String> },
+    /// Only PIDs that hold the GPU when the request arrives are signalled.
+    KillProcesses { pids: Vec<String> },
+    /// Writes the Xorg snippet, or removes it for `None`.
+    XorgSnippet { dgpu_primary: Option<bool> },
+    /// Sets the display MUX for the next boot.
+    SwitchMux { mux: Mux },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Response {
+    Done,
+    Processes(ProcessList),
+    Failed {
+        message: String,
+        /// Kept apart so the daemon still reports a timeout as one.
+        timeout: Option<(String, u64)>,
+    },
+}
+
+impl From<Result<Response>> for Response {
+    fn from(result: Result<Response>) -> Self {
+        match result {
+            Ok(response) => response,
+            Err(e) => Response::Failed {
+                message: format!("{:#}", e),
+                timeout: e
+                    .chain()
+                    .find_map(|cause| cause.downcast_ref::<CommandTimeout>())
+                    .map(|t| (t.command.clone(), t.secs)),
+            },
+        }
+    }
+}
+
+/// Sends privileged operations to the helper at `path` from now on.
+pub fn use_socket(path: impl Into<PathBuf>) {
+    let _ = SOCKET.set(path.into());
+}
+
+pub fn is_remote() -> bool {
+    SOCKET.get().is_some()
+}
+
+/// Runs `request` through the helper, or in-process when running as root.
+pub fn run(request: Request) -> Result<Response> {
+    let response = match SOCKET.get() {
+        Some(path) => send(path, &request)?,
+        None => execute(request).into(),
+    };
+    match response {
+        Response::Failed { message, timeout } => Err(match timeout {
+            Some((command, secs)) => {
+                anyhow::Error::new(CommandTimeout { command, secs }).context(message)
+            }
+            None => anyhow!(message),
+        }),
+        response => Ok(response),
+    }
+}
+
+fn send(path: &Path, request: &Request) -> Result<Response> {
+    let stream = UnixStream::connect(path)
+        .with_context(|| format!("Can't reach nvsleepify-helper at {}", path.display()))?;
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    (&stream).write_all(line.as_bytes())?;
+    let mut reply = String::new();
+    BufReader::new(&stream).read_line(&mut reply)?;
+    if reply.is_empty() {
+        return Err(anyhow!("nvsleepify-helper closed the connection"));
+    }
+    Ok(serde_json::from_str(&reply)?)
+}
+
+/// The GPU named by the daemon, if it really is an Nvidia display device.
+fn checked_gpu(address: &PciAddress) -> Result<PciDevice> {
+    let gpu = PciDevice::new(address.clone());
+    let read = |file: &str| backend::read_trimmed(gpu.path.join(file)).unwrap_or_default();
+    if pci::is_nvidia_display(&read("vendor"), &read("class")) {
+        Ok(gpu)
+    } else {
+        Err(anyhow!("{} is not an Nvidia GPU", address))
+    }
+}
+
+/// DRM nodes end up in an lsof command line, so only accept their exact shape.
+fn is_drm_node(node: &str) -> bool {
+    node.strip_prefix("/dev/dri/")
+        .and_then(|name| {
+            name.strip_prefix("card")
+                .or_else(|| name.strip_prefix("renderD"))
+        })
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Carries out a request in this process.
+pub fn execute(request: Request) -> Result<Response> {
+    match request {
+        Request::ListProcesses { nodes } => {
+            if let Some(node) = nodes.iter().find(|node| !is_drm_node(node)) {
+                return Err(anyhow!("Refusing to check {}", node));
+            }
+            Ok(Response::Processes(system::find_processes_using_nvidia(
+                &nodes,
+            )?))
+        }
+        Request::Step { step, address } => {
+            let _changing = CHANGING.lock().unwrap_or_else(|e| e.into_inner());
+            let gpu = address.as_ref().map(checked_gpu).transpose()?;
+            step.perform(gpu.as_ref())?;
+            Ok(Response::Done)
+        }
+        Request::KillProcesses { pids } => {
+            let _changing = CHANGING.lock().unwrap_or_else(|e| e.into_inner());
+            let nodes = PciDevice::find_nvidia_gpu()
+                .map(|gpu| gpu.get_device_nodes())
+                .unwrap_or_default();
+            let targets: ProcessList = system::find_processes_using_nvidia(&nodes)?
+                .into_iter()
+                .filter(|proc| pids.contains(&proc.pid))
+                .collect();
+            system::signal_processes(&targets);
+            Ok(Response::Done)
+        }
+        Request::XorgSnippet { dgpu_primary } => {
+            let _changing = CHANGING.lock().unwrap_or_else(|e| e.into_inner());
+            xorg::write_snippet(dgpu_primary)?;
+            Ok(Response::Done)
+        }
+        Request::SwitchMux { mux } => {
+            let _changing = CHANGING.lock().unwrap_or_else(|e| e.into_inner());
+            platform::write_mux(mux)?;
+            Ok(Response::Done)
+        }
+    }
+}
+
+/// The socket systemd passed in (socket activation), or a freshly bound one.
+fn listener() -> Result<UnixListener> {
+    let activated = std::env::var("LISTEN_PID").ok() == Some(std::process::id().to_string())
+        && std::env::var("LISTEN_FDS").ok().as_deref() == Some("1");
+    if activated {
+        // SAFETY: systemd hands over exactly one listening socket as fd 3.
+        return Ok(unsafe { UnixListener::from_raw_fd(3) });
+    }
+    let path = Path::new(SOCKET_PATH);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let _ = std::fs::remove_file(path);
+    UnixListener::bind(path).with_context(|| format!("Failed to bind {}", path.display()))
+}
+
+fn peer_uid(stream: &UnixStream) -> Option<u32> {
+    // SAFETY: all-zero is a valid `ucred`; getsockopt fills in at most `len` bytes.
+    let mut cred: libc::ucred = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let rc = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    (rc == 0).then_some(cred.uid)
+}
+
+fn user_uid(name: &str) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    // SAFETY: all-zero is a valid `passwd`; getpwnam_r fills it in.
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    // SAFETY: every pointer is valid and `buf.len()` is the buffer's size.
+    let rc = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut passwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    (rc == 0 && !result.is_null()).then_some(passwd.pw_uid)
+}
+
+/// Only root and the daemon's own user; members of the nvsleepify group
+/// (see `[access]`) may call the daemon but not the helper.
+fn is_trusted_peer(stream: &UnixStream) -> bool {
+    match peer_uid(stream) {
+        Some(0) => true,
+        Some(uid) => user_uid(DAEMON_USER) == Some(uid),
+        None => false,
+    }
+}
+
+fn handle(stream: UnixStream) {
+    if !is_trusted_peer(&stream) {
+        return;
+    }
+    let mut reader = BufReader::new(&stream);
+    let mut line = String::new();
+    while matches!(reader.read_line(&mut line), Ok(n) if n > 0) {
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => execute(request).into(),
+            Err(e) => Response::Failed {
+                message: format!("Malformed request: {}", e),
+                timeout: None,
+            },
+        };
+        line.clear();
+        let Ok(mut reply) = serde_json::to_string(&response) else {
+            return;
+        };
+        reply.push('\n');
+        if (&stream).write_all(reply.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+/// Entry point of nvsleepify-helper. Each connection gets a thread, so a
+/// process listing isn't stuck behind a slow modprobe.
+pub fn serve() -> Result<()> {
+    let config = DaemonConfig::load()?;
+    system::apply_config(&config);
+    let listener = listener()?;
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                std::thread::spawn(move || handle(stream));
+            }
+            Err(e) => eprintln!("Failed to accept a connection: {}", e),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use crate::backend;
+use crate::system;
+use std::path::Path;
+use std::process::Command;
+
+/// Targets whose sleep writes a hibernation image.
+const HIBERNATE_TARGETS: [&str; 3] = [
+    "hibernate.target",
+    "hybrid-sleep.target",
+    "suspend-then-hibernate.target",
+];
+
+/// Save and restore the nvidia driver's state around the image.
+const NVIDIA_HIBERNATE_UNITS: [&str; 2] = ["nvidia-hibernate.service", "nvidia-resume.service"];
+
+/// Whether the sleep logind just announced writes an image. PrepareForSleep
+/// doesn't say which kind of sleep it is, but logind has queued the start
+/// job for the target by then.
+pub fn entering() -> bool {
+    let Ok(output) = system::output_with_timeout(Command::new("systemctl").args([
+        "list-jobs",
+        "--no-legend",
+        "--plain",
+    ])) else {
+        return false;
+    };
+    // "JOB UNIT TYPE STATE"
+    String::from_utf8_lossy(&output.stdout).lines().any(|line| {
+        let mut fields = line.split_whitespace().skip(1);
+        matches!(
+            (fields.next(), fields.next()),
+            (Some(unit), Some("start")) if HIBERNATE_TARGETS.contains(&unit)
+        )
+    })
+}
+
+/// Whether the nvidia hibernate and resume hooks will run. Without them the
+/// driver loses the GPU's state across the image.
+pub fn nvidia_hooks_enabled() -> bool {
+    NVIDIA_HIBERNATE_UNITS.iter().all(|unit| {
+        system::status_with_timeout(Command::new("systemctl").args(["is-enabled", "--quiet", unit]))
+            .map(|s| s.success())
+            .unwrap_or(false)
+    })
+}
+
+/// Whether the nvidia driver is loaded.
+pub fn driver_loaded() -> bool {
+    backend::sysfs().exists(Path::new("/sys/module/nvidia"))
+}
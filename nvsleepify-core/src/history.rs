@@ -0,0 +1,244 @@
+use crate::protocol::PowerState;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::Path;
+
+const HISTORY_FILE: &str = "/var/lib/nvsleepify/history.jsonl";
+/// Once the file grows past this many records it is trimmed back to it.
+const MAX_RECORDS: usize = 1000;
+
+/// A persisted change of the GPU's power state.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransitionRecord {
+    pub timestamp: u64,
+    pub from: PowerState,
+    pub to: PowerState,
+    /// What caused the change, e.g. "SetMode(Integrated)" or "external".
+    pub trigger: String,
+}
+
+impl TransitionRecord {
+    /// D-Bus representation: (unix_timestamp, from, to, trigger)
+    pub fn to_tuple(&self) -> (u64, PowerState, PowerState, String) {
+        (self.timestamp, self.from, self.to, self.trigger.clone())
+    }
+
+    /// The inverse of `to_tuple`.
+    pub fn from_tuple(
+        (timestamp, from, to, trigger): (u64, PowerState, PowerState, String),
+    ) -> Self {
+        Self {
+            timestamp,
+            from,
+            to,
+            trigger,
+        }
+    }
+}
+
+pub fn format_duration(secs: u64) -> String {
+    let (h, m, s) = (secs / 3600, (secs % 3600) / 60, secs % 60);
+    if h > 0 {
+        format!("{}h {}m", h, m)
+    } else if m > 0 {
+        format!("{}m {}s", m, s)
+    } else {
+        format!("{}s", s)
+    }
+}
+
+/// Reads durations like "45m", "1h30m", "90s" or "2h 15m"; a bare number is
+/// seconds. Returns seconds.
+pub fn parse_duration(input: &str) -> Result<u64, String> {
+    let input = input.trim();
+    if let Ok(secs) = input.parse::<u64>() {
+        return Ok(secs);
+    }
+    let mut total: u64 = 0;
+    let mut number = String::new();
+    let mut any_unit = false;
+    for c in input.chars().filter(|c| !c.is_whitespace()) {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => {
+                return Err(format!(
+                    "Invalid duration '{}': unknown unit '{}'",
+                    input, c
+                ))
+            }
+        };
+        let value: u64 = number.parse().map_err(|_| {
+            format!(
+                "Invalid duration '{}': expected a number before '{}'",
+                input, c
+            )
+        })?;
+        total = total.saturating_add(value.saturating_mul(unit));
+        number.clear();
+        any_unit = true;
+    }
+    if !number.is_empty() || !any_unit {
+        return Err(format!(
+            "Invalid duration '{}': use e.g. 45m, 1h30m or 90s",
+            input
+        ));
+    }
+    Ok(total)
+}
+
+/// "Suspended for 2h 14m" or "Awake for 35m 10s", given the last transition
+/// as (to, unix_timestamp). None if it doesn't match the current state, e.g.
+/// because the change happened while the daemon wasn't running.
+pub fn time_in_state(power_state: PowerState, last: (PowerState, u64), now: u64) -> Option<String> {
+    let (to, timestamp) = last;
+    if timestamp == 0 || to.is_asleep() != power_state.is_asleep() {
+        return None;
+    }
+    let label = if power_state.is_asleep() {
+        "Suspended"
+    } else {
+        "Awake"
+    };
+    Some(format!(
+        "{} for {}",
+        label,
+        format_duration(now.saturating_sub(timestamp))
+    ))
+}
+
+pub fn append(record: &TransitionRecord) -> Result<()> {
+    let path = Path::new(HISTORY_FILE);
+    if let Some(parent) = path.parent() {
+        if !parent.exists() {
+            std::fs::create_dir_all(parent)?;
+        }
+    }
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{}", serde_json::to_string(record)?)?;
+    drop(file);
+
+    // Trim in batches so we don't rewrite the file on every transition.
+    let records = load_all();
+    if records.len() > MAX_RECORDS + MAX_RECORDS / 10 {
+        let keep = &records[records.len() - MAX_RECORDS..];
+        let mut content = String::new();
+        for record in keep {
+            content.push_str(&serde_json::to_string(record)?);
+            content.push('\n');
+        }
+        std::fs::write(path, content)?;
+    }
+    Ok(())
+}
+
+/// All records, oldest first. Unreadable lines are skipped.
+pub fn load_all() -> Vec<TransitionRecord> {
+    std::fs::read_to_string(HISTORY_FILE)
+        .map(|content| {
+            content
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The most recent `limit` records, oldest first.
+pub fn load_recent(limit: usize) -> Vec<TransitionRecord> {
+    let mut records = load_all();
+    if records.len() > limit {
+        records.drain(..records.len() - limit);
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_open_interval_runs_until_now() {
+        assert_eq!(
+            time_in_state(
+                PowerState::D3cold,
+                (PowerState::D3cold, 1000),
+                1000 + 2 * 3600 + 14 * 60
+            ),
+            Some("Suspended for 2h 14m".to_string())
+        );
+        assert_eq!(
+            time_in_state(PowerState::D0, (PowerState::D0, 1000), 1000 + 35 * 60 + 10),
+            Some("Awake for 35m 10s".to_string())
+        );
+        // Runtime suspend without cutting power still counts as awake.
+        assert_eq!(
+            time_in_state(PowerState::D3hot, (PowerState::D0, 1000), 1005),
+            Some("Awake for 5s".to_string())
+        );
+    }
+
+    #[test]
+    fn a_transition_from_the_future_counts_as_zero() {
+        // The clock stepped back since the record was written.
+        assert_eq!(
+            time_in_state(PowerState::D0, (PowerState::D0, 5000), 4000),
+            Some("Awake for 0s".to_string())
+        );
+    }
+
+    #[test]
+    fn a_stale_transition_shows_nothing() {
+        // The GPU woke while the daemon wasn't watching.
+        assert_eq!(
+            time_in_state(PowerState::D0, (PowerState::D3cold, 1000), 2000),
+            None
+        );
+        assert_eq!(
+            time_in_state(PowerState::NotFound, (PowerState::D0, 1000), 2000),
+            None
+        );
+    }
+
+    #[test]
+    fn an_empty_log_shows_nothing() {
+        // What LastTransition reports before any change was seen.
+        assert_eq!(
+            time_in_state(PowerState::Unknown, (PowerState::Unknown, 0), 2000),
+            None
+        );
+        assert_eq!(
+            time_in_state(PowerState::D0, (PowerState::D0, 0), 2000),
+            None
+        );
+    }
+
+    #[test]
+    fn durations_round_trip() {
+        for (input, secs) in [
+            ("90", 90),
+            ("90s", 90),
+            ("45m", 2700),
+            ("1h30m", 5400),
+            ("2h 15m", 8100),
+        ] {
+            assert_eq!(parse_duration(input), Ok(secs), "{}", input);
+        }
+        for input in ["", "h", "5x", "1h30"] {
+            assert!(parse_duration(input).is_err(), "{}", input);
+        }
+        assert_eq!(format_duration(8100), "2h 15m");
+        assert_eq!(format_duration(2700), "45m 0s");
+        assert_eq!(format_duration(9), "9s");
+    }
+}
@@ -0,0 +1,98 @@
+use crate::config::IdleConfig;
+use crate::history::format_duration;
+use crate::pci::Usage;
+use std::time::{Duration, Instant};
+
+/// How often the monitor asks nvidia-smi while processes hold the awake GPU.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Follows the awake GPU's utilization while processes hold it, to catch
+/// ones that keep a handle open but do no work, which open file descriptors
+/// alone can't tell apart from busy ones.
+#[derive(Debug)]
+pub struct IdleTracker {
+    config: IdleConfig,
+    /// When utilization last dropped to the threshold, if it stayed there.
+    since: Option<Instant>,
+    last_sample: Option<Instant>,
+    last: Option<Usage>,
+    /// Already told the user about the current idle streak.
+    announced: bool,
+}
+
+impl IdleTracker {
+    pub fn new(config: IdleConfig) -> Self {
+        Self {
+            config,
+            since: None,
+            last_sample: None,
+            last: None,
+            announced: false,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.after > 0
+    }
+
+    /// Whether it's time for another sample.
+    pub fn due(&self) -> bool {
+        self.enabled()
+            && self
+                .last_sample
+                .is_none_or(|at| at.elapsed() >= SAMPLE_INTERVAL)
+    }
+
+    /// Takes a sample; a failed one (None) ends the streak, since nothing
+    /// says the GPU was idle, and so does a gap in sampling.
+    pub fn record(&mut self, usage: Option<Usage>) {
+        let now = Instant::now();
+        if self
+            .last_sample
+            .is_some_and(|at| now - at > 2 * SAMPLE_INTERVAL)
+        {
+            self.reset();
+        }
+        self.last_sample = Some(now);
+        self.last = usage;
+        match usage {
+            Some(usage) if usage.utilization <= self.config.utilization => {
+                self.since.get_or_insert(now);
+            }
+            _ => self.reset(),
+        }
+    }
+
+    /// Whether the GPU has stayed at or below the threshold for `[idle] after`.
+    pub fn is_idle(&self) -> bool {
+        self.enabled()
+            && self
+                .since
+                .is_some_and(|since| since.elapsed() >= Duration::from_secs(self.config.after))
+    }
+
+    /// Returns true the first time it's called in an idle streak.
+    pub fn announce(&mut self) -> bool {
+        !std::mem::replace(&mut self.announced, true)
+    }
+
+    /// The GPU slept, got busy or was let go: start over.
+    pub fn reset(&mut self) {
+        self.since = None;
+        self.announced = false;
+    }
+
+    /// E.g. "GPU idle for 10m at ≤2% utilization, 1 compute context open".
+    pub fn describe(&self) -> String {
+        let idle_for = self.since.map(|since| since.elapsed()).unwrap_or_default();
+        let mut text = format!(
+            "GPU idle for {} at ≤{}% utilization",
+            format_duration(idle_for.as_secs()),
+            self.config.utilization
+        );
+        if let Some(usage) = self.last.filter(|usage| usage.compute_apps > 0) {
+            text.push_str(&format!(", {} compute context(s) open", usage.compute_apps));
+        }
+        text
+    }
+}
@@ -0,0 +1,129 @@
+use crate::eventlog::unix_now;
+use crate::history::format_duration;
+
+/// D-Bus representation: (id, owner, app, reason, since_unix_timestamp)
+pub type LeaseTuple = (u32, String, String, String, u64);
+
+/// A request from an application to keep the GPU awake, like a systemd
+/// inhibitor. Held until released or until the owner leaves the bus.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lease {
+    pub id: u32,
+    /// Unique bus name of the connection that took it, e.g. ":1.42".
+    pub owner: String,
+    pub app: String,
+    pub reason: String,
+    pub since: u64,
+}
+
+impl Lease {
+    pub fn to_tuple(&self) -> LeaseTuple {
+        (
+            self.id,
+            self.owner.clone(),
+            self.app.clone(),
+            self.reason.clone(),
+            self.since,
+        )
+    }
+
+    pub fn from_tuple((id, owner, app, reason, since): LeaseTuple) -> Self {
+        Self {
+            id,
+            owner,
+            app,
+            reason,
+            since,
+        }
+    }
+}
+
+impl std::fmt::Display for Lease {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "#{} {} ({}): {}, for {}",
+            self.id,
+            self.app,
+            self.owner,
+            self.reason,
+            format_duration(unix_now().saturating_sub(self.since))
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Leases {
+    held: Vec<Lease>,
+    last_id: u32,
+}
+
+impl Leases {
+    pub fn acquire(&mut self, owner: &str, app: &str, reason: &str) -> u32 {
+        self.last_id = self.last_id.wrapping_add(1).max(1);
+        self.held.push(Lease {
+            id: self.last_id,
+            owner: owner.to_string(),
+            app: app.to_string(),
+            reason: reason.to_string(),
+            since: unix_now(),
+        });
+        self.last_id
+    }
+
+    /// The lease with `id`, if it exists and `owner` took it.
+    pub fn release(&mut self, id: u32, owner: &str) -> Option<Lease> {
+        let index = self
+            .held
+            .iter()
+            .position(|lease| lease.id == id && lease.owner == owner)?;
+        Some(self.held.remove(index))
+    }
+
+    /// Drops every lease of a connection that left the bus.
+    pub fn release_owner(&mut self, owner: &str) -> Vec<Lease> {
+        let (released, kept) = std::mem::take(&mut self.held)
+            .into_iter()
+            .partition(|lease| lease.owner == owner);
+        self.held = kept;
+        released
+    }
+
+    pub fn held(&self) -> &[Lease] {
+        &self.held
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.held.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_owner_releases_a_lease() {
+        let mut leases = Leases::default();
+        let id = leases.acquire(":1.42", "steam", "Playing a game");
+        assert!(leases.release(id, ":1.7").is_none());
+        assert_eq!(leases.held().len(), 1);
+        let lease = leases.release(id, ":1.42").unwrap();
+        assert_eq!(lease.app, "steam");
+        assert!(leases.is_empty());
+    }
+
+    #[test]
+    fn leaving_the_bus_drops_every_lease_of_the_connection() {
+        let mut leases = Leases::default();
+        let first = leases.acquire(":1.42", "steam", "Playing a game");
+        let other = leases.acquire(":1.7", "blender", "Rendering");
+        let second = leases.acquire(":1.42", "steam", "Shader cache");
+        assert!(first < other && other < second);
+
+        let released: Vec<u32> = leases.release_owner(":1.42").iter().map(|l| l.id).collect();
+        assert_eq!(released, [first, second]);
+        assert_eq!(leases.held().len(), 1);
+        assert_eq!(leases.held()[0].id, other);
+    }
+}
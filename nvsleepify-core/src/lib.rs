@@ -0,0 +1,34 @@
+//! The parts of nvsleepify that don't need D-Bus or a user interface: PCI
+//! and sysfs access, the sleep and wake sequences and the helper they run
+//! through, the configuration, policies, the saved mode, history and the
+//! protocol types. The daemon, CLI, tray and settings window build on it.
+
+pub mod backend;
+pub mod command;
+pub mod config;
+pub mod conflicts;
+pub mod display;
+pub mod display_manager;
+pub mod driver;
+pub mod eventlog;
+pub mod events;
+pub mod faults;
+pub mod helper;
+pub mod hibernate;
+pub mod history;
+pub mod idle;
+pub mod leases;
+pub mod output;
+pub mod pci;
+pub mod platform;
+pub mod profiles;
+pub mod protocol;
+pub mod savings;
+pub mod simulate;
+pub mod state;
+pub mod stats;
+pub mod sysfs;
+pub mod system;
+pub mod transition;
+pub mod verify;
+pub mod xorg;
@@ -0,0 +1,54 @@
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// How much the CLI prints besides what was asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Verbosity {
+    /// `--quiet`: only results, prompts, warnings and errors.
+    Quiet,
+    /// Also progress and confirmation of what was done.
+    #[default]
+    Normal,
+    /// `--verbose`: also how it was done.
+    Verbose,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+/// Set once at startup from `--quiet` or `--verbose`.
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        1 => Verbosity::Normal,
+        _ => Verbosity::Verbose,
+    }
+}
+
+/// Colors are used when stdout is a terminal, unless turned off with
+/// `--no-color` or a non-empty NO_COLOR (https://no-color.org).
+pub fn init_color(no_color: bool) {
+    let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    if no_color || no_color_env {
+        colored::control::set_override(false);
+    }
+}
+
+/// Progress and confirmation of what was done, e.g. "Restoring Integrated
+/// mode...". Left out with `--quiet`.
+pub fn info(message: impl Display) {
+    if verbosity() >= Verbosity::Normal {
+        println!("{}", message);
+    }
+}
+
+/// How something was done, e.g. which backend carried out a command. Only
+/// printed with `--verbose`.
+pub fn detail(message: impl Display) {
+    if verbosity() >= Verbosity::Verbose {
+        println!("{}", message);
+    }
+}
@@ -0,0 +1,569 @@
+use crate::backend;
+use crate::protocol::{PciAddress, PowerState};
+use crate::simulate;
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+pub const DEVICES_DIR: &str = "/sys/bus/pci/devices";
+/// The driver that hands devices to virtual machines.
+pub const VFIO_DRIVER: &str = "vfio-pci";
+/// Bytes in the standard (type 0) config space header.
+pub const CONFIG_HEADER_LEN: usize = 64;
+
+/// udev's device database, where seat assignments are recorded.
+const UDEV_DATA_DIR: &str = "/run/udev/data";
+/// The seat every device belongs to unless assigned elsewhere.
+pub const DEFAULT_SEAT: &str = "seat0";
+
+/// Where distributions install the PCI ID database, most common first.
+const PCI_IDS_PATHS: [&str; 3] = [
+    "/usr/share/hwdata/pci.ids",
+    "/usr/share/misc/pci.ids",
+    "/usr/share/pci.ids",
+];
+
+/// Vendor and device name from the PCI ID database, e.g. "NVIDIA Corporation
+/// GA107M [GeForce RTX 3050 Mobile]". IDs are as sysfs shows them ("0x10de").
+pub fn device_name(vendor: &str, device: &str) -> Option<String> {
+    let vendor = vendor.trim_start_matches("0x");
+    let device = device.trim_start_matches("0x");
+    let content = PCI_IDS_PATHS
+        .iter()
+        .find_map(|path| std::fs::read_to_string(path).ok())?;
+    let mut lines = content.lines();
+    let vendor_name = lines.find_map(|line| {
+        let (id, name) = line.split_once("  ")?;
+        (id == vendor).then(|| name.to_string())
+    })?;
+    // Device lines follow their vendor, indented by one tab; subsystem lines by two.
+    let device_name = lines
+        .take_while(|line| line.starts_with('\t') || line.starts_with('#') || line.is_empty())
+        .filter(|line| !line.starts_with("\t\t"))
+        .find_map(|line| {
+            let (id, name) = line.trim_start_matches('\t').split_once("  ")?;
+            (id == device).then(|| name.to_string())
+        });
+    Some(match device_name {
+        Some(device_name) => format!("{} {}", vendor_name, device_name),
+        None => format!("{} device {}", vendor_name, device),
+    })
+}
+
+/// Nvidia vendor ID with a display class: VGA (0x0300) or 3D controller (0x0302).
+pub fn is_nvidia_display(vendor: &str, class: &str) -> bool {
+    vendor == "0x10de" && class.starts_with("0x03")
+}
+
+/// Live readings from an awake GPU. Either may be missing depending on the driver.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Sensors {
+    /// Degrees Celsius.
+    pub temperature: Option<f64>,
+    /// Watts.
+    pub power_draw: Option<f64>,
+}
+
+/// How hard an awake GPU is working, from nvidia-smi.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    /// Percent of the last sample period a kernel ran.
+    pub utilization: u32,
+    /// Processes with a CUDA context open on it.
+    pub compute_apps: usize,
+}
+
+impl Sensors {
+    pub fn is_empty(&self) -> bool {
+        self.temperature.is_none() && self.power_draw.is_none()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub path: PathBuf,
+}
+
+impl PciDevice {
+    pub fn new(address: PciAddress) -> Self {
+        Self {
+            path: Path::new(DEVICES_DIR).join(address.as_str()),
+            address,
+        }
+    }
+
+    /// The devices in `DEVICES_DIR`, skipping entries that aren't addresses.
+    fn list() -> Vec<Self> {
+        backend::sysfs()
+            .read_dir(Path::new(DEVICES_DIR))
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|path| path.file_name()?.to_string_lossy().parse().ok())
+            .map(PciDevice::new)
+            .collect()
+    }
+
+    pub fn unbind_driver(&self) -> Result<()> {
+        let driver_path = self.path.join("driver/unbind");
+        if !backend::sysfs().exists(&driver_path) {
+            // Already unbound
+            return Ok(());
+        }
+        // echo address > driver/unbind
+        backend::sysfs().write(&driver_path, self.address.as_str())?;
+        Ok(())
+    }
+
+    /// Lets the kernel runtime-suspend the device, the other functions in its
+    /// slot and the bridge above it (`power/control` = auto), so it can reach
+    /// D3cold with or without a driver.
+    pub fn allow_runtime_pm(&self) -> Result<()> {
+        for function in self.slot_functions() {
+            backend::sysfs()
+                .write(&function.path.join("power/control"), "auto")
+                .with_context(|| format!("Failed to allow runtime PM on {}", function.address))?;
+        }
+        let bridge_control = backend::sysfs()
+            .canonicalize(&self.path)
+            .ok()
+            .and_then(|path| Some(path.parent()?.join("power/control")))
+            .filter(|control| backend::sysfs().exists(control));
+        if let Some(control) = bridge_control {
+            backend::sysfs()
+                .write(&control, "auto")
+                .context("Failed to allow runtime PM on the bridge")?;
+        }
+        Ok(())
+    }
+
+    /// The standard header of the device's config space: IDs, command and
+    /// status, BARs and interrupt line. Readable without root.
+    pub fn config_header(&self) -> Result<Vec<u8>> {
+        let mut config = backend::sysfs()
+            .read_bytes(&self.path.join("config"))
+            .with_context(|| format!("Failed to read the config space of {}", self.address))?;
+        if config.len() < CONFIG_HEADER_LEN {
+            return Err(anyhow!("Short config space for {}", self.address));
+        }
+        config.truncate(CONFIG_HEADER_LEN);
+        Ok(config)
+    }
+
+    /// Writes back the dwords of a saved `config_header` that differ from
+    /// the device's, last first like the kernel's pci_restore_config_space:
+    /// the BARs are in place before the command register enables decoding.
+    /// The IDs are read-only and skipped. Returns how many were written.
+    pub fn restore_config_header(&self, saved: &[u8]) -> Result<usize> {
+        let current = self.config_header()?;
+        if saved.len() != CONFIG_HEADER_LEN {
+            return Err(anyhow!("Saved config space has the wrong size"));
+        }
+        let config = self.path.join("config");
+        let mut written = 0;
+        for offset in (4..CONFIG_HEADER_LEN).step_by(4).rev() {
+            let dword = &saved[offset..offset + 4];
+            if dword == &current[offset..offset + 4] {
+                continue;
+            }
+            backend::sysfs()
+                .write_at(&config, offset as u64, dword)
+                .with_context(|| {
+                    format!(
+                        "Failed to restore the config space of {} at {:#x}",
+                        self.address, offset
+                    )
+                })?;
+            written += 1;
+        }
+        Ok(written)
+    }
+
+    /// Name of the bound driver, e.g. "nvidia" or "vfio-pci".
+    pub fn driver(&self) -> Option<String> {
+        let link = backend::sysfs().read_link(&self.path.join("driver")).ok()?;
+        Some(link.file_name()?.to_string_lossy().into_owned())
+    }
+
+    /// The device and the other functions in its slot (HDMI audio, USB-C
+    /// controller), which a VM has to be given together.
+    pub fn slot_functions(&self) -> Vec<PciDevice> {
+        let mut functions: Vec<PciDevice> = Self::list()
+            .into_iter()
+            .filter(|device| device.address.slot() == self.address.slot())
+            .collect();
+        functions.sort_by(|a, b| a.address.cmp(&b.address));
+        if functions.is_empty() {
+            functions.push(self.clone());
+        }
+        functions
+    }
+
+    /// Rebinds the device to vfio-pci. driver_override keeps the old driver
+    /// from claiming it again on the next probe.
+    pub fn bind_vfio(&self) -> Result<()> {
+        if self.driver().as_deref() == Some(VFIO_DRIVER) {
+            return Ok(());
+        }
+        backend::sysfs()
+            .write(&self.path.join("driver_override"), VFIO_DRIVER)
+            .context("Failed to set driver_override")?;
+        self.unbind_driver()?;
+        self.probe_driver()?;
+        if self.driver().as_deref() != Some(VFIO_DRIVER) {
+            return Err(anyhow!(
+                "{} didn't bind to {} (is the vfio-pci module available?)",
+                self.address,
+                VFIO_DRIVER
+            ));
+        }
+        Ok(())
+    }
+
+    /// Undoes `bind_vfio` and lets the regular driver probe the device.
+    /// Does nothing for devices that aren't set up for vfio-pci.
+    pub fn release_vfio(&self) -> Result<()> {
+        let override_path = self.path.join("driver_override");
+        let overridden = backend::read_trimmed(&override_path).as_deref() == Some(VFIO_DRIVER);
+        if !overridden && self.driver().as_deref() != Some(VFIO_DRIVER) {
+            return Ok(());
+        }
+        // An empty write clears the override.
+        backend::sysfs()
+            .write(&override_path, "\n")
+            .context("Failed to clear driver_override")?;
+        self.unbind_driver()?;
+        self.probe_driver()
+    }
+
+    /// Asks the kernel to bind a driver to the device again.
+    pub fn probe_driver(&self) -> Result<()> {
+        backend::sysfs()
+            .write(
+                Path::new("/sys/bus/pci/drivers_probe"),
+                self.address.as_str(),
+            )
+            .context("Failed to probe for a driver")?;
+        Ok(())
+    }
+
+    pub fn get_slot_path(&self) -> Option<PathBuf> {
+        // Try to find physical slot in /sys/bus/pci/slots
+        // This is heuristic; sometimes there's a 'slot' file in the device dir
+        // containing the number.
+
+        // 1. Check if 'slot' file exists in device dir
+        let slot_file = self.path.join("slot");
+        if let Some(slot_num) = backend::read_trimmed(&slot_file) {
+            let slot_num = slot_num.as_str();
+            let pci_slots = Path::new("/sys/bus/pci/slots");
+            if backend::sysfs().exists(pci_slots) {
+                for path in backend::sysfs().read_dir(pci_slots).ok()? {
+                    // Some systems use the address as the slot name, some use numbers
+                    // If we found a number in 'slot' file, look for directory with that number
+                    if path.file_name()?.to_string_lossy() == slot_num {
+                        return Some(path);
+                    }
+                }
+
+                // If that failed, sometimes the slot directory name IS the number in the slot file
+                let candidate = pci_slots.join(slot_num);
+                if backend::sysfs().exists(&candidate) {
+                    return Some(candidate);
+                }
+            }
+        }
+
+        // Fallback: Iterate over all slots and match based on address
+        // This handles cases where 'slot' file is missing in device directory
+        let pci_slots = Path::new("/sys/bus/pci/slots");
+        if backend::sysfs().exists(pci_slots) {
+            if let Ok(entries) = backend::sysfs().read_dir(pci_slots) {
+                for entry in entries {
+                    let address_file = entry.join("address");
+                    if let Some(addr_content) = backend::read_trimmed(address_file) {
+                        let addr_content = addr_content.as_str();
+                        // self.address is normally "0000:01:00.0"
+                        // addr_content in slot is usually "0000:01:00" (bus address)
+                        // Verify if device address starts with the slot address
+                        if !addr_content.is_empty()
+                            && (self.address.as_str() == addr_content
+                                || self.address.as_str().starts_with(addr_content))
+                        {
+                            return Some(entry);
+                        }
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    pub fn set_slot_power(&self, on: bool) -> Result<()> {
+        let slot_dir = self.get_slot_path().ok_or_else(|| {
+            anyhow!(
+                "Could not find PCI slot for device {}. (Is acpiphp loaded?)",
+                self.address
+            )
+        })?;
+        let power_file = slot_dir.join("power");
+        let val = if on { "1" } else { "0" };
+
+        // If the power file doesn't exist, we can't control slot power.
+        if !backend::sysfs().exists(&power_file) {
+            return Err(anyhow!(
+                "Slot power control file not found at {:?}",
+                power_file
+            ));
+        }
+
+        backend::sysfs()
+            .write(&power_file, val)
+            .context("Failed to write to slot power file")?;
+        Ok(())
+    }
+
+    /// Takes the device off the bus until the next rescan.
+    pub fn remove(&self) -> Result<()> {
+        backend::sysfs()
+            .write(&self.path.join("remove"), "1")
+            .with_context(|| format!("Failed to remove {}", self.address))?;
+        Ok(())
+    }
+
+    pub fn rescan() -> Result<()> {
+        backend::sysfs()
+            .write(Path::new("/sys/bus/pci/rescan"), "1")
+            .context("Failed to rescan PCI bus")?;
+        Ok(())
+    }
+
+    pub fn find_nvidia_gpu() -> Result<Self> {
+        let pci_root = Path::new(DEVICES_DIR);
+        for path in backend::sysfs().read_dir(pci_root)? {
+            let vendor_path = path.join("vendor");
+            // let device_path = path.join("device"); // Device ID, not needed strictly if we trust vendor
+
+            if backend::sysfs().exists(&vendor_path) {
+                let vendor = backend::sysfs().read(&vendor_path)?;
+                let class = backend::read_trimmed(path.join("class")).unwrap_or_default();
+                if is_nvidia_display(vendor.trim(), &class) {
+                    if let Ok(address) = path.file_name().unwrap().to_string_lossy().parse() {
+                        return Ok(PciDevice::new(address));
+                    }
+                }
+            }
+        }
+        Err(anyhow!("No Nvidia GPU found on PCI bus"))
+    }
+
+    /// Every display controller on the bus (any vendor), by address.
+    pub fn find_display_devices() -> Vec<Self> {
+        let mut devices: Vec<Self> = Self::list()
+            .into_iter()
+            .filter(|device| {
+                backend::read_trimmed(device.path.join("class"))
+                    .is_some_and(|c| c.starts_with("0x03"))
+            })
+            .collect();
+        devices.sort_by(|a, b| a.address.cmp(&b.address));
+        devices
+    }
+
+    /// Vendor and device ID as sysfs shows them, e.g. ("0x10de", "0x25a2").
+    pub fn ids(&self) -> (String, String) {
+        (
+            backend::read_trimmed(self.path.join("vendor")).unwrap_or_default(),
+            backend::read_trimmed(self.path.join("device")).unwrap_or_default(),
+        )
+    }
+
+    pub fn get_power_state(&self) -> PowerState {
+        if let Some(state) = simulate::power_state() {
+            let (vendor, _) = self.ids();
+            let class = backend::read_trimmed(self.path.join("class")).unwrap_or_default();
+            if is_nvidia_display(&vendor, &class) {
+                return state;
+            }
+        }
+        backend::read_trimmed(self.path.join("power_state")).map_or(PowerState::Unknown, |state| {
+            PowerState::from(state.as_str())
+        })
+    }
+
+    /// The device's hwmon directory, e.g. .../hwmon/hwmon3. Only present while
+    /// the card is powered and its driver registers one (nouveau does, the
+    /// proprietary driver doesn't).
+    pub fn hwmon_path(&self) -> Option<PathBuf> {
+        backend::sysfs()
+            .read_dir(&self.path.join("hwmon"))
+            .ok()?
+            .into_iter()
+            .find(|path| {
+                path.file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with("hwmon"))
+            })
+    }
+
+    /// Reads temperature and power from hwmon. Doesn't touch the GPU itself.
+    pub fn hwmon_sensors(&self) -> Sensors {
+        let Some(hwmon) = self.hwmon_path() else {
+            return Sensors::default();
+        };
+        let read = |file: &str| -> Option<f64> {
+            backend::read_trimmed(hwmon.join(file))?.parse::<f64>().ok()
+        };
+        Sensors {
+            // millidegrees Celsius
+            temperature: read("temp1_input").map(|t| t / 1000.0),
+            // microwatts
+            power_draw: read("power1_average")
+                .or_else(|| read("power1_input"))
+                .map(|p| p / 1_000_000.0),
+        }
+    }
+
+    /// Queries the proprietary driver through nvidia-smi. This opens the device,
+    /// which resets its runtime PM idle timer, so only use it when the GPU is
+    /// busy anyway.
+    pub fn nvidia_smi_sensors(&self) -> Sensors {
+        let output = crate::command::output_with_timeout(
+            std::process::Command::new("nvidia-smi")
+                .arg(format!("--id={}", self.address))
+                .arg("--query-gpu=temperature.gpu,power.draw")
+                .arg("--format=csv,noheader,nounits"),
+        );
+        let Ok(output) = output else {
+            return Sensors::default();
+        };
+        if !output.status.success() {
+            return Sensors::default();
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let mut fields = stdout
+            .lines()
+            .next()
+            .unwrap_or("")
+            .split(',')
+            .map(|field| field.trim().parse::<f64>().ok());
+        Sensors {
+            temperature: fields.next().flatten(),
+            // "[N/A]" on cards without power readings
+            power_draw: fields.next().flatten(),
+        }
+    }
+
+    /// Queries utilization and open compute contexts through nvidia-smi,
+    /// with the same caveat as `nvidia_smi_sensors`. None when nvidia-smi is
+    /// missing or can't read the GPU.
+    pub fn nvidia_smi_usage(&self) -> Option<Usage> {
+        let query = |arg: &str| {
+            let output = crate::command::output_with_timeout(
+                std::process::Command::new("nvidia-smi")
+                    .arg(format!("--id={}", self.address))
+                    .arg(arg)
+                    .arg("--format=csv,noheader,nounits"),
+            )
+            .ok()?;
+            output
+                .status
+                .success()
+                .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+        };
+        let utilization = query("--query-gpu=utilization.gpu")?
+            .lines()
+            .next()?
+            .trim()
+            .parse()
+            .ok()?;
+        let compute_apps = query("--query-compute-apps=pid")?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .count();
+        Some(Usage {
+            utilization,
+            compute_apps,
+        })
+    }
+
+    /// GPU memory in MiB per PID, graphics and compute processes alike, from
+    /// NVML through `nvidia-smi -q -d PIDS`, with the same caveat as
+    /// `nvidia_smi_sensors`. None when nvidia-smi is missing or can't read the
+    /// GPU.
+    pub fn nvidia_smi_memory(&self) -> Option<HashMap<u32, u64>> {
+        let output = crate::command::output_with_timeout(
+            std::process::Command::new("nvidia-smi")
+                .arg(format!("--id={}", self.address))
+                .args(["-q", "-d", "PIDS"]),
+        )
+        .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        // "Process ID : 1234", then its "Type", "Name" and
+        // "Used GPU Memory : 512 MiB" lines.
+        let mut memory = HashMap::new();
+        let mut pid = None;
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            match key.trim() {
+                "Process ID" => pid = value.trim().parse::<u32>().ok(),
+                "Used GPU Memory" => {
+                    let mib = value.split_whitespace().next().and_then(|n| n.parse().ok());
+                    if let (Some(pid), Some(mib)) = (pid.take(), mib) {
+                        memory.insert(pid, mib);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Some(memory)
+    }
+
+    /// The seat the device is assigned to (`loginctl attach`), from udev's
+    /// database: the PCI device's own entry, then its DRM cards'. Unassigned
+    /// devices belong to "seat0".
+    pub fn seat(&self) -> String {
+        let mut entries = vec![format!("+pci:{}", self.address)];
+        if let Ok(cards) = backend::sysfs().read_dir(&self.path.join("drm")) {
+            entries.extend(
+                cards
+                    .iter()
+                    .filter_map(|card| backend::read_trimmed(card.join("dev")))
+                    .map(|dev| format!("c{}", dev)),
+            );
+        }
+        entries
+            .iter()
+            .filter_map(|entry| std::fs::read_to_string(Path::new(UDEV_DATA_DIR).join(entry)).ok())
+            .find_map(|data| {
+                data.lines()
+                    .find_map(|line| line.strip_prefix("E:ID_SEAT="))
+                    .map(str::to_string)
+            })
+            .unwrap_or_else(|| DEFAULT_SEAT.to_string())
+    }
+
+    pub fn get_device_nodes(&self) -> Vec<String> {
+        let mut nodes = Vec::new();
+        // Check drm dir: /sys/bus/pci/devices/.../drm/cardX/
+        let drm_path = self.path.join("drm");
+        if backend::sysfs().exists(&drm_path) {
+            if let Ok(entries) = backend::sysfs().read_dir(&drm_path) {
+                for entry in entries {
+                    let Some(name) = entry.file_name() else {
+                        continue;
+                    };
+                    let name = name.to_string_lossy().to_string();
+                    if name.starts_with("card") || name.starts_with("render") {
+                        nodes.push(format!("/dev/dri/{}", name));
+                    }
+                }
+            }
+        }
+        nodes
+    }
+}
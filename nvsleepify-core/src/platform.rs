@@ -0,0 +1,224 @@
+use crate::backend;
+use crate::pci::PciDevice;
+use crate::protocol::Capabilities;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Where distributions install NVML, the library behind nvidia-smi.
+const NVML_PATHS: [&str; 4] = [
+    "/usr/lib/x86_64-linux-gnu/libnvidia-ml.so.1",
+    "/usr/lib64/libnvidia-ml.so.1",
+    "/usr/lib/libnvidia-ml.so.1",
+    "/usr/lib/nvidia/libnvidia-ml.so.1",
+];
+const BBSWITCH_PATHS: [&str; 2] = ["/proc/acpi/bbswitch", "/sys/module/bbswitch"];
+/// Firmware switches that power the dGPU off, "1" meaning off: asus-wmi's,
+/// and the asus-armoury firmware attribute that replaces it on newer kernels.
+const DGPU_DISABLE_PATHS: [&str; 2] = [
+    "/sys/devices/platform/asus-nb-wmi/dgpu_disable",
+    "/sys/class/firmware-attributes/asus-armoury/attributes/dgpu_disable/current_value",
+];
+/// The panel MUX of ASUS laptops, "0" wiring the panel to the dGPU and "1"
+/// to the integrated GPU; asus-wmi's and asus-armoury's again. A new value
+/// takes effect at the next boot.
+const GPU_MUX_PATHS: [&str; 2] = [
+    "/sys/devices/platform/asus-nb-wmi/gpu_mux_mode",
+    "/sys/class/firmware-attributes/asus-armoury/attributes/gpu_mux_mode/current_value",
+];
+
+/// PCI capability ID of power management.
+const PM_CAPABILITY: u8 = 0x01;
+/// PMC bit: the device can signal PME from D3cold, i.e. it has D3cold support.
+const PME_D3COLD: u16 = 1 << 15;
+
+/// The last probe with the GPU on the bus. Most of what's probed is read
+/// from the GPU's sysfs directory, which is gone while it's powered off.
+static LAST: Mutex<Option<Capabilities>> = Mutex::new(None);
+/// The MUX position this boot runs with, once a switch is pending. The
+/// firmware reports the new one right away.
+static BOOTED_MUX: Mutex<Option<Mux>> = Mutex::new(None);
+
+/// Which GPU a laptop's display MUX wires the built-in panel to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Mux {
+    /// The integrated GPU drives the panel (Optimus).
+    Hybrid,
+    /// The Nvidia GPU drives the panel directly.
+    Discrete,
+}
+
+impl Mux {
+    fn value(self) -> &'static str {
+        match self {
+            Mux::Hybrid => "1",
+            Mux::Discrete => "0",
+        }
+    }
+}
+
+impl fmt::Display for Mux {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mux::Hybrid => write!(f, "hybrid"),
+            Mux::Discrete => write!(f, "dGPU"),
+        }
+    }
+}
+
+/// What this machine offers for powering the Nvidia GPU down. While the GPU
+/// is off the bus, returns what was found when it was last on it.
+pub fn probe() -> Capabilities {
+    let Ok(gpu) = PciDevice::find_nvidia_gpu() else {
+        let mut capabilities = LAST.lock().unwrap().clone().unwrap_or_default();
+        // A powered-off slot shows it can be switched even without a probe.
+        capabilities.slot_power |= any_slot_off();
+        capabilities.bbswitch = bbswitch();
+        capabilities.nvml = nvml();
+        capabilities.dgpu_disable = dgpu_switch(None).is_some();
+        return capabilities;
+    };
+    let acpi_off = has_power_resources(&gpu.path);
+    let capabilities = Capabilities {
+        slot_power: gpu
+            .get_slot_path()
+            .is_some_and(|slot| backend::sysfs().exists(&slot.join("power"))),
+        bridge_runtime_pm: bridge(&gpu).is_some_and(|bridge| {
+            backend::read_trimmed(bridge.join("power/control")).as_deref() == Some("auto")
+        }),
+        d3cold: backend::read_trimmed(gpu.path.join("d3cold_allowed")).as_deref() == Some("1")
+            && (acpi_off || pme_from_d3cold(&gpu.path)),
+        acpi_off,
+        bbswitch: bbswitch(),
+        nvml: nvml(),
+        dgpu_disable: dgpu_switch(None).is_some(),
+    };
+    *LAST.lock().unwrap() = Some(capabilities.clone());
+    capabilities
+}
+
+/// The firmware's dGPU switch: `configured` if it exists, else the first
+/// known one present.
+pub fn dgpu_switch(configured: Option<&Path>) -> Option<PathBuf> {
+    match configured {
+        Some(path) => backend::sysfs().exists(path).then(|| path.to_path_buf()),
+        None => DGPU_DISABLE_PATHS
+            .iter()
+            .map(PathBuf::from)
+            .find(|path| backend::sysfs().exists(path)),
+    }
+}
+
+fn mux_path() -> Option<PathBuf> {
+    GPU_MUX_PATHS
+        .iter()
+        .map(PathBuf::from)
+        .find(|path| backend::sysfs().exists(path))
+}
+
+/// The MUX position as the firmware reports it, i.e. including a pending switch.
+fn read_mux() -> Option<Mux> {
+    match backend::read_trimmed(mux_path()?)?.as_str() {
+        "0" => Some(Mux::Discrete),
+        "1" => Some(Mux::Hybrid),
+        _ => None,
+    }
+}
+
+/// The MUX position in effect, or None without a MUX nvsleepify knows.
+pub fn mux() -> Option<Mux> {
+    BOOTED_MUX.lock().unwrap().or_else(read_mux)
+}
+
+/// The position the MUX switches to at the next boot, if it differs.
+pub fn pending_mux() -> Option<Mux> {
+    read_mux().filter(|pending| mux() != Some(*pending))
+}
+
+/// Remembers the MUX position in effect before it is switched, so `mux`
+/// keeps reporting it until the reboot. Called in the process that asked for
+/// the switch, which may not be the one writing it.
+pub fn pin_mux() {
+    let mut booted = BOOTED_MUX.lock().unwrap();
+    if booted.is_none() {
+        *booted = read_mux();
+    }
+}
+
+/// Sets the MUX for the next boot.
+pub fn write_mux(mux: Mux) -> Result<()> {
+    let path = mux_path().ok_or_else(|| anyhow!("No display MUX found"))?;
+    backend::sysfs()
+        .write(&path, mux.value())
+        .map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))
+}
+
+fn bridge(gpu: &PciDevice) -> Option<PathBuf> {
+    let path = backend::sysfs().canonicalize(&gpu.path).ok()?;
+    let parent = path.parent()?;
+    backend::sysfs()
+        .exists(&parent.join("power/control"))
+        .then(|| parent.to_path_buf())
+}
+
+/// ACPI power resources the GPU's firmware node lists for D3 (`_PR3`),
+/// whose `_OFF` methods cut its power.
+fn has_power_resources(device: &Path) -> bool {
+    backend::sysfs()
+        .read_dir(&device.join("firmware_node/power_resources_D3hot"))
+        .is_ok_and(|entries| !entries.is_empty())
+}
+
+/// Reads the PM capability from config space. Only root can read past the
+/// first 64 bytes, where it usually is, so this is false for other users.
+fn pme_from_d3cold(device: &Path) -> bool {
+    let Ok(config) = std::fs::read(backend::host_path(&device.join("config"))) else {
+        return false;
+    };
+    let byte = |offset: usize| config.get(offset).copied();
+    // Status register bit 4: the capability list is present.
+    if byte(0x06).is_none_or(|status| status & 0x10 == 0) {
+        return false;
+    }
+    let mut next = byte(0x34).unwrap_or(0) & !0x3;
+    // Bounded in case of a malformed, looping list.
+    for _ in 0..48 {
+        if next == 0 {
+            break;
+        }
+        let at = next as usize;
+        let (Some(id), Some(following)) = (byte(at), byte(at + 1)) else {
+            break;
+        };
+        if id == PM_CAPABILITY {
+            let (Some(low), Some(high)) = (byte(at + 2), byte(at + 3)) else {
+                break;
+            };
+            return u16::from_le_bytes([low, high]) & PME_D3COLD != 0;
+        }
+        next = following & !0x3;
+    }
+    false
+}
+
+fn any_slot_off() -> bool {
+    backend::sysfs()
+        .read_dir(Path::new("/sys/bus/pci/slots"))
+        .is_ok_and(|slots| {
+            slots
+                .iter()
+                .any(|slot| backend::read_trimmed(slot.join("power")).as_deref() == Some("0"))
+        })
+}
+
+fn bbswitch() -> bool {
+    BBSWITCH_PATHS
+        .iter()
+        .any(|path| backend::sysfs().exists(Path::new(path)))
+}
+
+fn nvml() -> bool {
+    NVML_PATHS.iter().any(|path| Path::new(path).exists())
+}
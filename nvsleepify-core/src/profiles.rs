@@ -0,0 +1,276 @@
+use crate::backend;
+use crate::config::ProfilesConfig;
+use crate::protocol::Mode;
+use crate::simulate;
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Modes chosen per power context while `remember` is on.
+const PROFILES_FILE: &str = "/var/lib/nvsleepify/profiles";
+
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+/// What the laptop is running from. Docks are told apart by the name of the
+/// power supply they show up as, e.g. "ucsi-source-psy-USBC000:001".
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PowerContext {
+    Battery,
+    Ac,
+    Dock(String),
+}
+
+impl std::fmt::Display for PowerContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PowerContext::Battery => write!(f, "battery"),
+            PowerContext::Ac => write!(f, "ac"),
+            PowerContext::Dock(name) => write!(f, "dock:{}", name),
+        }
+    }
+}
+
+impl FromStr for PowerContext {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "battery" => Ok(PowerContext::Battery),
+            "ac" => Ok(PowerContext::Ac),
+            _ => match s.strip_prefix("dock:") {
+                Some(name) if !name.is_empty() => Ok(PowerContext::Dock(name.to_string())),
+                _ => Err(format!("Unknown power context: {}", s)),
+            },
+        }
+    }
+}
+
+/// AC adapter `online` attributes, by the names different firmware uses.
+pub const AC_ONLINE_PATHS: [&str; 3] = [
+    "/sys/class/power_supply/ACAD/online",
+    "/sys/class/power_supply/AC/online",
+    "/sys/class/power_supply/ADP1/online",
+];
+
+pub fn get_charging_status() -> bool {
+    if let Some(online) = simulate::charging() {
+        return online;
+    }
+    for path in AC_ONLINE_PATHS {
+        if let Some(online) = backend::read_trimmed(path) {
+            return online == "1";
+        }
+    }
+    // Fallback: If we genuinely can't tell, assume charging to be safe (never sleep unwantedly)
+    true
+}
+
+/// External power supplies that are currently online, by sysfs name.
+pub fn online_sources() -> Vec<String> {
+    match simulate::charging() {
+        Some(true) => return vec!["AC".to_string()],
+        Some(false) => return Vec::new(),
+        None => {}
+    }
+    let sysfs = backend::sysfs();
+    let Ok(entries) = sysfs.read_dir(Path::new(POWER_SUPPLY_DIR)) else {
+        return Vec::new();
+    };
+    entries
+        .into_iter()
+        .filter(|path| {
+            backend::read_trimmed(path.join("type")).as_deref() != Some("Battery")
+                && backend::read_trimmed(path.join("online")).as_deref() == Some("1")
+        })
+        .filter_map(|path| Some(path.file_name()?.to_string_lossy().into_owned()))
+        .collect()
+}
+
+/// Per-context modes: the configured defaults, overridden by choices
+/// remembered from SetMode.
+#[derive(Debug, Clone, Default)]
+pub struct Profiles {
+    configured: BTreeMap<PowerContext, Mode>,
+    docks: Vec<String>,
+    remember: bool,
+}
+
+impl Profiles {
+    pub fn from_config(config: &ProfilesConfig) -> Result<Self> {
+        let parse = |value: &str| {
+            Mode::from_str(value).map_err(|e| anyhow!("Invalid mode in [profiles]: {}", e))
+        };
+        let mut configured = BTreeMap::new();
+        if let Some(mode) = &config.ac {
+            configured.insert(PowerContext::Ac, parse(mode)?);
+        }
+        if let Some(mode) = &config.battery {
+            configured.insert(PowerContext::Battery, parse(mode)?);
+        }
+        for (name, mode) in &config.docks {
+            configured.insert(PowerContext::Dock(name.clone()), parse(mode)?);
+        }
+        Ok(Self {
+            configured,
+            docks: config.docks.keys().cloned().collect(),
+            remember: config.remember,
+        })
+    }
+
+    /// Whether the mode follows the power context at all.
+    pub fn is_enabled(&self) -> bool {
+        self.remember || !self.configured.is_empty()
+    }
+
+    /// A configured dock that is online wins over plain AC.
+    pub fn current(&self) -> PowerContext {
+        let online = online_sources();
+        if let Some(dock) = self.docks.iter().find(|dock| online.contains(dock)) {
+            return PowerContext::Dock(dock.clone());
+        }
+        if get_charging_status() {
+            PowerContext::Ac
+        } else {
+            PowerContext::Battery
+        }
+    }
+
+    /// The mode to switch to on entering `context`, if any.
+    pub fn mode_for(&self, context: &PowerContext) -> Option<Mode> {
+        if self.remember {
+            if let Some(mode) = load_remembered().remove(context) {
+                return Some(mode);
+            }
+        }
+        self.configured.get(context).copied()
+    }
+
+    /// Records an explicit mode choice for `context`, if remembering is on.
+    pub fn remember(&self, context: &PowerContext, mode: Mode) -> Result<()> {
+        if !self.remember {
+            return Ok(());
+        }
+        let mut remembered = load_remembered();
+        remembered.insert(context.clone(), mode);
+        save_remembered(&remembered)
+    }
+}
+
+fn load_remembered() -> BTreeMap<PowerContext, Mode> {
+    let Ok(content) = std::fs::read_to_string(PROFILES_FILE) else {
+        return BTreeMap::new();
+    };
+    content
+        .lines()
+        .filter_map(|line| {
+            let (context, mode) = line.rsplit_once(' ')?;
+            Some((context.parse().ok()?, mode.parse().ok()?))
+        })
+        .collect()
+}
+
+fn save_remembered(remembered: &BTreeMap<PowerContext, Mode>) -> Result<()> {
+    let path = Path::new(PROFILES_FILE);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let content: String = remembered
+        .iter()
+        .map(|(context, mode)| format!("{} {}\n", context, mode.to_string().to_lowercase()))
+        .collect();
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{Backends, MockSysfs};
+    use crate::config::ProfilesConfig;
+    use std::sync::Arc;
+
+    fn supplies(online: &[(&str, &str, &str)]) -> (Arc<MockSysfs>, backend::ScopedBackends) {
+        let sysfs = Arc::new(MockSysfs::default());
+        for (name, kind, value) in online {
+            let dir = Path::new(POWER_SUPPLY_DIR).join(name);
+            sysfs.set(dir.join("type"), kind);
+            sysfs.set(dir.join("online"), value);
+        }
+        let scope = backend::scoped(Backends {
+            sysfs: sysfs.clone(),
+            ..Backends::default()
+        });
+        (sysfs, scope)
+    }
+
+    fn profiles() -> Profiles {
+        let config = ProfilesConfig {
+            ac: Some("standard".into()),
+            battery: Some("integrated".into()),
+            docks: [(
+                "ucsi-source-psy-USBC000:001".to_string(),
+                "vfio".to_string(),
+            )]
+            .into(),
+            remember: false,
+        };
+        Profiles::from_config(&config).unwrap()
+    }
+
+    #[test]
+    fn contexts_parse_back_from_their_names() {
+        for context in [
+            PowerContext::Battery,
+            PowerContext::Ac,
+            PowerContext::Dock("ucsi-source-psy-USBC000:001".into()),
+        ] {
+            assert_eq!(context.to_string().parse(), Ok(context));
+        }
+        assert!("dock:".parse::<PowerContext>().is_err());
+        assert!("mains".parse::<PowerContext>().is_err());
+    }
+
+    #[test]
+    fn an_online_dock_wins_over_ac() {
+        let (_sysfs, _scope) = supplies(&[
+            ("AC", "Mains", "1"),
+            ("ucsi-source-psy-USBC000:001", "USB", "1"),
+            ("BAT0", "Battery", "1"),
+        ]);
+        let profiles = profiles();
+        let context = profiles.current();
+        assert_eq!(
+            context,
+            PowerContext::Dock("ucsi-source-psy-USBC000:001".into())
+        );
+        assert_eq!(profiles.mode_for(&context), Some(Mode::Vfio));
+    }
+
+    #[test]
+    fn unplugged_is_battery() {
+        let (_sysfs, _scope) = supplies(&[
+            ("AC", "Mains", "0"),
+            ("ucsi-source-psy-USBC000:001", "USB", "0"),
+        ]);
+        let profiles = profiles();
+        assert_eq!(profiles.current(), PowerContext::Battery);
+        assert_eq!(
+            profiles.mode_for(&PowerContext::Battery),
+            Some(Mode::Integrated)
+        );
+        assert_eq!(profiles.mode_for(&PowerContext::Dock("other".into())), None);
+    }
+
+    #[test]
+    fn invalid_configured_modes_are_rejected() {
+        let config = ProfilesConfig {
+            ac: Some("turbo".into()),
+            ..ProfilesConfig::default()
+        };
+        assert!(Profiles::from_config(&config).is_err());
+        assert!(!Profiles::from_config(&ProfilesConfig::default())
+            .unwrap()
+            .is_enabled());
+    }
+}
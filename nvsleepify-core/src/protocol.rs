@@ -0,0 +1,487 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "dbus")]
+use zvariant::{Signature, Type, Value};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    #[default]
+    Standard, // nvsleepify off (GPU awake)
+    Integrated, // nvsleepify on (GPU asleep)
+    Optimized,  // nvsleepify auto
+    Vfio,       // GPU handed to vfio-pci for VM passthrough
+}
+
+impl Mode {
+    /// Modes that kill the processes holding the GPU when they are set, so
+    /// frontends confirm first.
+    pub fn kills_processes(&self) -> bool {
+        matches!(self, Mode::Integrated | Mode::Vfio)
+    }
+}
+
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Mode::Standard => write!(f, "Standard"),
+            Mode::Integrated => write!(f, "Integrated"),
+            Mode::Optimized => write!(f, "Optimized"),
+            Mode::Vfio => write!(f, "Vfio"),
+        }
+    }
+}
+
+impl std::str::FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "standard" | "std" | "off" => Ok(Mode::Standard),
+            "integrated" | "int" | "on" => Ok(Mode::Integrated),
+            "optimized" | "opt" | "auto" => Ok(Mode::Optimized),
+            "vfio" | "passthrough" => Ok(Mode::Vfio),
+            _ => Err(format!("Unknown mode: {}", s)),
+        }
+    }
+}
+
+/// A PCI device's power state as sysfs reports it, or `NotFound` while the
+/// GPU is off the bus. A string (`s`) on the wire, e.g. "D3cold".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PowerState {
+    D0,
+    D1,
+    D2,
+    D3hot,
+    D3cold,
+    /// Off the bus: the slot is powered down or the device was removed.
+    NotFound,
+    /// Unreadable, or a state this version doesn't know.
+    #[default]
+    Unknown,
+}
+
+impl PowerState {
+    /// Powered down far enough to save power: D3cold or off the bus.
+    pub fn is_asleep(&self) -> bool {
+        matches!(self, PowerState::D3cold | PowerState::NotFound)
+    }
+
+    /// Runtime suspended by its driver, with or without power.
+    pub fn is_suspended(&self) -> bool {
+        matches!(self, PowerState::D3hot | PowerState::D3cold)
+    }
+}
+
+impl std::fmt::Display for PowerState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            PowerState::D0 => "D0",
+            PowerState::D1 => "D1",
+            PowerState::D2 => "D2",
+            PowerState::D3hot => "D3hot",
+            PowerState::D3cold => "D3cold",
+            PowerState::NotFound => "NotFound",
+            PowerState::Unknown => "Unknown",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Never fails: the kernel's "unknown" and "error", and names from a newer
+/// version, read as `Unknown`.
+impl From<&str> for PowerState {
+    fn from(s: &str) -> Self {
+        match s.trim() {
+            "D0" => PowerState::D0,
+            "D1" => PowerState::D1,
+            "D2" => PowerState::D2,
+            "D3hot" => PowerState::D3hot,
+            "D3cold" => PowerState::D3cold,
+            "NotFound" => PowerState::NotFound,
+            _ => PowerState::Unknown,
+        }
+    }
+}
+
+impl Serialize for PowerState {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for PowerState {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(PowerState::from(
+            String::deserialize(deserializer)?.as_str(),
+        ))
+    }
+}
+
+#[cfg(feature = "dbus")]
+impl Type for PowerState {
+    fn signature() -> Signature<'static> {
+        String::signature()
+    }
+}
+
+/// A PCI address in sysfs' form, "domain:bus:device.function" in lowercase
+/// hex, e.g. "0000:01:00.0". Only valid addresses parse, so one can be put
+/// into a sysfs path safely. A string (`s`) on the wire.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PciAddress(String);
+
+impl PciAddress {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The address without the function, e.g. "0000:01:00", which names the
+    /// slot all of a card's functions share.
+    pub fn slot(&self) -> &str {
+        &self.0[..10]
+    }
+}
+
+impl std::fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::str::FromStr for PciAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let address = s.trim().to_lowercase();
+        let bytes = address.as_bytes();
+        let valid = bytes.len() == 12
+            && bytes.iter().enumerate().all(|(i, &b)| match i {
+                4 | 7 => b == b':',
+                10 => b == b'.',
+                11 => (b'0'..=b'7').contains(&b),
+                _ => b.is_ascii_hexdigit(),
+            })
+            && u8::from_str_radix(&address[8..10], 16).is_ok_and(|device| device < 32);
+        if valid {
+            Ok(PciAddress(address))
+        } else {
+            Err(format!(
+                "Invalid PCI address: {} (expected e.g. 0000:01:00.0)",
+                s
+            ))
+        }
+    }
+}
+
+impl Serialize for PciAddress {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for PciAddress {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(feature = "dbus")]
+impl Type for PciAddress {
+    fn signature() -> Signature<'static> {
+        String::signature()
+    }
+}
+
+/// One step of the sleep and wake sequences, for carrying them out by hand
+/// when the full sequence fails (`nvsleepify pci`, `module` and `service`).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManualStep {
+    /// Unbind the GPU's driver.
+    Unbind,
+    /// Let the kernel bind a driver to it again.
+    Bind,
+    /// Switch its hotplug slot off.
+    PowerOff,
+    /// Switch every powered-off hotplug slot on.
+    PowerOn,
+    Rescan,
+    UnloadModules,
+    LoadModules,
+    StopServices,
+    StartServices,
+}
+
+impl ManualStep {
+    /// Steps that take the GPU away from whatever uses it, so they are
+    /// refused while processes hold it or it drives the session.
+    pub fn takes_gpu_down(&self) -> bool {
+        matches!(
+            self,
+            ManualStep::Unbind | ManualStep::PowerOff | ManualStep::UnloadModules
+        )
+    }
+}
+
+impl std::fmt::Display for ManualStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::str::FromStr for ManualStep {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let steps = [
+            ManualStep::Unbind,
+            ManualStep::Bind,
+            ManualStep::PowerOff,
+            ManualStep::PowerOn,
+            ManualStep::Rescan,
+            ManualStep::UnloadModules,
+            ManualStep::LoadModules,
+            ManualStep::StopServices,
+            ManualStep::StartServices,
+        ];
+        steps
+            .into_iter()
+            .find(|step| step.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("Unknown step: {}", s))
+    }
+}
+
+/// A process holding the GPU's device nodes. `(ss)` on the wire.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "dbus", derive(Type, Value))]
+pub struct ProcessInfo {
+    pub name: String,
+    pub pid: String,
+}
+
+impl std::fmt::Display for ProcessInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (PID {})", self.name, self.pid)
+    }
+}
+
+/// Blocking processes as reported over D-Bus.
+pub type ProcessList = Vec<ProcessInfo>;
+
+/// What `Info` returns: the saved mode, the GPU's power state, the processes
+/// keeping it awake and whether a sleep waits for them to exit.
+/// `(ssa(ss)b)` on the wire.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "dbus", derive(Type))]
+pub struct StatusReport {
+    pub mode: String,
+    pub power_state: PowerState,
+    pub processes: ProcessList,
+    /// Set by `SleepWhenIdle` until the last blocking process exits.
+    pub pending_sleep: bool,
+}
+
+impl StatusReport {
+    /// Unknown mode names (from a newer daemon) read as Standard.
+    pub fn mode(&self) -> Mode {
+        self.mode.parse().unwrap_or_default()
+    }
+}
+
+/// What a successful `SetMode` returns: the daemon's message and the state
+/// the GPU was left in. `(sss)` on the wire.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "dbus", derive(Type))]
+pub struct TransitionResult {
+    pub mode: String,
+    pub power_state: PowerState,
+    pub message: String,
+}
+
+/// How long one step took in each cycle of a `Bench`, in seconds.
+/// `(sad)` on the wire.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "dbus", derive(Type))]
+pub struct StepTimings {
+    pub step: String,
+    pub seconds: Vec<f64>,
+}
+
+/// One display controller, as `GetDevices` reports it.
+/// `(ssssssasb)` on the wire.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "dbus", derive(Type))]
+pub struct DeviceInfo {
+    pub address: PciAddress,
+    /// "0x10de"
+    pub vendor_id: String,
+    pub device_id: String,
+    /// From the PCI ID database, or the IDs when it isn't installed.
+    pub name: String,
+    pub power_state: PowerState,
+    /// Bound driver, empty if none.
+    pub driver: String,
+    /// The other functions in the same slot (audio, USB-C), by address.
+    pub functions: Vec<PciAddress>,
+    /// Whether this is the GPU nvsleepify puts to sleep.
+    pub managed: bool,
+}
+
+/// What the machine supports for powering the GPU down, as
+/// `GetCapabilities` reports it. `(bbbbbb)` on the wire.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "dbus", derive(Type))]
+pub struct Capabilities {
+    /// The GPU's hotplug slot has a `power` switch (acpiphp or pciehp).
+    pub slot_power: bool,
+    /// The bridge above it allows runtime PM, so it can suspend too.
+    pub bridge_runtime_pm: bool,
+    /// The kernel allows D3cold and the platform or the GPU supports it.
+    pub d3cold: bool,
+    /// ACPI power resources (`_PR3`) whose `_OFF` cuts the GPU's power.
+    pub acpi_off: bool,
+    /// The bbswitch module is loaded.
+    pub bbswitch: bool,
+    /// NVML (libnvidia-ml) is installed.
+    pub nvml: bool,
+    /// The firmware has a switch that cuts the dGPU's power (ASUS
+    /// `dgpu_disable`).
+    pub dgpu_disable: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Command {
+    Status,
+    Devices,
+    Capabilities,
+    Watch,
+    Top,
+    Logs {
+        follow: bool,
+    },
+    Events {
+        follow: bool,
+    },
+    Ps,
+    Kill {
+        pids: Vec<u32>,
+    },
+    Run {
+        command: Vec<String>,
+    },
+    History {
+        limit: u32,
+    },
+    Stats,
+    Savings,
+    Explain,
+    Migrate,
+    Set {
+        mode: Mode,
+        /// Wait up to this many seconds for the GPU to reach the target state.
+        wait: Option<u64>,
+        /// For Integrated: sleep once the blocking processes exit instead of
+        /// killing them.
+        when_idle: bool,
+        /// Skip the check that refuses to power down a GPU displaying the
+        /// session.
+        force: bool,
+    },
+    Delay(u32),
+    /// Keep the GPU awake for this many seconds; None ends a temporary wake.
+    Wake {
+        seconds: Option<u32>,
+    },
+    Step(ManualStep),
+    Bench {
+        cycles: u32,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Response {
+    Ok,
+    Error(String),
+    StatusOutput(String),
+    ProcessesRunning(ProcessList),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pci_addresses_parse_in_sysfs_form() {
+        let cases = [
+            ("0000:01:00.0", Some("0000:01:00.0")),
+            ("0000:3b:1f.7", Some("0000:3b:1f.7")),
+            // Case and surrounding whitespace from a sysfs read don't matter.
+            ("0000:0A:00.1\n", Some("0000:0a:00.1")),
+            ("10000:01:00.0", None),
+            // Without the domain, as lspci prints it.
+            ("01:00.0", None),
+            ("0000:01:00", None),
+            ("0000-01-00.0", None),
+            ("0000:01:00.8", None),
+            // Devices only go up to 0x1f.
+            ("0000:01:20.0", None),
+            ("0000:0g:00.0", None),
+            ("", None),
+        ];
+        for (input, expected) in cases {
+            let parsed = input.parse::<PciAddress>().ok();
+            assert_eq!(
+                parsed.as_ref().map(PciAddress::as_str),
+                expected,
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn pci_address_slot_leaves_out_the_function() {
+        let address: PciAddress = "0000:01:00.1".parse().unwrap();
+        assert_eq!(address.slot(), "0000:01:00");
+        assert!(serde_json::from_str::<PciAddress>("\"01:00.0\"").is_err());
+    }
+
+    #[test]
+    fn power_states_read_from_sysfs() {
+        let cases = [
+            ("D0", PowerState::D0),
+            ("D1", PowerState::D1),
+            ("D2", PowerState::D2),
+            ("D3hot\n", PowerState::D3hot),
+            ("D3cold", PowerState::D3cold),
+            ("NotFound", PowerState::NotFound),
+            // The kernel's own placeholders and names from a newer version.
+            ("unknown", PowerState::Unknown),
+            ("error", PowerState::Unknown),
+            ("D4", PowerState::Unknown),
+            ("d0", PowerState::Unknown),
+            ("", PowerState::Unknown),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(PowerState::from(input), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn power_states_round_trip_as_strings() {
+        for state in [
+            PowerState::D0,
+            PowerState::D3hot,
+            PowerState::D3cold,
+            PowerState::NotFound,
+            PowerState::Unknown,
+        ] {
+            let json = serde_json::to_string(&state).unwrap();
+            assert_eq!(json, format!("\"{}\"", state));
+            assert_eq!(serde_json::from_str::<PowerState>(&json).unwrap(), state);
+        }
+        assert!(PowerState::NotFound.is_asleep());
+        assert!(!PowerState::D3hot.is_asleep());
+    }
+}
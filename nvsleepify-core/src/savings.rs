@@ -0,0 +1,238 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+const SAVINGS_FILE: &str = "/var/lib/nvsleepify/power_savings";
+const DISCHARGE_FILE: &str = "/var/lib/nvsleepify/discharge.json";
+/// The last sleep that saved too little, until one saves enough.
+const RESIDUAL_FILE: &str = "/var/lib/nvsleepify/residual.json";
+/// Battery time needed in a state before its average draw is reported.
+const MIN_DISCHARGE_SECS: f64 = 60.0;
+/// Battery samples averaged on each side of a transition (at 2 s per tick).
+const WINDOW: usize = 15;
+/// Samples needed after the GPU went to sleep before estimating, so the
+/// battery reading has settled.
+const SETTLE: usize = 10;
+
+/// Estimates how much power sleeping the GPU saves by comparing the battery
+/// discharge rate just before it went to sleep with the rate once asleep.
+/// Only sleep transitions are measured: the GPU is idle right before them,
+/// while after a wake it is usually busy.
+#[derive(Debug, Default)]
+pub struct SavingsTracker {
+    samples: Vec<f64>,
+    asleep: Option<bool>,
+    /// Average draw right before the GPU last went to sleep, until measured.
+    before_sleep: Option<f64>,
+    estimate: Option<f64>,
+}
+
+/// Battery draw in watts over the windows before and after one sleep.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct SleepDraw {
+    pub before: f64,
+    pub after: f64,
+}
+
+impl SleepDraw {
+    pub fn saved(&self) -> f64 {
+        self.before - self.after
+    }
+
+    /// Whether the sleep saved less than `min_saving` watts, as when the
+    /// firmware reports D3cold while the card still draws power. A load
+    /// that grew meanwhile looks the same, so this is only a hint.
+    pub fn is_residual(&self, min_saving: f64) -> bool {
+        min_saving > 0.0 && self.saved() < min_saving
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "the battery drew {:.1} W before the sleep and {:.1} W after",
+            self.before, self.after
+        )
+    }
+
+    /// The last residual sleep, unless a later one saved enough.
+    pub fn load_residual() -> Option<Self> {
+        std::fs::read_to_string(RESIDUAL_FILE)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+    }
+
+    /// Records this sleep as residual, for `nvsleepify status`.
+    pub fn save_residual(&self) -> Result<()> {
+        let path = Path::new(RESIDUAL_FILE);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Forgets the residual sleep, once the GPU woke or a sleep saved enough.
+    pub fn clear_residual() -> Result<()> {
+        match std::fs::remove_file(RESIDUAL_FILE) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn average(samples: &[f64]) -> f64 {
+    samples.iter().sum::<f64>() / samples.len() as f64
+}
+
+impl SavingsTracker {
+    /// Starts with the last persisted estimate.
+    pub fn load() -> Self {
+        Self {
+            estimate: std::fs::read_to_string(SAVINGS_FILE)
+                .ok()
+                .and_then(|s| s.trim().parse().ok()),
+            ..Default::default()
+        }
+    }
+
+    /// Watts saved while the GPU is asleep, if measured.
+    pub fn estimate(&self) -> Option<f64> {
+        self.estimate
+    }
+
+    /// Feeds one battery reading (None on AC power). Returns the draw around
+    /// a sleep once it has settled; the estimate is updated when it saved
+    /// power.
+    pub fn sample(&mut self, asleep: bool, draw: Option<f64>) -> Option<SleepDraw> {
+        let Some(draw) = draw else {
+            // Readings on AC reflect the charge rate, not consumption.
+            self.samples.clear();
+            self.before_sleep = None;
+            return None;
+        };
+
+        if self.asleep.is_some_and(|was| was != asleep) {
+            self.before_sleep =
+                (asleep && self.samples.len() >= SETTLE).then(|| average(&self.samples));
+            self.samples.clear();
+        }
+        self.asleep = Some(asleep);
+        self.samples.push(draw);
+        if self.samples.len() > WINDOW {
+            self.samples.remove(0);
+        }
+
+        if asleep && self.samples.len() >= SETTLE {
+            if let Some(before) = self.before_sleep.take() {
+                let measured = SleepDraw {
+                    before,
+                    after: average(&self.samples),
+                };
+                // A negative difference means the load changed meanwhile.
+                if measured.saved() > 0.0 {
+                    self.estimate = Some(measured.saved());
+                }
+                return Some(measured);
+            }
+        }
+        None
+    }
+}
+
+pub fn save(watts: f64) -> Result<()> {
+    let path = Path::new(SAVINGS_FILE);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, format!("{:.2}", watts))?;
+    Ok(())
+}
+
+pub fn describe(watts: f64) -> String {
+    format!("≈ {:.1} W saved while asleep", watts)
+}
+
+/// Battery time spent with the GPU in one state, and the energy drawn.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(default)]
+pub struct Discharge {
+    pub secs: f64,
+    pub joules: f64,
+}
+
+impl Discharge {
+    /// Average discharge rate in watts, once enough time was sampled.
+    pub fn watts(&self) -> Option<f64> {
+        (self.secs >= MIN_DISCHARGE_SECS).then(|| self.joules / self.secs)
+    }
+
+    /// Hours `energy_wh` lasts at the average rate.
+    pub fn runtime_hours(&self, energy_wh: f64) -> Option<f64> {
+        self.watts()
+            .filter(|watts| *watts > 0.0)
+            .map(|watts| energy_wh / watts)
+    }
+}
+
+/// Discharge on battery with the GPU asleep and awake, accumulated from the
+/// same samples as the savings estimate and kept across restarts.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct DischargeReport {
+    pub asleep: Discharge,
+    pub awake: Discharge,
+}
+
+impl DischargeReport {
+    pub fn load() -> Self {
+        std::fs::read_to_string(DISCHARGE_FILE)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Path::new(DISCHARGE_FILE);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Adds a reading of `watts` that held for `secs`.
+    pub fn add(&mut self, asleep: bool, watts: f64, secs: f64) {
+        let discharge = if asleep {
+            &mut self.asleep
+        } else {
+            &mut self.awake
+        };
+        discharge.secs += secs;
+        discharge.joules += watts * secs;
+    }
+
+    /// D-Bus representation: (asleep_secs, asleep_joules, awake_secs, awake_joules)
+    pub fn to_tuple(&self) -> (f64, f64, f64, f64) {
+        (
+            self.asleep.secs,
+            self.asleep.joules,
+            self.awake.secs,
+            self.awake.joules,
+        )
+    }
+
+    /// The inverse of `to_tuple`.
+    pub fn from_tuple(
+        (asleep_secs, asleep_joules, awake_secs, awake_joules): (f64, f64, f64, f64),
+    ) -> Self {
+        Self {
+            asleep: Discharge {
+                secs: asleep_secs,
+                joules: asleep_joules,
+            },
+            awake: Discharge {
+                secs: awake_secs,
+                joules: awake_joules,
+            },
+        }
+    }
+}
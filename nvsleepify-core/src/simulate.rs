@@ -0,0 +1,81 @@
+use crate::protocol::{PowerState, ProcessInfo, ProcessList};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static OVERRIDES: Mutex<Overrides> = Mutex::new(Overrides {
+    charging: None,
+    power_state: None,
+    processes: None,
+});
+
+/// Fake readings standing in for the real ones; None reads the hardware.
+#[derive(Debug, Clone)]
+struct Overrides {
+    charging: Option<bool>,
+    power_state: Option<PowerState>,
+    processes: Option<ProcessList>,
+}
+
+/// Turns on the overrides; called once at startup for `--simulate`.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+fn overrides() -> Option<Overrides> {
+    is_enabled().then(|| OVERRIDES.lock().unwrap().clone())
+}
+
+/// The injected charger state, if any.
+pub fn charging() -> Option<bool> {
+    overrides()?.charging
+}
+
+/// The injected power state of the Nvidia GPU, if any.
+pub fn power_state() -> Option<PowerState> {
+    overrides()?.power_state
+}
+
+/// The injected processes holding the GPU, if any.
+pub fn processes() -> Option<ProcessList> {
+    overrides()?.processes
+}
+
+/// "Kills" injected processes by dropping them from the list. Returns false
+/// when no process list is injected, so real processes get signalled.
+pub fn kill(procs: &[ProcessInfo]) -> bool {
+    if !is_enabled() {
+        return false;
+    }
+    let mut overrides = OVERRIDES.lock().unwrap();
+    let Some(list) = overrides.processes.as_mut() else {
+        return false;
+    };
+    list.retain(|p| !procs.iter().any(|killed| killed.pid == p.pid));
+    true
+}
+
+pub fn set_charging(online: bool) {
+    OVERRIDES.lock().unwrap().charging = Some(online);
+}
+
+pub fn set_processes(processes: ProcessList) {
+    OVERRIDES.lock().unwrap().processes = Some(processes);
+}
+
+pub fn set_power_state(power_state: PowerState) {
+    OVERRIDES.lock().unwrap().power_state = Some(power_state);
+}
+
+/// Drops every injected reading.
+pub fn reset() {
+    *OVERRIDES.lock().unwrap() = Overrides {
+        charging: None,
+        power_state: None,
+        processes: None,
+    };
+}
@@ -0,0 +1,70 @@
+use crate::protocol::Mode;
+use crate::sysfs;
+use anyhow::Result;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The mode the daemon restores at startup and enforces.
+pub const MODE_FILE: &str = "/var/lib/nvsleepify/mode";
+/// Seconds the daemon waits after login before restoring the mode.
+pub const DELAY_FILE: &str = "/var/lib/nvsleepify/restore_delay";
+
+pub fn save_mode(mode: Mode) -> Result<()> {
+    let path = Path::new(MODE_FILE);
+    if let Some(parent) = path.parent() {
+        if !parent.exists() {
+            std::fs::create_dir_all(parent)?;
+        }
+    }
+    let content = match mode {
+        Mode::Standard => "standard",
+        Mode::Integrated => "integrated",
+        Mode::Optimized => "optimized",
+        Mode::Vfio => "vfio",
+    };
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+/// The saved mode, Standard if it can't be read.
+pub async fn read_mode() -> Mode {
+    match tokio::fs::read_to_string(MODE_FILE).await {
+        Ok(content) => Mode::from_str(content.trim()).unwrap_or(Mode::Standard),
+        Err(_) => Mode::Standard,
+    }
+}
+
+pub async fn read_delay() -> u32 {
+    sysfs::read_trimmed(DELAY_FILE)
+        .await
+        .and_then(|content| content.parse().ok())
+        .unwrap_or(0)
+}
+
+pub async fn write_delay(seconds: u32) -> Result<()> {
+    if let Some(parent) = Path::new(DELAY_FILE).parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(DELAY_FILE, seconds.to_string()).await?;
+    Ok(())
+}
+
+pub fn load_mode() -> Result<Mode> {
+    let path = Path::new(MODE_FILE);
+    if !path.exists() {
+        return Ok(Mode::Standard);
+    }
+    let content = std::fs::read_to_string(path)?;
+    Mode::from_str(content.trim()).map_err(|e| anyhow::anyhow!(e))
+}
+
+pub fn save_delay(seconds: u32) -> Result<()> {
+    let path = Path::new(DELAY_FILE);
+    if let Some(parent) = path.parent() {
+        if !parent.exists() {
+            std::fs::create_dir_all(parent)?;
+        }
+    }
+    std::fs::write(path, seconds.to_string())?;
+    Ok(())
+}
@@ -0,0 +1,175 @@
+use crate::eventlog::unix_now;
+use crate::protocol::PowerState;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+const STATS_FILE: &str = "/var/lib/nvsleepify/stats.json";
+/// How often changes are written out.
+const SAVE_INTERVAL_SECS: u64 = 30;
+
+/// Lifetime counters, kept across daemon restarts.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Stats {
+    /// Unix time the counters started at.
+    pub since: u64,
+    /// Power state changes into D3cold or off the bus.
+    pub sleeps: u64,
+    /// Power state changes back out of them.
+    pub wakes: u64,
+    pub d3cold_secs: u64,
+    /// Times the monitor found the GPU awake against the mode and put it back to sleep.
+    pub enforcement_retries: u64,
+    /// Failed mode changes and enforcement attempts.
+    pub failed_transitions: u64,
+}
+
+impl Stats {
+    /// D-Bus representation:
+    /// (since, sleeps, wakes, d3cold_secs, enforcement_retries, failed_transitions)
+    pub fn to_tuple(&self) -> (u64, u64, u64, u64, u64, u64) {
+        (
+            self.since,
+            self.sleeps,
+            self.wakes,
+            self.d3cold_secs,
+            self.enforcement_retries,
+            self.failed_transitions,
+        )
+    }
+
+    /// The inverse of `to_tuple`.
+    pub fn from_tuple(
+        (since, sleeps, wakes, d3cold_secs, enforcement_retries, failed_transitions): (
+            u64,
+            u64,
+            u64,
+            u64,
+            u64,
+            u64,
+        ),
+    ) -> Self {
+        Self {
+            since,
+            sleeps,
+            wakes,
+            d3cold_secs,
+            enforcement_retries,
+            failed_transitions,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Tracker {
+    stats: Stats,
+    in_d3cold_since: Option<Instant>,
+    /// D3cold time not yet added to `stats`, in fractions of a second.
+    pending_secs: f64,
+    /// Counters changed since the last save.
+    dirty: bool,
+}
+
+fn tracker() -> &'static Mutex<Tracker> {
+    static TRACKER: OnceLock<Mutex<Tracker>> = OnceLock::new();
+    TRACKER.get_or_init(|| Mutex::new(Tracker::default()))
+}
+
+/// Picks up the persisted counters; called once at daemon start.
+pub fn load() {
+    let mut stats: Stats = std::fs::read_to_string(STATS_FILE)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default();
+    if stats.since == 0 {
+        stats.since = unix_now();
+    }
+    tracker().lock().unwrap().stats = stats;
+}
+
+fn save(stats: &Stats) -> Result<()> {
+    let path = Path::new(STATS_FILE);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_string_pretty(stats)?)?;
+    std::fs::rename(tmp, path)?;
+    Ok(())
+}
+
+/// Current counters, including time spent in D3cold up to now.
+pub fn snapshot() -> Stats {
+    let mut t = tracker().lock().unwrap();
+    flush_d3cold(&mut t);
+    t.stats.clone()
+}
+
+fn flush_d3cold(t: &mut Tracker) {
+    if let Some(since) = t.in_d3cold_since {
+        t.pending_secs += since.elapsed().as_secs_f64();
+        t.in_d3cold_since = Some(Instant::now());
+    }
+    let whole = t.pending_secs.floor();
+    t.stats.d3cold_secs += whole as u64;
+    t.pending_secs -= whole;
+}
+
+/// Records the current power state; called on every monitor tick.
+pub fn observe(power_state: PowerState) {
+    let mut t = tracker().lock().unwrap();
+    match (power_state == PowerState::D3cold, t.in_d3cold_since) {
+        (true, None) => t.in_d3cold_since = Some(Instant::now()),
+        (false, Some(_)) => {
+            flush_d3cold(&mut t);
+            t.in_d3cold_since = None;
+        }
+        _ => {}
+    }
+}
+
+pub fn record_transition(from: PowerState, to: PowerState) {
+    let mut t = tracker().lock().unwrap();
+    match (from.is_asleep(), to.is_asleep()) {
+        (false, true) => t.stats.sleeps += 1,
+        (true, false) => t.stats.wakes += 1,
+        _ => return,
+    }
+    t.dirty = true;
+}
+
+pub fn record_enforcement_retry() {
+    let mut t = tracker().lock().unwrap();
+    t.stats.enforcement_retries += 1;
+    t.dirty = true;
+}
+
+pub fn record_failure() {
+    let mut t = tracker().lock().unwrap();
+    t.stats.failed_transitions += 1;
+    t.dirty = true;
+}
+
+/// Periodically persists the counters when they changed or D3cold time accrued.
+pub async fn save_loop() {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(SAVE_INTERVAL_SECS));
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let stats = {
+            let mut t = tracker().lock().unwrap();
+            if !t.dirty && t.in_d3cold_since.is_none() {
+                continue;
+            }
+            t.dirty = false;
+            flush_d3cold(&mut t);
+            t.stats.clone()
+        };
+        if let Ok(Err(e)) = tokio::task::spawn_blocking(move || save(&stats)).await {
+            eprintln!("Failed to save statistics: {}", e);
+        }
+    }
+}
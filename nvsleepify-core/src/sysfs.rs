@@ -0,0 +1,129 @@
+use crate::backend;
+use crate::pci::{self, PciDevice};
+use crate::profiles::AC_ONLINE_PATHS;
+use crate::protocol::PowerState;
+use crate::simulate;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A sysfs attribute with surrounding whitespace removed.
+pub async fn read_trimmed(path: impl AsRef<Path>) -> Option<String> {
+    let content = tokio::fs::read_to_string(backend::host_path(path.as_ref()))
+        .await
+        .ok()?;
+    Some(content.trim().to_string())
+}
+
+async fn read_parsed<T: FromStr>(path: impl AsRef<Path>) -> Option<T> {
+    read_trimmed(path).await?.parse().ok()
+}
+
+/// Async counterpart of `PciDevice::find_nvidia_gpu`.
+pub async fn nvidia_gpu() -> Option<PciDevice> {
+    let dir = Path::new(pci::DEVICES_DIR);
+    let mut entries = tokio::fs::read_dir(backend::host_path(dir)).await.ok()?;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = dir.join(entry.file_name());
+        let Some(vendor) = read_trimmed(path.join("vendor")).await else {
+            continue;
+        };
+        let class = read_trimmed(path.join("class")).await.unwrap_or_default();
+        if pci::is_nvidia_display(&vendor, &class) {
+            let Ok(address) = entry.file_name().to_string_lossy().parse() else {
+                continue;
+            };
+            return Some(PciDevice::new(address));
+        }
+    }
+    None
+}
+
+/// The GPU's PCI power state, or `NotFound` while it is off the bus.
+pub async fn power_state() -> PowerState {
+    if let Some(state) = simulate::power_state() {
+        return state;
+    }
+    match nvidia_gpu().await {
+        Some(gpu) => read_trimmed(gpu.path.join("power_state"))
+            .await
+            .map_or(PowerState::Unknown, |state| {
+                PowerState::from(state.as_str())
+            }),
+        None => PowerState::NotFound,
+    }
+}
+
+/// Async counterpart of `profiles::get_charging_status`.
+pub async fn charging() -> bool {
+    if let Some(online) = simulate::charging() {
+        return online;
+    }
+    for path in AC_ONLINE_PATHS {
+        if let Some(online) = read_trimmed(path).await {
+            return online == "1";
+        }
+    }
+    // Can't tell: assume charging, so the GPU is never put to sleep unwantedly.
+    true
+}
+
+/// Energy left in the batteries in watt-hours, from `energy_now`, or from
+/// `charge_now` and the design voltage on batteries that count charge.
+pub async fn battery_energy() -> Option<f64> {
+    let dir = Path::new("/sys/class/power_supply");
+    let mut entries = tokio::fs::read_dir(backend::host_path(dir)).await.ok()?;
+    let mut total = None;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if !entry.file_name().to_string_lossy().starts_with("BAT") {
+            continue;
+        }
+        let path = dir.join(entry.file_name());
+        // µWh, or µAh times µV.
+        let microwatt_hours = match read_parsed::<f64>(path.join("energy_now")).await {
+            Some(uwh) => Some(uwh),
+            None => match (
+                read_parsed::<f64>(path.join("charge_now")).await,
+                read_parsed::<f64>(path.join("voltage_min_design")).await,
+            ) {
+                (Some(uah), Some(uv)) => Some(uah * uv / 1_000_000.0),
+                _ => None,
+            },
+        };
+        if let Some(uwh) = microwatt_hours {
+            *total.get_or_insert(0.0) += uwh / 1_000_000.0;
+        }
+    }
+    total
+}
+
+/// Total battery discharge rate in watts, or None when not discharging or the
+/// batteries don't report it.
+pub async fn battery_power_draw() -> Option<f64> {
+    let dir = Path::new("/sys/class/power_supply");
+    let mut entries = tokio::fs::read_dir(backend::host_path(dir)).await.ok()?;
+    let mut total = None;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if !entry.file_name().to_string_lossy().starts_with("BAT") {
+            continue;
+        }
+        let path = dir.join(entry.file_name());
+        if read_trimmed(path.join("status")).await.as_deref() != Some("Discharging") {
+            continue;
+        }
+        // power_now is in µW; batteries without it report µA and µV instead.
+        let microwatts = match read_parsed::<f64>(path.join("power_now")).await {
+            Some(uw) => Some(uw),
+            None => match (
+                read_parsed::<f64>(path.join("current_now")).await,
+                read_parsed::<f64>(path.join("voltage_now")).await,
+            ) {
+                (Some(ua), Some(uv)) => Some(ua * uv / 1_000_000.0),
+                _ => None,
+            },
+        };
+        if let Some(uw) = microwatts {
+            *total.get_or_insert(0.0) += uw / 1_000_000.0;
+        }
+    }
+    total
+}
@@ -0,0 +1,549 @@
+use crate::backend;
+use crate::config::{
+    DaemonConfig, DgpuDisable, IdleConfig, KillPolicy, ModuleSet, ModulesConfig, PersistenceConfig,
+    PlatformConfig, ServerConfig, SleepConfig,
+};
+use crate::driver::{self, Driver};
+use crate::helper::{self, Request, Response};
+use crate::output;
+use crate::platform;
+use crate::protocol::{Mode, ProcessInfo, ProcessList};
+use crate::simulate;
+use anyhow::{anyhow, Context, Result};
+use colored::*;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Mutex;
+
+pub use crate::command::{
+    output_with_timeout, set_command_timeout, status_with_timeout, CommandTimeout,
+    DEFAULT_COMMAND_TIMEOUT_SECS,
+};
+pub use crate::profiles::{get_charging_status, AC_ONLINE_PATHS};
+
+static MODULES: Mutex<ModulesConfig> = Mutex::new(ModulesConfig::new());
+static KILL_POLICY: Mutex<KillPolicy> = Mutex::new(KillPolicy::Always);
+static SERVER: Mutex<ServerConfig> = Mutex::new(ServerConfig::new());
+static IDLE: Mutex<IdleConfig> = Mutex::new(IdleConfig::new());
+static PERSISTENCE: Mutex<PersistenceConfig> = Mutex::new(PersistenceConfig::new());
+static SLEEP: Mutex<Option<SleepConfig>> = Mutex::new(None);
+static PLATFORM: Mutex<PlatformConfig> = Mutex::new(PlatformConfig::new());
+/// Persistence mode as last set since the driver loaded; None while it's
+/// whatever nvidia-persistenced chose.
+static PERSISTENCE_SET: Mutex<Option<bool>> = Mutex::new(None);
+
+/// Applies the daemon settings the sleep and wake steps depend on. Used
+/// wherever steps run: the daemon, nvsleepify-helper and `--direct`.
+pub fn apply_config(config: &DaemonConfig) {
+    set_command_timeout(config.commands.timeout);
+    let mut modules = config.modules.clone();
+    if config.server.enabled {
+        // Nothing drives a display, so nvidia_modeset and nvidia_drm aren't needed.
+        modules.load = ModuleSet::Compute;
+    }
+    *MODULES.lock().unwrap() = modules;
+    *KILL_POLICY.lock().unwrap() = config.processes.kill;
+    *SERVER.lock().unwrap() = config.server.clone();
+    *IDLE.lock().unwrap() = config.idle.clone();
+    *PERSISTENCE.lock().unwrap() = config.persistence.clone();
+    *SLEEP.lock().unwrap() = Some(config.sleep.clone());
+    *PLATFORM.lock().unwrap() = config.platform.clone();
+}
+
+/// The firmware's dGPU switch that sleeps cut the power with instead of the
+/// slot, per `[platform]`.
+pub fn dgpu_switch() -> Option<PathBuf> {
+    let config = PLATFORM.lock().unwrap().clone();
+    match config.dgpu_disable {
+        DgpuDisable::Never => None,
+        DgpuDisable::Auto => platform::dgpu_switch(config.path.as_deref()),
+    }
+}
+
+/// `[platform] switch_mux`.
+pub fn switch_mux() -> bool {
+    PLATFORM.lock().unwrap().switch_mux
+}
+
+/// `[processes] kill`, what sleeps may do with the GPU's users.
+pub fn kill_policy() -> KillPolicy {
+    *KILL_POLICY.lock().unwrap()
+}
+
+/// `[server]`, the headless server profile.
+pub fn server() -> ServerConfig {
+    SERVER.lock().unwrap().clone()
+}
+
+/// `[idle]`, when processes holding the awake GPU count as idle.
+pub fn idle() -> IdleConfig {
+    IDLE.lock().unwrap().clone()
+}
+
+/// `[sleep]`, the stages a sleep escalates through. An empty strategy
+/// falls back to the default, powering the slot off.
+pub fn sleep_config() -> SleepConfig {
+    let config = SLEEP.lock().unwrap().clone().unwrap_or_default();
+    if config.strategy.is_empty() {
+        return SleepConfig::default();
+    }
+    config
+}
+
+pub fn is_user_logged_in() -> bool {
+    // Check if any user with UID >= 1000 has a session using loginctl
+    if let Ok(output) = output_with_timeout(
+        Command::new("loginctl")
+            .arg("list-users")
+            .arg("--no-legend"),
+    ) {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        for line in stdout.lines() {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            // Output format: " UID USER"
+            if let Some(uid_str) = parts.first() {
+                if let Ok(uid) = uid_str.parse::<u32>() {
+                    // Filter out system users (typically UID < 1000)
+                    if (1000..65534).contains(&uid) {
+                        return true;
+                    }
+                }
+            }
+        }
+    }
+
+    // Fallback: check /run/user for any active user runtime directories with UID >= 1000
+    if let Ok(entries) = std::fs::read_dir("/run/user") {
+        for entry in entries.flatten() {
+            if let Ok(file_name) = entry.file_name().into_string() {
+                if let Ok(uid) = file_name.parse::<u32>() {
+                    if (1000..65534).contains(&uid) {
+                        return true;
+                    }
+                }
+            }
+        }
+    }
+
+    false
+}
+
+/// Asks nvsleepify-helper when running unprivileged, since lsof only sees
+/// other users' processes as root.
+pub fn get_processes_using_nvidia(extra_paths: &[String]) -> Result<ProcessList> {
+    if let Some(procs) = simulate::processes() {
+        return Ok(procs);
+    }
+    match helper::run(Request::ListProcesses {
+        nodes: extra_paths.to_vec(),
+    })? {
+        Response::Processes(procs) => Ok(procs),
+        other => Err(anyhow!("Unexpected helper response: {:?}", other)),
+    }
+}
+
+pub fn find_processes_using_nvidia(extra_paths: &[String]) -> Result<ProcessList> {
+    // The device nodes are the real machine's, not the fake GPU's.
+    if backend::sysfs_root().is_some() {
+        return Ok(Vec::new());
+    }
+    // Basic nvidia paths that are always relevant
+    // We will use sh to run lsof with glob pattern for /dev/nvidia*
+    // And append specific DRI paths provided by caller
+
+    let mut paths_to_check = vec!["/dev/nvidia[0-9]*".to_string()];
+    paths_to_check.extend_from_slice(extra_paths);
+
+    let path_args = paths_to_check.join(" ");
+
+    let output = output_with_timeout(Command::new("sh").arg("-c").arg(format!(
+        "lsof -w {} | grep -v PID | awk '{{print $1, $2}}' | sort -u",
+        path_args
+    )))
+    .context("Failed to run lsof")?;
+
+    let stdout = String::from_utf8(output.stdout)?;
+    let mut procs = Vec::new();
+    for line in stdout.lines() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() >= 2 {
+            let name = parts[0];
+            // Ignore nvidia-powerd (shows as nvidia-po) as it's a service we stop gracefully
+            if name.starts_with("nvidia-po") || name.starts_with("nvidia-pe") {
+                continue;
+            }
+            procs.push(ProcessInfo {
+                name: name.to_string(),
+                pid: parts[1].to_string(),
+            });
+        }
+    }
+    Ok(procs)
+}
+
+/// Extra details about a process, read from /proc.
+#[derive(Debug, Clone, Default)]
+pub struct ProcessDetails {
+    pub user: String,
+    pub cmdline: String,
+}
+
+fn username_for_uid(uid: u32) -> Option<String> {
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    let rc = unsafe { libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if rc != 0 || result.is_null() {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(pwd.pw_name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+pub fn get_process_details(pid: &str) -> ProcessDetails {
+    let proc_dir = std::path::Path::new("/proc").join(pid);
+
+    let user = std::fs::read_to_string(proc_dir.join("status"))
+        .ok()
+        .and_then(|status| {
+            // "Uid:\t<real>\t<effective>\t<saved>\t<fs>"
+            let line = status.lines().find(|l| l.starts_with("Uid:"))?;
+            line.split_whitespace().nth(1)?.parse::<u32>().ok()
+        })
+        .map(|uid| username_for_uid(uid).unwrap_or_else(|| uid.to_string()))
+        .unwrap_or_else(|| "?".to_string());
+
+    // Arguments are NUL-separated; kernel threads have an empty cmdline.
+    let cmdline = std::fs::read(proc_dir.join("cmdline"))
+        .map(|raw| {
+            raw.split(|b| *b == 0)
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .unwrap_or_default();
+
+    ProcessDetails { user, cmdline }
+}
+
+/// How long processes get to exit after SIGTERM before being sent SIGKILL.
+const KILL_GRACE: std::time::Duration = std::time::Duration::from_secs(3);
+
+fn process_exists(pid: &str) -> bool {
+    std::path::Path::new("/proc").join(pid).exists()
+}
+
+/// Kills the processes that still hold the GPU, through nvsleepify-helper
+/// when running unprivileged.
+pub fn kill_processes(procs: &[ProcessInfo]) -> Result<()> {
+    if simulate::kill(procs) {
+        return Ok(());
+    }
+    helper::run(Request::KillProcesses {
+        pids: procs.iter().map(|proc| proc.pid.clone()).collect(),
+    })?;
+    Ok(())
+}
+
+/// Sends SIGTERM to every process, waits up to `KILL_GRACE` for them to exit,
+/// then escalates to SIGKILL for any that are still alive.
+pub fn signal_processes(procs: &[ProcessInfo]) {
+    if backend::sysfs_root().is_some() {
+        return;
+    }
+    for proc in procs {
+        let _ = status_with_timeout(Command::new("kill").arg("-15").arg(&proc.pid));
+    }
+
+    let deadline = std::time::Instant::now() + KILL_GRACE;
+    let mut survivors: Vec<&String> = procs.iter().map(|proc| &proc.pid).collect();
+    while !survivors.is_empty() && std::time::Instant::now() < deadline {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        survivors.retain(|pid| process_exists(pid));
+    }
+
+    for pid in &survivors {
+        eprintln!(
+            "{} PID {} ignored SIGTERM, sending SIGKILL",
+            "WARN:".yellow(),
+            pid
+        );
+        let _ = status_with_timeout(Command::new("kill").arg("-9").arg(pid));
+    }
+}
+
+/// Failures are only warned about (the services are optional), except for
+/// timeouts: a hung systemctl means the transition can't go on.
+fn run_systemctl(action: &str, service: &str) -> Result<()> {
+    match backend::services().run(action, service) {
+        Err(e) if e.is::<CommandTimeout>() => Err(e),
+        Err(e) => {
+            eprintln!(
+                "{} Failed to {} {}: {}",
+                "WARN:".yellow(),
+                action,
+                service,
+                e
+            );
+            Ok(())
+        }
+        Ok(()) => Ok(()),
+    }
+}
+
+/// The driver the steps manage, per `[modules] driver`.
+pub fn gpu_driver() -> Driver {
+    driver::current(MODULES.lock().unwrap().driver)
+}
+
+pub fn stop_services() -> Result<()> {
+    if gpu_driver() == Driver::Nouveau {
+        output::detail("No services to stop for nouveau");
+        return Ok(());
+    }
+    output::info("Stopping systemd services...".blue());
+    let server = server().enabled;
+    // nvidia-powerd (Dynamic Boost) and nvidia-fallback (nouveau for the
+    // display) are laptop and desktop services; servers are left as they are.
+    let services: &[&str] = if server {
+        &["nvidia-persistenced"]
+    } else {
+        &["nvidia-persistenced", "nvidia-powerd"]
+    };
+    for svc in services {
+        run_systemctl("stop", svc)?;
+    }
+
+    let services_to_disable = [
+        "nvidia-suspend.service",
+        "nvidia-hibernate.service",
+        "nvidia-resume.service",
+        "nvidia-persistenced.service",
+        "nvidia-powerd.service",
+    ];
+    for svc in services_to_disable {
+        if server && svc == "nvidia-powerd.service" {
+            continue;
+        }
+        run_systemctl("disable", svc)?;
+    }
+
+    // Unloading the driver drops persistence mode along with it.
+    *PERSISTENCE_SET.lock().unwrap() = None;
+
+    if !server {
+        // Mask nvidia-fallback.service to prevent it from interfering
+        run_systemctl("stop", "nvidia-fallback.service")?;
+        run_systemctl("mask", "nvidia-fallback.service")?;
+    }
+
+    Ok(())
+}
+
+pub fn start_services() -> Result<()> {
+    if gpu_driver() == Driver::Nouveau {
+        output::detail("No services to start for nouveau");
+        return Ok(());
+    }
+    output::info("Starting systemd services...".blue());
+    let server = server();
+
+    if !server.enabled {
+        // Unmask nvidia-fallback.service
+        run_systemctl("unmask", "nvidia-fallback.service")?;
+    }
+
+    let services = ["nvidia-persistenced", "nvidia-powerd"];
+    let services_to_enable = [
+        "nvidia-suspend.service",
+        "nvidia-hibernate.service",
+        "nvidia-resume.service",
+        "nvidia-persistenced.service",
+        "nvidia-powerd.service",
+    ];
+    // Servers skip nvidia-powerd, and nvidia-persistenced unless
+    // `[server] persistenced` keeps the driver initialized between jobs.
+    let skipped = |svc: &str| {
+        server.enabled
+            && (svc.starts_with("nvidia-powerd")
+                || (svc.starts_with("nvidia-persistenced") && !server.persistenced))
+    };
+    for svc in services.into_iter().filter(|svc| !skipped(svc)) {
+        run_systemctl("start", svc)?;
+    }
+    for svc in services_to_enable.into_iter().filter(|svc| !skipped(svc)) {
+        run_systemctl("enable", svc)?;
+    }
+    if !server.enabled {
+        apply_persistence();
+    }
+    Ok(())
+}
+
+/// Turns persistence mode on or off for the saved mode, per `[persistence]`.
+/// It's only set when it changes: nvidia-smi opens the GPU, which would keep
+/// it from runtime suspending if done on every Optimized mode check. Failures
+/// are warned about, as for the services.
+fn apply_persistence() {
+    let config = PERSISTENCE.lock().unwrap().clone();
+    if !config.manage || backend::sysfs_root().is_some() {
+        return;
+    }
+    let mode = crate::state::load_mode().unwrap_or_default();
+    let wanted = mode == Mode::Standard && config.standard;
+    let mut set = PERSISTENCE_SET.lock().unwrap();
+    if *set == Some(wanted) {
+        return;
+    }
+    let status = status_with_timeout(
+        Command::new("nvidia-smi").arg(format!("--persistence-mode={}", u8::from(wanted))),
+    );
+    match status {
+        Ok(status) if status.success() => *set = Some(wanted),
+        Ok(status) => eprintln!(
+            "{} nvidia-smi failed to set persistence mode: {}",
+            "WARN:".yellow(),
+            status
+        ),
+        Err(e) => eprintln!("{} Failed to set persistence mode: {}", "WARN:".yellow(), e),
+    }
+}
+
+pub fn unload_modules() -> Result<()> {
+    output::info("Unloading kernel modules...".blue());
+    let driver = gpu_driver();
+    if driver == Driver::Nouveau {
+        return unload_nouveau();
+    }
+    // Order matters: nvidia_uvm, nvidia_modeset, nvidia_drm, nvidia
+    // Dependencies: drm depends on nvidia, modeset depends on nvidia...
+    // To be safe, try `modprobe -r nvidia_drm nvidia_modeset nvidia_uvm nvidia`,
+    // leaving out the ones that aren't loaded (compute-only wakes), which
+    // modprobe -r would fail on.
+    let loaded: Vec<&str> = NVIDIA_MODULES
+        .into_iter()
+        .filter(|module| module_loaded(module))
+        .collect();
+    if loaded.is_empty() {
+        driver::remember_unloaded(driver);
+        return Ok(());
+    }
+    // Read before the module, and its parameters, go away.
+    let gsp = driver::loaded().is_some_and(|info| info.gsp);
+    match backend::modules().unload(&loaded) {
+        Err(e) if e.is::<CommandTimeout>() => Err(e),
+        Err(_) => Err(anyhow!(
+            "Failed to unload nvidia modules: {}",
+            describe_module_users(&NVIDIA_MODULES)
+        )),
+        Ok(()) => {
+            driver::remember_unloaded(driver);
+            if gsp && backend::sysfs_root().is_none() {
+                std::thread::sleep(driver::GSP_SETTLE);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// nouveau also drives the console on the GPU's outputs, which holds the
+/// module while anything is shown there.
+fn unload_nouveau() -> Result<()> {
+    if module_loaded(driver::NOUVEAU_MODULE) {
+        match backend::modules().unload(&[driver::NOUVEAU_MODULE]) {
+            Err(e) if e.is::<CommandTimeout>() => return Err(e),
+            Err(_) => {
+                return Err(anyhow!(
+                    "Failed to unload nouveau: {}",
+                    describe_module_users(&[driver::NOUVEAU_MODULE])
+                ))
+            }
+            Ok(()) => {}
+        }
+    }
+    driver::remember_unloaded(Driver::Nouveau);
+    Ok(())
+}
+
+const NVIDIA_MODULES: [&str; 4] = ["nvidia_drm", "nvidia_modeset", "nvidia_uvm", "nvidia"];
+
+fn module_loaded(module: &str) -> bool {
+    backend::sysfs().exists(&std::path::Path::new("/sys/module").join(module))
+}
+
+/// Explains what keeps `modules` loaded after a failed unload: modules
+/// holding them (from `/sys/module/*/holders`), their reference counts, and
+/// processes with the device nodes open.
+fn describe_module_users(modules: &[&str]) -> String {
+    let mut reasons = Vec::new();
+    for module in modules {
+        let dir = std::path::Path::new("/sys/module").join(module);
+        if !backend::sysfs().exists(&dir) {
+            continue;
+        }
+        let refcnt = backend::read_trimmed(dir.join("refcnt")).unwrap_or_else(|| "?".to_string());
+        let mut holders: Vec<String> = backend::sysfs()
+            .read_dir(&dir.join("holders"))
+            .map(|entries| {
+                entries
+                    .iter()
+                    .filter_map(|e| Some(e.file_name()?.to_string_lossy().into_owned()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        holders.sort();
+        if holders.is_empty() {
+            if refcnt != "0" {
+                reasons.push(format!("{} in use (refcnt {})", module, refcnt));
+            }
+        } else {
+            reasons.push(format!(
+                "{} held by {} (refcnt {})",
+                module,
+                holders.join(", "),
+                refcnt
+            ));
+        }
+    }
+
+    if let Ok(procs) = find_processes_using_nvidia(&[]) {
+        if !procs.is_empty() {
+            let procs: Vec<String> = procs
+                .iter()
+                .map(|proc| format!("PID {} {}", proc.pid, proc.name))
+                .collect();
+            reasons.push(format!("used by {}", procs.join(", ")));
+        }
+    }
+
+    if reasons.is_empty() {
+        "modprobe failed but no holder or user was found; check dmesg".to_string()
+    } else {
+        reasons.join("; ")
+    }
+}
+
+pub fn load_modules() -> Result<()> {
+    output::info("Loading kernel modules...".blue());
+    let config = MODULES.lock().unwrap().clone();
+    if driver::current(config.driver) == Driver::Nouveau {
+        return backend::modules()
+            .load(
+                driver::NOUVEAU_MODULE,
+                &config.params(driver::NOUVEAU_MODULE)?,
+            )
+            .context("Failed to load nouveau.");
+    }
+    let flavor = driver::detect().map(|info| info.flavor);
+    for module in config.load.modules() {
+        let mut params = config.params(module)?;
+        if let Some(flavor) = flavor {
+            params = driver::filter_params(flavor, module, params);
+        }
+        backend::modules()
+            .load(module, &params)
+            .context("Failed to load nvidia modules.")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,627 @@
+use crate::backend;
+use crate::eventlog;
+use crate::helper::{self, Request};
+use crate::pci::{self, PciDevice};
+use crate::protocol::ManualStep;
+use crate::system;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard, TryLockError};
+use std::time::{Duration, Instant};
+
+/// Held for the whole of a sleep or wake, so D-Bus requests and the monitor
+/// loop never interleave their modprobe and systemctl calls.
+static IN_PROGRESS: Mutex<()> = Mutex::new(());
+
+/// Returned to requests arriving while another transition runs.
+pub const BUSY: &str = "Busy: another GPU transition is in progress, try again shortly";
+
+pub type TransitionGuard = MutexGuard<'static, ()>;
+
+/// Claims the transition slot, or None if a transition is running.
+pub fn try_begin() -> Option<TransitionGuard> {
+    match IN_PROGRESS.try_lock() {
+        Ok(guard) => Some(guard),
+        // A panicking transition doesn't make the next one unsafe to start.
+        Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+pub fn in_progress() -> bool {
+    matches!(IN_PROGRESS.try_lock(), Err(TryLockError::WouldBlock))
+}
+
+/// Progress of the running sequence, so one cut short by a crash or power
+/// loss can be undone when the daemon starts again. In the state directory,
+/// /var/lib/nvsleepify unless the backends say otherwise.
+const JOURNAL_FILE: &str = "transition.json";
+
+/// Config space headers of the GPU's functions while its slot is off, with
+/// `[sleep] restore_config_space`. Also in the state directory.
+const SAVED_CONFIG_FILE: &str = "pci-config.json";
+
+/// One step of a sleep or wake sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Step {
+    StopServices,
+    UnloadModules,
+    UnbindDriver,
+    PowerOffSlot,
+    PowerOnSlots,
+    Rescan,
+    LoadModules,
+    StartServices,
+    /// Only used to undo UnbindDriver.
+    ProbeDriver,
+    /// Hands the GPU and the other functions in its slot to vfio-pci.
+    BindVfio,
+    /// Gives them back to their regular drivers, if they were on vfio-pci.
+    ReleaseVfio,
+    /// Lets runtime PM suspend the GPU, its slot functions and its bridge.
+    AllowRuntimePm,
+}
+
+/// Powers the GPU down. Processes must already be gone.
+pub const SLEEP: [Step; 4] = [
+    Step::StopServices,
+    Step::UnloadModules,
+    Step::UnbindDriver,
+    Step::PowerOffSlot,
+];
+
+/// Leaves the driver bound and lets runtime PM suspend the GPU. Processes
+/// must already be gone.
+pub const RUNTIME_SUSPEND: [Step; 2] = [Step::StopServices, Step::AllowRuntimePm];
+
+/// Takes the driver off the GPU and lets runtime PM suspend it, with its
+/// slot still powered.
+pub const UNBIND: [Step; 4] = [
+    Step::StopServices,
+    Step::UnloadModules,
+    Step::UnbindDriver,
+    Step::AllowRuntimePm,
+];
+
+/// Hands the GPU to vfio-pci. It must be on the bus and unused.
+pub const VFIO: [Step; 2] = [Step::StopServices, Step::BindVfio];
+
+/// Brings the GPU back and loads its driver.
+pub const WAKE: [Step; 5] = [
+    Step::ReleaseVfio,
+    Step::PowerOnSlots,
+    Step::Rescan,
+    Step::LoadModules,
+    Step::StartServices,
+];
+
+impl Step {
+    /// What the step does, as in "Failed to <describe>".
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Step::StopServices => "stop services",
+            Step::UnloadModules => "unload modules",
+            Step::UnbindDriver => "unbind driver",
+            Step::PowerOffSlot => "power off slot",
+            Step::PowerOnSlots => "power on slots",
+            Step::Rescan => "rescan PCI bus",
+            Step::LoadModules => "load modules",
+            Step::StartServices => "start services",
+            Step::ProbeDriver => "rebind driver",
+            Step::BindVfio => "bind vfio-pci",
+            Step::ReleaseVfio => "release vfio-pci",
+            Step::AllowRuntimePm => "allow runtime PM",
+        }
+    }
+
+    /// The step undoing this one. Powering slots on and rescanning are left
+    /// alone: a powered GPU without a driver is still better than one whose
+    /// slot state is unknown.
+    pub fn inverse(&self) -> Option<Step> {
+        match self {
+            Step::StopServices => Some(Step::StartServices),
+            Step::UnloadModules => Some(Step::LoadModules),
+            Step::UnbindDriver => Some(Step::ProbeDriver),
+            Step::PowerOffSlot => Some(Step::PowerOnSlots),
+            Step::LoadModules => Some(Step::UnloadModules),
+            Step::StartServices => Some(Step::StopServices),
+            Step::BindVfio => Some(Step::ReleaseVfio),
+            // Like powering on: the GPU on its regular driver is the safe state.
+            // Runtime PM only lets it suspend while unused.
+            Step::PowerOnSlots
+            | Step::Rescan
+            | Step::ProbeDriver
+            | Step::ReleaseVfio
+            | Step::AllowRuntimePm => None,
+        }
+    }
+
+    /// `gpu` is the device being powered down; steps that need it fail without one.
+    /// Goes through nvsleepify-helper when the daemon runs unprivileged.
+    pub fn apply(&self, gpu: Option<&PciDevice>) -> Result<()> {
+        helper::run(Request::Step {
+            step: *self,
+            address: gpu.map(|gpu| gpu.address.clone()),
+        })?;
+        Ok(())
+    }
+
+    /// Does the step in this process, which must be root.
+    pub fn perform(&self, device: Option<&PciDevice>) -> Result<()> {
+        let gpu = || device.ok_or_else(|| anyhow!("Nvidia GPU not found"));
+        match self {
+            Step::StopServices => system::stop_services(),
+            Step::UnloadModules => system::unload_modules(),
+            Step::UnbindDriver => gpu()?.unbind_driver(),
+            Step::PowerOffSlot => {
+                if system::sleep_config().restore_config_space {
+                    save_config_space(gpu()?);
+                }
+                match system::dgpu_switch() {
+                    Some(switch) => disable_dgpu(gpu()?, &switch),
+                    None => gpu()?.set_slot_power(false),
+                }
+            }
+            Step::PowerOnSlots => {
+                enable_dgpu();
+                power_on_slots();
+                Ok(())
+            }
+            Step::Rescan => {
+                // Best effort, like the slot writes: the modules step reports
+                // a missing GPU more clearly.
+                let _ = PciDevice::rescan();
+                std::thread::sleep(Duration::from_secs(1));
+                restore_config_space();
+                Ok(())
+            }
+            Step::LoadModules => system::load_modules(),
+            Step::StartServices => system::start_services(),
+            Step::ProbeDriver => gpu()?.probe_driver(),
+            Step::BindVfio => {
+                backend::modules().load(pci::VFIO_DRIVER, &[])?;
+                gpu()?
+                    .slot_functions()
+                    .iter()
+                    .try_for_each(PciDevice::bind_vfio)
+            }
+            Step::AllowRuntimePm => gpu()?.allow_runtime_pm(),
+            // Nothing to release while the GPU is off the bus.
+            Step::ReleaseVfio => device.map_or(Ok(()), |gpu| {
+                gpu.slot_functions()
+                    .iter()
+                    .try_for_each(PciDevice::release_vfio)
+            }),
+        }
+    }
+}
+
+impl From<ManualStep> for Step {
+    fn from(step: ManualStep) -> Self {
+        match step {
+            ManualStep::Unbind => Step::UnbindDriver,
+            ManualStep::Bind => Step::ProbeDriver,
+            ManualStep::PowerOff => Step::PowerOffSlot,
+            ManualStep::PowerOn => Step::PowerOnSlots,
+            ManualStep::Rescan => Step::Rescan,
+            ManualStep::UnloadModules => Step::UnloadModules,
+            ManualStep::LoadModules => Step::LoadModules,
+            ManualStep::StopServices => Step::StopServices,
+            ManualStep::StartServices => Step::StartServices,
+        }
+    }
+}
+
+/// Saves the config space headers of `gpu`'s slot functions for
+/// `restore_config_space`. Best effort: a wake without them works as before.
+fn save_config_space(gpu: &PciDevice) {
+    let headers: BTreeMap<String, Vec<u8>> = gpu
+        .slot_functions()
+        .iter()
+        .filter_map(|function| {
+            let header = function
+                .config_header()
+                .inspect_err(|e| eventlog::warn(e.to_string()))
+                .ok()?;
+            Some((function.address.to_string(), header))
+        })
+        .collect();
+    let saved = serde_json::to_string(&headers)
+        .map_err(anyhow::Error::from)
+        .and_then(|json| {
+            let path = backend::state_path(SAVED_CONFIG_FILE);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            Ok(std::fs::write(path, json)?)
+        });
+    if let Err(e) = saved {
+        eventlog::warn(format!("Failed to save the GPU's config space: {}", e));
+    }
+}
+
+/// Writes the headers `save_config_space` kept back to the functions that
+/// are on the bus again, where the rescan left them different, then forgets
+/// them.
+fn restore_config_space() {
+    let path = backend::state_path(SAVED_CONFIG_FILE);
+    let Ok(content) = std::fs::read_to_string(&path) else {
+        return;
+    };
+    let _ = std::fs::remove_file(path);
+    let Ok(headers) = serde_json::from_str::<BTreeMap<String, Vec<u8>>>(&content) else {
+        eventlog::warn("Discarded an unreadable saved config space");
+        return;
+    };
+    for (address, header) in headers {
+        let Ok(address) = address.parse() else {
+            continue;
+        };
+        let function = PciDevice::new(address);
+        if !backend::sysfs().exists(&function.path) {
+            eventlog::warn(format!(
+                "{} is not back after the rescan; its config space wasn't restored",
+                function.address
+            ));
+            continue;
+        }
+        match function.restore_config_header(&header) {
+            Ok(0) => {}
+            Ok(written) => eventlog::info(format!(
+                "Restored {} config space registers of {}",
+                written, function.address
+            )),
+            Err(e) => eventlog::warn(e.to_string()),
+        }
+    }
+}
+
+/// Takes the GPU's functions off the bus, then has the firmware cut its
+/// power. Flipping the switch with the device still there can hang the
+/// machine. If the firmware refuses, a rescan brings the functions back.
+fn disable_dgpu(gpu: &PciDevice, switch: &Path) -> Result<()> {
+    for function in gpu.slot_functions().iter().rev() {
+        function.remove()?;
+    }
+    if let Err(e) = backend::sysfs().write(switch, "1") {
+        let _ = PciDevice::rescan();
+        return Err(anyhow!("Failed to write {}: {}", switch.display(), e));
+    }
+    Ok(())
+}
+
+/// Gives the dGPU its power back if the firmware switch has it off. The
+/// rescan that follows finds it again.
+fn enable_dgpu() {
+    let Some(switch) = system::dgpu_switch() else {
+        return;
+    };
+    if backend::read_trimmed(&switch).as_deref() == Some("1") {
+        if let Err(e) = backend::sysfs().write(&switch, "0") {
+            eventlog::warn(format!("Failed to write {}: {}", switch.display(), e));
+        }
+    }
+}
+
+/// Turns on every powered-off hotplug slot. The GPU's slot can't be looked up
+/// while it is off the bus.
+fn power_on_slots() {
+    let sysfs = backend::sysfs();
+    let Ok(entries) = sysfs.read_dir(Path::new("/sys/bus/pci/slots")) else {
+        return;
+    };
+    for entry in entries {
+        let power_path = entry.join("power");
+        if backend::read_trimmed(&power_path).as_deref() == Some("0") {
+            let _ = sysfs.write(&power_path, "1");
+        }
+    }
+}
+
+/// A sequence that stopped partway, after undoing what it had done.
+#[derive(Debug)]
+pub struct TransitionError {
+    pub failed: Step,
+    pub error: anyhow::Error,
+    /// Undo steps that ran successfully, in order.
+    pub rolled_back: Vec<Step>,
+    /// Undo steps that failed too, leaving the system partly transitioned.
+    pub rollback_failures: Vec<(Step, anyhow::Error)>,
+}
+
+impl fmt::Display for TransitionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to {}: {}", self.failed.describe(), self.error)?;
+        if !self.rolled_back.is_empty() {
+            let steps: Vec<&str> = self.rolled_back.iter().map(Step::describe).collect();
+            write!(f, " (rolled back: {})", steps.join(", "))?;
+        }
+        for (step, e) in &self.rollback_failures {
+            write!(f, " (rollback failed to {}: {})", step.describe(), e)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Journal {
+    /// Steps that completed.
+    applied: Vec<Step>,
+    /// The step that was started last; it may have partly happened.
+    running: Step,
+}
+
+/// Written and synced before each step. Undo steps are safe to repeat, so a
+/// crash during rollback is handled by rolling back again.
+fn write_journal(journal: &Journal) -> Result<()> {
+    let path = backend::state_path(JOURNAL_FILE);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    let mut file = std::fs::File::create(&tmp)?;
+    file.write_all(serde_json::to_string(journal)?.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(tmp, path)?;
+    Ok(())
+}
+
+fn clear_journal() {
+    let _ = std::fs::remove_file(backend::state_path(JOURNAL_FILE));
+}
+
+/// Undoes a sequence the daemon was killed in the middle of, e.g. between
+/// unbinding the driver and powering off the slot. Run at startup before the
+/// saved mode is restored, which then redoes the transition from a known
+/// state. Returns a description of what was found, if anything.
+pub fn recover() -> Option<String> {
+    let content = std::fs::read_to_string(backend::state_path(JOURNAL_FILE)).ok()?;
+    let Ok(journal) = serde_json::from_str::<Journal>(&content) else {
+        clear_journal();
+        return Some("Discarded an unreadable transition journal".to_string());
+    };
+    let gpu = PciDevice::find_nvidia_gpu().ok();
+    let mut failures = Vec::new();
+    let undo = journal
+        .applied
+        .iter()
+        .chain(std::iter::once(&journal.running))
+        .rev()
+        .filter_map(Step::inverse);
+    for step in undo {
+        if let Err(e) = step.apply(gpu.as_ref()) {
+            failures.push(format!("failed to {}: {}", step.describe(), e));
+        }
+    }
+    clear_journal();
+
+    let mut message = format!(
+        "Rolled back a transition interrupted while trying to {}",
+        journal.running.describe()
+    );
+    if !failures.is_empty() {
+        message.push_str(&format!(" ({})", failures.join("; ")));
+    }
+    Some(message)
+}
+
+/// Runs `steps` in order. If one fails, the steps already applied are undone
+/// in reverse order.
+pub fn run(steps: &[Step], gpu: Option<&PciDevice>) -> Result<(), TransitionError> {
+    run_timed(steps, gpu).map(|_| ())
+}
+
+/// `run`, returning how long each step took.
+pub fn run_timed(
+    steps: &[Step],
+    gpu: Option<&PciDevice>,
+) -> Result<Vec<(Step, Duration)>, TransitionError> {
+    let mut timings = Vec::new();
+    for (i, step) in steps.iter().enumerate() {
+        let journal = Journal {
+            applied: steps[..i].to_vec(),
+            running: *step,
+        };
+        if let Err(e) = write_journal(&journal) {
+            eventlog::warn(format!("Failed to write transition journal: {}", e));
+        }
+        let started = Instant::now();
+        let Err(error) = step.apply(gpu) else {
+            timings.push((*step, started.elapsed()));
+            continue;
+        };
+        eventlog::warn(format!(
+            "Failed to {}: {}. Rolling back.",
+            step.describe(),
+            error
+        ));
+        let mut rolled_back = Vec::new();
+        let mut rollback_failures = Vec::new();
+        for undo in steps[..i].iter().rev().filter_map(Step::inverse) {
+            match undo.apply(gpu) {
+                Ok(()) => rolled_back.push(undo),
+                Err(e) => rollback_failures.push((undo, e)),
+            }
+        }
+        clear_journal();
+        return Err(TransitionError {
+            failed: *step,
+            error,
+            rolled_back,
+            rollback_failures,
+        });
+    }
+    clear_journal();
+    Ok(timings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{
+        Backends, MockModules, MockServices, MockSysfs, ModuleManager, ScopedBackends,
+    };
+    use crate::config::{DaemonConfig, KernelDriver};
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    const GPU: &str = "/sys/bus/pci/devices/0000:01:00.0";
+    const SLOT_POWER: &str = "/sys/bus/pci/slots/1/power";
+    const MODULES: [&str; 4] = ["nvidia", "nvidia_modeset", "nvidia_uvm", "nvidia_drm"];
+
+    struct Machine {
+        sysfs: Arc<MockSysfs>,
+        services: Arc<MockServices>,
+        modules: Arc<MockModules>,
+        gpu: PciDevice,
+        state_dir: PathBuf,
+        _scope: ScopedBackends,
+    }
+
+    impl Drop for Machine {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.state_dir);
+        }
+    }
+
+    impl Machine {
+        fn modules_loaded(&self) -> bool {
+            MODULES.iter().all(|module| self.modules.is_loaded(module))
+        }
+
+        fn ran(&self, action: &str, service: &str) -> bool {
+            self.services
+                .calls
+                .lock()
+                .unwrap()
+                .contains(&(action.to_string(), service.to_string()))
+        }
+    }
+
+    /// A laptop with the GPU awake on the nvidia driver, in a hotplug slot
+    /// whose power can be switched.
+    fn machine() -> Machine {
+        // The settings are global, so every test applies the same ones.
+        // Managing persistence mode would run nvidia-smi.
+        let mut config = DaemonConfig::default();
+        config.modules.driver = KernelDriver::Nvidia;
+        config.persistence.manage = false;
+        system::apply_config(&config);
+
+        let sysfs = Arc::new(MockSysfs::default());
+        for (file, value) in [
+            ("vendor", "0x10de"),
+            ("class", "0x030000"),
+            ("slot", "1"),
+            ("driver/unbind", ""),
+            ("power/control", "on"),
+        ] {
+            sysfs.set(Path::new(GPU).join(file), value);
+        }
+        sysfs.set(SLOT_POWER, "1");
+        sysfs.set("/sys/bus/pci/slots/1/address", "0000:01:00");
+        sysfs.set("/sys/bus/pci/rescan", "");
+        sysfs.set("/sys/bus/pci/drivers_probe", "");
+        let modules = Arc::new(MockModules::new(sysfs.clone()));
+        for module in MODULES {
+            modules.load(module, &[]).unwrap();
+        }
+        let services = Arc::new(MockServices::default());
+        // Each test keeps its journal apart from the others and the machine's.
+        static MACHINES: AtomicUsize = AtomicUsize::new(0);
+        let state_dir = std::env::temp_dir().join(format!(
+            "nvsleepify-test-{}-{}",
+            std::process::id(),
+            MACHINES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let scope = backend::scoped(Backends {
+            sysfs: sysfs.clone(),
+            services: services.clone(),
+            modules: modules.clone(),
+            state_dir: state_dir.clone(),
+        });
+        let gpu = PciDevice::find_nvidia_gpu().unwrap();
+        Machine {
+            sysfs,
+            services,
+            modules,
+            gpu,
+            state_dir,
+            _scope: scope,
+        }
+    }
+
+    #[test]
+    fn sleep_powers_off_the_slot() {
+        let machine = machine();
+        let timings = run_timed(&SLEEP, Some(&machine.gpu)).unwrap();
+
+        let steps: Vec<Step> = timings.iter().map(|(step, _)| *step).collect();
+        assert_eq!(steps, SLEEP);
+        assert!(machine.ran("stop", "nvidia-persistenced"));
+        assert!(MODULES
+            .iter()
+            .all(|module| !machine.modules.is_loaded(module)));
+        assert!(machine.sysfs.writes().contains(&(
+            Path::new(GPU).join("driver/unbind"),
+            "0000:01:00.0".to_string()
+        )));
+        assert_eq!(machine.sysfs.get(SLOT_POWER).as_deref(), Some("0"));
+        assert!(!backend::state_path(JOURNAL_FILE).exists());
+    }
+
+    #[test]
+    fn wake_undoes_sleep() {
+        let machine = machine();
+        run(&SLEEP, Some(&machine.gpu)).unwrap();
+        run(&WAKE, Some(&machine.gpu)).unwrap();
+
+        assert_eq!(machine.sysfs.get(SLOT_POWER).as_deref(), Some("1"));
+        assert_eq!(
+            machine.sysfs.get("/sys/bus/pci/rescan").as_deref(),
+            Some("1")
+        );
+        assert!(machine.modules_loaded());
+        assert!(machine.ran("start", "nvidia-persistenced"));
+    }
+
+    #[test]
+    fn failed_power_off_rolls_back() {
+        let machine = machine();
+        // Without acpiphp there's no slot to switch.
+        machine.sysfs.remove("/sys/bus/pci/slots");
+        let error = run(&SLEEP, Some(&machine.gpu)).unwrap_err();
+
+        assert_eq!(error.failed, Step::PowerOffSlot);
+        assert_eq!(
+            error.rolled_back,
+            [Step::ProbeDriver, Step::LoadModules, Step::StartServices]
+        );
+        assert!(error.rollback_failures.is_empty());
+        assert!(machine.modules_loaded());
+        assert!(machine.ran("start", "nvidia-persistenced"));
+        assert!(!backend::state_path(JOURNAL_FILE).exists());
+    }
+
+    #[test]
+    fn recover_undoes_an_interrupted_sleep() {
+        let machine = machine();
+        machine.modules.unload(&MODULES).unwrap();
+        write_journal(&Journal {
+            applied: vec![Step::StopServices, Step::UnloadModules],
+            running: Step::UnbindDriver,
+        })
+        .unwrap();
+
+        let message = recover().unwrap();
+        assert!(message.contains("unbind driver"), "{}", message);
+        assert!(machine.modules_loaded());
+        assert!(machine.ran("start", "nvidia-persistenced"));
+        assert!(recover().is_none());
+    }
+}
@@ -0,0 +1,136 @@
+use crate::backend;
+use crate::pci::PciDevice;
+use crate::protocol::PowerState;
+use crate::system;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+/// How long the GPU gets to leave the bus (or reach D3cold) after its slot
+/// was powered off.
+const SETTLE: Duration = Duration::from_secs(3);
+/// Kernel log lines quoted as evidence.
+const KERNEL_LOG_LINES: usize = 5;
+
+fn read_attr(dir: &Path, attr: &str) -> Option<String> {
+    backend::read_trimmed(dir.join(attr))
+}
+
+/// Waits up to `timeout` for `gpu` to reach D3cold or leave the bus, for
+/// the sleep stages that leave its slot powered.
+pub fn reaches_d3cold(gpu: &PciDevice, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if gpu.get_power_state().is_asleep() {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(250));
+    }
+}
+
+/// Checks that a sleep that reported success really left the GPU powered
+/// down. If it didn't, returns why it might still be up.
+pub fn after_sleep() -> Result<(), String> {
+    let deadline = Instant::now() + SETTLE;
+    let (gpu, state) = loop {
+        let Ok(gpu) = PciDevice::find_nvidia_gpu() else {
+            return Ok(());
+        };
+        let state = gpu.get_power_state();
+        if state == PowerState::D3cold {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            break (gpu, state);
+        }
+        std::thread::sleep(Duration::from_millis(250));
+    };
+
+    let mut evidence = vec![format!(
+        "GPU {} is still on the bus in {}",
+        gpu.address, state
+    )];
+    evidence.extend(collect_evidence(&gpu));
+    Err(format!(
+        "Sleep sequence completed but the GPU didn't power down: {}",
+        evidence.join("; ")
+    ))
+}
+
+fn collect_evidence(gpu: &PciDevice) -> Vec<String> {
+    let mut evidence = Vec::new();
+
+    match backend::sysfs().read_link(&gpu.path.join("driver")) {
+        Ok(driver) => evidence.push(format!(
+            "bound to {}",
+            driver.file_name().unwrap_or_default().to_string_lossy()
+        )),
+        Err(_) => evidence.push("no driver bound".to_string()),
+    }
+
+    let power = gpu.path.join("power");
+    if let Some(status) = read_attr(&power, "runtime_status") {
+        let control = read_attr(&power, "control").unwrap_or_else(|| "?".into());
+        evidence.push(format!("runtime_status {} (control {})", status, control));
+    }
+
+    // A bridge kept active (e.g. runtime PM disabled on it) can't cut power
+    // to the slot below it.
+    if let Some(bridge) = backend::sysfs()
+        .canonicalize(&gpu.path)
+        .ok()
+        .and_then(|path| path.parent().map(Path::to_path_buf))
+        .filter(|parent| backend::sysfs().exists(&parent.join("power/runtime_status")))
+    {
+        let power = bridge.join("power");
+        evidence.push(format!(
+            "bridge {} runtime_status {} (control {})",
+            bridge.file_name().unwrap_or_default().to_string_lossy(),
+            read_attr(&power, "runtime_status").unwrap_or_else(|| "?".into()),
+            read_attr(&power, "control").unwrap_or_else(|| "?".into())
+        ));
+    }
+
+    if let Some(slot) = gpu.get_slot_path() {
+        if let Some(power) = read_attr(&slot, "power") {
+            evidence.push(format!("slot power {}", power));
+        }
+    }
+
+    let holders = system::get_processes_using_nvidia(&gpu.get_device_nodes()).unwrap_or_default();
+    if !holders.is_empty() {
+        let holders: Vec<String> = holders.iter().map(ToString::to_string).collect();
+        evidence.push(format!("opened by {}", holders.join(", ")));
+    }
+
+    let log = kernel_log(gpu.address.as_str());
+    if !log.is_empty() {
+        evidence.push(format!("kernel log: {}", log.join(" | ")));
+    }
+    evidence
+}
+
+/// Recent kernel messages about the GPU or PCI hotplug, which show re-adds
+/// and failed power-offs.
+fn kernel_log(address: &str) -> Vec<String> {
+    let Ok(output) = system::output_with_timeout(Command::new("journalctl").args([
+        "-k",
+        "--since=-1min",
+        "--no-pager",
+        "-o",
+        "cat",
+    ])) else {
+        return Vec::new();
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines: Vec<String> = stdout
+        .lines()
+        .filter(|line| line.contains(address) || line.contains("pciehp") || line.contains("NVRM"))
+        .map(str::to_string)
+        .collect();
+    lines.drain(..lines.len().saturating_sub(KERNEL_LOG_LINES));
+    lines
+}
@@ -0,0 +1,80 @@
+use crate::config::DaemonConfig;
+use crate::helper::{self, Request};
+use crate::protocol::Mode;
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// Read by Xorg at startup; only present in Standard mode.
+pub const XORG_SNIPPET: &str = "/etc/X11/xorg.conf.d/10-nvsleepify.conf";
+
+const HEADER: &str = "# Generated by nvsleepify for Standard mode and removed in the other modes.\n# Set generate = false under [xorg] in /etc/nvsleepify/nvsleepifyd.toml to manage it yourself.\n";
+
+/// Lets the Nvidia driver drive outputs wired to the dGPU (reverse PRIME)
+/// while the iGPU renders the desktop.
+const OFFLOAD: &str = r#"Section "OutputClass"
+    Identifier "nvsleepify-nvidia"
+    MatchDriver "nvidia-drm"
+    Driver "nvidia"
+    Option "AllowEmptyInitialConfiguration"
+EndSection
+
+Section "ServerLayout"
+    Identifier "nvsleepify-layout"
+    Option "AllowNVIDIAGPUScreens"
+EndSection
+"#;
+
+/// Renders the desktop on the dGPU, with PRIME output to the iGPU's panel.
+const DGPU_PRIMARY: &str = r#"Section "OutputClass"
+    Identifier "nvsleepify-nvidia"
+    MatchDriver "nvidia-drm"
+    Driver "nvidia"
+    Option "AllowEmptyInitialConfiguration"
+    Option "PrimaryGPU" "yes"
+EndSection
+"#;
+
+fn snippet(dgpu_primary: bool) -> String {
+    let body = if dgpu_primary { DGPU_PRIMARY } else { OFFLOAD };
+    format!("{}\n{}", HEADER, body)
+}
+
+/// Writes or removes the Xorg snippet to match `mode`. The snippet pins the
+/// dGPU whenever it is present at X startup, so it only exists in Standard.
+/// Returns whether the file changed.
+pub fn sync(mode: Mode) -> Result<bool> {
+    let config = DaemonConfig::load()?;
+    // Headless servers have no X server to configure.
+    if !config.xorg.generate || config.server.enabled {
+        return Ok(false);
+    }
+    let config = config.xorg;
+    let current = std::fs::read_to_string(XORG_SNIPPET).ok();
+    let dgpu_primary = (mode == Mode::Standard).then_some(config.dgpu_primary);
+    if current == dgpu_primary.map(snippet) {
+        return Ok(false);
+    }
+    // /etc/X11 is root's, so this goes through nvsleepify-helper when unprivileged.
+    helper::run(Request::XorgSnippet { dgpu_primary })?;
+    Ok(true)
+}
+
+/// Writes the snippet for `dgpu_primary`, or removes it for `None`.
+pub fn write_snippet(dgpu_primary: Option<bool>) -> Result<()> {
+    let path = Path::new(XORG_SNIPPET);
+    match dgpu_primary {
+        Some(dgpu_primary) => {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(path, snippet(dgpu_primary))
+                .with_context(|| format!("Failed to write {}", path.display()))
+        }
+        None => match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Failed to remove {}", path.display()))
+            }
+            _ => Ok(()),
+        },
+    }
+}
@@ -0,0 +1,146 @@
+use crate::error::Error;
+use std::ffi::{CStr, CString};
+use std::sync::OnceLock;
+use tokio::task::spawn_blocking;
+use zbus::fdo::DBusProxy;
+use zbus::names::BusName;
+use zbus::{Connection, MessageHeader};
+
+/// Buffer for getgrnam_r/getpwuid_r. Large groups can need more than
+/// sysconf's suggestion, so start generously.
+const NSS_BUFFER_SIZE: usize = 64 * 1024;
+
+static GROUP: OnceLock<Option<String>> = OnceLock::new();
+
+/// Limits the methods that change state to root and members of `group`.
+/// Without a group, anyone the bus policy lets through may call them.
+pub fn set_group(group: Option<String>) {
+    let _ = GROUP.set(group.filter(|g| !g.is_empty()));
+}
+
+/// The UID of a D-Bus method's caller, as the bus reports it.
+pub async fn caller_uid(conn: &Connection, header: &MessageHeader<'_>) -> Option<u32> {
+    let sender = header.sender().ok().flatten()?;
+    DBusProxy::new(conn)
+        .await
+        .ok()?
+        .get_connection_unix_user(BusName::Unique(sender.to_owned()))
+        .await
+        .ok()
+}
+
+/// Checks the caller of a state-changing method against the configured group.
+pub async fn authorize(conn: &Connection, header: &MessageHeader<'_>) -> Result<(), Error> {
+    if GROUP.get().cloned().flatten().is_none() {
+        return Ok(());
+    }
+    let sender = header
+        .sender()
+        .ok()
+        .flatten()
+        .ok_or_else(|| Error::PermissionDenied("Can't identify the caller".to_string()))?;
+    let credentials = DBusProxy::new(conn)
+        .await?
+        .get_connection_credentials(BusName::Unique(sender.to_owned()))
+        .await
+        .map_err(|e| Error::PermissionDenied(format!("Can't identify the caller: {}", e)))?;
+    let uid = credentials
+        .unix_user_id()
+        .ok_or_else(|| Error::PermissionDenied("The bus didn't report the caller's UID".into()))?;
+    authorize_uid(uid, credentials.unix_group_ids().cloned()).await
+}
+
+/// The same check for a caller known by UID, e.g. a socket peer. Without
+/// `gids` the user's groups are looked up.
+pub async fn authorize_uid(uid: u32, gids: Option<Vec<u32>>) -> Result<(), Error> {
+    let Some(group) = GROUP.get().cloned().flatten() else {
+        return Ok(());
+    };
+    let allowed = spawn_blocking({
+        let group = group.clone();
+        move || is_allowed(uid, gids.as_deref(), &group)
+    })
+    .await
+    .map_err(|e| Error::Failed(format!("Internal error: {}", e)))??;
+    if allowed {
+        Ok(())
+    } else {
+        Err(Error::PermissionDenied(format!(
+            "Only root and members of the '{}' group may do this",
+            group
+        )))
+    }
+}
+
+/// Root always passes. Otherwise the bus's group list is used when it sends
+/// one (dbus-broker, dbus-daemon 1.14+); older buses only report the UID, so
+/// the user's primary group and the group's member list are checked instead.
+fn is_allowed(uid: u32, gids: Option<&[u32]>, group: &str) -> Result<bool, Error> {
+    if uid == 0 {
+        return Ok(true);
+    }
+    let Some((gid, members)) = lookup_group(group) else {
+        return Err(Error::PermissionDenied(format!(
+            "Access is limited to the '{}' group, which doesn't exist",
+            group
+        )));
+    };
+    if let Some(gids) = gids {
+        return Ok(gids.contains(&gid));
+    }
+    Ok(match lookup_user(uid) {
+        Some((name, primary_gid)) => primary_gid == gid || members.contains(&name),
+        None => false,
+    })
+}
+
+/// The group's GID and listed members.
+pub(crate) fn lookup_group(name: &str) -> Option<(u32, Vec<String>)> {
+    let name = CString::new(name).ok()?;
+    let mut buf = vec![0 as libc::c_char; NSS_BUFFER_SIZE];
+    // SAFETY: all-zero is a valid `group`; getgrnam_r fills it in.
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    // SAFETY: every pointer is valid and `buf.len()` is the buffer's size.
+    let rc = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut group,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if rc != 0 || result.is_null() {
+        return None;
+    }
+    let mut members = Vec::new();
+    let mut member = group.gr_mem;
+    // SAFETY: gr_mem is a NULL-terminated array of C strings inside `buf`.
+    unsafe {
+        while !member.is_null() && !(*member).is_null() {
+            members.push(CStr::from_ptr(*member).to_string_lossy().into_owned());
+            member = member.add(1);
+        }
+    }
+    Some((group.gr_gid, members))
+}
+
+/// The user's name and primary GID.
+fn lookup_user(uid: u32) -> Option<(String, u32)> {
+    let mut buf = vec![0 as libc::c_char; NSS_BUFFER_SIZE];
+    // SAFETY: all-zero is a valid `passwd`; getpwuid_r fills it in.
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    // SAFETY: every pointer is valid and `buf.len()` is the buffer's size.
+    let rc =
+        unsafe { libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if rc != 0 || result.is_null() {
+        return None;
+    }
+    // SAFETY: pw_name points to a C string inside `buf`.
+    let name = unsafe { CStr::from_ptr(passwd.pw_name) }
+        .to_string_lossy()
+        .into_owned();
+    Some((name, passwd.pw_gid))
+}
@@ -0,0 +1,206 @@
+use crate::access;
+use crate::daemon::{self, SharedState};
+use crate::error::Error;
+use crate::protocol::{Mode, PowerState, ProcessList};
+use crate::system;
+use tokio::task::spawn_blocking;
+use zbus::{dbus_interface, Connection, MessageHeader, SignalContext};
+
+/// Object path of the stable applet interface. Kept apart from the Manager
+/// object so its methods can change without affecting widgets.
+pub const APPLET_PATH: &str = "/org/nvsleepify/Applet";
+/// Bumped only for incompatible changes, alongside the interface name.
+pub const APPLET_VERSION: u32 = 1;
+/// How often the published properties are refreshed. Blocking processes have
+/// no change notification, so this bounds how stale `Blockers` can be.
+const REFRESH_SECS: u64 = 5;
+
+/// Everything the applet properties are derived from.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AppletSnapshot {
+    pub mode: Mode,
+    pub power_state: PowerState,
+    pub blockers: ProcessList,
+    pub on_battery: bool,
+}
+
+impl AppletSnapshot {
+    fn sample() -> Self {
+        let report = daemon::info_logic();
+        Self {
+            mode: report.mode(),
+            power_state: report.power_state,
+            blockers: report.processes,
+            on_battery: !system::get_charging_status(),
+        }
+    }
+
+    /// One of the icons installed by nvsleepify, matching the tray applet.
+    pub fn icon_name(&self) -> &'static str {
+        if !self.blockers.is_empty() {
+            "nvsleepify-gpu-active"
+        } else if self.power_state == PowerState::D3cold {
+            "nvsleepify-gpu-suspended"
+        } else if self.power_state == PowerState::NotFound || self.mode == Mode::Integrated {
+            "nvsleepify-gpu-off"
+        } else {
+            "nvsleepify-gpu-active"
+        }
+    }
+
+    /// "kill-blockers": the mode wants the GPU asleep but processes hold it awake.
+    /// "switch-mode": on battery with the GPU idle but kept awake by Standard mode.
+    /// "none": nothing to suggest.
+    pub fn recommended_action(&self) -> &'static str {
+        let asleep = self.power_state.is_asleep();
+        if self.mode != Mode::Standard && !asleep && !self.blockers.is_empty() {
+            "kill-blockers"
+        } else if self.mode == Mode::Standard
+            && self.on_battery
+            && self.power_state == PowerState::D0
+            && self.blockers.is_empty()
+        {
+            "switch-mode"
+        } else {
+            "none"
+        }
+    }
+}
+
+/// Stable, versioned D-Bus surface for desktop widgets (Plasma applets, bars).
+/// Everything widgets need is exposed as properties, so they can bind to
+/// org.freedesktop.DBus.Properties.PropertiesChanged instead of polling.
+pub(crate) struct NvSleepifyApplet {
+    state: SharedState,
+    snapshot: AppletSnapshot,
+}
+
+impl NvSleepifyApplet {
+    pub(crate) fn new(state: SharedState) -> Self {
+        Self {
+            state,
+            snapshot: AppletSnapshot::default(),
+        }
+    }
+}
+
+#[dbus_interface(name = "org.nvsleepify.Applet1")]
+impl NvSleepifyApplet {
+    #[dbus_interface(property)]
+    async fn version(&self) -> u32 {
+        APPLET_VERSION
+    }
+
+    /// "Standard", "Integrated" or "Optimized".
+    #[dbus_interface(property)]
+    async fn mode(&self) -> String {
+        self.snapshot.mode.to_string()
+    }
+
+    /// PCI power state ("D0", "D3cold", ...) or "NotFound" when the GPU is off the bus.
+    #[dbus_interface(property)]
+    async fn power_state(&self) -> String {
+        self.snapshot.power_state.to_string()
+    }
+
+    #[dbus_interface(property)]
+    async fn asleep(&self) -> bool {
+        self.snapshot.power_state.is_asleep()
+    }
+
+    /// Processes keeping the GPU awake: [(name, pid)]
+    #[dbus_interface(property)]
+    async fn blockers(&self) -> ProcessList {
+        self.snapshot.blockers.clone()
+    }
+
+    #[dbus_interface(property)]
+    async fn icon_name(&self) -> String {
+        self.snapshot.icon_name().to_string()
+    }
+
+    /// "none", "kill-blockers" or "switch-mode". New values may be added
+    /// within version 1; widgets should treat unknown ones as "none".
+    #[dbus_interface(property)]
+    async fn recommended_action(&self) -> String {
+        self.snapshot.recommended_action().to_string()
+    }
+
+    /// Returns: (success, message)
+    async fn set_mode(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: MessageHeader<'_>,
+        mode: String,
+    ) -> zbus::fdo::Result<(bool, String)> {
+        if let Err(e) = access::authorize(conn, &header).await {
+            return Ok((false, e.to_string()));
+        }
+        let ctxt = SignalContext::new(conn, daemon::OBJECT_PATH)?;
+        let requester = access::caller_uid(conn, &header).await;
+        let result =
+            daemon::request_mode(&self.state, Some(&ctxt), requester, mode, false, false).await;
+        let (success, msg, _) = Error::reply(result.map(|r| r.message));
+        Ok((success, msg))
+    }
+
+    /// Kills every process in `Blockers`.
+    /// Returns: (success, message)
+    async fn kill_blockers(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: MessageHeader<'_>,
+    ) -> (bool, String) {
+        if let Err(e) = access::authorize(conn, &header).await {
+            return (false, e.to_string());
+        }
+        let (success, msg, _) = Error::reply(daemon::request_kill(vec![]).await);
+        (success, msg)
+    }
+}
+
+/// Samples the daemon state and pushes property changes to subscribers.
+pub(crate) async fn publish(conn: &Connection) {
+    let Ok(snapshot) = spawn_blocking(AppletSnapshot::sample).await else {
+        return;
+    };
+    let Ok(iface_ref) = conn
+        .object_server()
+        .interface::<_, NvSleepifyApplet>(APPLET_PATH)
+        .await
+    else {
+        return;
+    };
+    let mut iface = iface_ref.get_mut().await;
+    if iface.snapshot == snapshot {
+        return;
+    }
+    let old = std::mem::replace(&mut iface.snapshot, snapshot);
+    let new = &iface.snapshot;
+    let ctxt = iface_ref.signal_context();
+
+    if old.mode != new.mode {
+        let _ = iface.mode_changed(ctxt).await;
+    }
+    if old.power_state != new.power_state {
+        let _ = iface.power_state_changed(ctxt).await;
+        let _ = iface.asleep_changed(ctxt).await;
+    }
+    if old.blockers != new.blockers {
+        let _ = iface.blockers_changed(ctxt).await;
+    }
+    if old.icon_name() != new.icon_name() {
+        let _ = iface.icon_name_changed(ctxt).await;
+    }
+    if old.recommended_action() != new.recommended_action() {
+        let _ = iface.recommended_action_changed(ctxt).await;
+    }
+}
+
+pub(crate) async fn publish_loop(conn: Connection) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(REFRESH_SECS));
+    loop {
+        interval.tick().await;
+        publish(&conn).await;
+    }
+}
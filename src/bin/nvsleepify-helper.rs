@@ -0,0 +1,10 @@
+use anyhow::Result;
+use nvsleepify::helper;
+
+fn main() -> Result<()> {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("nvsleepify-helper must run as root.");
+        std::process::exit(1);
+    }
+    helper::serve()
+}
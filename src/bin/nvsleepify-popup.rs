@@ -0,0 +1,336 @@
+use anyhow::{anyhow, Result};
+use eframe::egui;
+use futures_util::StreamExt;
+use nvsleepify::client::NvSleepifyManagerProxy;
+use nvsleepify::error::Error;
+use nvsleepify::eventlog::unix_now;
+use nvsleepify::i18n;
+use nvsleepify::protocol::{Mode, PowerState, ProcessInfo, ProcessList};
+use nvsleepify::tr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use zbus::Connection;
+
+/// Blocking processes don't produce signals, so they are polled.
+const POLL_SECS: u64 = 5;
+
+/// Entries of the mode selector, with the message ID of their label.
+const MODES: [(Mode, &str); 4] = [
+    (Mode::Standard, "tray-mode-standard"),
+    (Mode::Integrated, "tray-mode-integrated"),
+    (Mode::Optimized, "tray-mode-optimized"),
+    (Mode::Vfio, "tray-mode-vfio"),
+];
+
+/// Keep-awake buttons, as message ID and seconds.
+const WAKE_DURATIONS: [(&str, u32); 3] = [
+    ("tray-keep-awake-30m", 30 * 60),
+    ("tray-keep-awake-1h", 60 * 60),
+    ("tray-keep-awake-2h", 2 * 60 * 60),
+];
+
+#[derive(Debug, Clone, Default)]
+struct State {
+    mode: Mode,
+    power_state: PowerState,
+    processes: ProcessList,
+    /// Unix time at which a temporary wake ends.
+    wake_until: Option<u64>,
+    last_error: Option<String>,
+}
+
+/// What the buttons ask of the daemon, carried out on the tokio runtime.
+enum Action {
+    SetMode(Mode),
+    Kill(ProcessInfo),
+    WakeFor(u32),
+    CancelWake,
+}
+
+async fn fetch(proxy: &NvSleepifyManagerProxy<'_>) -> State {
+    match proxy.info().await {
+        Ok(report) => {
+            let wake_until = match proxy.wake_remaining().await {
+                Ok(remaining) if remaining > 0 => Some(unix_now() + remaining as u64),
+                _ => None,
+            };
+            State {
+                mode: report.mode(),
+                power_state: report.power_state,
+                processes: report.processes,
+                wake_until,
+                last_error: None,
+            }
+        }
+        Err(e) => State {
+            last_error: Some(format!(
+                "Failed to query daemon: {} (is nvsleepifyd.service running?)",
+                Error::from(e)
+            )),
+            ..Default::default()
+        },
+    }
+}
+
+/// Carries out `action`. A killed blocker is followed by the current mode
+/// again, so the GPU can go to sleep, as from the tray's menu.
+async fn perform(proxy: &NvSleepifyManagerProxy<'_>, action: Action) -> Result<(), String> {
+    match action {
+        Action::SetMode(mode) => proxy
+            .set_mode_with_options(mode.to_string(), mode.kills_processes())
+            .await
+            .map(|_| ())
+            .map_err(|e| tr!("tray-error-set-mode", error = Error::from(e).to_string())),
+        Action::Kill(process) => {
+            let Ok(pid) = process.pid.parse() else {
+                return Err(tr!("tray-error-invalid-pid", pid = process.pid));
+            };
+            if let Err(e) = proxy.kill_process(pid, process.name).await {
+                return Err(tr!("tray-error-kill", error = Error::from(e).to_string()));
+            }
+            let current = fetch(proxy).await;
+            if current.last_error.is_none() && current.mode != Mode::Standard {
+                if let Err(e) = proxy.set_mode(current.mode.to_string()).await {
+                    return Err(tr!("tray-error-sleep", error = Error::from(e).to_string()));
+                }
+            }
+            Ok(())
+        }
+        Action::WakeFor(_) | Action::CancelWake => {
+            let result = match action {
+                Action::WakeFor(seconds) => proxy.wake_for(seconds).await,
+                _ => proxy.cancel_wake().await,
+            };
+            result
+                .map(|_| ())
+                .map_err(|e| tr!("tray-error-keep-awake", error = Error::from(e).to_string()))
+        }
+    }
+}
+
+/// Keeps `state` current, refreshing on StateChanged, every `POLL_SECS` and
+/// after each action, until the window closes.
+async fn serve(
+    proxy: NvSleepifyManagerProxy<'static>,
+    state: Arc<Mutex<State>>,
+    ctx: egui::Context,
+    mut actions: mpsc::UnboundedReceiver<Action>,
+) {
+    let mut signals = proxy.receive_state_changed().await.ok();
+    let mut interval = tokio::time::interval(Duration::from_secs(POLL_SECS));
+    let mut error = None;
+    loop {
+        let mut fresh = fetch(&proxy).await;
+        fresh.last_error = fresh.last_error.or(error.take());
+        *state.lock().unwrap() = fresh;
+        ctx.request_repaint();
+
+        let mut stream_ended = false;
+        let action = match signals.as_mut() {
+            Some(stream) => {
+                tokio::select! {
+                    action = actions.recv() => Some(action),
+                    signal = stream.next() => {
+                        stream_ended = signal.is_none();
+                        None
+                    }
+                    _ = interval.tick() => None,
+                }
+            }
+            None => {
+                tokio::select! {
+                    action = actions.recv() => Some(action),
+                    _ = interval.tick() => None,
+                }
+            }
+        };
+        if stream_ended {
+            signals = None;
+        }
+        match action {
+            Some(Some(action)) => error = perform(&proxy, action).await.err(),
+            // The window is gone.
+            Some(None) => return,
+            None => {}
+        }
+    }
+}
+
+struct Popup {
+    state: Arc<Mutex<State>>,
+    actions: mpsc::UnboundedSender<Action>,
+    /// A mode that kills the blockers, waiting for the user to confirm.
+    confirm: Option<Mode>,
+    /// Losing focus closes the popup, once it has had it.
+    was_focused: bool,
+}
+
+impl Popup {
+    fn send(&self, action: Action) {
+        let _ = self.actions.send(action);
+    }
+
+    fn mode_selector(&mut self, ui: &mut egui::Ui, state: &State) {
+        for (mode, label) in MODES {
+            let selected = state.mode == mode || self.confirm == Some(mode);
+            if !ui.radio(selected, i18n::tr(label, &[])).clicked() || selected {
+                continue;
+            }
+            if mode.kills_processes() && !state.processes.is_empty() {
+                self.confirm = Some(mode);
+            } else {
+                self.confirm = None;
+                self.send(Action::SetMode(mode));
+            }
+        }
+
+        let Some(mode) = self.confirm else {
+            return;
+        };
+        ui.add_space(4.0);
+        ui.group(|ui| {
+            ui.label(tr!("popup-confirm-kill"));
+            for proc in &state.processes {
+                ui.label(format!("• {}", proc));
+            }
+            ui.horizontal(|ui| {
+                if ui.button(tr!("popup-kill-and-switch")).clicked() {
+                    self.confirm = None;
+                    self.send(Action::SetMode(mode));
+                }
+                if ui.button(tr!("popup-cancel")).clicked() {
+                    self.confirm = None;
+                }
+            });
+        });
+    }
+
+    fn keep_awake(&self, ui: &mut egui::Ui, state: &State) {
+        ui.label(egui::RichText::new(tr!("tray-keep-awake")).strong());
+        ui.horizontal_wrapped(|ui| {
+            for (label, seconds) in WAKE_DURATIONS {
+                if ui.button(i18n::tr(label, &[])).clicked() {
+                    self.send(Action::WakeFor(seconds));
+                }
+            }
+            if state.wake_until.is_some()
+                && ui
+                    .button(tr!("tray-cancel-wake", mode = state.mode.to_string()))
+                    .clicked()
+            {
+                self.send(Action::CancelWake);
+            }
+        });
+    }
+
+    fn blockers(&self, ui: &mut egui::Ui, state: &State) {
+        ui.label(egui::RichText::new(tr!("tray-blockers", count = state.processes.len())).strong());
+        let kill = if state.mode == Mode::Standard {
+            tr!("tray-kill")
+        } else {
+            tr!("tray-kill-retry")
+        };
+        for proc in &state.processes {
+            ui.horizontal(|ui| {
+                if ui.small_button(&kill).clicked() {
+                    self.send(Action::Kill(proc.clone()));
+                }
+                ui.label(proc.to_string());
+            });
+        }
+    }
+}
+
+impl eframe::App for Popup {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        let focused = ctx.input(|i| i.viewport().focused);
+        let lost_focus = focused == Some(false) && self.was_focused;
+        self.was_focused |= focused == Some(true);
+        if lost_focus || ctx.input(|i| i.key_pressed(egui::Key::Escape)) {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+        }
+
+        let state = self.state.lock().unwrap().clone();
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("nvsleepify");
+            if !matches!(
+                state.power_state,
+                PowerState::Unknown | PowerState::NotFound
+            ) {
+                ui.label(tr!(
+                    "tray-tooltip-power",
+                    state = state.power_state.to_string()
+                ));
+            }
+            if let Some(until) = state.wake_until {
+                let minutes = until.saturating_sub(unix_now()).div_ceil(60);
+                ui.label(tr!(
+                    "tray-tooltip-kept-awake",
+                    hours = minutes / 60,
+                    minutes = format!("{:02}", minutes % 60)
+                ));
+            }
+            ui.separator();
+            self.mode_selector(ui, &state);
+            // Standard already keeps the GPU awake.
+            if state.mode != Mode::Standard {
+                ui.separator();
+                self.keep_awake(ui, &state);
+            }
+            if !state.processes.is_empty() {
+                ui.separator();
+                self.blockers(ui, &state);
+            }
+            if let Some(err) = &state.last_error {
+                ui.separator();
+                ui.colored_label(ui.visuals().error_fg_color, err);
+            }
+        });
+        // Counts the keep-awake time down between refreshes.
+        if state.wake_until.is_some() {
+            ctx.request_repaint_after(Duration::from_secs(30));
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    // zbus runs on tokio; eframe owns the main thread.
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()?;
+    let proxy = runtime
+        .block_on(async {
+            let connection = Connection::system().await?;
+            NvSleepifyManagerProxy::new(&connection).await
+        })
+        .map_err(|e| anyhow!("Failed to connect to system bus: {}", e))?;
+
+    let state = Arc::new(Mutex::new(State::default()));
+    let (tx, rx) = mpsc::unbounded_channel();
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default()
+            .with_title("nvsleepify")
+            .with_app_id("nvsleepify-popup")
+            .with_inner_size([320.0, 400.0])
+            .with_resizable(false)
+            .with_always_on_top(),
+        ..Default::default()
+    };
+    let handle = runtime.handle().clone();
+    eframe::run_native(
+        "nvsleepify",
+        options,
+        Box::new(move |cc| {
+            handle.spawn(serve(proxy, state.clone(), cc.egui_ctx.clone(), rx));
+            Box::new(Popup {
+                state,
+                actions: tx,
+                confirm: None,
+                was_focused: false,
+            })
+        }),
+    )
+    .map_err(|e| anyhow!("Failed to open the popup: {}", e))
+}
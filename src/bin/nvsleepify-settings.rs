@@ -0,0 +1,315 @@
+use adw::prelude::*;
+use anyhow::{anyhow, Result};
+use futures_util::StreamExt;
+use gtk4 as gtk;
+use libadwaita as adw;
+use nvsleepify::client::NvSleepifyManagerProxy;
+use nvsleepify::error::Error;
+use nvsleepify::protocol::{Mode, PowerState, ProcessList, StatusReport};
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::rc::Rc;
+use std::sync::OnceLock;
+use zbus::Connection;
+
+/// Order of the entries in the mode combo row.
+const MODES: [Mode; 4] = [
+    Mode::Standard,
+    Mode::Integrated,
+    Mode::Optimized,
+    Mode::Vfio,
+];
+/// Blocking processes don't produce signals, so they are polled.
+const POLL_SECS: u32 = 5;
+
+/// zbus runs on tokio; GTK owns the main thread. D-Bus calls are spawned here
+/// and their results awaited from the GLib main loop.
+fn runtime() -> &'static tokio::runtime::Runtime {
+    static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .expect("Failed to start tokio runtime")
+    })
+}
+
+async fn on_runtime<T, F>(future: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: Future<Output = zbus::Result<T>> + Send + 'static,
+{
+    runtime()
+        .spawn(future)
+        .await
+        .map_err(|e| anyhow!("Internal error: {}", e))?
+        .map_err(|e| anyhow!("{}", e))
+}
+
+struct Ui {
+    window: adw::ApplicationWindow,
+    toasts: adw::ToastOverlay,
+    mode_row: adw::ComboRow,
+    power_row: adw::ActionRow,
+    blockers: adw::PreferencesGroup,
+    blocker_rows: RefCell<Vec<adw::ActionRow>>,
+    delay_spin: gtk::SpinButton,
+    /// Set while widgets are updated from daemon state, so their change
+    /// handlers don't send the values straight back.
+    updating: Cell<bool>,
+    proxy: NvSleepifyManagerProxy<'static>,
+}
+
+impl Ui {
+    fn toast(&self, message: &str) {
+        self.toasts.add_toast(adw::Toast::new(message));
+    }
+
+    fn apply(&self, mode: Mode, power_state: PowerState, processes: &ProcessList, delay: u32) {
+        self.updating.set(true);
+        if let Some(index) = MODES.iter().position(|m| *m == mode) {
+            self.mode_row.set_selected(index as u32);
+        }
+        self.power_row.set_subtitle(&power_state.to_string());
+        self.delay_spin.set_value(delay as f64);
+        self.updating.set(false);
+
+        for row in self.blocker_rows.borrow_mut().drain(..) {
+            self.blockers.remove(&row);
+        }
+        self.blockers.set_visible(!processes.is_empty());
+        for proc in processes {
+            let row = adw::ActionRow::builder()
+                .title(proc.name.as_str())
+                .subtitle(format!("PID {}", proc.pid))
+                .build();
+            if let Ok(pid) = proc.pid.parse::<u32>() {
+                let button = gtk::Button::builder()
+                    .icon_name("process-stop-symbolic")
+                    .tooltip_text("Kill")
+                    .valign(gtk::Align::Center)
+                    .build();
+                button.add_css_class("flat");
+                let proxy = self.proxy.clone();
+                let toasts = self.toasts.clone();
+                button.connect_clicked(move |_| {
+                    let proxy = proxy.clone();
+                    let toasts = toasts.clone();
+                    glib_spawn(async move {
+                        let result =
+                            on_runtime(async move { proxy.kill_processes(vec![pid]).await }).await;
+                        if let Err(e) = result {
+                            toasts.add_toast(adw::Toast::new(&Error::from(e).to_string()));
+                        }
+                    });
+                });
+                row.add_suffix(&button);
+            }
+            self.blockers.add(&row);
+            self.blocker_rows.borrow_mut().push(row);
+        }
+    }
+}
+
+fn glib_spawn(future: impl Future<Output = ()> + 'static) {
+    gtk::glib::MainContext::default().spawn_local(future);
+}
+
+async fn refresh(ui: Rc<Ui>) {
+    let proxy = ui.proxy.clone();
+    let result = on_runtime(async move {
+        let info = proxy.info().await?;
+        let delay = proxy.restore_delay().await?;
+        Ok((info, delay))
+    })
+    .await;
+    match result {
+        Ok((report, delay)) => {
+            ui.apply(report.mode(), report.power_state, &report.processes, delay);
+        }
+        Err(e) => ui.toast(&format!("Failed to query daemon: {}", e)),
+    }
+}
+
+async fn set_mode(ui: Rc<Ui>, mode: Mode) {
+    let proxy = ui.proxy.clone();
+    let current = on_runtime(async move { proxy.info().await }).await;
+    if let Ok(StatusReport { processes, .. }) = &current {
+        if mode.kills_processes() && !processes.is_empty() && !confirm_kill(&ui, processes).await {
+            refresh(ui).await;
+            return;
+        }
+    }
+
+    let proxy = ui.proxy.clone();
+    if let Err(e) = on_runtime(async move { proxy.set_mode(mode.to_string()).await }).await {
+        ui.toast(&format!("Set Mode failed: {}", Error::from(e)));
+    }
+    refresh(ui).await;
+}
+
+async fn confirm_kill(ui: &Ui, processes: &ProcessList) -> bool {
+    let mut body = String::from(
+        "The following processes are using the Nvidia GPU and will be killed to sleep it:\n",
+    );
+    for proc in processes {
+        body.push_str(&format!("\n{}", proc));
+    }
+    let dialog = adw::MessageDialog::new(
+        Some(&ui.window),
+        Some("Kill blocking processes?"),
+        Some(&body),
+    );
+    dialog.add_responses(&[("cancel", "Cancel"), ("kill", "Kill and Switch")]);
+    dialog.set_response_appearance("kill", adw::ResponseAppearance::Destructive);
+    dialog.set_default_response(Some("cancel"));
+    dialog.set_close_response("cancel");
+    dialog.choose_future().await.as_str() == "kill"
+}
+
+fn build_ui(app: &adw::Application) {
+    let proxy = match runtime().block_on(async {
+        let connection = Connection::system().await?;
+        NvSleepifyManagerProxy::new(&connection).await
+    }) {
+        Ok(proxy) => proxy,
+        Err(e) => {
+            eprintln!("Failed to connect to system bus: {}", e);
+            app.quit();
+            return;
+        }
+    };
+
+    let mode_row = adw::ComboRow::builder()
+        .title("Mode")
+        .model(&gtk::StringList::new(&[
+            "Standard (Always On)",
+            "Integrated (Force Sleep)",
+            "Optimized (Auto)",
+            "VFIO (VM Passthrough)",
+        ]))
+        .build();
+    let power_row = adw::ActionRow::builder().title("Power state").build();
+    let status = adw::PreferencesGroup::builder().title("GPU").build();
+    status.add(&mode_row);
+    status.add(&power_row);
+
+    let blockers = adw::PreferencesGroup::builder()
+        .title("Blocking Processes")
+        .description("These keep the GPU awake.")
+        .visible(false)
+        .build();
+
+    let delay_spin = gtk::SpinButton::with_range(0.0, 600.0, 5.0);
+    delay_spin.set_valign(gtk::Align::Center);
+    let delay_row = adw::ActionRow::builder()
+        .title("Restore delay")
+        .subtitle("Seconds to wait after login before applying the saved mode")
+        .build();
+    delay_row.add_suffix(&delay_spin);
+    let startup = adw::PreferencesGroup::builder().title("Startup").build();
+    startup.add(&delay_row);
+
+    let page = adw::PreferencesPage::new();
+    page.add(&status);
+    page.add(&blockers);
+    page.add(&startup);
+
+    let toasts = adw::ToastOverlay::new();
+    toasts.set_child(Some(&page));
+    toasts.set_vexpand(true);
+
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    content.append(&adw::HeaderBar::new());
+    content.append(&toasts);
+
+    let window = adw::ApplicationWindow::builder()
+        .application(app)
+        .title("nvsleepify")
+        .default_width(480)
+        .default_height(560)
+        .content(&content)
+        .build();
+
+    let ui = Rc::new(Ui {
+        window: window.clone(),
+        toasts,
+        mode_row: mode_row.clone(),
+        power_row,
+        blockers,
+        blocker_rows: RefCell::new(Vec::new()),
+        delay_spin: delay_spin.clone(),
+        updating: Cell::new(false),
+        proxy: proxy.clone(),
+    });
+
+    {
+        let ui = ui.clone();
+        mode_row.connect_selected_notify(move |row| {
+            if ui.updating.get() {
+                return;
+            }
+            if let Some(&mode) = MODES.get(row.selected() as usize) {
+                glib_spawn(set_mode(ui.clone(), mode));
+            }
+        });
+    }
+    {
+        let ui = ui.clone();
+        delay_spin.connect_value_changed(move |spin| {
+            if ui.updating.get() {
+                return;
+            }
+            let seconds = spin.value() as u32;
+            let proxy = ui.proxy.clone();
+            let ui = ui.clone();
+            glib_spawn(async move {
+                if let Err(e) =
+                    on_runtime(async move { proxy.set_restore_delay(seconds).await }).await
+                {
+                    ui.toast(&format!("Failed to set delay: {}", e));
+                }
+            });
+        });
+    }
+
+    // Refresh on StateChanged, and periodically for blocking processes.
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<()>();
+    {
+        let tx = tx.clone();
+        runtime().spawn(async move {
+            let Ok(mut signals) = proxy.receive_state_changed().await else {
+                return;
+            };
+            while signals.next().await.is_some() {
+                if tx.send(()).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    gtk::glib::timeout_add_seconds_local(POLL_SECS, move || {
+        let _ = tx.send(());
+        gtk::glib::ControlFlow::Continue
+    });
+    {
+        let ui = ui.clone();
+        glib_spawn(async move {
+            refresh(ui.clone()).await;
+            while rx.recv().await.is_some() {
+                refresh(ui.clone()).await;
+            }
+        });
+    }
+
+    window.present();
+}
+
+fn main() -> gtk::glib::ExitCode {
+    let app = adw::Application::builder()
+        .application_id("org.nvsleepify.Settings")
+        .build();
+    app.connect_activate(build_ui);
+    app.run()
+}
@@ -1,36 +1,88 @@
 use anyhow::{anyhow, Result};
+use futures_util::StreamExt;
 use ksni::TrayMethods;
 use notify_rust::Notification;
-use nvsleepify::protocol::Mode;
-use std::str::FromStr;
-use std::sync::atomic::{AtomicBool, Ordering};
+use nvsleepify::client::{ensure_daemon_running, NvSleepifyManagerProxy};
+use nvsleepify::config::{IconStyle, TrayConfig};
+use nvsleepify::desktop;
+use nvsleepify::error::Error;
+use nvsleepify::eventlog::unix_now;
+use nvsleepify::history;
+use nvsleepify::logind;
+use nvsleepify::pci::{PciDevice, Sensors};
+use nvsleepify::protocol::{Mode, PowerState, ProcessInfo, ProcessList, StatusReport};
+use nvsleepify::savings;
+use nvsleepify::tr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::sync::mpsc;
-use zbus::{dbus_proxy, Connection};
-
-#[dbus_proxy(
-    interface = "org.nvsleepify.Manager",
-    default_service = "org.nvsleepify.Service",
-    default_path = "/org/nvsleepify/Manager"
-)]
-trait NvSleepifyManager {
-    fn status(&self) -> zbus::Result<String>;
-    fn info(&self) -> zbus::Result<(String, String, Vec<(String, String)>)>;
-    fn set_mode(&self, mode_str: String) -> zbus::Result<(bool, String, Vec<(String, String)>)>;
-}
-
-#[derive(Debug, Clone, Copy)]
+use zbus::Connection;
+
+/// Refresh interval when the signal subscription isn't available.
+const POLL_SECS: u64 = 2;
+
+/// Durations offered under "Keep Awake", as message ID and seconds.
+const WAKE_DURATIONS: [(&str, u32); 3] = [
+    ("tray-keep-awake-30m", 30 * 60),
+    ("tray-keep-awake-1h", 60 * 60),
+    ("tray-keep-awake-2h", 2 * 60 * 60),
+];
+
+/// "Keep awake 1h" on the sleep-blocked notification.
+const KEEP_AWAKE_SECS: u32 = 60 * 60;
+
+/// How long after acting from the menu a tray on another seat than the GPU's
+/// still reports what happened.
+const REQUEST_NOTIFY_SECS: u64 = 60;
+
+/// Rendered copies of icons/*.svg, used when no icon theme provides the named icons.
+const FALLBACK_ICONS: [(&str, &[u8]); 3] = [
+    (
+        "nvsleepify-gpu-active",
+        include_bytes!("../../icons/nvsleepify-gpu-active.png"),
+    ),
+    (
+        "nvsleepify-gpu-suspended",
+        include_bytes!("../../icons/nvsleepify-gpu-suspended.png"),
+    ),
+    (
+        "nvsleepify-gpu-off",
+        include_bytes!("../../icons/nvsleepify-gpu-off.png"),
+    ),
+];
+
+#[derive(Debug, Clone)]
 enum TrayCommand {
     SetMode(Mode),
+    /// Kill one blocking process, as listed in the menu, and re-apply the
+    /// current mode.
+    KillProcess(ProcessInfo),
+    /// Kill every blocking process and re-apply the current mode.
+    KillAll,
+    /// Keep the GPU awake for this many seconds, then return to the current mode.
+    WakeFor(u32),
+    CancelWake,
     ToggleNotifications,
+    /// Start nvsleepify-popup, from a left click or "Quick Settings".
+    OpenPopup,
+    /// A StatusNotifierWatcher appeared (true) or went away (false).
+    TrayHostChanged(bool),
     Quit,
 }
 
 #[derive(Debug, Default, Clone)]
 struct UiState {
     mode: Mode,
-    power_state: String,
-    processes: Vec<(String, String)>,
+    power_state: PowerState,
+    processes: ProcessList,
+    /// Unix time at which a temporary wake ends.
+    wake_until: Option<u64>,
+    /// Only read while the GPU is in D0.
+    sensors: Sensors,
+    /// Power state the last observed transition went to, and when.
+    last_transition: Option<(PowerState, u64)>,
+    /// Watts saved while the GPU sleeps, once the daemon has measured it.
+    savings: Option<f64>,
     last_error: Option<String>,
 }
 
@@ -38,18 +90,78 @@ struct UiState {
 struct NvSleepifyTray {
     state: UiState,
     notifications_enabled: Arc<AtomicBool>,
+    /// Send embedded pixmaps instead of icon names.
+    use_pixmaps: bool,
     tx: mpsc::UnboundedSender<TrayCommand>,
 }
 
+/// Whether `name` is installed in any icon theme or pixmap directory on the
+/// XDG data path. Sizes and themes aren't distinguished: any copy is enough for
+/// the host's lookup to fall back to it.
+fn icon_installed(name: &str) -> bool {
+    let data_dirs = desktop::data_dirs();
+
+    let has_icon = |dir: &std::path::Path| {
+        ["svg", "png"]
+            .iter()
+            .any(|ext| dir.join(format!("{}.{}", name, ext)).exists())
+    };
+    let subdirs = |dir: &std::path::Path| -> Vec<std::path::PathBuf> {
+        std::fs::read_dir(dir)
+            .map(|entries| entries.flatten().map(|e| e.path()).collect())
+            .unwrap_or_default()
+    };
+
+    data_dirs.iter().any(|data_dir| {
+        // <data>/icons/<theme>/<size>/apps/<name>.<ext>
+        has_icon(&data_dir.join("pixmaps"))
+            || subdirs(&data_dir.join("icons")).iter().any(|theme| {
+                subdirs(theme)
+                    .iter()
+                    .any(|size| has_icon(&size.join("apps")))
+            })
+    })
+}
+
+/// nvsleepify-popup, when it was built and installed next to the tray.
+fn popup_binary() -> Option<std::path::PathBuf> {
+    let path = std::env::current_exe()
+        .ok()?
+        .with_file_name("nvsleepify-popup");
+    path.exists().then_some(path)
+}
+
+/// Decodes an embedded PNG into the ARGB32 pixmap format ksni expects.
+fn decode_icon(png_bytes: &[u8]) -> Option<ksni::Icon> {
+    let mut decoder = png::Decoder::new(png_bytes);
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().ok()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut buf).ok()?;
+    if frame.color_type != png::ColorType::Rgba || frame.bit_depth != png::BitDepth::Eight {
+        return None;
+    }
+
+    let mut data = Vec::with_capacity(frame.buffer_size());
+    for pixel in buf[..frame.buffer_size()].chunks_exact(4) {
+        data.extend_from_slice(&[pixel[3], pixel[0], pixel[1], pixel[2]]);
+    }
+    Some(ksni::Icon {
+        width: frame.width as i32,
+        height: frame.height as i32,
+        data,
+    })
+}
+
 impl NvSleepifyTray {
     fn icon_name_for_state(state: &UiState) -> String {
-        if !state.processes.is_empty() {
+        if !state.processes.is_empty() || state.wake_until.is_some() {
             return "nvsleepify-gpu-active".into();
         }
-        if state.power_state == "D3cold" {
+        if state.power_state == PowerState::D3cold {
             return "nvsleepify-gpu-suspended".into();
         }
-        if state.power_state == "NotFound" {
+        if state.power_state == PowerState::NotFound {
             return "nvsleepify-gpu-off".into();
         }
         if state.mode == Mode::Integrated {
@@ -59,30 +171,167 @@ impl NvSleepifyTray {
     }
 
     fn title_for_state(state: &UiState) -> String {
-        if !state.processes.is_empty() {
-            format!("GPU Active ({} proc)", state.processes.len())
-        } else if state.power_state == "D3cold" {
-            "GPU Suspended (D3cold)".into()
+        let title = if !state.processes.is_empty() {
+            tr!("tray-title-active", count = state.processes.len())
+        } else if state.power_state == PowerState::D3cold {
+            tr!("tray-title-suspended")
         } else {
             format!("nvsleepify ({})", state.mode)
+        };
+        match describe_sensors(&state.sensors) {
+            Some(sensors) => format!("{} - {}", title, sensors),
+            None => title,
+        }
+    }
+
+    fn blockers_menu(&self) -> ksni::MenuItem<Self> {
+        use ksni::menu::*;
+
+        // Killing only gets the GPU to sleep if the mode wants it asleep.
+        let action = if self.state.mode == Mode::Standard {
+            tr!("tray-kill")
+        } else {
+            tr!("tray-kill-retry")
+        };
+
+        let mut submenu: Vec<MenuItem<Self>> = Vec::new();
+        for proc in &self.state.processes {
+            let Ok(pid) = proc.pid.parse::<u32>() else {
+                continue;
+            };
+            let proc = proc.clone();
+            submenu.push(
+                SubMenu {
+                    // Underscores mark access keys in menu labels.
+                    label: format!("{} (PID {})", proc.name.replace('_', "__"), pid),
+                    submenu: vec![StandardItem {
+                        label: action.clone(),
+                        icon_name: "process-stop".into(),
+                        activate: {
+                            let tx = self.tx.clone();
+                            Box::new(move |_| {
+                                let _ = tx.send(TrayCommand::KillProcess(proc.clone()));
+                            })
+                        },
+                        ..Default::default()
+                    }
+                    .into()],
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+        submenu.push(MenuItem::Separator);
+        submenu.push(
+            StandardItem {
+                label: if self.state.mode == Mode::Standard {
+                    tr!("tray-kill-all")
+                } else {
+                    tr!("tray-kill-all-sleep")
+                },
+                icon_name: "process-stop".into(),
+                activate: {
+                    let tx = self.tx.clone();
+                    Box::new(move |_| {
+                        let _ = tx.send(TrayCommand::KillAll);
+                    })
+                },
+                ..Default::default()
+            }
+            .into(),
+        );
+
+        SubMenu {
+            label: tr!("tray-blockers", count = self.state.processes.len()),
+            submenu,
+            ..Default::default()
         }
+        .into()
+    }
+
+    fn keep_awake_menu(&self) -> ksni::MenuItem<Self> {
+        use ksni::menu::*;
+
+        let mut submenu: Vec<MenuItem<Self>> = WAKE_DURATIONS
+            .iter()
+            .map(|&(label, seconds)| {
+                let tx = self.tx.clone();
+                StandardItem {
+                    label: nvsleepify::i18n::tr(label, &[]),
+                    activate: Box::new(move |_| {
+                        let _ = tx.send(TrayCommand::WakeFor(seconds));
+                    }),
+                    ..Default::default()
+                }
+                .into()
+            })
+            .collect();
+        if self.state.wake_until.is_some() {
+            submenu.push(MenuItem::Separator);
+            submenu.push(
+                StandardItem {
+                    label: tr!("tray-cancel-wake", mode = self.state.mode.to_string()),
+                    activate: {
+                        let tx = self.tx.clone();
+                        Box::new(move |_| {
+                            let _ = tx.send(TrayCommand::CancelWake);
+                        })
+                    },
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+
+        SubMenu {
+            label: tr!("tray-keep-awake"),
+            icon_name: "alarm-symbolic".into(),
+            submenu,
+            ..Default::default()
+        }
+        .into()
     }
 
     fn tooltip_for_state(state: &UiState) -> ksni::ToolTip {
         let mut lines = Vec::new();
-        lines.push(format!("Mode: {}", state.mode));
+        lines.push(tr!("tray-tooltip-mode", mode = state.mode.to_string()));
+        if let Some(until) = state.wake_until {
+            let minutes = until.saturating_sub(unix_now()).div_ceil(60);
+            lines.push(tr!(
+                "tray-tooltip-kept-awake",
+                hours = minutes / 60,
+                minutes = format!("{:02}", minutes % 60)
+            ));
+        }
 
-        if !state.power_state.is_empty() && state.power_state != "NotFound" {
-            lines.push(format!("Power: {}", state.power_state));
+        if !matches!(
+            state.power_state,
+            PowerState::Unknown | PowerState::NotFound
+        ) {
+            lines.push(tr!(
+                "tray-tooltip-power",
+                state = state.power_state.to_string()
+            ));
+        }
+        if let Some(last) = state.last_transition {
+            if let Some(time) = history::time_in_state(state.power_state, last, unix_now()) {
+                lines.push(time);
+            }
+        }
+        if let Some(sensors) = describe_sensors(&state.sensors) {
+            lines.push(tr!("tray-tooltip-sensors", sensors = sensors));
+        }
+        if let Some(watts) = state.savings {
+            lines.push(savings::describe(watts));
         }
         if !state.processes.is_empty() {
-            lines.push("Processes using GPU:".into());
-            for (name, pid) in &state.processes {
-                lines.push(format!("- {} (PID {})", name, pid));
+            lines.push(tr!("tray-tooltip-processes"));
+            for proc in &state.processes {
+                lines.push(format!("- {}", proc));
             }
         }
         if let Some(err) = &state.last_error {
-            lines.push(format!("Error: {}", err));
+            lines.push(tr!("tray-tooltip-error", error = err.as_str()));
         }
 
         ksni::ToolTip {
@@ -98,6 +347,23 @@ impl ksni::Tray for NvSleepifyTray {
         "nvsleepify-tray".into()
     }
 
+    fn watcher_online(&self) {
+        let _ = self.tx.send(TrayCommand::TrayHostChanged(true));
+    }
+
+    fn watcher_offline(&self, reason: ksni::OfflineReason) -> bool {
+        eprintln!("No system tray available: {:?}", reason);
+        let _ = self.tx.send(TrayCommand::TrayHostChanged(false));
+        // Keep running: ksni registers the icon once a watcher shows up.
+        true
+    }
+
+    fn activate(&mut self, _x: i32, _y: i32) {
+        if popup_binary().is_some() {
+            let _ = self.tx.send(TrayCommand::OpenPopup);
+        }
+    }
+
     fn category(&self) -> ksni::Category {
         ksni::Category::Hardware
     }
@@ -111,9 +377,25 @@ impl ksni::Tray for NvSleepifyTray {
     }
 
     fn icon_name(&self) -> String {
+        if self.use_pixmaps {
+            return String::new();
+        }
         Self::icon_name_for_state(&self.state)
     }
 
+    fn icon_pixmap(&self) -> Vec<ksni::Icon> {
+        if !self.use_pixmaps {
+            return Vec::new();
+        }
+        let name = Self::icon_name_for_state(&self.state);
+        FALLBACK_ICONS
+            .iter()
+            .find(|(icon, _)| *icon == name)
+            .and_then(|(_, png_bytes)| decode_icon(png_bytes))
+            .into_iter()
+            .collect()
+    }
+
     fn tool_tip(&self) -> ksni::ToolTip {
         Self::tooltip_for_state(&self.state)
     }
@@ -121,9 +403,9 @@ impl ksni::Tray for NvSleepifyTray {
     fn menu(&self) -> Vec<ksni::MenuItem<Self>> {
         use ksni::menu::*;
 
-        vec![
+        let mut items = vec![
             CheckmarkItem {
-                label: "Standard (Always On)".into(),
+                label: tr!("tray-mode-standard"),
                 checked: self.state.mode == Mode::Standard,
                 activate: {
                     let tx = self.tx.clone();
@@ -135,7 +417,7 @@ impl ksni::Tray for NvSleepifyTray {
             }
             .into(),
             CheckmarkItem {
-                label: "Integrated (Force Sleep)".into(),
+                label: tr!("tray-mode-integrated"),
                 checked: self.state.mode == Mode::Integrated,
                 activate: {
                     let tx = self.tx.clone();
@@ -147,7 +429,7 @@ impl ksni::Tray for NvSleepifyTray {
             }
             .into(),
             CheckmarkItem {
-                label: "Optimized (Auto)".into(),
+                label: tr!("tray-mode-optimized"),
                 checked: self.state.mode == Mode::Optimized,
                 activate: {
                     let tx = self.tx.clone();
@@ -158,9 +440,49 @@ impl ksni::Tray for NvSleepifyTray {
                 ..Default::default()
             }
             .into(),
-            MenuItem::Separator,
             CheckmarkItem {
-                label: "Notifications".into(),
+                label: tr!("tray-mode-vfio"),
+                checked: self.state.mode == Mode::Vfio,
+                activate: {
+                    let tx = self.tx.clone();
+                    Box::new(move |_| {
+                        let _ = tx.send(TrayCommand::SetMode(Mode::Vfio));
+                    })
+                },
+                ..Default::default()
+            }
+            .into(),
+        ];
+
+        // Standard already keeps the GPU awake.
+        if self.state.mode != Mode::Standard {
+            items.push(self.keep_awake_menu());
+        }
+
+        if !self.state.processes.is_empty() {
+            items.push(MenuItem::Separator);
+            items.push(self.blockers_menu());
+        }
+
+        items.push(MenuItem::Separator);
+        if popup_binary().is_some() {
+            items.push(
+                StandardItem {
+                    label: tr!("tray-quick-settings"),
+                    activate: {
+                        let tx = self.tx.clone();
+                        Box::new(move |_| {
+                            let _ = tx.send(TrayCommand::OpenPopup);
+                        })
+                    },
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+        items.extend([
+            CheckmarkItem {
+                label: tr!("tray-notifications"),
                 checked: self.notifications_enabled.load(Ordering::Relaxed),
                 activate: {
                     let tx = self.tx.clone();
@@ -173,7 +495,7 @@ impl ksni::Tray for NvSleepifyTray {
             .into(),
             MenuItem::Separator,
             StandardItem {
-                label: "Quit".into(),
+                label: tr!("tray-quit"),
                 icon_name: "application-exit".into(),
                 activate: {
                     let tx = self.tx.clone();
@@ -184,19 +506,20 @@ impl ksni::Tray for NvSleepifyTray {
                 ..Default::default()
             }
             .into(),
-        ]
+        ]);
+        items
     }
 }
 
-fn confirm_kill_processes(procs: &[(String, String)]) -> bool {
+fn confirm_kill_processes(procs: &[ProcessInfo]) -> bool {
     if procs.is_empty() {
         return true;
     }
 
-    let mut text = String::new();
-    text.push_str("The following processes are using the Nvidia GPU and may need to be killed to sleep it:\n\n");
-    for (name, pid) in procs {
-        text.push_str(&format!("- {} (PID {})\n", name, pid));
+    let mut text = tr!("confirm-blockers");
+    text.push_str("\n\n");
+    for proc in procs {
+        text.push_str(&format!("- {}\n", proc));
     }
 
     let result = rfd::MessageDialog::new()
@@ -215,14 +538,130 @@ fn is_gpu_driver_loaded() -> bool {
     false
 }
 
+/// The mode wants the GPU asleep, but processes keep it awake.
+fn sleep_blocked(state: &UiState) -> bool {
+    state.mode == Mode::Integrated
+        && state.power_state == PowerState::D0
+        && state.wake_until.is_none()
+        && !state.processes.is_empty()
+}
+
+/// Offers to kill the blockers or keep the GPU awake for a while; the chosen
+/// action comes back as a tray command.
+fn notify_sleep_blocked(processes: &ProcessList, tx: mpsc::UnboundedSender<TrayCommand>) {
+    let names: Vec<String> = processes.iter().map(|p| p.to_string()).collect();
+    let body = tr!("notify-sleep-blocked", processes = names.join(", "));
+    tokio::task::spawn_blocking(move || {
+        let Ok(notification) = Notification::new()
+            .summary("nvsleepify")
+            .body(&body)
+            .action("kill", &tr!("notify-action-kill"))
+            .action("keep-awake", &tr!("notify-action-keep-awake"))
+            .action("dismiss", &tr!("notify-action-dismiss"))
+            .show()
+        else {
+            return;
+        };
+        // Blocks until an action is chosen or the notification is closed.
+        notification.wait_for_action(|action| {
+            let cmd = match action {
+                "kill" => TrayCommand::KillAll,
+                "keep-awake" => TrayCommand::WakeFor(KEEP_AWAKE_SECS),
+                _ => return,
+            };
+            let _ = tx.send(cmd);
+        });
+    });
+}
+
+/// Kills the chosen blocker (all of them for None) and, unless the GPU is
+/// meant to stay awake, re-applies the current mode so it can go to sleep.
+async fn kill_and_retry(
+    proxy: &NvSleepifyManagerProxy<'_>,
+    process: Option<ProcessInfo>,
+) -> Result<(), String> {
+    let result = match process {
+        // By name too, so a PID reused since the menu was built is left alone.
+        Some(process) => match process.pid.parse() {
+            Ok(pid) => proxy.kill_process(pid, process.name).await,
+            Err(_) => return Err(tr!("tray-error-invalid-pid", pid = process.pid)),
+        },
+        None => proxy.kill_processes(vec![]).await,
+    };
+    if let Err(e) = result {
+        return Err(tr!("tray-error-kill", error = Error::from(e).to_string()));
+    }
+
+    let current = fetch_info(proxy).await;
+    if current.last_error.is_none() && current.mode != Mode::Standard {
+        if let Err(e) = proxy.set_mode(current.mode.to_string()).await {
+            return Err(tr!("tray-error-sleep", error = Error::from(e).to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// e.g. "52 °C, 14.3 W"
+fn describe_sensors(sensors: &Sensors) -> Option<String> {
+    let mut parts = Vec::new();
+    if let Some(temp) = sensors.temperature {
+        parts.push(format!("{:.0} °C", temp));
+    }
+    if let Some(power) = sensors.power_draw {
+        parts.push(format!("{:.1} W", power));
+    }
+    (!parts.is_empty()).then(|| parts.join(", "))
+}
+
+/// Reads the awake GPU's sensors. hwmon is free to read; nvidia-smi keeps the
+/// card from idling, so it's only asked when something is using the GPU anyway.
+fn read_sensors(gpu_in_use: bool) -> Sensors {
+    let Ok(gpu) = PciDevice::find_nvidia_gpu() else {
+        return Sensors::default();
+    };
+    let sensors = gpu.hwmon_sensors();
+    if sensors.is_empty() && gpu_in_use && is_gpu_driver_loaded() {
+        return gpu.nvidia_smi_sensors();
+    }
+    sensors
+}
+
 async fn fetch_info(proxy: &NvSleepifyManagerProxy<'_>) -> UiState {
     match proxy.info().await {
-        Ok((mode_str, power_state, processes)) => {
-            let mode = Mode::from_str(&mode_str).unwrap_or(Mode::Standard);
+        Ok(report) => {
+            let mode = report.mode();
+            let StatusReport {
+                power_state,
+                processes,
+                ..
+            } = report;
+            // Older daemons don't know about temporary wakes.
+            let wake_until = match proxy.wake_remaining().await {
+                Ok(remaining) if remaining > 0 => Some(unix_now() + remaining as u64),
+                _ => None,
+            };
+            let savings = proxy.power_savings().await.ok().filter(|w| *w > 0.0);
+            let last_transition = proxy
+                .last_transition()
+                .await
+                .ok()
+                .map(|(_, to, timestamp)| (to, timestamp));
+            let sensors = if power_state == PowerState::D0 {
+                let gpu_in_use = !processes.is_empty();
+                tokio::task::spawn_blocking(move || read_sensors(gpu_in_use))
+                    .await
+                    .unwrap_or_default()
+            } else {
+                Sensors::default()
+            };
             UiState {
                 mode,
                 power_state,
                 processes,
+                wake_until,
+                sensors,
+                last_transition,
+                savings,
                 last_error: None,
             }
         }
@@ -242,6 +681,11 @@ async fn main() -> Result<()> {
         .await
         .map_err(|e| anyhow!("Failed to connect to system bus: {}. Is dbus running?", e))?;
 
+    // Not fatal: the tooltip reports the daemon as unreachable until it shows up.
+    if let Err(e) = ensure_daemon_running(&connection).await {
+        eprintln!("{}", e);
+    }
+
     let proxy = NvSleepifyManagerProxy::new(&connection).await.map_err(|e| {
         anyhow!(
             "Failed to connect to nvsleepify daemon at org.nvsleepify.Service: {}. Is nvsleepifyd.service running?",
@@ -252,57 +696,119 @@ async fn main() -> Result<()> {
     let (tx, mut rx) = mpsc::unbounded_channel::<TrayCommand>();
 
     let initial_state = fetch_info(&proxy).await;
-    let notifications_enabled = Arc::new(AtomicBool::new(true));
+    let mut config = TrayConfig::load();
+    let notifications_enabled = Arc::new(AtomicBool::new(config.notifications));
+    let use_pixmaps = match config.icon_style {
+        IconStyle::Auto => !FALLBACK_ICONS.iter().all(|(name, _)| icon_installed(name)),
+        IconStyle::Theme => false,
+        IconStyle::Embedded => true,
+    };
     let tray = NvSleepifyTray {
         state: initial_state.clone(),
         notifications_enabled: notifications_enabled.clone(),
-        tx,
+        use_pixmaps,
+        tx: tx.clone(),
     };
 
+    // Whether a tray host is showing the icon. Without one, notifications are
+    // the only feedback, so they are sent regardless of the user's setting.
+    let tray_visible = Arc::new(AtomicBool::new(true));
+
+    // On a multi-seat machine only the GPU's seat hears about it, and this
+    // tray's user for a while after acting on it (unix time of the last action).
+    let on_gpu_seat = logind::on_gpu_seat();
+    let last_request = Arc::new(AtomicU64::new(0));
+
     let handle = tray
+        .assume_sni_available(true)
         .spawn()
         .await
         .map_err(|e| anyhow!("Tray spawn failed: {e}"))?;
 
-    // Polling logic
+    // Update logic: refresh on StateChanged, with a slow poll as fallback
     {
         let handle = handle.clone();
         let proxy = NvSleepifyManagerProxy::new(&connection).await?;
+        let connection = connection.clone();
         let notifications_enabled = notifications_enabled.clone();
+        let tray_visible = tray_visible.clone();
+        let last_request = last_request.clone();
+        // Only needed to pick up blocking processes, which don't produce signals.
+        let fallback_poll_secs = config.poll_interval.max(1);
         tokio::spawn(async move {
-            let mut interval = tokio::time::interval(std::time::Duration::from_secs(2));
+            let mut signals = proxy.receive_state_changed().await.ok();
+            let poll_secs = if signals.is_some() {
+                fallback_poll_secs
+            } else {
+                POLL_SECS
+            };
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(poll_secs));
+            interval.tick().await;
             let mut last_state = initial_state;
             loop {
-                interval.tick().await;
-                let new_state = fetch_info(&proxy).await;
-
-                if notifications_enabled.load(Ordering::Relaxed) {
-                    if last_state.power_state != "D0" && new_state.power_state == "D0" {
-                        if is_gpu_driver_loaded() {
-                            tokio::task::spawn_blocking(|| {
-                                let _ = Notification::new()
-                                    .summary("nvsleepify")
-                                    .body("GPU Woke up (D0)")
-                                    .show();
-                            });
+                let mut stream_ended = false;
+                match signals.as_mut() {
+                    Some(stream) => {
+                        tokio::select! {
+                            signal = stream.next() => stream_ended = signal.is_none(),
+                            _ = interval.tick() => {}
                         }
                     }
-                    if last_state.power_state != "D3cold" && new_state.power_state == "D3cold" {
+                    None => {
+                        interval.tick().await;
+                    }
+                }
+                if stream_ended {
+                    signals = None;
+                    interval = tokio::time::interval(std::time::Duration::from_secs(POLL_SECS));
+                }
+                let mut new_state = fetch_info(&proxy).await;
+                if new_state.last_error.is_some() && last_state.last_error.is_none() {
+                    // The daemon went away; try once to bring it back.
+                    if ensure_daemon_running(&connection).await.is_ok() {
+                        new_state = fetch_info(&proxy).await;
+                    }
+                }
+
+                let requested = unix_now().saturating_sub(last_request.load(Ordering::Relaxed))
+                    < REQUEST_NOTIFY_SECS;
+                if (on_gpu_seat || requested)
+                    && (notifications_enabled.load(Ordering::Relaxed)
+                        || !tray_visible.load(Ordering::Relaxed))
+                {
+                    if last_state.power_state != PowerState::D0
+                        && new_state.power_state == PowerState::D0
+                        && is_gpu_driver_loaded()
+                    {
+                        tokio::task::spawn_blocking(|| {
+                            let _ = Notification::new()
+                                .summary("nvsleepify")
+                                .body(&tr!("notify-woke"))
+                                .show();
+                        });
+                    }
+                    if last_state.power_state != PowerState::D3cold
+                        && new_state.power_state == PowerState::D3cold
+                    {
                         tokio::task::spawn_blocking(|| {
                             let _ = Notification::new()
                                 .summary("nvsleepify")
-                                .body("GPU Suspended (D3cold)")
+                                .body(&tr!("notify-suspended"))
                                 .show();
                         });
                     }
                     if last_state.mode != new_state.mode {
+                        let mode = new_state.mode;
                         tokio::task::spawn_blocking(move || {
                             let _ = Notification::new()
                                 .summary("nvsleepify")
-                                .body(&format!("Mode changed to {}", new_state.mode))
+                                .body(&tr!("notify-mode-changed", mode = mode.to_string()))
                                 .show();
                         });
                     }
+                    if sleep_blocked(&new_state) && !sleep_blocked(&last_state) {
+                        notify_sleep_blocked(&new_state.processes, tx.clone());
+                    }
                 }
 
                 last_state = new_state.clone();
@@ -321,27 +827,97 @@ async fn main() -> Result<()> {
         let proxy = NvSleepifyManagerProxy::new(&connection).await?;
         tokio::spawn(async move {
             while let Some(cmd) = rx.recv().await {
+                if matches!(
+                    cmd,
+                    TrayCommand::SetMode(_)
+                        | TrayCommand::KillProcess(_)
+                        | TrayCommand::KillAll
+                        | TrayCommand::WakeFor(_)
+                        | TrayCommand::CancelWake
+                ) {
+                    last_request.store(unix_now(), Ordering::Relaxed);
+                }
                 match cmd {
                     TrayCommand::ToggleNotifications => {
                         let current = notifications_enabled.load(Ordering::Relaxed);
                         notifications_enabled.store(!current, Ordering::Relaxed);
+                        config.notifications = !current;
+                        if let Err(e) = config.save() {
+                            eprintln!("Failed to save tray settings: {}", e);
+                        }
                         let _ = handle.update(|_| {}).await;
                     }
+                    TrayCommand::TrayHostChanged(visible) => {
+                        let was_visible = tray_visible.swap(visible, Ordering::Relaxed);
+                        if was_visible && !visible {
+                            tokio::task::spawn_blocking(|| {
+                                let _ = Notification::new()
+                                    .summary("nvsleepify")
+                                    .body(&tr!("notify-no-tray"))
+                                    .show();
+                            });
+                        }
+                    }
+                    TrayCommand::OpenPopup => {
+                        let Some(popup) = popup_binary() else {
+                            continue;
+                        };
+                        match tokio::process::Command::new(popup).spawn() {
+                            // Reaped in the background so it doesn't linger as a zombie.
+                            Ok(mut child) => {
+                                tokio::spawn(async move { child.wait().await });
+                            }
+                            Err(e) => eprintln!("Failed to open the quick settings: {}", e),
+                        }
+                    }
                     TrayCommand::Quit => {
                         let _ = handle.shutdown().await;
                         std::process::exit(0);
                     }
+                    TrayCommand::KillProcess(_) | TrayCommand::KillAll => {
+                        let process = match cmd {
+                            TrayCommand::KillProcess(process) => Some(process),
+                            _ => None,
+                        };
+                        let error = kill_and_retry(&proxy, process).await.err();
+                        let mut refreshed = fetch_info(&proxy).await;
+                        refreshed.last_error = refreshed.last_error.or(error);
+                        let _ = handle
+                            .update(|tray: &mut NvSleepifyTray| {
+                                tray.state = refreshed;
+                            })
+                            .await;
+                    }
+                    TrayCommand::WakeFor(_) | TrayCommand::CancelWake => {
+                        let result = match cmd {
+                            TrayCommand::WakeFor(seconds) => proxy.wake_for(seconds).await,
+                            _ => proxy.cancel_wake().await,
+                        };
+                        let error = result.err().map(|e| {
+                            tr!("tray-error-keep-awake", error = Error::from(e).to_string())
+                        });
+                        let mut refreshed = fetch_info(&proxy).await;
+                        refreshed.last_error = refreshed.last_error.or(error);
+                        let _ = handle
+                            .update(|tray: &mut NvSleepifyTray| {
+                                tray.state = refreshed;
+                            })
+                            .await;
+                    }
                     TrayCommand::SetMode(mode) => {
                         // Check blocking procs for Integrated or Optimized mode?
                         // If we are setting mode to Integrated, and there are processes, we might want to warn.
                         // But if we trust the daemon to force kill (Integrated), maybe we should warn first.
-                        // The daemon's current logic for `set_mode` Integrated is `sleep_logic(true)` which kills.
+                        // Past this point the user agreed to killing them (or turned the
+                        // confirmation off), which the daemon's "ask" kill policy needs to hear.
 
                         let current = fetch_info(&proxy).await;
-                        if mode == Mode::Integrated && !current.processes.is_empty() {
-                            if !confirm_kill_processes(&current.processes) {
-                                continue; // User cancelled
-                            }
+                        if mode.kills_processes()
+                            && !current.processes.is_empty()
+                            && config.confirm_kill
+                            && !confirm_kill_processes(&current.processes)
+                        {
+                            continue; // User cancelled
                         }
 
                         // What about Optimized? Daemon uses soft sleep.
@@ -349,25 +925,17 @@ async fn main() -> Result<()> {
                         // the daemon returns failure but stays in Optimized mode (and will retry in loop).
                         // That seems fine.
 
-                        match proxy.set_mode(mode.to_string()).await {
-                            Ok((success, msg, _procs)) => {
-                                if !success {
-                                    let _ = handle
-                                        .update(|tray: &mut NvSleepifyTray| {
-                                            tray.state.last_error =
-                                                Some(format!("Set Mode failed: {}", msg));
-                                        })
-                                        .await;
-                                }
-                            }
-                            Err(e) => {
-                                let _ = handle
-                                    .update(|tray: &mut NvSleepifyTray| {
-                                        tray.state.last_error =
-                                            Some(format!("Set Mode failed: {}", e));
-                                    })
-                                    .await;
-                            }
+                        if let Err(e) = proxy
+                            .set_mode_with_options(mode.to_string(), mode.kills_processes())
+                            .await
+                        {
+                            let e = Error::from(e);
+                            let _ = handle
+                                .update(|tray: &mut NvSleepifyTray| {
+                                    tray.state.last_error =
+                                        Some(tr!("tray-error-set-mode", error = e.to_string()));
+                                })
+                                .await;
                         }
 
                         let refreshed = fetch_info(&proxy).await;
@@ -1,15 +1,50 @@
 use anyhow::Result;
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use clap_complete::Shell;
 use nvsleepify::{
-    client,
-    protocol::{Command, Mode},
+    backend, cli,
+    eventlog::{self, Level},
+    history,
+    launcher::Launcher,
+    output::{self, Verbosity},
+    protocol::{Command, ManualStep, Mode, PowerState},
+    rpc,
 };
+use std::path::PathBuf;
+use std::process::ExitCode;
 
 #[derive(Parser)]
 #[command(name = "nvsleepify")]
 #[command(about = "Manage Nvidia dGPU power state (sleep/wake) on Linux", long_about = None)]
 struct Cli {
+    /// Run the sleep/wake logic in-process instead of through nvsleepifyd (requires root)
+    #[arg(long, global = true)]
+    direct: bool,
+
+    /// Talk to nvsleepifyd over its JSON-RPC socket instead of D-Bus
+    #[arg(long, global = true, value_name = "PATH", num_args = 0..=1, default_missing_value = rpc::DEFAULT_SOCKET_PATH, conflicts_with = "direct")]
+    socket: Option<PathBuf>,
+
+    /// Don't ask for confirmation before killing processes (also needed when stdin isn't a terminal)
+    #[arg(short, long, global = true)]
+    yes: bool,
+
+    /// With --direct, use this directory of fixture files instead of /sys; modprobe and systemctl are only printed (env: NVSLEEPIFY_SYSFS_ROOT)
+    #[arg(long, global = true, value_name = "DIR")]
+    sysfs_root: Option<PathBuf>,
+
+    /// Don't color the output (also off with NO_COLOR set, or when stdout isn't a terminal)
+    #[arg(long, global = true)]
+    no_color: bool,
+
+    /// Only print results, prompts, warnings and errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Also print how commands are carried out
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -18,13 +53,146 @@ struct Cli {
 enum Commands {
     /// Get GPU status
     Status,
+    /// List display controllers with their IDs, names, power states and drivers
+    Devices,
+    /// Show what this machine supports for powering the GPU down
+    Capabilities,
+    /// Continuously display GPU status as it changes
+    Watch,
+    /// Live view of the processes using the GPU, with their GPU memory while it's awake
+    Top,
+    /// Show recent daemon events (transitions and errors)
+    Logs {
+        /// Keep printing new events as they happen
+        #[arg(short, long)]
+        follow: bool,
+    },
+    /// Show notable daemon events: mode changes, transitions, enforcement, policy decisions, failures
+    Events {
+        /// Keep printing new events as they happen
+        #[arg(short, long)]
+        follow: bool,
+    },
+    /// List processes currently using the GPU (exit status 1 if none)
+    Ps,
+    /// Terminate processes using the GPU
+    Kill {
+        /// Only kill these PIDs (default: every process using the GPU)
+        pids: Vec<u32>,
+    },
+    /// Show past power state changes and how long each state lasted
+    History {
+        /// Number of transitions to show
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: u32,
+    },
+    /// Show lifetime sleep/wake statistics
+    Stats,
+    /// Compare battery drain and runtime with the GPU asleep and awake
+    Savings,
+    /// Explain why the GPU is in its current state
+    Explain,
+    /// Take over from envycontrol, supergfxctl or optimus-manager
+    Migrate,
+    /// Wake the GPU, run a program on it with render offload, then restore the previous mode
+    Run {
+        /// Program and its arguments
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Add a "Launch on dGPU" action to applications' launcher entries
+    Desktop {
+        /// Desktop file ids (e.g. "steam") or paths to .desktop files
+        #[arg(required = true)]
+        apps: Vec<String>,
+        /// Remove the action again
+        #[arg(long)]
+        remove: bool,
+    },
+    /// Print how to start games from Steam or Lutris through `nvsleepify run`
+    Launcher {
+        #[arg(value_enum)]
+        launcher: Launcher,
+        /// Write a wrapper script to ~/.local/bin and use it in the instructions
+        #[arg(long)]
+        install: bool,
+    },
+    /// Install the systemd units, D-Bus files and tray autostart entry, and start the daemon
+    Install {
+        /// Only install the files, don't enable and start nvsleepifyd
+        #[arg(long)]
+        no_enable: bool,
+    },
+    /// Remove what install set up and undo nvsleepify's changes to the system
+    Uninstall {
+        /// Also remove the configuration and saved state
+        #[arg(long)]
+        purge: bool,
+    },
+    /// Bundle diagnostics for a bug report into a tarball, with host and user names scrubbed
+    Report {
+        /// Where to write it; defaults to ./nvsleepify-report-<timestamp>.tar.gz
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
     /// Set power management mode
     Set {
         #[arg(value_enum)]
-        mode: Mode,
+        mode: ModeArg,
         /// Show GUI confirmation dialog if processes need to be killed
         #[arg(long)]
         gui: bool,
+        /// Block until the GPU has reached the target power state
+        #[arg(long)]
+        wait: bool,
+        /// Seconds to wait with --wait before failing
+        #[arg(long, default_value_t = 30, requires = "wait")]
+        timeout: u64,
+        /// With integrated: don't kill processes using the GPU, sleep once they exit
+        #[arg(long)]
+        when_idle: bool,
+        /// Power the GPU down even if it is the boot display or the only GPU
+        #[arg(long, conflicts_with = "when_idle")]
+        force: bool,
+    },
+    /// Keep the GPU awake for a while, then return to the saved mode
+    Wake {
+        /// How long, e.g. 45m, 1h30m or 90s; calling again restarts the timer
+        #[arg(long = "for", value_name = "DURATION", value_parser = parse_wake_duration, required_unless_present = "cancel", conflicts_with = "cancel")]
+        duration: Option<u32>,
+        /// End the temporary wake now and re-apply the saved mode
+        #[arg(long)]
+        cancel: bool,
+    },
+    /// Block until the GPU reaches a power state, e.g. before a workload that needs it
+    WaitForState {
+        /// d0, d1, d2, d3hot or d3cold (which includes off the bus)
+        #[arg(value_parser = parse_power_state)]
+        state: PowerState,
+        /// Seconds to wait before failing (default: no limit)
+        #[arg(long)]
+        timeout: Option<u64>,
+    },
+    /// Time each step of powering the GPU off and on, over several cycles
+    Bench {
+        /// Number of sleep/wake cycles (at most 50)
+        #[arg(short = 'n', long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..=50))]
+        cycles: u32,
+    },
+    /// Run one step of the sleep/wake sequence on the GPU by hand
+    Pci {
+        #[command(subcommand)]
+        action: PciAction,
+    },
+    /// Load or unload the Nvidia kernel modules by hand
+    Module {
+        #[command(subcommand)]
+        action: ModuleAction,
+    },
+    /// Start or stop the services that hold the GPU by hand
+    Service {
+        #[command(subcommand)]
+        action: ServiceAction,
     },
     /// Set delay before restoring GPU state on boot
     Delay {
@@ -38,9 +206,98 @@ enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+enum PciAction {
+    /// Unbind the GPU's driver
+    Unbind,
+    /// Let the kernel bind a driver to the GPU again
+    Bind,
+    /// Switch the GPU's hotplug slot off or on
+    Power {
+        #[arg(value_enum)]
+        state: Switch,
+    },
+    /// Rescan the PCI bus for devices
+    Rescan,
+}
+
+/// `Mode` as a command line argument; the core crate doesn't depend on clap.
+#[derive(Clone, Copy, ValueEnum)]
+enum ModeArg {
+    Standard,
+    Integrated,
+    Optimized,
+    Vfio,
+}
+
+impl From<ModeArg> for Mode {
+    fn from(mode: ModeArg) -> Self {
+        match mode {
+            ModeArg::Standard => Mode::Standard,
+            ModeArg::Integrated => Mode::Integrated,
+            ModeArg::Optimized => Mode::Optimized,
+            ModeArg::Vfio => Mode::Vfio,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Switch {
+    On,
+    Off,
+}
+
+#[derive(Subcommand)]
+enum ModuleAction {
+    Load,
+    Unload,
+}
+
+#[derive(Subcommand)]
+enum ServiceAction {
+    Start,
+    Stop,
+}
+
+fn parse_wake_duration(input: &str) -> Result<u32, String> {
+    let secs = history::parse_duration(input)?;
+    match u32::try_from(secs) {
+        Ok(secs) if secs > 0 => Ok(secs),
+        _ => Err(format!(
+            "Invalid duration '{}': must be more than 0s",
+            input
+        )),
+    }
+}
+
+fn parse_power_state(input: &str) -> Result<PowerState, String> {
+    ["D0", "D1", "D2", "D3hot", "D3cold"]
+        .into_iter()
+        .find(|state| state.eq_ignore_ascii_case(input))
+        .map(PowerState::from)
+        .ok_or_else(|| {
+            format!(
+                "Invalid power state '{}': expected d0, d1, d2, d3hot or d3cold",
+                input
+            )
+        })
+}
+
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
     let cli = Cli::parse();
+    output::init_color(cli.no_color);
+    output::set_verbosity(if cli.quiet {
+        Verbosity::Quiet
+    } else if cli.verbose {
+        Verbosity::Verbose
+    } else {
+        Verbosity::Normal
+    });
+    if cli.quiet {
+        // --direct reports each step through the event log.
+        eventlog::set_print_level(Level::Warn);
+    }
 
     // Default to displaying help if no subcommand is provided
     let command_enum = match cli.command {
@@ -48,14 +305,89 @@ async fn main() -> Result<()> {
         None => {
             use clap::CommandFactory;
             Cli::command().print_help()?;
-            return Ok(());
+            return Ok(ExitCode::SUCCESS);
         }
     };
 
     let (cmd, gui) = match command_enum {
         Commands::Status => (Command::Status, false),
-        Commands::Set { mode, gui } => (Command::Set(mode), gui),
+        Commands::Devices => (Command::Devices, false),
+        Commands::Capabilities => (Command::Capabilities, false),
+        Commands::Watch => (Command::Watch, false),
+        Commands::Top => (Command::Top, false),
+        Commands::Logs { follow } => (Command::Logs { follow }, false),
+        Commands::Events { follow } => (Command::Events { follow }, false),
+        Commands::Ps => (Command::Ps, false),
+        Commands::Kill { pids } => (Command::Kill { pids }, false),
+        Commands::History { limit } => (Command::History { limit }, false),
+        Commands::Stats => (Command::Stats, false),
+        Commands::Savings => (Command::Savings, false),
+        Commands::Explain => (Command::Explain, false),
+        Commands::Migrate => (Command::Migrate, false),
+        Commands::Run { command } => (Command::Run { command }, false),
+        Commands::Set {
+            mode,
+            gui,
+            wait,
+            timeout,
+            when_idle,
+            force,
+        } => (
+            Command::Set {
+                mode: mode.into(),
+                wait: wait.then_some(timeout),
+                when_idle,
+                force,
+            },
+            gui,
+        ),
         Commands::Delay { seconds } => (Command::Delay(seconds), false),
+        Commands::Bench { cycles } => (Command::Bench { cycles }, false),
+        Commands::Pci { action } => {
+            let step = match action {
+                PciAction::Unbind => ManualStep::Unbind,
+                PciAction::Bind => ManualStep::Bind,
+                PciAction::Power { state: Switch::Off } => ManualStep::PowerOff,
+                PciAction::Power { state: Switch::On } => ManualStep::PowerOn,
+                PciAction::Rescan => ManualStep::Rescan,
+            };
+            (Command::Step(step), false)
+        }
+        Commands::Module { action } => {
+            let step = match action {
+                ModuleAction::Load => ManualStep::LoadModules,
+                ModuleAction::Unload => ManualStep::UnloadModules,
+            };
+            (Command::Step(step), false)
+        }
+        Commands::Service { action } => {
+            let step = match action {
+                ServiceAction::Start => ManualStep::StartServices,
+                ServiceAction::Stop => ManualStep::StopServices,
+            };
+            (Command::Step(step), false)
+        }
+        Commands::Wake { duration, .. } => (Command::Wake { seconds: duration }, false),
+        Commands::Desktop { apps, remove } => {
+            return cli::desktop(&apps, remove).map(|()| ExitCode::SUCCESS)
+        }
+        Commands::Launcher { launcher, install } => {
+            return cli::launcher(launcher, install).map(|()| ExitCode::SUCCESS)
+        }
+        Commands::Install { no_enable } => {
+            return cli::install(no_enable).map(|()| ExitCode::SUCCESS)
+        }
+        Commands::Uninstall { purge } => {
+            return cli::uninstall(purge, cli.yes).map(|()| ExitCode::SUCCESS)
+        }
+        Commands::Report { output } => {
+            return cli::report(output).await.map(|()| ExitCode::SUCCESS)
+        }
+        Commands::WaitForState { state, timeout } => {
+            return cli::wait_for_state(state, timeout)
+                .await
+                .map(|()| ExitCode::SUCCESS)
+        }
         Commands::Completion { shell } => {
             use clap::CommandFactory;
             clap_complete::generate(
@@ -64,9 +396,25 @@ async fn main() -> Result<()> {
                 "nvsleepify",
                 &mut std::io::stdout(),
             );
-            return Ok(());
+            return Ok(ExitCode::SUCCESS);
         }
     };
 
-    client::run(cmd, gui).await
+    let sysfs_root = cli
+        .sysfs_root
+        .or_else(|| std::env::var_os(backend::SYSFS_ROOT_ENV).map(PathBuf::from));
+    if let Some(root) = sysfs_root {
+        if !cli.direct {
+            anyhow::bail!("--sysfs-root only applies with --direct; start nvsleepifyd with it to use it through the daemon");
+        }
+        backend::use_sysfs_root(root)?;
+    }
+
+    let opts = cli::Options {
+        gui,
+        direct: cli.direct,
+        yes: cli.yes,
+        socket: cli.socket,
+    };
+    cli::run(cmd, opts).await
 }
@@ -1,11 +1,66 @@
 use anyhow::Result;
-use nvsleepify::daemon;
+use clap::Parser;
+use nvsleepify::eventlog::{self, Level};
+use nvsleepify::{backend, config, daemon, helper};
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(name = "nvsleepifyd")]
+#[command(about = "nvsleepify daemon", long_about = None)]
+struct Cli {
+    /// Use this directory of fixture files instead of /sys; modprobe and systemctl are only printed (env: NVSLEEPIFY_SYSFS_ROOT)
+    #[arg(long, value_name = "DIR")]
+    sysfs_root: Option<PathBuf>,
+
+    /// Read settings from this file instead of /etc/nvsleepify/nvsleepifyd.toml
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Only print messages at or above this level: info, warn or error (`nvsleepify logs` still has all)
+    #[arg(long, value_name = "LEVEL", default_value = "info")]
+    log_level: Level,
+
+    /// Don't recover, restore or enforce anything at startup; the GPU is left alone until a mode is set
+    #[arg(long)]
+    no_restore: bool,
+
+    /// Running in a terminal rather than under systemd: prefix output with the time and level
+    #[arg(long)]
+    foreground: bool,
+
+    /// Serve org.nvsleepify.Debug to inject fake charger, process and power state readings, for testing policies and frontends
+    #[arg(long)]
+    simulate: bool,
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    if unsafe { libc::geteuid() } != 0 {
-        eprintln!("Daemon must run as root.");
-        std::process::exit(1);
+    let cli = Cli::parse();
+    eventlog::set_print_level(cli.log_level);
+    eventlog::set_timestamps(cli.foreground);
+    if let Some(path) = cli.config {
+        config::use_daemon_config(path)?;
+    }
+    let sysfs_root = cli
+        .sysfs_root
+        .or_else(|| std::env::var_os(backend::SYSFS_ROOT_ENV).map(PathBuf::from));
+    if let Some(root) = sysfs_root {
+        // A fake tree needs no privileges, so the helper isn't involved.
+        backend::use_sysfs_root(root)?;
+    } else if unsafe { libc::geteuid() } != 0 {
+        // Unprivileged: nvsleepify-helper does what needs root.
+        if !std::path::Path::new(helper::SOCKET_PATH).exists() {
+            eprintln!(
+                "Daemon must run as root, or with nvsleepify-helper listening on {}.",
+                helper::SOCKET_PATH
+            );
+            std::process::exit(1);
+        }
+        helper::use_socket(helper::SOCKET_PATH);
     }
-    daemon::run().await
+    daemon::run(daemon::RunOptions {
+        no_restore: cli.no_restore,
+        simulate: cli.simulate,
+    })
+    .await
 }
@@ -0,0 +1,1660 @@
+use crate::client::{ensure_daemon_running, is_daemon_running, NvSleepifyManagerProxy};
+use crate::config::DaemonConfig;
+use crate::daemon;
+use crate::desktop;
+use crate::display::{self, Evidence};
+use crate::display_manager;
+use crate::driver;
+use crate::error::Error;
+use crate::eventlog::{format_timestamp, unix_now};
+use crate::history::format_duration;
+use crate::install;
+use crate::launcher::{self, Launcher};
+use crate::migrate;
+use crate::output;
+use crate::pci::{self, PciDevice};
+use crate::platform;
+use crate::protocol::{
+    Capabilities, Command, DeviceInfo, ManualStep, Mode, PowerState, ProcessInfo, ProcessList,
+    StatusReport, StepTimings, TransitionResult,
+};
+use crate::report;
+use crate::rpc::{Call, SocketClient};
+use crate::savings::DischargeReport;
+use crate::state;
+use crate::sysfs;
+use crate::system;
+use crate::tr;
+use crate::xorg;
+use anyhow::{anyhow, Context, Result};
+use colored::*;
+use futures_util::StreamExt;
+use std::collections::HashSet;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use tokio::task::spawn_blocking;
+use zbus::Connection;
+
+/// Flags that apply across subcommands.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Show GUI dialogs instead of terminal prompts.
+    pub gui: bool,
+    /// Run the daemon logic in-process instead of going through D-Bus.
+    pub direct: bool,
+    /// Assume "yes" for confirmation prompts.
+    pub yes: bool,
+    /// Talk to the daemon over its JSON-RPC socket instead of D-Bus.
+    pub socket: Option<PathBuf>,
+}
+
+/// Where commands are carried out: by the daemon over D-Bus or its socket
+/// (`--socket`), or in-process with the daemon's own logic when running with
+/// `--direct`.
+enum Backend<'a> {
+    Daemon(NvSleepifyManagerProxy<'a>),
+    Socket(SocketClient),
+    Direct,
+}
+
+/// How `nvsleepify run` keeps the GPU awake; see `Backend::hold_awake`.
+enum WakeHold {
+    Lock(u32),
+    Renewed(tokio::task::JoinHandle<()>),
+    Direct,
+}
+
+/// Length of the temporary wake `run` renews over the socket, and how often
+/// it's renewed. If the CLI dies, the saved mode applies again within this.
+const RUN_WAKE_SECS: u32 = 60;
+const RUN_WAKE_RENEW: std::time::Duration = std::time::Duration::from_secs(20);
+
+impl Backend<'_> {
+    /// For features that only exist in a running daemon (signals, event history).
+    fn daemon(&self, feature: &str) -> Result<&NvSleepifyManagerProxy<'_>> {
+        match self {
+            Backend::Daemon(proxy) => Ok(proxy),
+            Backend::Socket(_) => Err(anyhow!(
+                "{} needs D-Bus signals and is not available with --socket",
+                feature
+            )),
+            Backend::Direct => Err(anyhow!(
+                "{} requires the nvsleepify daemon and is not available with --direct",
+                feature
+            )),
+        }
+    }
+
+    async fn status(&self) -> Result<String> {
+        match self {
+            Backend::Daemon(proxy) => Ok(proxy.status().await?),
+            Backend::Socket(socket) => Ok(socket.call(Call::Status).await?),
+            Backend::Direct => Ok(spawn_blocking(daemon::status_logic).await?),
+        }
+    }
+
+    async fn devices(&self) -> Result<Vec<DeviceInfo>> {
+        match self {
+            Backend::Daemon(proxy) => Ok(proxy.get_devices().await?),
+            Backend::Socket(socket) => Ok(socket.call(Call::GetDevices).await?),
+            Backend::Direct => Ok(spawn_blocking(daemon::devices_logic).await?),
+        }
+    }
+
+    async fn capabilities(&self) -> Result<Capabilities> {
+        match self {
+            Backend::Daemon(proxy) => Ok(proxy.get_capabilities().await?),
+            Backend::Socket(socket) => Ok(socket.call(Call::GetCapabilities).await?),
+            Backend::Direct => Ok(spawn_blocking(platform::probe).await?),
+        }
+    }
+
+    async fn info(&self) -> Result<StatusReport> {
+        match self {
+            Backend::Daemon(proxy) => Ok(proxy.info().await?),
+            Backend::Socket(socket) => Ok(socket.call(Call::Info).await?),
+            Backend::Direct => Ok(spawn_blocking(daemon::info_logic).await?),
+        }
+    }
+
+    async fn explain(&self) -> Result<String> {
+        match self {
+            Backend::Socket(socket) => Ok(socket.call(Call::Explain).await?),
+            _ => Ok(self
+                .daemon("explain")?
+                .explain()
+                .await
+                .map_err(Error::from)?),
+        }
+    }
+
+    /// `kill_procs`: the user agreed to killing the processes holding the GPU.
+    /// `force`: skip the display check.
+    async fn set_mode(
+        &self,
+        mode: Mode,
+        kill_procs: bool,
+        force: bool,
+    ) -> Result<TransitionResult, Error> {
+        match self {
+            Backend::Daemon(proxy) if force => {
+                Ok(proxy.set_mode_forced(mode.to_string(), kill_procs).await?)
+            }
+            Backend::Daemon(proxy) => Ok(proxy
+                .set_mode_with_options(mode.to_string(), kill_procs)
+                .await?),
+            Backend::Socket(socket) => {
+                socket
+                    .call(Call::SetMode {
+                        mode: mode.to_string(),
+                        kill_procs,
+                        force,
+                    })
+                    .await
+            }
+            Backend::Direct => {
+                spawn_blocking(move || daemon::set_mode_logic(&mode.to_string(), kill_procs, force))
+                    .await
+                    .unwrap_or_else(|e| Err(Error::Failed(e.to_string())))
+            }
+        }
+    }
+
+    async fn sleep_when_idle(&self) -> Result<TransitionResult, Error> {
+        match self {
+            Backend::Daemon(proxy) => Ok(proxy.sleep_when_idle().await?),
+            Backend::Socket(socket) => socket.call(Call::SleepWhenIdle).await,
+            Backend::Direct => Err(Error::InvalidArgs(
+                "--when-idle needs the daemon to watch the processes and is not available with --direct"
+                    .to_string(),
+            )),
+        }
+    }
+
+    async fn kill_processes(&self, pids: Vec<u32>) -> Result<String, Error> {
+        match self {
+            Backend::Daemon(proxy) => Ok(proxy.kill_processes(pids).await?),
+            Backend::Socket(socket) => socket.call(Call::KillProcesses { pids }).await,
+            Backend::Direct => spawn_blocking(move || daemon::kill_logic(&pids))
+                .await
+                .unwrap_or_else(|e| Err(Error::Failed(e.to_string()))),
+        }
+    }
+
+    async fn run_step(&self, step: ManualStep) -> Result<String, Error> {
+        match self {
+            Backend::Daemon(proxy) => Ok(proxy.run_step(step.to_string()).await?),
+            Backend::Socket(socket) => {
+                socket
+                    .call(Call::RunStep {
+                        step: step.to_string(),
+                    })
+                    .await
+            }
+            Backend::Direct => spawn_blocking(move || daemon::run_step_logic(step))
+                .await
+                .unwrap_or_else(|e| Err(Error::Failed(e.to_string()))),
+        }
+    }
+
+    async fn bench(&self, cycles: u32) -> Result<Vec<StepTimings>, Error> {
+        match self {
+            Backend::Daemon(proxy) => Ok(proxy.bench(cycles).await?),
+            Backend::Socket(socket) => socket.call(Call::Bench { cycles }).await,
+            Backend::Direct => spawn_blocking(move || daemon::bench_logic(cycles))
+                .await
+                .unwrap_or_else(|e| Err(Error::Failed(e.to_string()))),
+        }
+    }
+
+    async fn wake_for(&self, seconds: u32) -> Result<String> {
+        match self {
+            Backend::Socket(socket) => Ok(socket.call(Call::WakeFor { seconds }).await?),
+            _ => Ok(self
+                .daemon("wake")?
+                .wake_for(seconds)
+                .await
+                .map_err(Error::from)?),
+        }
+    }
+
+    async fn cancel_wake(&self) -> Result<String> {
+        match self {
+            Backend::Socket(socket) => Ok(socket.call(Call::CancelWake).await?),
+            _ => Ok(self
+                .daemon("wake")?
+                .cancel_wake()
+                .await
+                .map_err(Error::from)?),
+        }
+    }
+
+    async fn kill_process(&self, process: &ProcessInfo) -> Result<String, Error> {
+        let pid: u32 = process
+            .pid
+            .parse()
+            .map_err(|_| Error::InvalidArgs(format!("Invalid PID {}", process.pid)))?;
+        let name = process.name.clone();
+        match self {
+            Backend::Daemon(proxy) => Ok(proxy.kill_process(pid, name).await?),
+            Backend::Socket(socket) => socket.call(Call::KillProcess { pid, name }).await,
+            Backend::Direct => spawn_blocking(move || daemon::kill_one_logic(pid, &name))
+                .await
+                .unwrap_or_else(|e| Err(Error::Failed(e.to_string()))),
+        }
+    }
+
+    /// Keeps the GPU awake for `nvsleepify run` without touching the saved
+    /// mode, so a crash before `release_awake` can't leave it changed. Over
+    /// D-Bus this is a wake lock, which the daemon drops when our connection
+    /// goes away. The socket has no connection to tie one to, so a short
+    /// temporary wake is renewed while the program runs. In-process, the GPU
+    /// is just woken.
+    async fn hold_awake(&self, reason: &str) -> Result<WakeHold, Error> {
+        match self {
+            Backend::Daemon(proxy) => Ok(WakeHold::Lock(
+                proxy
+                    .acquire_wake_lock("nvsleepify run".to_string(), reason.to_string())
+                    .await?,
+            )),
+            Backend::Socket(socket) => {
+                let renew = Call::WakeFor {
+                    seconds: RUN_WAKE_SECS,
+                };
+                socket.call::<String>(renew.clone()).await?;
+                let socket = socket.clone();
+                Ok(WakeHold::Renewed(tokio::spawn(async move {
+                    loop {
+                        tokio::time::sleep(RUN_WAKE_RENEW).await;
+                        if let Err(e) = socket.call::<String>(renew.clone()).await {
+                            let warning = tr!("run-renew-failed", error = e.to_string());
+                            eprintln!("{}", warning.yellow());
+                        }
+                    }
+                })))
+            }
+            Backend::Direct => spawn_blocking(daemon::wake_logic)
+                .await
+                .unwrap_or_else(|e| Err(Error::Failed(e.to_string())))
+                .map(|_| WakeHold::Direct),
+        }
+    }
+
+    /// Ends `hold`, letting the saved mode, `mode`, apply again.
+    async fn release_awake(&self, hold: WakeHold, mode: Mode, yes: bool) -> Result<()> {
+        output::info(tr!("run-restoring", mode = mode.to_string()));
+        let result = match (self, hold) {
+            (Backend::Daemon(proxy), WakeHold::Lock(id)) => proxy
+                .release_wake_lock(id)
+                .await
+                .map_err(Error::from)
+                .map(|_| ()),
+            (_, WakeHold::Renewed(renew)) => {
+                renew.abort();
+                self.cancel_wake()
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| Error::Failed(e.to_string()))
+            }
+            (_, _) => return restore_mode(self, mode, yes).await,
+        };
+        if let Err(e) = result {
+            let error = tr!(
+                "run-restore-failed",
+                mode = mode.to_string(),
+                error = e.to_string()
+            );
+            println!("{}", error.red());
+        }
+        Ok(())
+    }
+
+    async fn set_restore_delay(&self, seconds: u32) -> Result<String> {
+        match self {
+            Backend::Daemon(proxy) => Ok(proxy
+                .set_restore_delay(seconds)
+                .await
+                .map_err(Error::from)?),
+            Backend::Socket(socket) => Ok(socket.call(Call::SetRestoreDelay { seconds }).await?),
+            Backend::Direct => Ok(spawn_blocking(move || state::save_delay(seconds))
+                .await?
+                .map(|_| format!("Restore delay set to {} seconds", seconds))?),
+        }
+    }
+}
+
+#[cfg(feature = "gui-dialogs")]
+fn confirm_kill_processes(procs: &[ProcessInfo]) -> bool {
+    if procs.is_empty() {
+        return true;
+    }
+
+    let mut text = tr!("confirm-blockers");
+    text.push_str("\n\n");
+    for proc in procs {
+        text.push_str(&format!("- {}\n", proc));
+    }
+
+    let result = rfd::MessageDialog::new()
+        .set_title("nvsleepify")
+        .set_description(&text)
+        .set_buttons(rfd::MessageButtons::YesNo)
+        .show();
+
+    matches!(result, rfd::MessageDialogResult::Yes)
+}
+
+/// Built without the `gui-dialogs` feature: ask on the terminal instead.
+#[cfg(not(feature = "gui-dialogs"))]
+fn confirm_kill_processes(procs: &[ProcessInfo]) -> bool {
+    eprintln!("{}", tr!("confirm-no-gui").yellow());
+    confirm_kill_processes_cli(procs, false)
+}
+
+/// Asks for confirmation on the terminal. `--yes` skips the prompt; without a
+/// terminal to ask on (scripts, pipes) we refuse rather than block.
+fn confirm_cli(yes: bool) -> bool {
+    confirm_cli_with(&tr!("confirm-proceed"), yes)
+}
+
+fn confirm_cli_with(prompt: &str, yes: bool) -> bool {
+    if yes {
+        return true;
+    }
+    if !std::io::stdin().is_terminal() {
+        eprintln!("{}", tr!("confirm-no-terminal").red());
+        return false;
+    }
+
+    dialoguer::Confirm::new()
+        .with_prompt(prompt)
+        .default(false)
+        .interact()
+        .unwrap_or(false)
+}
+
+fn confirm_kill_processes_cli(procs: &[ProcessInfo], yes: bool) -> bool {
+    if procs.is_empty() {
+        return true;
+    }
+
+    println!("{}", tr!("confirm-blockers").yellow());
+    for proc in procs {
+        println!("- {}", proc);
+    }
+    println!();
+
+    confirm_cli(yes)
+}
+
+/// Unix timestamp of the most recent local midnight.
+fn local_midnight(now: u64) -> u64 {
+    let t = now as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&t, &mut tm) }.is_null() {
+        return now - now % 86400;
+    }
+    tm.tm_hour = 0;
+    tm.tm_min = 0;
+    tm.tm_sec = 0;
+    let midnight = unsafe { libc::mktime(&mut tm) };
+    if midnight < 0 {
+        now - now % 86400
+    } else {
+        midnight as u64
+    }
+}
+
+/// Enough to cover today for the "time asleep" total, whatever `limit` is.
+const HISTORY_FETCH: u32 = 1000;
+
+async fn show_history(proxy: &NvSleepifyManagerProxy<'_>, limit: u32) -> Result<()> {
+    let records = proxy.get_history(HISTORY_FETCH.max(limit)).await?;
+    if records.is_empty() {
+        println!("{}", tr!("history-none"));
+        return Ok(());
+    }
+
+    let now = unix_now();
+    // Each record's state lasts until the next record (or until now for the last one).
+    let ends: Vec<u64> = records
+        .iter()
+        .skip(1)
+        .map(|(timestamp, _, _, _)| *timestamp)
+        .chain(std::iter::once(now))
+        .collect();
+
+    println!(
+        "{:<19}  {:<20} {:<12} {}",
+        tr!("history-time"),
+        tr!("history-transition"),
+        tr!("history-duration"),
+        tr!("history-trigger")
+    );
+    let start = records.len().saturating_sub(limit as usize);
+    for ((timestamp, from, to, trigger), end) in records.iter().zip(&ends).skip(start) {
+        let mut duration = format_duration(end.saturating_sub(*timestamp));
+        if *end == now {
+            duration.push('+');
+        }
+        let transition = format!("{} -> {}", from, to);
+        let transition = if to.is_asleep() {
+            transition.green()
+        } else {
+            transition.normal()
+        };
+        println!(
+            "{:<19}  {:<20} {:<12} {}",
+            format_timestamp(*timestamp),
+            transition,
+            duration,
+            trigger
+        );
+    }
+
+    let midnight = local_midnight(now);
+    let asleep_today: u64 = records
+        .iter()
+        .zip(&ends)
+        .filter(|((_, _, to, _), _)| to.is_asleep())
+        .map(|((timestamp, _, _, _), end)| end.saturating_sub((*timestamp).max(midnight)))
+        .sum();
+    println!();
+    println!(
+        "{}",
+        tr!(
+            "history-asleep-today",
+            asleep = format_duration(asleep_today),
+            elapsed = format_duration(now - midnight)
+        )
+    );
+
+    // Kernel faults within the transitions shown; daemons without the method have none.
+    let since = records[start].0;
+    let faults = proxy.get_faults(HISTORY_FETCH).await.unwrap_or_default();
+    let faults: Vec<_> = faults
+        .iter()
+        .filter(|(timestamp, _, _)| *timestamp >= since)
+        .collect();
+    if !faults.is_empty() {
+        println!();
+        println!(
+            "{:<19}  {:<20} {}",
+            tr!("history-time"),
+            tr!("history-fault"),
+            tr!("history-message")
+        );
+        for (timestamp, kind, message) in faults {
+            println!(
+                "{:<19}  {:<20} {}",
+                format_timestamp(*timestamp),
+                kind.red(),
+                message
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn show_savings(proxy: &NvSleepifyManagerProxy<'_>) -> Result<()> {
+    let (asleep_secs, asleep_joules, awake_secs, awake_joules, energy_wh) =
+        proxy.get_discharge_report().await?;
+    let report =
+        DischargeReport::from_tuple((asleep_secs, asleep_joules, awake_secs, awake_joules));
+    let energy = (energy_wh > 0.0).then_some(energy_wh);
+    let hours = |hours: f64| format_duration((hours * 3600.0) as u64);
+
+    println!(
+        "{:<15} {:<11} {:<14} {}",
+        tr!("savings-on-battery"),
+        tr!("savings-time"),
+        tr!("savings-draw"),
+        tr!("savings-runtime")
+    );
+    for (label, discharge) in [
+        (tr!("savings-asleep"), report.asleep),
+        (tr!("savings-awake"), report.awake),
+    ] {
+        let draw = discharge
+            .watts()
+            .map(|watts| format!("{:.1} W", watts))
+            .unwrap_or_else(|| tr!("savings-not-measured"));
+        let runtime = energy
+            .and_then(|wh| discharge.runtime_hours(wh))
+            .map(hours)
+            .unwrap_or_else(|| "-".to_string());
+        println!(
+            "{:<15} {:<11} {:<14} {}",
+            label,
+            format_duration(discharge.secs as u64),
+            draw,
+            runtime
+        );
+    }
+    println!();
+    match (report.asleep.watts(), report.awake.watts()) {
+        (Some(asleep), Some(awake)) => {
+            println!(
+                "{}",
+                tr!(
+                    "savings-saved",
+                    watts = format!("{:.1}", awake - asleep),
+                    percent = format!("{:.0}", (awake - asleep) / awake * 100.0)
+                )
+            );
+            if let Some(wh) = energy {
+                let gained = wh / asleep - wh / awake;
+                println!(
+                    "{}",
+                    tr!(
+                        "savings-gained",
+                        energy = format!("{:.1}", wh),
+                        runtime = hours(gained.max(0.0))
+                    )
+                );
+            }
+        }
+        _ => println!("{}", tr!("savings-no-data")),
+    }
+    Ok(())
+}
+
+async fn show_stats(proxy: &NvSleepifyManagerProxy<'_>) -> Result<()> {
+    let (since, sleeps, wakes, d3cold_secs, retries, failures) = proxy.get_stats().await?;
+    println!("{}", tr!("stats-since", time = format_timestamp(since)));
+    println!("  {}", tr!("stats-sleeps", count = sleeps));
+    println!("  {}", tr!("stats-wakes", count = wakes));
+    println!(
+        "  {}",
+        tr!("stats-d3cold", duration = format_duration(d3cold_secs))
+    );
+    println!("  {}", tr!("stats-retries", count = retries));
+    let failures = if failures == 0 {
+        failures.to_string().normal()
+    } else {
+        failures.to_string().red()
+    };
+    println!("  {}", tr!("stats-failures", count = failures.to_string()));
+    Ok(())
+}
+
+/// Min, average and max of each step, and of whole cycles.
+fn show_bench(timings: &[StepTimings]) {
+    let cycles = timings.iter().map(|t| t.seconds.len()).max().unwrap_or(0);
+    let totals: Vec<f64> = (0..cycles)
+        .map(|cycle| timings.iter().filter_map(|t| t.seconds.get(cycle)).sum())
+        .collect();
+    let row = |name: &str, seconds: &[f64]| {
+        let min = seconds.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = seconds.iter().copied().fold(0.0, f64::max);
+        let avg = seconds.iter().sum::<f64>() / seconds.len().max(1) as f64;
+        format!("{:<18} {:>8.2}s {:>8.2}s {:>8.2}s", name, min, avg, max)
+    };
+    println!(
+        "{:<18} {:>9} {:>9} {:>9}",
+        tr!("bench-step"),
+        tr!("bench-min"),
+        tr!("bench-avg"),
+        tr!("bench-max")
+    );
+    for timing in timings {
+        println!("{}", row(&timing.step, &timing.seconds));
+    }
+    println!("{}", row(&tr!("bench-whole-cycle"), &totals).bold());
+}
+
+fn print_log_entry(timestamp: u64, level: &str, message: &str) {
+    let level = match level {
+        "ERROR" => level.red(),
+        "WARN" => level.yellow(),
+        _ => level.normal(),
+    };
+    println!("[{}] {:<5} {}", format_timestamp(timestamp), level, message);
+}
+
+async fn logs(proxy: &NvSleepifyManagerProxy<'_>, follow: bool) -> Result<()> {
+    // Subscribe before fetching history so nothing falls in between.
+    let mut stream = if follow {
+        Some(proxy.receive_log_entry().await?)
+    } else {
+        None
+    };
+
+    for (timestamp, level, message) in proxy.get_logs().await? {
+        print_log_entry(timestamp, &level, &message);
+    }
+
+    if let Some(stream) = stream.as_mut() {
+        while let Some(signal) = stream.next().await {
+            let args = signal.args()?;
+            print_log_entry(args.timestamp, &args.level, &args.message);
+        }
+    }
+    Ok(())
+}
+
+fn print_event(timestamp: u64, kind: &str, message: &str) {
+    let kind_label = format!("{:<11}", kind);
+    let kind_label = match kind {
+        "failure" => kind_label.red(),
+        "enforcement" => kind_label.yellow(),
+        "policy" => kind_label.cyan(),
+        "transition" => kind_label.green(),
+        _ => kind_label.normal(),
+    };
+    println!(
+        "[{}] {} {}",
+        format_timestamp(timestamp),
+        kind_label,
+        message
+    );
+}
+
+async fn events(proxy: &NvSleepifyManagerProxy<'_>, follow: bool) -> Result<()> {
+    // Subscribe before fetching recent events so nothing falls in between.
+    let mut stream = if follow {
+        Some(proxy.receive_event().await?)
+    } else {
+        None
+    };
+
+    for (timestamp, kind, message) in proxy.get_events().await? {
+        print_event(timestamp, &kind, &message);
+    }
+
+    if let Some(stream) = stream.as_mut() {
+        while let Some(signal) = stream.next().await {
+            let args = signal.args()?;
+            print_event(args.timestamp, &args.kind, &args.message);
+        }
+    }
+    Ok(())
+}
+
+/// Prints the processes holding the GPU. Exits with status 1 if there are none,
+/// so scripts can use `nvsleepify ps` as a check.
+async fn ps(backend: &Backend<'_>) -> Result<ExitCode> {
+    let procs = backend.info().await?.processes;
+    if procs.is_empty() {
+        output::info(tr!("ps-none"));
+        return Ok(ExitCode::FAILURE);
+    }
+
+    println!("{:>8}  {:<12} {:<16} COMMAND", "PID", "USER", "NAME");
+    for proc in &procs {
+        let details = system::get_process_details(&proc.pid);
+        println!(
+            "{:>8}  {:<12} {:<16} {}",
+            proc.pid, details.user, proc.name, details.cmdline
+        );
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn kill(backend: &Backend<'_>, pids: Vec<u32>, yes: bool) -> Result<()> {
+    let targets: ProcessList = backend
+        .info()
+        .await?
+        .processes
+        .into_iter()
+        .filter(|proc| {
+            pids.is_empty() || proc.pid.parse::<u32>().is_ok_and(|pid| pids.contains(&pid))
+        })
+        .collect();
+    if targets.is_empty() {
+        output::info(tr!("kill-none"));
+        return Ok(());
+    }
+
+    // Without PIDs, let the user pick which ones to kill.
+    if pids.is_empty() && !yes && targets.len() > 1 && std::io::stdin().is_terminal() {
+        let Some(chosen) = choose_processes(&targets) else {
+            println!("{}", tr!("aborted"));
+            return Ok(());
+        };
+        return kill_each(backend, &chosen).await;
+    }
+
+    println!("{}", tr!("kill-targets").yellow());
+    for proc in &targets {
+        println!("- {}", proc);
+    }
+    println!();
+
+    if !confirm_cli(yes) {
+        println!("{}", tr!("aborted"));
+        return Ok(());
+    }
+
+    let pids = targets
+        .iter()
+        .filter_map(|proc| proc.pid.parse::<u32>().ok())
+        .collect();
+    match backend.kill_processes(pids).await {
+        Ok(msg) => output::info(msg.green()),
+        Err(e) => {
+            for proc in e.processes() {
+                println!(
+                    "  {}",
+                    tr!("kill-still-running", process = proc.to_string())
+                );
+            }
+            return Err(e.into());
+        }
+    }
+    Ok(())
+}
+
+/// Asks which of `procs` to kill. None if the user picked none or cancelled.
+fn choose_processes(procs: &[ProcessInfo]) -> Option<ProcessList> {
+    let chosen = dialoguer::MultiSelect::new()
+        .with_prompt(tr!("choose-kill"))
+        .items(procs)
+        .interact()
+        .ok()?;
+    let chosen: ProcessList = chosen.into_iter().map(|i| procs[i].clone()).collect();
+    (!chosen.is_empty()).then_some(chosen)
+}
+
+/// Kills each process by PID and name, so one that exited and had its PID
+/// reused in the meantime is left alone.
+async fn kill_each(backend: &Backend<'_>, procs: &[ProcessInfo]) -> Result<()> {
+    let mut failed = false;
+    for proc in procs {
+        match backend.kill_process(proc).await {
+            Ok(msg) => output::info(format!("{}: {}", proc, msg.green())),
+            Err(e) => {
+                println!("{}", format!("{}: {}", proc, e).red());
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        return Err(anyhow!(tr!("kill-some-failed")));
+    }
+    Ok(())
+}
+
+/// Whether the GPU has settled in the state `mode` is supposed to produce.
+/// Returns the observed power state alongside for error reporting.
+fn gpu_reached_target(mode: Mode) -> (bool, PowerState) {
+    let want_awake = match mode {
+        Mode::Standard => true,
+        Mode::Integrated => false,
+        Mode::Optimized => system::get_charging_status(),
+        Mode::Vfio => true,
+    };
+    match PciDevice::find_nvidia_gpu() {
+        Ok(gpu) => {
+            let state = gpu.get_power_state();
+            let reached = if mode == Mode::Vfio {
+                state == PowerState::D0 && gpu.driver().as_deref() == Some(pci::VFIO_DRIVER)
+            } else if want_awake {
+                // Compute-only wakes create no DRM nodes, so check the driver.
+                state == PowerState::D0
+                    && matches!(
+                        gpu.driver().as_deref(),
+                        Some("nvidia" | driver::NOUVEAU_MODULE)
+                    )
+            } else {
+                state == PowerState::D3cold
+            };
+            (reached, state)
+        }
+        // A powered-off slot removes the device from the bus entirely.
+        Err(_) => (!want_awake, PowerState::NotFound),
+    }
+}
+
+async fn wait_for_target(mode: Mode, timeout_secs: u64) -> Result<()> {
+    let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(timeout_secs);
+    loop {
+        let (reached, state) = tokio::task::spawn_blocking(move || gpu_reached_target(mode))
+            .await
+            .unwrap_or((false, PowerState::Unknown));
+        if reached {
+            output::info(tr!("wait-reached", state = state.to_string()));
+            return Ok(());
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return Err(anyhow!(
+                "Timed out after {}s waiting for the GPU to settle in {} mode (power state: {})",
+                timeout_secs,
+                mode,
+                state
+            ));
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    }
+}
+
+/// Blocks until the GPU is in `target`, failing after `timeout_secs` if
+/// given. A GPU off the bus counts as D3cold.
+pub async fn wait_for_state(target: PowerState, timeout_secs: Option<u64>) -> Result<()> {
+    let deadline =
+        timeout_secs.map(|secs| tokio::time::Instant::now() + std::time::Duration::from_secs(secs));
+    loop {
+        let state = sysfs::power_state().await;
+        let reached =
+            state == target || (target == PowerState::D3cold && state == PowerState::NotFound);
+        if reached {
+            output::info(tr!("wait-reached", state = state.to_string()));
+            return Ok(());
+        }
+        if deadline.is_some_and(|deadline| tokio::time::Instant::now() >= deadline) {
+            return Err(anyhow!(
+                "Timed out after {}s waiting for the GPU to reach {} (power state: {})",
+                timeout_secs.unwrap_or_default(),
+                target,
+                state
+            ));
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    }
+}
+
+/// Environment for PRIME render offload onto the Nvidia GPU.
+const OFFLOAD_ENV: [(&str, &str); 3] = [
+    ("__NV_PRIME_RENDER_OFFLOAD", "1"),
+    ("__GLX_VENDOR_LIBRARY_NAME", "nvidia"),
+    ("__VK_LAYER_NV_optimus", "NVIDIA_only"),
+];
+
+/// Wakes the GPU if needed, runs `command` with render offload enabled and lets
+/// the saved mode apply again once it exits. Returns the command's status.
+async fn run_offloaded(backend: &Backend<'_>, command: Vec<String>, yes: bool) -> Result<ExitCode> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| anyhow!("No command given"))?;
+
+    let previous = backend.info().await?.mode();
+    if previous == Mode::Vfio {
+        return Err(anyhow!(tr!("run-vfio")));
+    }
+    let hold = if previous != Mode::Standard {
+        output::info(tr!("run-waking", mode = previous.to_string()));
+        let hold = backend
+            .hold_awake(&format!("Running {}", program))
+            .await
+            .map_err(|e| anyhow!("Failed to wake GPU: {}", e))?;
+        wait_for_target(Mode::Standard, 30).await?;
+        Some(hold)
+    } else {
+        None
+    };
+
+    // Ctrl+C reaches the child through the terminal; we stay alive to restore the mode.
+    let _sigint = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt())?;
+
+    for (name, value) in OFFLOAD_ENV {
+        output::detail(format!("{}={}", name, value));
+    }
+
+    let status = tokio::process::Command::new(program)
+        .args(args)
+        .envs(OFFLOAD_ENV)
+        .status()
+        .await;
+
+    if let Some(hold) = hold {
+        backend.release_awake(hold, previous, yes).await?;
+    }
+
+    let status = status.map_err(|e| anyhow!("Failed to run {}: {}", program, e))?;
+    Ok(status
+        .code()
+        .and_then(|code| u8::try_from(code).ok())
+        .map_or(ExitCode::FAILURE, ExitCode::from))
+}
+
+/// Applies `mode` again after `run` woke the GPU in-process.
+async fn restore_mode(backend: &Backend<'_>, mode: Mode, yes: bool) -> Result<()> {
+    if mode.kills_processes() {
+        let processes = backend.info().await?.processes;
+        if !confirm_kill_processes_cli(&processes, yes) {
+            output::info(tr!("run-leaving-awake"));
+            return Ok(());
+        }
+    }
+
+    if let Err(e) = backend.set_mode(mode, mode.kills_processes(), false).await {
+        let error = tr!(
+            "run-restore-failed",
+            mode = mode.to_string(),
+            error = e.to_string()
+        );
+        println!("{}", error.red());
+    }
+    Ok(())
+}
+
+async fn draw_watch(proxy: &NvSleepifyManagerProxy<'_>) -> Result<()> {
+    let StatusReport {
+        mode,
+        power_state,
+        processes: procs,
+        pending_sleep,
+    } = proxy.info().await?;
+    // Older daemons don't track transitions; just leave the line out.
+    let transition = proxy.last_transition().await.ok();
+
+    // Clear screen and move the cursor home before redrawing.
+    print!("\x1B[2J\x1B[H");
+    println!("{}", tr!("watch-title").bold());
+    println!();
+    println!("{}", tr!("watch-mode", mode = mode.to_string()));
+    println!("{}", tr!("watch-power", state = power_state.to_string()));
+    match transition {
+        Some((from, to, timestamp)) if timestamp > 0 => {
+            let now = unix_now();
+            println!(
+                "{}",
+                tr!(
+                    "watch-last-change",
+                    from = from.to_string(),
+                    to = to.to_string(),
+                    ago = format_duration(now.saturating_sub(timestamp))
+                )
+            );
+        }
+        _ => println!("{}", tr!("watch-no-change")),
+    }
+    if procs.is_empty() {
+        println!("{}", tr!("watch-no-blockers"));
+    } else {
+        println!("{}", tr!("watch-blockers", count = procs.len()).yellow());
+        for proc in &procs {
+            println!("  {}", proc);
+        }
+    }
+    if pending_sleep {
+        println!("{}", tr!("watch-pending"));
+    }
+    Ok(())
+}
+
+async fn watch(proxy: &NvSleepifyManagerProxy<'_>) -> Result<()> {
+    // Prefer the daemon's StateChanged signal; keep a slow poll so blockers
+    // (which don't trigger signals) still refresh, and poll faster if the
+    // subscription can't be set up.
+    let mut signals = proxy.receive_state_changed().await.ok();
+    let poll_secs = if signals.is_some() { 5 } else { 2 };
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(poll_secs));
+
+    loop {
+        draw_watch(proxy).await?;
+        let mut stream_ended = false;
+        match signals.as_mut() {
+            Some(stream) => {
+                tokio::select! {
+                    signal = stream.next() => stream_ended = signal.is_none(),
+                    _ = interval.tick() => {}
+                }
+            }
+            None => {
+                interval.tick().await;
+            }
+        }
+        if stream_ended {
+            signals = None;
+        }
+    }
+}
+
+/// Redraws the `top` view. `seen` holds the PIDs listed last time, so
+/// newcomers stand out; None on the first draw.
+async fn draw_top(
+    proxy: &NvSleepifyManagerProxy<'_>,
+    seen: &mut Option<HashSet<String>>,
+) -> Result<()> {
+    let StatusReport {
+        mode,
+        power_state,
+        mut processes,
+        ..
+    } = proxy.info().await?;
+    // nvidia-smi would keep an idle GPU awake; with processes on it, it's busy anyway.
+    let memory = if power_state == PowerState::D0 && !processes.is_empty() {
+        spawn_blocking(|| {
+            PciDevice::find_nvidia_gpu()
+                .ok()
+                .and_then(|gpu| gpu.nvidia_smi_memory())
+        })
+        .await
+        .ok()
+        .flatten()
+    } else {
+        None
+    };
+    let memory_of = |proc: &ProcessInfo| {
+        let pid = proc.pid.parse::<u32>().ok()?;
+        memory.as_ref()?.get(&pid).copied()
+    };
+    processes.sort_by_key(|proc| (std::cmp::Reverse(memory_of(proc)), proc.pid.clone()));
+
+    print!("\x1B[2J\x1B[H");
+    println!("{}", tr!("top-title").bold());
+    println!();
+    println!("{}", tr!("watch-mode", mode = mode.to_string()));
+    println!("{}", tr!("watch-power", state = power_state.to_string()));
+    println!();
+    if processes.is_empty() {
+        println!("{}", tr!("top-no-processes"));
+    } else {
+        println!(
+            "{}",
+            format!("{:>8}  {:>10}  NAME", "PID", "GPU MEMORY").bold()
+        );
+        for proc in &processes {
+            let memory = memory_of(proc).map_or("-".to_string(), |mib| format!("{} MiB", mib));
+            let line = format!("{:>8}  {:>10}  {}", proc.pid, memory, proc.name);
+            if seen.as_ref().is_some_and(|seen| !seen.contains(&proc.pid)) {
+                println!("{}", line.green().bold());
+            } else {
+                println!("{}", line);
+            }
+        }
+    }
+    *seen = Some(processes.into_iter().map(|proc| proc.pid).collect());
+    Ok(())
+}
+
+async fn top(proxy: &NvSleepifyManagerProxy<'_>) -> Result<()> {
+    // Redraw on every state change and daemon event, so a process grabbing
+    // the GPU as it wakes shows up at once. Processes opening an awake GPU
+    // don't produce signals, so poll as well.
+    let streams = [
+        proxy
+            .receive_state_changed()
+            .await
+            .ok()
+            .map(|stream| stream.map(|_| ()).boxed()),
+        proxy
+            .receive_event()
+            .await
+            .ok()
+            .map(|stream| stream.map(|_| ()).boxed()),
+    ];
+    let mut signals = futures_util::stream::select_all(streams.into_iter().flatten());
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(2));
+    let mut seen = None;
+    loop {
+        draw_top(proxy, &mut seen).await?;
+        tokio::select! {
+            Some(()) = signals.next(), if !signals.is_empty() => {}
+            _ = interval.tick() => {}
+        }
+    }
+}
+
+/// What to do about processes blocking a sleep, asked on the terminal.
+fn choose_blocker_action(procs: &[ProcessInfo]) -> Option<bool> {
+    println!("{}", tr!("confirm-blockers").yellow());
+    for proc in procs {
+        println!("- {}", proc);
+    }
+    println!();
+    if !std::io::stdin().is_terminal() {
+        eprintln!("{}", tr!("confirm-no-terminal-idle").red());
+        return None;
+    }
+    let choice = dialoguer::Select::new()
+        .with_prompt(tr!("choose-sleep"))
+        .items(&[
+            tr!("choose-sleep-kill"),
+            tr!("choose-sleep-idle"),
+            tr!("choose-sleep-abort"),
+        ])
+        .default(2)
+        .interact()
+        .ok()?;
+    match choice {
+        0 => Some(false),
+        1 => Some(true),
+        _ => None,
+    }
+}
+
+async fn set_mode(
+    backend: &Backend<'_>,
+    mode: Mode,
+    wait: Option<u64>,
+    mut when_idle: bool,
+    force: bool,
+    opts: &Options,
+) -> Result<()> {
+    if when_idle && mode != Mode::Integrated {
+        return Err(anyhow!("--when-idle only applies to integrated mode"));
+    }
+    // Checked here too, so the user isn't asked about blockers first. A MUX
+    // on the dGPU is left to the daemon, which may switch it for Integrated.
+    if matches!(mode, Mode::Integrated | Mode::Vfio) && !force {
+        if let Ok(gpu) = PciDevice::find_nvidia_gpu() {
+            let evidence = display::check(&gpu);
+            let mux_switch = mode == Mode::Integrated && evidence.contains(&Evidence::Mux);
+            if !evidence.is_empty() && !mux_switch {
+                return Err(anyhow!(display::advice(&evidence)));
+            }
+        }
+    }
+    // Whether the user agreed to killing them, which `[processes] kill = "ask"` requires.
+    let mut kill_procs = false;
+    if mode.kills_processes() && !when_idle {
+        let processes = backend.info().await?.processes;
+        let greeters = display_manager::greeters(&processes);
+        if !greeters.is_empty() {
+            return Err(anyhow!(display_manager::advice(&greeters)));
+        }
+        if !processes.is_empty() {
+            let confirmed = if opts.yes {
+                true
+            } else if opts.gui {
+                confirm_kill_processes(&processes)
+            } else if mode == Mode::Integrated && !matches!(backend, Backend::Direct) {
+                match choose_blocker_action(&processes) {
+                    Some(wait_for_exit) => {
+                        when_idle = wait_for_exit;
+                        true
+                    }
+                    None => false,
+                }
+            } else {
+                confirm_kill_processes_cli(&processes, false)
+            };
+
+            if !confirmed {
+                // Non-zero exit so scripts don't mistake this for a mode change.
+                return Err(anyhow!(tr!("set-mode-aborted")));
+            }
+            kill_procs = !when_idle;
+        }
+    }
+
+    if when_idle {
+        let result = backend.sleep_when_idle().await?;
+        output::info(tr!(
+            "set-mode-result",
+            mode = mode.to_string(),
+            message = result.message
+        ));
+        if let Some(timeout) = wait {
+            wait_for_target(mode, timeout).await?;
+        }
+        return Ok(());
+    }
+
+    match backend.set_mode(mode, kill_procs, force).await {
+        Ok(result) => {
+            output::detail(&result.message);
+            output::info(tr!(
+                "set-mode-done",
+                mode = mode.to_string(),
+                result = tr!("set-mode-success").green().to_string(),
+                state = result.power_state.to_string()
+            ));
+            if let Some(timeout) = wait {
+                wait_for_target(mode, timeout).await?;
+            }
+        }
+        Err(e) => {
+            if !e.processes().is_empty() {
+                println!("{}", tr!("set-mode-blockers").yellow());
+                for proc in e.processes() {
+                    println!("  {}", proc);
+                }
+            }
+            println!("{}", tr!("set-mode-error", error = e.to_string()).red());
+            if wait.is_some() {
+                return Err(anyhow!(tr!("set-mode-failed", mode = mode.to_string())));
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn wake(backend: &Backend<'_>, seconds: Option<u32>) -> Result<()> {
+    let Some(seconds) = seconds else {
+        println!("{}", backend.cancel_wake().await?);
+        return Ok(());
+    };
+    let msg = backend.wake_for(seconds).await?;
+    output::info(msg);
+    let remaining = match backend {
+        Backend::Daemon(proxy) => proxy.wake_remaining().await.unwrap_or(0),
+        // The socket has no WakeRemaining; an accepted wake is running.
+        _ => seconds,
+    };
+    if remaining > 0 {
+        output::info(tr!(
+            "wake-keeping",
+            duration = format_duration(seconds as u64)
+        ));
+    }
+    Ok(())
+}
+
+async fn capabilities(backend: &Backend<'_>) -> Result<()> {
+    let caps = backend.capabilities().await?;
+    let yes_no = |supported: bool| {
+        if supported {
+            tr!("caps-yes").green().to_string()
+        } else {
+            tr!("caps-no").red().to_string()
+        }
+    };
+    println!(
+        "{}",
+        tr!("caps-slot-power", supported = yes_no(caps.slot_power))
+    );
+    println!(
+        "{}",
+        tr!("caps-bridge-pm", supported = yes_no(caps.bridge_runtime_pm))
+    );
+    println!("{}", tr!("caps-d3cold", supported = yes_no(caps.d3cold)));
+    println!(
+        "{}",
+        tr!("caps-acpi-off", supported = yes_no(caps.acpi_off))
+    );
+    println!(
+        "{}",
+        tr!("caps-bbswitch", supported = yes_no(caps.bbswitch))
+    );
+    println!("{}", tr!("caps-nvml", supported = yes_no(caps.nvml)));
+    println!(
+        "{}",
+        tr!("caps-dgpu-disable", supported = yes_no(caps.dgpu_disable))
+    );
+    Ok(())
+}
+
+async fn devices(backend: &Backend<'_>) -> Result<()> {
+    let devices = backend.devices().await?;
+    if devices.is_empty() {
+        println!("{}", tr!("devices-none"));
+    }
+    for device in devices {
+        let marker = if device.managed {
+            format!(" {}", tr!("devices-managed")).green()
+        } else {
+            "".normal()
+        };
+        println!(
+            "{} {}{}",
+            device.address.as_str().bold(),
+            device.name,
+            marker
+        );
+        println!(
+            "  {}",
+            tr!(
+                "devices-ids",
+                vendor = device.vendor_id.as_str(),
+                device = device.device_id.as_str()
+            )
+        );
+        println!(
+            "  {}",
+            tr!("devices-power", state = device.power_state.to_string())
+        );
+        if !device.driver.is_empty() {
+            println!(
+                "  {}",
+                tr!("devices-driver", driver = device.driver.as_str())
+            );
+        }
+        if !device.functions.is_empty() {
+            let functions: Vec<&str> = device.functions.iter().map(|f| f.as_str()).collect();
+            println!(
+                "  {}",
+                tr!("devices-functions", functions = functions.join(", "))
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn migrate(backend: &Backend<'_>, opts: &Options) -> Result<()> {
+    let findings = spawn_blocking(migrate::scan).await?;
+    if findings.is_empty() {
+        println!("{}", tr!("migrate-none"));
+        return Ok(());
+    }
+
+    println!("{}", tr!("migrate-found").bold());
+    for finding in &findings {
+        let marker = if finding.conflicting {
+            tr!("migrate-conflicts").red()
+        } else {
+            tr!("migrate-kept").normal()
+        };
+        println!(
+            "  [{}] {} ({}): {}",
+            finding.tool,
+            finding.path.display(),
+            marker,
+            finding.description
+        );
+    }
+    println!();
+
+    let conflicting: Vec<_> = findings.iter().filter(|f| f.conflicting).collect();
+    if !conflicting.is_empty() {
+        println!(
+            "{}",
+            tr!(
+                "migrate-will-rename",
+                count = conflicting.len(),
+                suffix = migrate::BACKUP_SUFFIX
+            )
+        );
+        if !confirm_cli_with(&tr!("migrate-confirm"), opts.yes) {
+            return Err(anyhow!(tr!("migrate-aborted")));
+        }
+        if unsafe { libc::geteuid() } != 0 {
+            return Err(anyhow!(tr!("migrate-needs-root")));
+        }
+        for finding in conflicting {
+            let backup = migrate::back_up(&finding.path)?;
+            output::info(tr!(
+                "migrate-moved",
+                path = finding.path.display().to_string(),
+                backup = backup.display().to_string()
+            ));
+        }
+        println!("{}", tr!("migrate-rebuild-initramfs").yellow());
+    }
+
+    let tools: Vec<&str> = findings.iter().map(|f| f.tool).collect();
+    for (tool, service) in migrate::TOOL_SERVICES {
+        if tools.contains(&tool) {
+            println!(
+                "{}",
+                tr!("migrate-disable-tool", tool = tool, service = service)
+            );
+        }
+    }
+
+    if let Some(mode) = migrate::suggested_mode(&findings) {
+        println!();
+        if confirm_cli_with(
+            &tr!("migrate-switch-mode", mode = mode.to_string()),
+            opts.yes,
+        ) {
+            set_mode(backend, mode, None, false, false, opts).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Adds or removes the "Launch on dGPU" action for each app. Works on the
+/// user's launcher entries only, so it needs neither root nor the daemon.
+pub fn desktop(apps: &[String], remove: bool) -> Result<()> {
+    let mut failed = false;
+    for app in apps {
+        let result = if remove {
+            desktop::remove_action(app).map(|path| match path {
+                Some(path) => tr!("desktop-removed", path = path.display().to_string()),
+                None => tr!("desktop-no-action", app = app.as_str()),
+            })
+        } else {
+            desktop::find_entry(app)
+                .and_then(|entry| desktop::add_action(&entry))
+                .map(|path| tr!("desktop-added", path = path.display().to_string()))
+        };
+        match result {
+            Ok(msg) => output::info(msg),
+            Err(e) => {
+                println!("{}", format!("{}: {}", app, e).red());
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        return Err(anyhow!(tr!("desktop-some-failed")));
+    }
+    Ok(())
+}
+
+fn require_root(command: &str) -> Result<()> {
+    if unsafe { libc::geteuid() } != 0 {
+        return Err(anyhow!("{} must be run as root.", command));
+    }
+    Ok(())
+}
+
+/// Puts the units, D-Bus files and tray autostart entry in place, pointing
+/// at the binaries next to this one, and starts the daemon unless `no_enable`.
+/// Prints how to start games from `launcher` through `nvsleepify run`; with
+/// `install`, through a wrapper script written to ~/.local/bin first.
+pub fn launcher(launcher: Launcher, install: bool) -> Result<()> {
+    let command = if install {
+        let nvsleepify = std::env::current_exe().context("Can't find the nvsleepify binary")?;
+        let wrapper = launcher::install_wrapper(&nvsleepify)?;
+        output::info(format!(
+            "{}\n",
+            tr!("installed", path = wrapper.display().to_string())
+        ));
+        wrapper.display().to_string()
+    } else {
+        "nvsleepify run".to_string()
+    };
+    println!("{}", launcher::instructions(launcher, &command));
+    Ok(())
+}
+
+pub fn install(no_enable: bool) -> Result<()> {
+    require_root("install")?;
+    let bin_dir = install::bin_dir()?;
+    for path in install::write_files(&bin_dir)? {
+        output::info(tr!("installed", path = path.display().to_string()));
+    }
+    install::reload();
+    if no_enable {
+        println!("{}", tr!("install-start-daemon"));
+    } else {
+        install::enable_daemon()?;
+        output::info(tr!("install-done").green());
+    }
+    Ok(())
+}
+
+/// Stops the daemon, wakes the GPU the way Standard mode does (which also
+/// re-enables the nvidia services and unmasks nvidia-fallback.service),
+/// removes what `install` wrote and the Xorg snippet, and offers to move
+/// back files `migrate` set aside. `purge` also drops /etc/nvsleepify and
+/// /var/lib/nvsleepify.
+pub fn uninstall(purge: bool, yes: bool) -> Result<()> {
+    require_root("uninstall")?;
+    install::disable_units();
+
+    output::info(tr!("uninstall-waking").blue());
+    if let Err(e) = daemon::set_mode_logic(&Mode::Standard.to_string(), false, false) {
+        println!(
+            "{}",
+            tr!("uninstall-wake-failed", error = e.to_string()).red()
+        );
+    }
+    if let Err(e) = xorg::write_snippet(None) {
+        println!("{}", e.to_string().red());
+    }
+
+    for path in install::remove_files()? {
+        output::info(tr!("removed", path = path.display().to_string()));
+    }
+    install::reload();
+
+    let backups = migrate::backups();
+    if !backups.is_empty() {
+        println!("{}", tr!("uninstall-backups").bold());
+        for (path, backup) in &backups {
+            println!("  {} -> {}", backup.display(), path.display());
+        }
+        if confirm_cli_with(&tr!("uninstall-restore-backups"), yes) {
+            for backup in &backups {
+                match migrate::restore(backup) {
+                    Ok(()) => output::info(tr!(
+                        "uninstall-restored",
+                        path = backup.0.display().to_string()
+                    )),
+                    Err(e) => println!("{}", e.to_string().red()),
+                }
+            }
+        }
+    }
+
+    if purge {
+        for dir in install::purge()? {
+            output::info(tr!("removed", path = dir.display().to_string()));
+        }
+    }
+    output::info(tr!("uninstall-done").green());
+    Ok(())
+}
+
+pub async fn report(output: Option<PathBuf>) -> Result<()> {
+    if unsafe { libc::geteuid() } != 0 {
+        println!("{}", tr!("report-not-root").yellow());
+    }
+    output::info(tr!("report-collecting").blue());
+    let path = report::generate(output).await?;
+    output::info(tr!("report-written", path = path.display().to_string()).green());
+    Ok(())
+}
+
+/// `status` while the daemon isn't running, read from sysfs and /proc like
+/// `--direct` does; regular users can read all of it. Starting the daemon
+/// would need root, so it is left alone.
+async fn sysfs_status() -> Result<()> {
+    println!("{}", tr!("status-no-daemon").yellow());
+    if unsafe { libc::geteuid() } != 0 {
+        println!("{}", tr!("status-not-root").yellow());
+    }
+    print!("{}", spawn_blocking(daemon::status_logic).await?);
+    Ok(())
+}
+
+/// Carries out `command`. The exit code is the command's own where it has
+/// one (`ps`, `run`), success otherwise.
+pub async fn run(command: Command, opts: Options) -> Result<ExitCode> {
+    // Kept alive for the lifetime of the proxy borrowed by the backend.
+    let connection;
+    let backend = if opts.direct {
+        if unsafe { libc::geteuid() } != 0 {
+            return Err(anyhow!("--direct must be run as root."));
+        }
+        system::apply_config(&spawn_blocking(DaemonConfig::load).await??);
+        output::detail("Running the daemon logic in-process (--direct)");
+        Backend::Direct
+    } else if let Some(path) = &opts.socket {
+        output::detail(format!("Talking to nvsleepifyd over {}", path.display()));
+        Backend::Socket(SocketClient::new(path))
+    } else {
+        let system_bus = Connection::system().await;
+        if matches!(command, Command::Status) {
+            let daemon_running = match &system_bus {
+                Ok(connection) => is_daemon_running(connection).await,
+                Err(_) => false,
+            };
+            if !daemon_running {
+                return sysfs_status().await.map(|()| ExitCode::SUCCESS);
+            }
+        }
+        connection = system_bus.map_err(|e| {
+            anyhow!(
+                "Failed to connect to system bus: {}. Is dbus running? (--direct works without it)",
+                e
+            )
+        })?;
+
+        ensure_daemon_running(&connection).await.map_err(|e| {
+            anyhow!(
+                "{} The nvsleepify daemon (org.nvsleepify.Service) is not running; rerun with --direct as root to operate without it.",
+                e
+            )
+        })?;
+
+        let proxy = NvSleepifyManagerProxy::new(&connection).await.map_err(|e| {
+            anyhow!("Failed to connect to nvsleepify daemon at org.nvsleepify.Service: {}. Is nvsleepifyd.service running?", e)
+        })?;
+        output::detail("Talking to nvsleepifyd over D-Bus");
+        Backend::Daemon(proxy)
+    };
+
+    match command {
+        Command::Status => {
+            let status = backend.status().await?;
+            print!("{}", status);
+        }
+        Command::Devices => {
+            devices(&backend).await?;
+        }
+        Command::Capabilities => {
+            capabilities(&backend).await?;
+        }
+        Command::Watch => {
+            watch(backend.daemon("watch")?).await?;
+        }
+        Command::Top => {
+            top(backend.daemon("top")?).await?;
+        }
+        Command::Logs { follow } => {
+            logs(backend.daemon("logs")?, follow).await?;
+        }
+        Command::Events { follow } => {
+            events(backend.daemon("events")?, follow).await?;
+        }
+        Command::Ps => {
+            return ps(&backend).await;
+        }
+        Command::Kill { pids } => {
+            kill(&backend, pids, opts.yes).await?;
+        }
+        Command::History { limit } => {
+            show_history(backend.daemon("history")?, limit).await?;
+        }
+        Command::Stats => {
+            show_stats(backend.daemon("stats")?).await?;
+        }
+        Command::Savings => {
+            show_savings(backend.daemon("savings")?).await?;
+        }
+        Command::Explain => {
+            print!("{}", backend.explain().await?);
+        }
+        Command::Run { command } => {
+            return run_offloaded(&backend, command, opts.yes).await;
+        }
+        Command::Migrate => {
+            migrate(&backend, &opts).await?;
+        }
+        Command::Set {
+            mode,
+            wait,
+            when_idle,
+            force,
+        } => {
+            set_mode(&backend, mode, wait, when_idle, force, &opts).await?;
+        }
+        Command::Wake { seconds } => {
+            wake(&backend, seconds).await?;
+        }
+        Command::Delay(seconds) => {
+            let msg = backend.set_restore_delay(seconds).await?;
+            output::info(msg);
+        }
+        Command::Step(step) => {
+            let msg = backend.run_step(step).await?;
+            output::info(msg);
+        }
+        Command::Bench { cycles } => {
+            let prompt = tr!("bench-confirm", cycles = cycles);
+            if !confirm_cli_with(&prompt, opts.yes) {
+                return Err(anyhow!(tr!("aborted")));
+            }
+            output::info(tr!("bench-running", cycles = cycles));
+            show_bench(&backend.bench(cycles).await?);
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
@@ -1,107 +1,443 @@
-use crate::protocol::{Command, Mode};
+use crate::error::Error;
+use crate::eventlog;
+use crate::events;
+use crate::faults::FaultRecord;
+use crate::history::TransitionRecord;
+use crate::leases::{Lease, LeaseTuple};
+use crate::protocol::{
+    Capabilities, DeviceInfo, ManualStep, Mode, PowerState, ProcessInfo, StatusReport, StepTimings,
+    TransitionResult,
+};
+use crate::savings::DischargeReport;
+use crate::stats::Stats;
 use anyhow::{anyhow, Result};
-use colored::*;
+use futures_util::future::ready;
+use futures_util::{Stream, StreamExt};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::task::spawn_blocking;
 use zbus::{dbus_proxy, Connection};
 
+/// The daemon's `org.nvsleepify.Manager` interface. `Client` wraps it with
+/// typed results; the raw proxy is there for what it doesn't cover.
 #[dbus_proxy(
     interface = "org.nvsleepify.Manager",
     default_service = "org.nvsleepify.Service",
     default_path = "/org/nvsleepify/Manager"
 )]
-trait NvSleepifyManager {
+pub trait NvSleepifyManager {
     fn status(&self) -> zbus::Result<String>;
-    fn info(&self) -> zbus::Result<(String, String, Vec<(String, String)>)>;
-    fn set_mode(&self, mode_str: String) -> zbus::Result<(bool, String, Vec<(String, String)>)>;
+    fn info(&self) -> zbus::Result<StatusReport>;
+    fn explain(&self) -> zbus::Result<String>;
+    fn get_devices(&self) -> zbus::Result<Vec<DeviceInfo>>;
+    fn get_capabilities(&self) -> zbus::Result<Capabilities>;
+    fn gpu_temperature(&self) -> zbus::Result<(bool, f64)>;
+    fn set_mode(&self, mode_str: String) -> zbus::Result<TransitionResult>;
+    fn set_mode_with_options(
+        &self,
+        mode_str: String,
+        kill_procs: bool,
+    ) -> zbus::Result<TransitionResult>;
+    fn set_mode_forced(&self, mode_str: String, kill_procs: bool)
+        -> zbus::Result<TransitionResult>;
+    fn kill_policy(&self) -> zbus::Result<String>;
+    fn sleep_when_idle(&self) -> zbus::Result<TransitionResult>;
+    fn wake_for(&self, seconds: u32) -> zbus::Result<String>;
+    fn cancel_wake(&self) -> zbus::Result<String>;
+    fn acquire_wake_lock(&self, app: String, reason: String) -> zbus::Result<u32>;
+    fn release_wake_lock(&self, id: u32) -> zbus::Result<String>;
+    fn list_wake_locks(&self) -> zbus::Result<Vec<LeaseTuple>>;
+    fn wake_remaining(&self) -> zbus::Result<u32>;
+    fn power_savings(&self) -> zbus::Result<f64>;
+    fn get_stats(&self) -> zbus::Result<(u64, u64, u64, u64, u64, u64)>;
+    fn get_discharge_report(&self) -> zbus::Result<(f64, f64, f64, f64, f64)>;
+    fn last_transition(&self) -> zbus::Result<(PowerState, PowerState, u64)>;
+    fn get_history(&self, limit: u32) -> zbus::Result<Vec<(u64, PowerState, PowerState, String)>>;
+    fn get_faults(&self, limit: u32) -> zbus::Result<Vec<(u64, String, String)>>;
+    fn kill_processes(&self, pids: Vec<u32>) -> zbus::Result<String>;
+    fn kill_process(&self, pid: u32, name: String) -> zbus::Result<String>;
+    fn run_step(&self, step: String) -> zbus::Result<String>;
+    fn bench(&self, cycles: u32) -> zbus::Result<Vec<StepTimings>>;
     fn set_restore_delay(&self, seconds: u32) -> zbus::Result<String>;
+    fn restore_delay(&self) -> zbus::Result<u32>;
+    fn get_events(&self) -> zbus::Result<Vec<(u64, String, String)>>;
+    fn get_logs(&self) -> zbus::Result<Vec<(u64, String, String)>>;
+
+    #[dbus_proxy(signal)]
+    fn log_entry(&self, timestamp: u64, level: String, message: String) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn event(&self, timestamp: u64, kind: String, message: String) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn state_changed(&self, mode: String, power_state: PowerState) -> zbus::Result<()>;
 }
 
-fn confirm_kill_processes(procs: &[(String, String)]) -> bool {
-    if procs.is_empty() {
-        return true;
+const DAEMON_SERVICE: &str = "org.nvsleepify.Service";
+const DAEMON_UNIT: &str = "nvsleepifyd.service";
+/// How long to wait for a started daemon to claim its bus name.
+const DAEMON_START_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Whether the daemon is on the bus right now. Unlike `ensure_daemon_running`
+/// it never starts it.
+pub async fn is_daemon_running(connection: &Connection) -> bool {
+    let Ok(dbus) = zbus::fdo::DBusProxy::new(connection).await else {
+        return false;
+    };
+    dbus.name_has_owner(DAEMON_SERVICE.try_into().unwrap())
+        .await
+        .unwrap_or(false)
+}
+
+/// Makes sure the daemon is on the bus, starting it if it isn't: first through
+/// D-Bus activation, then with `systemctl start`, which asks polkit for
+/// authorization when not root.
+pub async fn ensure_daemon_running(connection: &Connection) -> Result<()> {
+    let dbus = zbus::fdo::DBusProxy::new(connection).await?;
+    let has_owner = || async {
+        dbus.name_has_owner(DAEMON_SERVICE.try_into().unwrap())
+            .await
+            .unwrap_or(false)
+    };
+    if has_owner().await {
+        return Ok(());
     }
 
-    let mut text = String::new();
-    text.push_str("The following processes are using the Nvidia GPU and may need to be killed to sleep it:\n\n");
-    for (name, pid) in procs {
-        text.push_str(&format!("- {} (PID {})\n", name, pid));
+    // Transparent when the activation file is installed; the bus starts
+    // nvsleepifyd.service and replies once it owns the name.
+    let activated = dbus
+        .start_service_by_name(DAEMON_SERVICE.try_into()?, 0)
+        .await
+        .is_ok();
+    if !activated {
+        eprintln!("The nvsleepify daemon is not running, starting it...");
+        let status = spawn_blocking(|| {
+            std::process::Command::new("systemctl")
+                .args(["start", DAEMON_UNIT])
+                .status()
+        })
+        .await?;
+        if !matches!(status, Ok(s) if s.success()) {
+            return Err(anyhow!("Failed to start {}.", DAEMON_UNIT));
+        }
     }
 
-    let result = rfd::MessageDialog::new()
-        .set_title("nvsleepify")
-        .set_description(&text)
-        .set_buttons(rfd::MessageButtons::YesNo)
-        .show();
+    let deadline = tokio::time::Instant::now() + DAEMON_START_TIMEOUT;
+    while tokio::time::Instant::now() < deadline {
+        if has_owner().await {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+    Err(anyhow!(
+        "Started {} but it did not appear on the bus within {}s.",
+        DAEMON_UNIT,
+        DAEMON_START_TIMEOUT.as_secs()
+    ))
+}
 
-    matches!(result, rfd::MessageDialogResult::Yes)
+/// A `StateChanged` signal: the saved mode or the GPU's power state changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateChange {
+    pub mode: Mode,
+    pub power_state: PowerState,
 }
 
-fn confirm_kill_processes_cli(procs: &[(String, String)]) -> bool {
-    if procs.is_empty() {
-        return true;
+/// Talks to nvsleepifyd over the system bus. Errors are the daemon's own
+/// (`Error::Busy`, `Error::BlockedByProcesses`, ...), so callers can match
+/// on them instead of parsing messages.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), nvsleepify::error::Error> {
+/// use futures_util::StreamExt;
+/// use nvsleepify::client::Client;
+/// use nvsleepify::protocol::Mode;
+///
+/// let client = Client::connect().await?;
+/// client.set_mode(Mode::Optimized).await?;
+/// let mut changes = client.state_changes().await?;
+/// while let Some(change) = changes.next().await {
+///     println!("{} / {}", change.mode, change.power_state);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Client {
+    proxy: NvSleepifyManagerProxy<'static>,
+}
+
+impl Client {
+    /// Connects to the system bus. The daemon doesn't have to be running yet;
+    /// see `ensure_daemon_running` to start it.
+    pub async fn connect() -> Result<Self, Error> {
+        Self::with_connection(&Connection::system().await?).await
     }
 
-    println!("{}", "The following processes are using the Nvidia GPU and may need to be killed to sleep it:".yellow());
-    for (name, pid) in procs {
-        println!("- {} (PID {})", name, pid);
+    /// Uses an existing bus connection.
+    pub async fn with_connection(connection: &Connection) -> Result<Self, Error> {
+        Ok(Self {
+            proxy: NvSleepifyManagerProxy::new(connection).await?,
+        })
     }
-    println!();
 
-    dialoguer::Confirm::new()
-        .with_prompt("Do you want to proceed?")
-        .default(false)
-        .interact()
-        .unwrap_or(false)
-}
+    pub fn proxy(&self) -> &NvSleepifyManagerProxy<'static> {
+        &self.proxy
+    }
 
-pub async fn run(command: Command, use_gui: bool) -> Result<()> {
-    let connection = Connection::system()
-        .await
-        .map_err(|e| anyhow!("Failed to connect to system bus: {}. Is dbus running?", e))?;
+    /// The human-readable report `nvsleepify status` prints.
+    pub async fn status(&self) -> Result<String, Error> {
+        Ok(self.proxy.status().await?)
+    }
+
+    pub async fn info(&self) -> Result<StatusReport, Error> {
+        Ok(self.proxy.info().await?)
+    }
 
-    let proxy = NvSleepifyManagerProxy::new(&connection).await.map_err(|e| {
-         anyhow!("Failed to connect to nvsleepify daemon at org.nvsleepify.Service: {}. Is nvsleepifyd.service running?", e)
-    })?;
+    pub async fn mode(&self) -> Result<Mode, Error> {
+        Ok(self.info().await?.mode())
+    }
 
-    match command {
-        Command::Status => {
-            let status = proxy.status().await?;
-            print!("{}", status);
-        }
-        Command::Set(mode) => {
-            if mode == Mode::Integrated {
-                let (_, _, processes) = proxy.info().await?;
-                if !processes.is_empty() {
-                    let confirmed = if use_gui {
-                        confirm_kill_processes(&processes)
-                    } else {
-                        confirm_kill_processes_cli(&processes)
-                    };
-
-                    if !confirmed {
-                        println!("Aborted by user.");
-                        return Ok(());
-                    }
-                }
-            }
-
-            let (success, msg, procs) = proxy.set_mode(mode.to_string()).await?;
-
-            if success {
-                println!("Set mode to {}: {}", mode, "Success.".green());
-            } else {
-                if !procs.is_empty() {
-                    println!("{}", "Processes using Nvidia GPU found:".yellow());
-                    for (name, pid) in &procs {
-                        println!("  {} (PID: {})", name, pid);
-                    }
-                }
-                println!("{}", format!("Error: {}", msg).red());
-            }
-        }
-        Command::Delay(seconds) => {
-            let msg = proxy.set_restore_delay(seconds).await?;
-            println!("{}", msg);
-        }
+    pub async fn set_mode(&self, mode: Mode) -> Result<TransitionResult, Error> {
+        Ok(self.proxy.set_mode(mode.to_string()).await?)
+    }
+
+    /// Like `set_mode`, telling the daemon the user agreed to killing the
+    /// processes holding the GPU; needed under `[processes] kill = "ask"`.
+    pub async fn set_mode_killing(&self, mode: Mode) -> Result<TransitionResult, Error> {
+        Ok(self
+            .proxy
+            .set_mode_with_options(mode.to_string(), true)
+            .await?)
+    }
+
+    /// Like `set_mode`, without the check that refuses to power down a GPU
+    /// displaying the session. `kill_procs` as for `set_mode_killing`.
+    pub async fn set_mode_forced(
+        &self,
+        mode: Mode,
+        kill_procs: bool,
+    ) -> Result<TransitionResult, Error> {
+        Ok(self
+            .proxy
+            .set_mode_forced(mode.to_string(), kill_procs)
+            .await?)
+    }
+
+    /// Every display controller; `managed` marks the one nvsleepify controls.
+    pub async fn devices(&self) -> Result<Vec<DeviceInfo>, Error> {
+        Ok(self.proxy.get_devices().await?)
+    }
+
+    /// What the machine supports for powering the GPU down, so UIs can hide
+    /// what won't work on it.
+    pub async fn capabilities(&self) -> Result<Capabilities, Error> {
+        Ok(self.proxy.get_capabilities().await?)
+    }
+
+    /// Integrated mode without killing anything: the GPU sleeps once the
+    /// processes using it have exited. `StatusReport::pending_sleep` is set
+    /// while waiting.
+    pub async fn sleep_when_idle(&self) -> Result<TransitionResult, Error> {
+        Ok(self.proxy.sleep_when_idle().await?)
+    }
+
+    /// Degrees Celsius, only available while the GPU is awake.
+    pub async fn gpu_temperature(&self) -> Result<Option<f64>, Error> {
+        let (available, celsius) = self.proxy.gpu_temperature().await?;
+        Ok(available.then_some(celsius))
+    }
+
+    /// Keeps the GPU awake for `duration`, then returns to the saved mode.
+    pub async fn wake_for(&self, duration: Duration) -> Result<String, Error> {
+        let seconds = u32::try_from(duration.as_secs()).unwrap_or(u32::MAX);
+        Ok(self.proxy.wake_for(seconds).await?)
+    }
+
+    pub async fn cancel_wake(&self) -> Result<String, Error> {
+        Ok(self.proxy.cancel_wake().await?)
+    }
+
+    /// Keeps the GPU awake until `release_wake_lock` or until this client's
+    /// connection closes. Returns the lock's id.
+    pub async fn acquire_wake_lock(&self, app: &str, reason: &str) -> Result<u32, Error> {
+        Ok(self
+            .proxy
+            .acquire_wake_lock(app.to_string(), reason.to_string())
+            .await?)
+    }
+
+    pub async fn release_wake_lock(&self, id: u32) -> Result<String, Error> {
+        Ok(self.proxy.release_wake_lock(id).await?)
+    }
+
+    pub async fn wake_locks(&self) -> Result<Vec<Lease>, Error> {
+        Ok(self
+            .proxy
+            .list_wake_locks()
+            .await?
+            .into_iter()
+            .map(Lease::from_tuple)
+            .collect())
+    }
+
+    /// Time left on a temporary wake, if one is active.
+    pub async fn wake_remaining(&self) -> Result<Option<Duration>, Error> {
+        let seconds = self.proxy.wake_remaining().await?;
+        Ok((seconds > 0).then(|| Duration::from_secs(seconds as u64)))
+    }
+
+    /// Watts saved while the GPU sleeps, once measured.
+    pub async fn power_savings(&self) -> Result<Option<f64>, Error> {
+        let watts = self.proxy.power_savings().await?;
+        Ok((watts > 0.0).then_some(watts))
+    }
+
+    /// Battery discharge with the GPU asleep and awake, and the energy left
+    /// in watt-hours if the batteries report it.
+    pub async fn discharge_report(&self) -> Result<(DischargeReport, Option<f64>), Error> {
+        let (asleep_secs, asleep_joules, awake_secs, awake_joules, energy_wh) =
+            self.proxy.get_discharge_report().await?;
+        let report =
+            DischargeReport::from_tuple((asleep_secs, asleep_joules, awake_secs, awake_joules));
+        Ok((report, (energy_wh > 0.0).then_some(energy_wh)))
+    }
+
+    pub async fn stats(&self) -> Result<Stats, Error> {
+        Ok(Stats::from_tuple(self.proxy.get_stats().await?))
+    }
+
+    /// The last power state change the daemon saw. Its trigger isn't part of
+    /// the reply; `history(1)` has it.
+    pub async fn last_transition(&self) -> Result<Option<TransitionRecord>, Error> {
+        let (from, to, timestamp) = self.proxy.last_transition().await?;
+        Ok((timestamp > 0).then(|| TransitionRecord {
+            timestamp,
+            from,
+            to,
+            trigger: String::new(),
+        }))
+    }
+
+    /// Up to `limit` persisted power state changes, oldest first.
+    pub async fn history(&self, limit: u32) -> Result<Vec<TransitionRecord>, Error> {
+        Ok(self
+            .proxy
+            .get_history(limit)
+            .await?
+            .into_iter()
+            .map(TransitionRecord::from_tuple)
+            .collect())
+    }
+
+    /// Up to `limit` recorded kernel faults of the GPU, oldest first.
+    pub async fn faults(&self, limit: u32) -> Result<Vec<FaultRecord>, Error> {
+        Ok(self
+            .proxy
+            .get_faults(limit)
+            .await?
+            .into_iter()
+            .map(FaultRecord::from_tuple)
+            .collect())
+    }
+
+    /// Kills the given processes if they hold the GPU; all of them for an empty list.
+    pub async fn kill_processes(&self, pids: &[u32]) -> Result<String, Error> {
+        Ok(self.proxy.kill_processes(pids.to_vec()).await?)
+    }
+
+    /// Kills one process as listed in `StatusReport::processes`; refused if
+    /// its PID has since been reused by another program.
+    pub async fn kill_process(&self, process: &ProcessInfo) -> Result<String, Error> {
+        let pid = process
+            .pid
+            .parse()
+            .map_err(|_| Error::InvalidArgs(format!("Invalid PID {}", process.pid)))?;
+        Ok(self.proxy.kill_process(pid, process.name.clone()).await?)
+    }
+
+    /// Runs one step of the sleep or wake sequence; enforcement stays off
+    /// until a mode is set.
+    pub async fn run_step(&self, step: ManualStep) -> Result<String, Error> {
+        Ok(self.proxy.run_step(step.to_string()).await?)
+    }
+
+    /// Powers the GPU off and on `cycles` times, timing each step.
+    pub async fn bench(&self, cycles: u32) -> Result<Vec<StepTimings>, Error> {
+        Ok(self.proxy.bench(cycles).await?)
+    }
+
+    pub async fn restore_delay(&self) -> Result<Duration, Error> {
+        Ok(Duration::from_secs(
+            self.proxy.restore_delay().await? as u64,
+        ))
+    }
+
+    pub async fn set_restore_delay(&self, delay: Duration) -> Result<String, Error> {
+        let seconds = u32::try_from(delay.as_secs()).unwrap_or(u32::MAX);
+        Ok(self.proxy.set_restore_delay(seconds).await?)
+    }
+
+    /// The daemon's recent log, oldest first.
+    pub async fn logs(&self) -> Result<Vec<eventlog::LogEntry>, Error> {
+        Ok(self
+            .proxy
+            .get_logs()
+            .await?
+            .into_iter()
+            .map(eventlog::LogEntry::from_tuple)
+            .collect())
+    }
+
+    /// The daemon's recent events, oldest first.
+    pub async fn events(&self) -> Result<Vec<events::Event>, Error> {
+        Ok(self
+            .proxy
+            .get_events()
+            .await?
+            .into_iter()
+            .map(events::Event::from_tuple)
+            .collect())
+    }
+
+    pub async fn state_changes(&self) -> Result<impl Stream<Item = StateChange> + Unpin, Error> {
+        Ok(self
+            .proxy
+            .receive_state_changed()
+            .await?
+            .filter_map(|signal| {
+                ready(signal.args().ok().and_then(|args| {
+                    Some(StateChange {
+                        mode: Mode::from_str(args.mode()).ok()?,
+                        power_state: *args.power_state(),
+                    })
+                }))
+            }))
+    }
+
+    pub async fn log_entries(
+        &self,
+    ) -> Result<impl Stream<Item = eventlog::LogEntry> + Unpin, Error> {
+        Ok(self.proxy.receive_log_entry().await?.filter_map(|signal| {
+            ready(signal.args().ok().map(|args| {
+                eventlog::LogEntry::from_tuple((
+                    *args.timestamp(),
+                    args.level().clone(),
+                    args.message().clone(),
+                ))
+            }))
+        }))
+    }
+
+    pub async fn event_stream(&self) -> Result<impl Stream<Item = events::Event> + Unpin, Error> {
+        Ok(self.proxy.receive_event().await?.filter_map(|signal| {
+            ready(signal.args().ok().map(|args| {
+                events::Event::from_tuple((
+                    *args.timestamp(),
+                    args.kind().clone(),
+                    args.message().clone(),
+                ))
+            }))
+        }))
     }
-    Ok(())
 }
@@ -1,234 +1,2520 @@
-use crate::pci::PciDevice;
-use crate::protocol::Mode;
+use crate::access;
+use crate::applet::{self, NvSleepifyApplet, APPLET_PATH};
+use crate::config::{DaemonConfig, KillPolicy, Reconcile, SleepStage};
+use crate::conflicts::{self, Conflict};
+use crate::display;
+use crate::display_manager;
+use crate::driver::{self, Driver};
+use crate::error::Error;
+use crate::eventlog::{self, unix_now};
+use crate::events::{self, EventKind};
+use crate::faults::{self, FaultRecord};
+use crate::helper;
+use crate::hibernate;
+use crate::history::{self, format_duration, TransitionRecord};
+use crate::idle::IdleTracker;
+use crate::inhibit;
+use crate::leases::{LeaseTuple, Leases};
+use crate::logind;
+use crate::metrics;
+use crate::notifications;
+use crate::pci::{self, PciDevice};
+use crate::platform::{self, Mux};
+use crate::profiles::{self, PowerContext, Profiles};
+use crate::protocol::{
+    Capabilities, DeviceInfo, ManualStep, Mode, PowerState, ProcessList, StatusReport, StepTimings,
+    TransitionResult,
+};
+use crate::rpc;
+use crate::savings::{self, DischargeReport, SavingsTracker, SleepDraw};
+use crate::simulate::{self, NvSleepifyDebug};
+use crate::state::{load_mode, read_delay, read_mode, save_mode, write_delay, MODE_FILE};
+use crate::stats;
+use crate::supergfx;
+use crate::sysfs;
 use crate::system;
+use crate::tr;
+use crate::transition::{self, Step};
+use crate::triggers::Triggers;
+use crate::units;
+use crate::verify;
+use crate::xorg;
 use anyhow::Result;
+use futures_util::StreamExt;
 
+use std::collections::HashMap;
 use std::fmt::Write;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::task::spawn_blocking;
-use zbus::{dbus_interface, ConnectionBuilder};
+use tokio::time::Instant;
+use zbus::{dbus_interface, Connection, ConnectionBuilder, MessageHeader, SignalContext};
 
-const MODE_FILE: &str = "/var/lib/nvsleepify/mode";
-const DELAY_FILE: &str = "/var/lib/nvsleepify/restore_delay";
+pub(crate) const OBJECT_PATH: &str = "/org/nvsleepify/Manager";
 
-struct NvSleepifyManager;
+/// How often to check whether conflicting switchers were started or stopped.
+const CONFLICT_CHECK_SECS: u64 = 30;
+
+/// How long an action's trigger is attributed to the next observed power state change.
+const TRIGGER_TTL_SECS: u64 = 30;
+
+/// How long the charger state must hold before Optimized mode acts on it.
+const DEBOUNCE: Duration = Duration::from_secs(2);
+/// How soon a failed or postponed enforcement is tried again.
+const RETRY: Duration = Duration::from_secs(2);
+/// Runtime PM moving the GPU between D0 and D3cold without it leaving the bus
+/// produces no uevent, so the monitor still looks at this interval.
+const FALLBACK_CHECK_SECS: u64 = 30;
+/// Monitor interval when uevents can't be received.
+const POLL_SECS: u64 = 2;
+/// How long hibernation waits for a running transition before giving up.
+const HIBERNATE_WAIT: Duration = Duration::from_secs(3);
+/// Battery sampling interval for the savings estimate.
+const SAVINGS_SAMPLE_SECS: u64 = 2;
+/// The discharge report is written out every this many samples on battery.
+const DISCHARGE_SAVE_SAMPLES: u64 = 30;
+/// Non-fatal kernel faults of one kind closer together than this are
+/// recorded once, since AER errors can come in floods.
+const FAULT_REPEAT_SECS: u64 = 60;
+/// Least time between two recoveries, so a GPU that keeps failing isn't
+/// power-cycled in a loop.
+const RECOVERY_COOLDOWN_SECS: u64 = 10 * 60;
+/// Sleep attempts, a second apart, while the login screen exits after the
+/// display manager was stopped.
+const GREETER_EXIT_TRIES: u32 = 5;
+/// How long a sleep deferred for the login screen waits for a session
+/// change before looking again, doubling up to `GREETER_BACKOFF_MAX`.
+const GREETER_BACKOFF_MIN: Duration = Duration::from_secs(10);
+const GREETER_BACKOFF_MAX: Duration = Duration::from_secs(5 * 60);
+/// How often the server profile looks for jobs on an idle, awake GPU.
+const SERVER_POLL: Duration = Duration::from_secs(10);
+
+/// Temporarily keeps the GPU awake regardless of the saved mode.
+#[derive(Debug, Clone, Copy)]
+struct WakeOverride {
+    until: u64,
+    /// Re-applied when the override expires.
+    previous: Mode,
+}
+
+/// Runtime state shared between the D-Bus interface and the monitor loop.
+#[derive(Debug, Default)]
+pub(crate) struct DaemonState {
+    last_transition: Option<TransitionRecord>,
+    /// Why the daemon last acted on the GPU, and when.
+    pending_trigger: Option<(String, u64)>,
+    wake_override: Option<WakeOverride>,
+    /// Unacknowledged switchers running alongside us; enforcement is paused
+    /// while there are any.
+    conflicts: Vec<Conflict>,
+    savings: SavingsTracker,
+    discharge: DischargeReport,
+    /// Wake locks taken by applications; the GPU stays awake while any is held.
+    leases: Leases,
+    /// Integrated was set with SleepWhenIdle: the monitor waits for the
+    /// blocking processes to exit instead of killing them.
+    deferred_sleep: bool,
+    /// The GPU was powered up for a hibernation image; the saved mode applies
+    /// again after thaw.
+    hibernating: bool,
+    /// Why the monitor leaves the GPU alone until a mode is set: started
+    /// with `--no-restore`, or a step was run by hand.
+    hands_off: Option<String>,
+    /// Power state the monitor last saw.
+    power_state: PowerState,
+    profiles: Profiles,
+    /// Limits the notifications sent when enforcement can't sleep the GPU.
+    sleep_blocked: notifications::Throttle,
+    /// Whether the monitor last saw a charger, and since when (unix time).
+    charger: Option<(bool, u64)>,
+    /// Power context the monitor last saw, and since when (unix time).
+    context: Option<(PowerContext, u64)>,
+    /// UID of whoever last set the mode; told about the GPU like the users
+    /// on its seat.
+    requester: Option<u32>,
+    /// `[display_manager] restart`.
+    restart_display_manager: bool,
+    /// The last wake nvsleepify didn't cause, with who held the GPU right
+    /// after, and when.
+    last_external_wake: Option<(String, u64)>,
+}
+
+impl DaemonState {
+    fn set_trigger(&mut self, trigger: impl Into<String>) {
+        self.pending_trigger = Some((trigger.into(), unix_now()));
+    }
+
+    /// Changes without a recent daemon action behind them are "external"
+    /// (runtime PM, another tool, a process opening the device).
+    fn take_trigger(&mut self) -> String {
+        match self.pending_trigger.take() {
+            Some((trigger, at)) if unix_now().saturating_sub(at) <= TRIGGER_TTL_SECS => trigger,
+            _ => EXTERNAL_TRIGGER.to_string(),
+        }
+    }
+}
+
+/// Trigger of power state changes nvsleepify didn't cause.
+const EXTERNAL_TRIGGER: &str = "external";
+
+/// Names the processes found holding the GPU right after an external wake.
+/// They are the likely cause; one that only opened it briefly, like
+/// nvidia-smi, is gone by then.
+async fn attribute_wake() -> String {
+    let users = spawn_blocking(|| {
+        PciDevice::find_nvidia_gpu()
+            .ok()
+            .and_then(|gpu| system::get_processes_using_nvidia(&gpu.get_device_nodes()).ok())
+            .unwrap_or_default()
+    })
+    .await
+    .unwrap_or_default();
+    if users.is_empty() {
+        return format!("{}, nothing held it when checked", EXTERNAL_TRIGGER);
+    }
+    let names: Vec<String> = users.iter().map(|p| p.to_string()).collect();
+    format!("{}, woken by {}", EXTERNAL_TRIGGER, names.join(", "))
+}
+
+pub(crate) type SharedState = Arc<Mutex<DaemonState>>;
+
+struct NvSleepifyManager {
+    state: SharedState,
+}
 
 #[dbus_interface(name = "org.nvsleepify.Manager")]
 impl NvSleepifyManager {
     async fn status(&self) -> String {
-        spawn_blocking(move || status_logic())
-            .await
-            .unwrap_or_else(|e| format!("Internal error: {}", e))
+        request_status(&self.state).await
+    }
+
+    /// Why the GPU is in its current state: a headline such as "Awake
+    /// because: Optimized mode + AC online (stable 3m)", followed by each
+    /// input to that decision.
+    async fn explain(&self) -> String {
+        request_explain(&self.state).await
+    }
+
+    /// Keeps the GPU awake until `ReleaseWakeLock` or until the caller
+    /// disconnects, waking it if the mode has it asleep. `app` and `reason`
+    /// are shown in `status`. Returns the lock's id.
+    async fn acquire_wake_lock(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: MessageHeader<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        app: String,
+        reason: String,
+    ) -> Result<u32, Error> {
+        access::authorize(conn, &header).await?;
+        let owner = header
+            .sender()
+            .ok()
+            .flatten()
+            .map(|sender| sender.to_string())
+            .ok_or_else(|| Error::InvalidArgs("Can't identify the caller".to_string()))?;
+        let id = {
+            let mut state = self.state.lock().unwrap();
+            state.set_trigger(format!("Wake lock ({})", app));
+            state.leases.acquire(&owner, &app, &reason)
+        };
+        events::emit(
+            EventKind::Policy,
+            format!("Wake lock #{} taken by {}: {}", id, app, reason),
+        );
+        if matches!(read_mode().await, Mode::Integrated | Mode::Optimized) {
+            let result = spawn_blocking(wake_logic)
+                .await
+                .unwrap_or_else(|e| Err(Error::Failed(format!("Internal error: {}", e))));
+            if let Err(e) = result {
+                // Give the lock back so a failed wake doesn't pin the state.
+                self.state.lock().unwrap().leases.release(id, &owner);
+                events::emit(EventKind::Failure, format!("Wake failed: {}", e));
+                return Err(e);
+            }
+            emit_state_changed(&ctxt).await;
+        }
+        Ok(id)
+    }
+
+    /// Releases a lock taken by the same connection. The saved mode applies
+    /// again once no locks are left.
+    async fn release_wake_lock(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        id: u32,
+    ) -> Result<String, Error> {
+        let owner = header
+            .sender()
+            .ok()
+            .flatten()
+            .map(|sender| sender.to_string())
+            .unwrap_or_default();
+        let Some(lease) = self.state.lock().unwrap().leases.release(id, &owner) else {
+            return Err(Error::InvalidArgs(format!(
+                "No wake lock #{} held by this connection",
+                id
+            )));
+        };
+        events::emit(
+            EventKind::Policy,
+            format!("Wake lock #{} released by {}", lease.id, lease.app),
+        );
+        reapply_after_leases(&self.state).await;
+        emit_state_changed(&ctxt).await;
+        Ok(format!("Released wake lock #{}", id))
+    }
+
+    /// Held wake locks.
+    /// Returns: [(id, owner_bus_name, app, reason, since_unix_timestamp)]
+    async fn list_wake_locks(&self) -> Vec<LeaseTuple> {
+        self.state
+            .lock()
+            .unwrap()
+            .leases
+            .held()
+            .iter()
+            .map(|lease| lease.to_tuple())
+            .collect()
     }
 
     /// Read-only info for UIs.
-    /// Returns: (mode_str, power_state, blocking_processes)
-    async fn info(&self) -> (String, String, Vec<(String, String)>) {
-        spawn_blocking(move || info_logic())
-            .await
-            .unwrap_or_else(|e| {
-                (
-                    "Unknown".to_string(),
-                    format!("Internal error: {}", e),
-                    vec![],
-                )
-            })
+    async fn info(&self) -> StatusReport {
+        request_info(&self.state).await
+    }
+
+    /// Switches to Integrated without killing anything: sleeps now if
+    /// nothing uses the GPU, otherwise as soon as the last blocking process
+    /// exits. `Info` reports the wait as `pending_sleep`.
+    async fn sleep_when_idle(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: MessageHeader<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+    ) -> Result<TransitionResult, Error> {
+        access::authorize(conn, &header).await?;
+        request_sleep_when_idle(&self.state, Some(&ctxt)).await
+    }
+
+    /// Every display controller on the bus with its IDs, name, power state,
+    /// driver and slot functions, and whether it is the GPU nvsleepify manages.
+    /// The managed GPU is missing while it is powered off the bus.
+    async fn get_devices(&self) -> Vec<DeviceInfo> {
+        spawn_blocking(devices_logic).await.unwrap_or_default()
+    }
+
+    /// What this machine supports for powering the GPU down. While the GPU
+    /// is off the bus, mostly what was found when it was last on it.
+    async fn get_capabilities(&self) -> Capabilities {
+        spawn_blocking(platform::probe).await.unwrap_or_default()
+    }
+
+    /// GPU temperature from hwmon, only read while the GPU is in D0.
+    /// Returns: (available, degrees_celsius)
+    async fn gpu_temperature(&self) -> (bool, f64) {
+        let temp = async {
+            let gpu = sysfs::nvidia_gpu().await?;
+            if sysfs::power_state().await != PowerState::D0 {
+                return None;
+            }
+            let hwmon = gpu.hwmon_path()?;
+            let millidegrees: f64 = sysfs::read_trimmed(hwmon.join("temp1_input"))
+                .await?
+                .parse()
+                .ok()?;
+            Some(millidegrees / 1000.0)
+        }
+        .await;
+        (temp.is_some(), temp.unwrap_or(0.0))
     }
 
     /// Set Mode.
-    async fn set_mode(&self, mode_str: String) -> (bool, String, Vec<(String, String)>) {
-        spawn_blocking(move || set_mode_logic(&mode_str))
+    /// Returns: the resulting mode and power state; fails with an
+    /// org.nvsleepify.Error.* error.
+    async fn set_mode(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: MessageHeader<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        mode_str: String,
+    ) -> Result<TransitionResult, Error> {
+        access::authorize(conn, &header).await?;
+        let requester = access::caller_uid(conn, &header).await;
+        request_mode(&self.state, Some(&ctxt), requester, mode_str, false, false).await
+    }
+
+    /// SetMode, with `kill_procs` saying the user agreed to killing the
+    /// processes holding the GPU. Under `[processes] kill = "ask"` only this
+    /// kills them; "never" refuses even then.
+    async fn set_mode_with_options(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: MessageHeader<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        mode_str: String,
+        kill_procs: bool,
+    ) -> Result<TransitionResult, Error> {
+        access::authorize(conn, &header).await?;
+        let requester = access::caller_uid(conn, &header).await;
+        request_mode(
+            &self.state,
+            Some(&ctxt),
+            requester,
+            mode_str,
+            kill_procs,
+            false,
+        )
+        .await
+    }
+
+    /// SetModeWithOptions, skipping the check that refuses to power down a
+    /// GPU displaying the session: the boot display, the only active GPU, a
+    /// lit monitor. Only this transition skips it; later sleeps check again.
+    async fn set_mode_forced(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: MessageHeader<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        mode_str: String,
+        kill_procs: bool,
+    ) -> Result<TransitionResult, Error> {
+        access::authorize(conn, &header).await?;
+        let requester = access::caller_uid(conn, &header).await;
+        request_mode(
+            &self.state,
+            Some(&ctxt),
+            requester,
+            mode_str,
+            kill_procs,
+            true,
+        )
+        .await
+    }
+
+    /// The configured kill policy: "never", "ask" or "always".
+    async fn kill_policy(&self) -> String {
+        system::kill_policy().to_string()
+    }
+
+    /// Keep the GPU awake for `seconds`, then return to the saved mode.
+    /// Calling it again while active restarts the timer.
+    async fn wake_for(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: MessageHeader<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        seconds: u32,
+    ) -> Result<String, Error> {
+        access::authorize(conn, &header).await?;
+        request_wake_for(&self.state, Some(&ctxt), seconds).await
+    }
+
+    /// End a temporary wake early and re-apply the saved mode.
+    async fn cancel_wake(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: MessageHeader<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+    ) -> Result<String, Error> {
+        access::authorize(conn, &header).await?;
+        request_cancel_wake(&self.state, Some(&ctxt)).await
+    }
+
+    /// Seconds left on the temporary wake, 0 if none is active.
+    async fn wake_remaining(&self) -> u32 {
+        self.state
+            .lock()
+            .unwrap()
+            .wake_override
+            .map(|o| o.until.saturating_sub(unix_now()) as u32)
+            .unwrap_or(0)
+    }
+
+    /// Measured battery power saved while the GPU sleeps, in watts; 0 if not measured yet.
+    async fn power_savings(&self) -> f64 {
+        self.state.lock().unwrap().savings.estimate().unwrap_or(0.0)
+    }
+
+    /// Battery discharge with the GPU asleep and awake, for comparing
+    /// runtimes, plus the energy left in the batteries (0 if unknown).
+    /// Returns: (asleep_secs, asleep_joules, awake_secs, awake_joules, energy_wh)
+    async fn get_discharge_report(&self) -> (f64, f64, f64, f64, f64) {
+        let (asleep_secs, asleep_joules, awake_secs, awake_joules) =
+            self.state.lock().unwrap().discharge.to_tuple();
+        let energy_wh = sysfs::battery_energy().await.unwrap_or(0.0);
+        (
+            asleep_secs,
+            asleep_joules,
+            awake_secs,
+            awake_joules,
+            energy_wh,
+        )
+    }
+
+    /// Lifetime statistics, kept across daemon restarts.
+    /// Returns: (since_unix_timestamp, sleeps, wakes, d3cold_secs, enforcement_retries, failed_transitions)
+    async fn get_stats(&self) -> (u64, u64, u64, u64, u64, u64) {
+        stats::snapshot().to_tuple()
+    }
+
+    /// Last observed power state change.
+    /// Returns: (from, to, unix_timestamp), or "Unknown" twice and 0 if none was seen yet.
+    async fn last_transition(&self) -> (PowerState, PowerState, u64) {
+        match self.state.lock().unwrap().last_transition.clone() {
+            Some(t) => (t.from, t.to, t.timestamp),
+            None => (PowerState::Unknown, PowerState::Unknown, 0),
+        }
+    }
+
+    /// Persisted power state changes, oldest first, at most `limit` of them.
+    /// Returns: [(unix_timestamp, from, to, trigger)]
+    async fn get_history(&self, limit: u32) -> Vec<(u64, PowerState, PowerState, String)> {
+        spawn_blocking(move || history::load_recent(limit as usize))
             .await
-            .unwrap_or_else(|e| (false, format!("Internal error: {}", e), vec![]))
+            .unwrap_or_default()
+            .iter()
+            .map(|r| r.to_tuple())
+            .collect()
+    }
+
+    /// Kernel faults recorded for the GPU (Xid errors, falling off the bus,
+    /// PCIe AER), oldest first, at most `limit` of them.
+    /// Returns: [(unix_timestamp, kind, message)]
+    async fn get_faults(&self, limit: u32) -> Vec<(u64, String, String)> {
+        spawn_blocking(move || faults::load_recent(limit as usize))
+            .await
+            .unwrap_or_default()
+            .iter()
+            .map(|r| r.to_tuple())
+            .collect()
+    }
+
+    /// Kill processes using the GPU. Only processes actually holding the GPU are
+    /// touched; `pids` narrows them down further (empty means all of them).
+    /// Returns: a success message; fails with org.nvsleepify.Error.BlockedByProcesses
+    /// listing the processes still running.
+    async fn kill_processes(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: MessageHeader<'_>,
+        pids: Vec<u32>,
+    ) -> Result<String, Error> {
+        access::authorize(conn, &header).await?;
+        request_kill(pids).await
+    }
+
+    /// Kill one process using the GPU, e.g. picked from a list of blockers.
+    /// `name` is the name it was listed under; if the PID now belongs to
+    /// another process (PID reuse), fails with org.nvsleepify.Error.InvalidArgs.
+    /// Returns: a success message; fails with org.nvsleepify.Error.BlockedByProcesses
+    /// if it survived.
+    async fn kill_process(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: MessageHeader<'_>,
+        pid: u32,
+        name: String,
+    ) -> Result<String, Error> {
+        access::authorize(conn, &header).await?;
+        request_kill_one(pid, name).await
+    }
+
+    /// Run one step of the sleep or wake sequence by hand: "Unbind", "Bind",
+    /// "PowerOff", "PowerOn", "Rescan", "UnloadModules", "LoadModules",
+    /// "StopServices" or "StartServices". Enforcement is off afterwards until
+    /// a mode is set.
+    /// Returns: a success message; fails like SetMode when processes hold the
+    /// GPU or it drives the display.
+    async fn run_step(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: MessageHeader<'_>,
+        step: String,
+    ) -> Result<String, Error> {
+        access::authorize(conn, &header).await?;
+        request_step(&self.state, step).await
+    }
+
+    /// Power the GPU off and on `cycles` times (at most 50), killing the
+    /// processes holding it as the kill policy allows, and time each step.
+    /// The GPU has to be awake.
+    /// Returns: a(sad) of each step's name and its duration in seconds per cycle.
+    async fn bench(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: MessageHeader<'_>,
+        cycles: u32,
+    ) -> Result<Vec<StepTimings>, Error> {
+        access::authorize(conn, &header).await?;
+        request_bench(&self.state, cycles).await
     }
 
     /// Set restore delay in seconds.
-    async fn set_restore_delay(&self, seconds: u32) -> String {
-        spawn_blocking(move || save_delay(seconds))
+    async fn set_restore_delay(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: MessageHeader<'_>,
+        seconds: u32,
+    ) -> Result<String, Error> {
+        access::authorize(conn, &header).await?;
+        write_delay(seconds)
             .await
-            .unwrap_or_else(|e| Err(anyhow::anyhow!("Internal error: {}", e)))
             .map(|_| format!("Restore delay set to {} seconds", seconds))
-            .unwrap_or_else(|e| format!("Failed to set delay: {}", e))
+            .map_err(|e| Error::from(e.context("Failed to set delay")))
+    }
+
+    /// Seconds the daemon waits after login before restoring the saved mode.
+    async fn restore_delay(&self) -> u32 {
+        read_delay().await
+    }
+
+    /// Recent notable events (mode changes, transitions, enforcement, policy
+    /// decisions, failures), oldest first.
+    /// Returns: [(unix_timestamp, kind, message)]
+    async fn get_events(&self) -> Vec<(u64, String, String)> {
+        events::recent().iter().map(|e| e.to_tuple()).collect()
+    }
+
+    /// Recent daemon events, oldest first.
+    /// Returns: [(unix_timestamp, level, message)]
+    async fn get_logs(&self) -> Vec<(u64, String, String)> {
+        eventlog::entries().iter().map(|e| e.to_tuple()).collect()
+    }
+
+    /// Emitted for every new event log entry.
+    #[dbus_interface(signal)]
+    async fn log_entry(
+        ctxt: &SignalContext<'_>,
+        timestamp: u64,
+        level: &str,
+        message: &str,
+    ) -> zbus::Result<()>;
+
+    /// Emitted for every notable event; `kind` is one of "mode", "transition",
+    /// "enforcement", "policy" or "failure".
+    #[dbus_interface(signal)]
+    async fn event(
+        ctxt: &SignalContext<'_>,
+        timestamp: u64,
+        kind: &str,
+        message: &str,
+    ) -> zbus::Result<()>;
+
+    /// Emitted whenever the mode or the GPU power state changes.
+    #[dbus_interface(signal)]
+    async fn state_changed(
+        ctxt: &SignalContext<'_>,
+        mode: &str,
+        power_state: PowerState,
+    ) -> zbus::Result<()>;
+}
+
+/// Shared by Manager.Status and the socket.
+pub(crate) async fn request_status(state: &SharedState) -> String {
+    let mut output = spawn_blocking(status_logic)
+        .await
+        .unwrap_or_else(|e| format!("Internal error: {}", e));
+    let state = state.lock().unwrap();
+    if let Some(reason) = &state.hands_off {
+        writeln!(output, "Enforcement: off ({}) until a mode is set", reason).unwrap();
+    }
+    if !state.leases.is_empty() {
+        writeln!(output, "Wake Locks:").unwrap();
+        for lease in state.leases.held() {
+            writeln!(output, "  {}", lease).unwrap();
+        }
+    }
+    output
+}
+
+/// Shared by Manager.Explain and the socket. Follows the monitor's order of
+/// precedence: hibernation, temporary wake, wake locks, paused enforcement,
+/// then the mode and what it depends on.
+pub(crate) async fn request_explain(state: &SharedState) -> String {
+    let mode = read_mode().await;
+    let power_state = sysfs::power_state().await;
+    let (processes, sources) = spawn_blocking(|| {
+        let processes = PciDevice::find_nvidia_gpu()
+            .ok()
+            .and_then(|gpu| system::get_processes_using_nvidia(&gpu.get_device_nodes()).ok())
+            .unwrap_or_default();
+        (processes, profiles::online_sources())
+    })
+    .await
+    .unwrap_or_default();
+    let names: Vec<String> = processes.iter().map(|p| p.to_string()).collect();
+    let blockers = names.join(", ");
+    let asleep = power_state.is_asleep();
+    let charging_now = sysfs::charging().await;
+    let now = unix_now();
+    let state = state.lock().unwrap();
+
+    let (charging, charger_since) = state.charger.unwrap_or((charging_now, now));
+    let stable = now.saturating_sub(charger_since);
+    let charger = format!(
+        "{} ({} {})",
+        if charging { "AC online" } else { "on battery" },
+        if stable < DEBOUNCE.as_secs() {
+            "debouncing, for"
+        } else {
+            "stable"
+        },
+        format_duration(stable)
+    );
+    let state_word = if asleep { "Asleep" } else { "Awake" };
+
+    let headline = if state.hibernating {
+        "Awake because: powered up for the hibernation image".to_string()
+    } else if let Some(o) = state.wake_override {
+        format!(
+            "Awake because: temporary wake ({} left, then {} mode)",
+            format_duration(o.until.saturating_sub(now)),
+            o.previous
+        )
+    } else if !state.leases.is_empty() {
+        format!(
+            "Awake because: {} wake lock(s) held",
+            state.leases.held().len()
+        )
+    } else if !state.conflicts.is_empty() {
+        format!(
+            "{} and left alone because: {}",
+            state_word,
+            conflicts::describe(&state.conflicts)
+        )
+    } else if let Some(reason) = &state.hands_off {
+        format!("{} and left alone because: {}", state_word, reason)
+    } else {
+        match mode {
+            Mode::Standard => format!("{} because: Standard mode", state_word),
+            Mode::Vfio => format!("{} because: VFIO mode (handed to vfio-pci)", state_word),
+            Mode::Integrated if asleep => "Asleep because: Integrated mode".to_string(),
+            Mode::Integrated if state.deferred_sleep => format!(
+                "Awake because: Integrated mode is waiting for {} to exit",
+                blockers
+            ),
+            Mode::Integrated if !processes.is_empty() => format!(
+                "Awake because: Integrated mode, but {} hold the GPU (kill policy: {})",
+                blockers,
+                system::kill_policy()
+            ),
+            Mode::Integrated => "Awake: Integrated mode, sleep pending".to_string(),
+            Mode::Optimized if !charging && !asleep && !processes.is_empty() => format!(
+                "Awake because: Optimized mode + {}, but {} hold the GPU",
+                charger, blockers
+            ),
+            Mode::Optimized => format!("{} because: Optimized mode + {}", state_word, charger),
+        }
+    };
+
+    let mut output = String::new();
+    writeln!(output, "{}", headline).unwrap();
+    writeln!(output).unwrap();
+    writeln!(output, "Mode:            {}", mode).unwrap();
+    writeln!(output, "Power State:     {}", power_state).unwrap();
+    writeln!(
+        output,
+        "Power Source:    {}",
+        if sources.is_empty() {
+            "Battery".to_string()
+        } else {
+            sources.join(", ")
+        }
+    )
+    .unwrap();
+    writeln!(output, "Charger:         {}", charger).unwrap();
+    if state.profiles.is_enabled() {
+        match &state.context {
+            Some((context, since)) => writeln!(
+                output,
+                "Profile:         {} for {}, [profiles] picks {}",
+                context,
+                format_duration(now.saturating_sub(*since)),
+                state
+                    .profiles
+                    .mode_for(context)
+                    .map(|m| m.to_string())
+                    .unwrap_or_else(|| "no mode".to_string())
+            )
+            .unwrap(),
+            None => writeln!(output, "Profile:         not evaluated yet").unwrap(),
+        }
+    } else {
+        writeln!(output, "Profile:         off").unwrap();
+    }
+    match state.wake_override {
+        Some(o) => writeln!(
+            output,
+            "Temporary Wake:  {} left",
+            format_duration(o.until.saturating_sub(now))
+        )
+        .unwrap(),
+        None => writeln!(output, "Temporary Wake:  none").unwrap(),
+    }
+    if state.leases.is_empty() {
+        writeln!(output, "Wake Locks:      none").unwrap();
+    } else {
+        writeln!(output, "Wake Locks:").unwrap();
+        for lease in state.leases.held() {
+            writeln!(output, "  {}", lease).unwrap();
+        }
+    }
+    writeln!(
+        output,
+        "Blockers:        {}",
+        if blockers.is_empty() {
+            "none"
+        } else {
+            &blockers
+        }
+    )
+    .unwrap();
+    if let Some((trigger, at)) = &state.last_external_wake {
+        writeln!(
+            output,
+            "Last Woken:      {} ago, {}",
+            format_duration(now.saturating_sub(*at)),
+            trigger
+        )
+        .unwrap();
+    }
+    writeln!(output, "Kill Policy:     {}", system::kill_policy()).unwrap();
+    let server = system::server();
+    if server.enabled {
+        writeln!(
+            output,
+            "Server Profile:  on, Integrated sleeps after {} unused",
+            format_duration(server.idle_after)
+        )
+        .unwrap();
+    }
+    writeln!(
+        output,
+        "Deferred Sleep:  {}",
+        if state.deferred_sleep {
+            "waiting for the blockers to exit"
+        } else {
+            "no"
+        }
+    )
+    .unwrap();
+    let enforcement = if state.hibernating {
+        "paused for hibernation".to_string()
+    } else if let Some(reason) = &state.hands_off {
+        format!("off ({})", reason)
+    } else if !state.conflicts.is_empty() {
+        format!("paused, {}", conflicts::describe(&state.conflicts))
+    } else {
+        "active".to_string()
+    };
+    writeln!(output, "Enforcement:     {}", enforcement).unwrap();
+    output
+}
+
+/// Shared by Manager.Info and the socket.
+pub(crate) async fn request_info(state: &SharedState) -> StatusReport {
+    spawn_blocking(info_logic)
+        .await
+        .map(|report| StatusReport {
+            pending_sleep: state.lock().unwrap().deferred_sleep,
+            ..report
+        })
+        .unwrap_or_else(|e| {
+            eventlog::error(format!("Info failed: {}", e));
+            StatusReport {
+                mode: "Unknown".to_string(),
+                ..Default::default()
+            }
+        })
+}
+
+/// Shared by Manager.SetMode, Applet1.SetMode and the socket. `ctxt` is the
+/// Manager's, if the daemon is on the bus.
+pub(crate) async fn request_mode(
+    state: &SharedState,
+    ctxt: Option<&SignalContext<'_>>,
+    requester: Option<u32>,
+    mode_str: String,
+    kill_procs: bool,
+    force: bool,
+) -> Result<TransitionResult, Error> {
+    let requested = mode_str.clone();
+    let shared = state.clone();
+    let result = spawn_blocking(move || {
+        let mode = parse_mode(&mode_str)?;
+        // Nothing below changes unless this request gets to run; a busy
+        // rejection leaves the running wake or deferred sleep alone.
+        let transition = begin_transition()?;
+        shared
+            .lock()
+            .unwrap()
+            .set_trigger(format!("SetMode({})", mode_str));
+        let result = set_mode_locked(&transition, mode, kill_procs, force)?;
+        let profiles = {
+            let mut state = shared.lock().unwrap();
+            state.requester = requester;
+            // An explicit mode choice ends any temporary wake and deferred sleep.
+            state.wake_override = None;
+            state.deferred_sleep = false;
+            state.hands_off = None;
+            state.profiles.clone()
+        };
+        if let Err(e) = profiles.remember(&profiles.current(), mode) {
+            eventlog::error(format!("Failed to remember the mode: {}", e));
+        }
+        Ok(result)
+    })
+    .await
+    .unwrap_or_else(|e| Err(Error::Failed(format!("Internal error: {}", e))));
+    match &result {
+        Ok(_) => events::emit(EventKind::Mode, format!("Mode set to {}", requested)),
+        Err(e) => {
+            events::emit(
+                EventKind::Failure,
+                format!("Set mode to {} failed: {}", requested, e),
+            );
+            if !matches!(e, Error::Busy(_)) {
+                stats::record_failure();
+            }
+        }
+    }
+    if let Some(ctxt) = ctxt {
+        emit_state_changed(ctxt).await;
+    }
+    result
+}
+
+/// Shared by Manager.SleepWhenIdle and the socket.
+pub(crate) async fn request_sleep_when_idle(
+    state: &SharedState,
+    ctxt: Option<&SignalContext<'_>>,
+) -> Result<TransitionResult, Error> {
+    {
+        let mut state = state.lock().unwrap();
+        state.set_trigger("SleepWhenIdle");
+        state.wake_override = None;
+        state.hands_off = None;
+    }
+    let result = spawn_blocking(deferred_sleep_logic)
+        .await
+        .unwrap_or_else(|e| Err(Error::Failed(format!("Internal error: {}", e))));
+    let result = result.map(|(result, pending)| {
+        state.lock().unwrap().deferred_sleep = pending;
+        if pending {
+            events::emit(
+                EventKind::Mode,
+                format!("Mode set to Integrated: {}", result.message),
+            );
+        } else {
+            events::emit(EventKind::Mode, "Mode set to Integrated");
+        }
+        result
+    });
+    if let Err(e) = &result {
+        events::emit(
+            EventKind::Failure,
+            format!("Set mode to Integrated failed: {}", e),
+        );
+    }
+    if let Some(ctxt) = ctxt {
+        emit_state_changed(ctxt).await;
+    }
+    result
+}
+
+/// Shared by Manager.WakeFor and the socket.
+pub(crate) async fn request_wake_for(
+    state: &SharedState,
+    ctxt: Option<&SignalContext<'_>>,
+    seconds: u32,
+) -> Result<String, Error> {
+    let saved = read_mode().await;
+    // An extended wake returns to the mode the first one interrupted.
+    let previous = {
+        let mut state = state.lock().unwrap();
+        let previous = state.wake_override.map(|o| o.previous).unwrap_or(saved);
+        if previous == Mode::Standard {
+            return Ok("GPU is already kept awake in Standard mode".to_string());
+        }
+        state.wake_override = Some(WakeOverride {
+            until: unix_now() + seconds as u64,
+            previous,
+        });
+        state.deferred_sleep = false;
+        state.set_trigger(format!("WakeFor({}s)", seconds));
+        previous
+    };
+    events::emit(
+        EventKind::Policy,
+        format!(
+            "Keeping GPU awake for {} seconds before returning to {} mode",
+            seconds, previous
+        ),
+    );
+
+    let result = spawn_blocking(wake_logic)
+        .await
+        .unwrap_or_else(|e| Err(Error::Failed(format!("Internal error: {}", e))));
+    if let Err(e) = &result {
+        events::emit(EventKind::Failure, format!("Wake failed: {}", e));
+    }
+    if let Some(ctxt) = ctxt {
+        emit_state_changed(ctxt).await;
+    }
+    result
+}
+
+/// Shared by Manager.CancelWake and the socket.
+pub(crate) async fn request_cancel_wake(
+    state: &SharedState,
+    ctxt: Option<&SignalContext<'_>>,
+) -> Result<String, Error> {
+    let Some(wake_override) = state.lock().unwrap().wake_override.take() else {
+        return Ok("No temporary wake active".to_string());
+    };
+    events::emit(EventKind::Policy, "Temporary wake cancelled");
+    if !state.lock().unwrap().leases.is_empty() {
+        return Ok(format!(
+            "Temporary wake cancelled; wake locks keep the GPU awake until {} mode applies",
+            wake_override.previous
+        ));
+    }
+    let result = apply_mode_after_override(state, wake_override).await;
+    if let Some(ctxt) = ctxt {
+        emit_state_changed(ctxt).await;
+    }
+    result.map(|r| r.message)
+}
+
+/// Shared by Manager.Bench and the socket.
+pub(crate) async fn request_bench(
+    state: &SharedState,
+    cycles: u32,
+) -> Result<Vec<StepTimings>, Error> {
+    state
+        .lock()
+        .unwrap()
+        .set_trigger(format!("Bench({} cycles)", cycles));
+    let result = spawn_blocking(move || bench_logic(cycles))
+        .await
+        .unwrap_or_else(|e| Err(Error::Failed(format!("Internal error: {}", e))));
+    match &result {
+        Ok(_) => eventlog::info(format!("Benchmarked {} sleep/wake cycles", cycles)),
+        Err(e) => eventlog::error(format!("Benchmark failed: {}", e)),
+    }
+    result
+}
+
+/// Shared by Manager.RunStep and the socket. Enforcement stays off afterwards,
+/// so the monitor doesn't undo the step, until a mode is set.
+pub(crate) async fn request_step(state: &SharedState, step: String) -> Result<String, Error> {
+    let step = ManualStep::from_str(&step).map_err(Error::InvalidArgs)?;
+    {
+        let mut state = state.lock().unwrap();
+        state.set_trigger(format!("Step({})", step));
+        state.hands_off = Some(format!("ran the {} step by hand", step));
+    }
+    let result = spawn_blocking(move || run_step_logic(step))
+        .await
+        .unwrap_or_else(|e| Err(Error::Failed(format!("Internal error: {}", e))));
+    match &result {
+        Ok(msg) => eventlog::info(msg.clone()),
+        Err(e) => eventlog::error(format!("Step {} failed: {}", step, e)),
+    }
+    result
+}
+
+/// Shared by Manager.KillProcesses, Applet1.KillBlockers and the socket.
+pub(crate) async fn request_kill(pids: Vec<u32>) -> Result<String, Error> {
+    let result = spawn_blocking(move || kill_logic(&pids))
+        .await
+        .unwrap_or_else(|e| Err(Error::Failed(format!("Internal error: {}", e))));
+    match &result {
+        Ok(msg) => eventlog::info(msg.clone()),
+        Err(e) => eventlog::error(format!("Kill processes failed: {}", e)),
+    }
+    result
+}
+
+/// Shared by Manager.KillProcess and the socket.
+pub(crate) async fn request_kill_one(pid: u32, name: String) -> Result<String, Error> {
+    let result = spawn_blocking(move || kill_one_logic(pid, &name))
+        .await
+        .unwrap_or_else(|e| Err(Error::Failed(format!("Internal error: {}", e))));
+    match &result {
+        Ok(msg) => eventlog::info(msg.clone()),
+        Err(e) => eventlog::error(format!("Kill process {} failed: {}", pid, e)),
+    }
+    result
+}
+
+async fn emit_state_changed(ctxt: &SignalContext<'_>) {
+    let (mode, power_state) = (read_mode().await, sysfs::power_state().await);
+    if let Err(e) = NvSleepifyManager::state_changed(ctxt, &mode.to_string(), power_state).await {
+        eprintln!("Failed to emit StateChanged signal: {}", e);
+    }
+}
+
+async fn apply_mode_after_override(
+    state: &SharedState,
+    wake_override: WakeOverride,
+) -> Result<TransitionResult, Error> {
+    let mode = wake_override.previous;
+    state
+        .lock()
+        .unwrap()
+        .set_trigger(format!("Temporary wake ended ({})", mode));
+    let result = spawn_blocking(move || set_mode_logic(&mode.to_string(), false, false))
+        .await
+        .unwrap_or_else(|e| Err(Error::Failed(format!("Internal error: {}", e))));
+    if let Err(e) = &result {
+        events::emit(
+            EventKind::Failure,
+            format!(
+                "Failed to return to {} mode after temporary wake: {}",
+                mode, e
+            ),
+        );
+    }
+    result
+}
+
+/// Once the last wake lock is gone, puts the GPU back into the state the saved
+/// mode calls for, unless a temporary wake still holds it.
+async fn reapply_after_leases(state: &SharedState) {
+    {
+        let mut state = state.lock().unwrap();
+        if !state.leases.is_empty() || state.wake_override.is_some() {
+            return;
+        }
+        state.set_trigger("Wake locks released");
+    }
+    let mode = read_mode().await;
+    if mode == Mode::Integrated && system::server().enabled {
+        // The monitor sleeps it once it has been unused for `idle_after`.
+        return;
+    }
+    let result = spawn_blocking(move || set_mode_logic(&mode.to_string(), false, false))
+        .await
+        .unwrap_or_else(|e| Err(Error::Failed(format!("Internal error: {}", e))));
+    if let Err(e) = result {
+        events::emit(
+            EventKind::Failure,
+            format!(
+                "Failed to return to {} mode after the wake locks were released: {}",
+                mode, e
+            ),
+        );
+    }
+}
+
+/// Drops the wake locks of connections that leave the bus, so a crashed
+/// application can't keep the GPU awake.
+async fn watch_lease_owners(conn: Connection, state: SharedState) {
+    let Ok(dbus) = zbus::fdo::DBusProxy::new(&conn).await else {
+        return;
+    };
+    let Ok(mut changes) = dbus.receive_name_owner_changed().await else {
+        eventlog::error("Can't watch for disconnects; wake locks are only released explicitly");
+        return;
+    };
+    while let Some(change) = changes.next().await {
+        let Ok(args) = change.args() else {
+            continue;
+        };
+        if args.new_owner().is_some() || !args.name().starts_with(':') {
+            continue;
+        }
+        let released = state
+            .lock()
+            .unwrap()
+            .leases
+            .release_owner(args.name().as_str());
+        if released.is_empty() {
+            continue;
+        }
+        for lease in &released {
+            events::emit(
+                EventKind::Policy,
+                format!(
+                    "Wake lock #{} released: {} left the bus",
+                    lease.id, lease.app
+                ),
+            );
+        }
+        reapply_after_leases(&state).await;
+    }
+}
+
+/// Holds a wake lock for `unit` while it runs, like an application would,
+/// going by its ActiveState on systemd's bus.
+async fn watch_unit(conn: Connection, state: SharedState, unit: String) {
+    let mut running = match units::running_states(&conn, &unit).await {
+        Ok(running) => Box::pin(running),
+        Err(e) => {
+            eventlog::error(format!(
+                "Can't watch {}, it won't keep the GPU awake: {}",
+                unit, e
+            ));
+            return;
+        }
+    };
+    let owner = units::lease_owner(&unit);
+    let ctxt = SignalContext::new(&conn, OBJECT_PATH).ok();
+    while let Some(running) = running.next().await {
+        let held = {
+            let state = state.lock().unwrap();
+            state.leases.held().iter().any(|lease| lease.owner == owner)
+        };
+        if running && !held {
+            let id = {
+                let mut state = state.lock().unwrap();
+                state.set_trigger(format!("Wake lock ({})", unit));
+                state.leases.acquire(&owner, &unit, "Unit is running")
+            };
+            events::emit(
+                EventKind::Policy,
+                format!("Wake lock #{} taken: {} is running", id, unit),
+            );
+            if matches!(read_mode().await, Mode::Integrated | Mode::Optimized) {
+                let result = spawn_blocking(wake_logic)
+                    .await
+                    .unwrap_or_else(|e| Err(Error::Failed(format!("Internal error: {}", e))));
+                if let Err(e) = result {
+                    events::emit(EventKind::Failure, format!("Wake failed: {}", e));
+                }
+            }
+        } else if !running && held {
+            for lease in state.lock().unwrap().leases.release_owner(&owner) {
+                events::emit(
+                    EventKind::Policy,
+                    format!("Wake lock #{} released: {} stopped", lease.id, unit),
+                );
+            }
+            reapply_after_leases(&state).await;
+        } else {
+            continue;
+        }
+        if let Some(ctxt) = &ctxt {
+            emit_state_changed(ctxt).await;
+        }
+    }
+}
+
+/// Powers a sleeping GPU up before a hibernation image is written, and
+/// applies the saved mode again after thaw. Suspend to RAM is left alone.
+async fn watch_hibernation(conn: Connection, state: SharedState) {
+    let mut sleeps = match logind::sleep_signals(&conn).await {
+        Ok(sleeps) => sleeps,
+        Err(e) => {
+            eventlog::error(format!(
+                "Can't watch for hibernation, a sleeping GPU may not survive it: {}",
+                e
+            ));
+            return;
+        }
+    };
+    let take_inhibitor =
+        || spawn_blocking(|| inhibit::delay_sleep("Preparing the Nvidia GPU for hibernation"));
+    let mut inhibitor = take_inhibitor().await.ok().flatten();
+    while let Some(start) = sleeps.next().await {
+        if !start {
+            if std::mem::take(&mut state.lock().unwrap().hibernating) {
+                let profiles = {
+                    let mut state = state.lock().unwrap();
+                    state.set_trigger("Thawed from hibernation");
+                    state.profiles.clone()
+                };
+                events::emit(
+                    EventKind::Policy,
+                    "Thawed from hibernation, applying the saved mode",
+                );
+                // Only we touched the GPU, so there's nothing to reconcile.
+                if let Err(e) =
+                    spawn_blocking(move || restore_logic(&profiles, Reconcile::PreferState))
+                        .await
+                        .unwrap_or_else(|e| Err(e.into()))
+                {
+                    events::emit(
+                        EventKind::Failure,
+                        format!("Failed to apply the saved mode after thaw: {}", e),
+                    );
+                }
+            }
+            inhibitor = take_inhibitor().await.ok().flatten();
+            continue;
+        }
+        if spawn_blocking(hibernate::entering).await.unwrap_or(false) {
+            prepare_for_hibernation(&state).await;
+        }
+        // Let the system go to sleep.
+        drop(inhibitor.take());
+    }
+}
+
+async fn prepare_for_hibernation(state: &SharedState) {
+    state.lock().unwrap().hibernating = true;
+    match spawn_blocking(hibernate_logic)
+        .await
+        .unwrap_or_else(|e| Err(Error::Failed(format!("Internal error: {}", e))))
+    {
+        Ok(true) => {
+            state
+                .lock()
+                .unwrap()
+                .set_trigger("Powered up for hibernation");
+            events::emit(
+                EventKind::Policy,
+                "Hibernating: powered the GPU up so the image matches the hardware at resume",
+            );
+        }
+        Ok(false) => {
+            // Already on the bus; nothing to put back after thaw.
+            state.lock().unwrap().hibernating = false;
+            if spawn_blocking(|| hibernate::driver_loaded() && !hibernate::nvidia_hooks_enabled())
+                .await
+                .unwrap_or(false)
+            {
+                events::emit(
+                    EventKind::Failure,
+                    "Hibernating with the nvidia driver loaded, but nvidia-hibernate.service \
+                     and nvidia-resume.service are not enabled; the GPU may not resume",
+                );
+            }
+        }
+        Err(e) => {
+            events::emit(
+                EventKind::Failure,
+                format!("Failed to power the GPU up for hibernation: {}", e),
+            );
+        }
+    }
+}
+
+async fn forward_log_entries(conn: Connection) {
+    let mut rx = eventlog::subscribe();
+    loop {
+        let entry = match rx.recv().await {
+            Ok(entry) => entry,
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+        };
+        if let Ok(ctxt) = SignalContext::new(&conn, OBJECT_PATH) {
+            let _ = NvSleepifyManager::log_entry(
+                &ctxt,
+                entry.timestamp,
+                &entry.level.to_string(),
+                &entry.message,
+            )
+            .await;
+        }
+    }
+}
+
+async fn forward_events(conn: Connection) {
+    let mut rx = events::subscribe();
+    loop {
+        let event = match rx.recv().await {
+            Ok(event) => event,
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+        };
+        if let Ok(ctxt) = SignalContext::new(&conn, OBJECT_PATH) {
+            let _ = NvSleepifyManager::event(
+                &ctxt,
+                event.timestamp,
+                &event.kind.to_string(),
+                &event.message,
+            )
+            .await;
+        }
+    }
+}
+
+/// Earliest of the pending deadlines.
+fn next_deadline(deadlines: &[Option<Instant>]) -> Option<Instant> {
+    deadlines.iter().flatten().min().copied()
+}
+
+/// Re-checks the GPU and enforces the mode whenever something may have
+/// changed: a GPU or power supply uevent, a write to the mode file, or a
+/// deadline (charger debounce, temporary wake expiry, retry, fallback check).
+async fn monitor_loop(
+    conn: Option<Connection>,
+    state: SharedState,
+    initial_power_state: PowerState,
+) {
+    let mut triggers = Triggers::open(std::path::Path::new(MODE_FILE));
+    // Logins and logouts, which are what makes the login screen let go.
+    let mut sessions = match conn.as_ref() {
+        Some(conn) => logind::session_changes(conn).await.ok(),
+        None => None,
+    };
+    let fallback = Duration::from_secs(if triggers.is_event_driven() {
+        FALLBACK_CHECK_SECS
+    } else {
+        POLL_SECS
+    });
+    let mut last_check: Option<Instant> = None;
+    let mut retry_at: Option<Instant> = None;
+
+    let mut last_charging = system::get_charging_status();
+    let mut stable_since = Instant::now();
+    state.lock().unwrap().charger = Some((last_charging, unix_now()));
+    let mut debounce_at = Some(stable_since + DEBOUNCE);
+    // Whether the current Optimized decision was announced as an event.
+    let mut policy_announced = false;
+    let mut last_seen: Option<(Mode, PowerState)> = Some((read_mode().await, initial_power_state));
+
+    // The power context the mode last followed; the startup restore already
+    // applied the one we start in.
+    let profiles = state.lock().unwrap().profiles.clone();
+    let mut context = current_context(&profiles).await;
+    let mut context_since = Instant::now();
+    state.lock().unwrap().context = Some((context.clone(), unix_now()));
+    let mut context_pending = false;
+
+    // Server profile: when the awake GPU was last seen unused.
+    let server = system::server();
+    let mut idle_since: Option<Instant> = None;
+    // Utilization while processes hold the awake GPU.
+    let mut idle = IdleTracker::new(system::idle());
+    // While the login screen blocks a deferred sleep: when to look again
+    // without a session change, and the backoff that got there.
+    let mut greeter_retry: Option<(Instant, Duration)> = None;
+
+    loop {
+        let mut session_changed = false;
+        let wake_override_at = state
+            .lock()
+            .unwrap()
+            .wake_override
+            .map(|o| Instant::now() + Duration::from_secs(o.until.saturating_sub(unix_now())));
+        if let Some(last) = last_check {
+            let deadline = next_deadline(&[
+                Some(last + fallback),
+                retry_at,
+                debounce_at,
+                wake_override_at,
+            ])
+            .unwrap_or(last + fallback);
+            let session_change = async {
+                match sessions.as_mut() {
+                    Some(stream) => stream.next().await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = triggers.next() => {}
+                change = session_change => {
+                    session_changed = true;
+                    if change.is_none() {
+                        sessions = None;
+                    }
+                }
+                _ = tokio::time::sleep_until(deadline) => {}
+            }
+        }
+        let now = Instant::now();
+        last_check = Some(now);
+        // Deadlines that passed are handled by this check.
+        retry_at = retry_at.filter(|t| *t > now);
+        debounce_at = debounce_at.filter(|t| *t > now);
+
+        let mode = read_mode().await;
+        let power_state = sysfs::power_state().await;
+        if let Some((last_mode, last_power)) = last_seen {
+            if last_power != power_state {
+                events::emit(
+                    EventKind::Transition,
+                    format!("Monitor: GPU power state {} -> {}", last_power, power_state),
+                );
+                let mut trigger = state.lock().unwrap().take_trigger();
+                // Something woke it behind our back: find out who.
+                if trigger == EXTERNAL_TRIGGER
+                    && last_power != PowerState::D0
+                    && power_state == PowerState::D0
+                {
+                    trigger = attribute_wake().await;
+                    events::emit(EventKind::Transition, format!("Monitor: GPU {}", trigger));
+                    state.lock().unwrap().last_external_wake = Some((trigger.clone(), unix_now()));
+                }
+                let record = {
+                    let mut state = state.lock().unwrap();
+                    let record = TransitionRecord {
+                        timestamp: unix_now(),
+                        from: last_power,
+                        to: power_state,
+                        trigger,
+                    };
+                    state.last_transition = Some(record.clone());
+                    record
+                };
+                metrics::record_transition(power_state);
+                stats::record_transition(last_power, power_state);
+                let _ = spawn_blocking(move || {
+                    if let Err(e) = history::append(&record) {
+                        eprintln!("Failed to write transition history: {}", e);
+                    }
+                })
+                .await;
+            }
+            if last_mode != mode || last_power != power_state {
+                if let Some(Ok(ctxt)) = conn.as_ref().map(|c| SignalContext::new(c, OBJECT_PATH)) {
+                    let _ = NvSleepifyManager::state_changed(&ctxt, &mode.to_string(), power_state)
+                        .await;
+                }
+            }
+        }
+        metrics::observe(mode, power_state);
+        stats::observe(power_state);
+        {
+            let mut state = state.lock().unwrap();
+            state.power_state = power_state;
+            if power_state.is_asleep() {
+                state.sleep_blocked.reset();
+            }
+        }
+        last_seen = Some((mode, power_state));
+
+        let expired = {
+            let mut state = state.lock().unwrap();
+            match state.wake_override {
+                Some(o) if unix_now() >= o.until => state.wake_override.take(),
+                _ => None,
+            }
+        };
+        if let Some(wake_override) = expired.filter(|_| !state.lock().unwrap().leases.is_empty()) {
+            events::emit(
+                EventKind::Policy,
+                format!(
+                    "Temporary wake expired, wake locks keep the GPU awake until {} mode applies",
+                    wake_override.previous
+                ),
+            );
+        } else if let Some(wake_override) =
+            expired.filter(|o| server.enabled && o.previous == Mode::Integrated)
+        {
+            // Left to the idle check below, like any job's wake.
+            events::emit(
+                EventKind::Policy,
+                format!(
+                    "Temporary wake expired, returning to {} mode once the GPU is unused for {}",
+                    wake_override.previous,
+                    format_duration(server.idle_after)
+                ),
+            );
+        } else if let Some(wake_override) = expired {
+            events::emit(
+                EventKind::Policy,
+                format!(
+                    "Temporary wake expired, returning to {} mode",
+                    wake_override.previous
+                ),
+            );
+            let _ = apply_mode_after_override(&state, wake_override).await;
+            continue;
+        }
+        {
+            let state = state.lock().unwrap();
+            if state.wake_override.is_some() || !state.leases.is_empty() {
+                // Behave like Standard until the override runs out and the
+                // wake locks are released.
+                continue;
+            }
+        }
+        if state.lock().unwrap().hibernating {
+            // The GPU has to stay on the bus until the image is written.
+            continue;
+        }
+        if state.lock().unwrap().hands_off.is_some() {
+            continue;
+        }
+        if !state.lock().unwrap().conflicts.is_empty() {
+            // Don't fight another switcher over the GPU.
+            continue;
+        }
+        if transition::in_progress() {
+            // A requested transition is running; check again shortly.
+            retry_at = Some(now + RETRY);
+            continue;
+        }
+
+        if profiles.is_enabled() {
+            let current = current_context(&profiles).await;
+            if current != context {
+                events::emit(
+                    EventKind::Policy,
+                    format!(
+                        "Monitor: Power context changed to {}. Debouncing...",
+                        current
+                    ),
+                );
+                context = current;
+                context_since = Instant::now();
+                state.lock().unwrap().context = Some((context.clone(), unix_now()));
+                debounce_at = Some(context_since + DEBOUNCE);
+                context_pending = true;
+            } else if context_pending && context_since.elapsed() >= DEBOUNCE {
+                context_pending = false;
+                let target = {
+                    let (profiles, context) = (profiles.clone(), context.clone());
+                    spawn_blocking(move || profiles.mode_for(&context))
+                        .await
+                        .ok()
+                        .flatten()
+                };
+                if let Some(target) = target.filter(|target| *target != mode) {
+                    events::emit(
+                        EventKind::Policy,
+                        format!("Profile: switching to {} mode on {}", target, context),
+                    );
+                    {
+                        let mut state = state.lock().unwrap();
+                        state.set_trigger(format!("Profile: {}", context));
+                        state.deferred_sleep = false;
+                    }
+                    match spawn_blocking(move || set_mode_logic(&target.to_string(), false, false))
+                        .await
+                    {
+                        Ok(Ok(_)) => {}
+                        Ok(Err(e)) => {
+                            // Try again on the next check.
+                            context_pending = true;
+                            retry_at = Some(Instant::now() + RETRY);
+                            if !matches!(e, Error::Busy(_)) {
+                                events::emit(
+                                    EventKind::Failure,
+                                    format!("Profile: switching to {} failed: {}", target, e),
+                                );
+                            }
+                        }
+                        Err(_) => {}
+                    }
+                    continue;
+                }
+            }
+        }
+
+        match mode {
+            Mode::Optimized => {
+                let current_charging = sysfs::charging().await;
+
+                if current_charging != last_charging {
+                    events::emit(
+                        EventKind::Policy,
+                        format!(
+                            "Monitor: Power state changed to {}. Debouncing...",
+                            if current_charging {
+                                "Charging"
+                            } else {
+                                "Unplugged"
+                            }
+                        ),
+                    );
+                    last_charging = current_charging;
+                    stable_since = Instant::now();
+                    state.lock().unwrap().charger = Some((current_charging, unix_now()));
+                    debounce_at = Some(stable_since + DEBOUNCE);
+                    policy_announced = false;
+                } else if stable_since.elapsed() >= DEBOUNCE {
+                    if !policy_announced {
+                        events::emit(
+                            EventKind::Policy,
+                            if current_charging {
+                                "Optimized: on AC, keeping the GPU awake"
+                            } else {
+                                "Optimized: on battery, putting the GPU to sleep"
+                            },
+                        );
+                        policy_announced = true;
+                    }
+                    if current_charging {
+                        state.lock().unwrap().set_trigger("Optimized: on AC");
+                        let result = spawn_blocking(wake_logic).await;
+                        if !matches!(result, Ok(Ok(_))) {
+                            retry_at = Some(Instant::now() + RETRY);
+                        }
+                        // Losing the race to a requested transition isn't a failure.
+                        match result {
+                            Ok(Err(e)) if !matches!(e, Error::Busy(_)) => {
+                                events::emit(
+                                    EventKind::Failure,
+                                    format!("Monitor: Wake failed: {}", e),
+                                );
+                                metrics::record_enforcement_failure();
+                                stats::record_failure();
+                            }
+                            _ => {}
+                        }
+                    } else {
+                        state.lock().unwrap().set_trigger("Optimized: on battery");
+                        let result = spawn_blocking(|| sleep_logic(Kill::No)).await;
+                        if !matches!(result, Ok(Ok(_))) {
+                            retry_at = Some(Instant::now() + RETRY);
+                        }
+                        match result {
+                            Ok(Err(e)) if !matches!(e, Error::Busy(_)) => {
+                                if !matches!(e, Error::BlockedByProcesses { .. }) {
+                                    events::emit(
+                                        EventKind::Failure,
+                                        format!("Monitor: Sleep failed: {}", e),
+                                    );
+                                }
+                                notify_sleep_blocked(conn.as_ref(), &state, &e);
+                                metrics::record_enforcement_failure();
+                                stats::record_failure();
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            Mode::Integrated => {
+                let power_state = sysfs::power_state().await;
+                let should_sleep = matches!(power_state, PowerState::D0 | PowerState::Unknown);
+                if !should_sleep {
+                    idle_since = None;
+                    idle.reset();
+                }
+
+                let deferred = state.lock().unwrap().deferred_sleep;
+                if !deferred || !should_sleep {
+                    greeter_retry = None;
+                }
+                // Held by the login screen, which only lets go on a login or logout.
+                let greeter_wait = greeter_retry
+                    .filter(|(at, _)| *at > now && !session_changed)
+                    .map(|(at, _)| at);
+                if !should_sleep && deferred {
+                    // Slept some other way, e.g. runtime PM after the last user left.
+                    state.lock().unwrap().deferred_sleep = false;
+                } else if let Some(at) = greeter_wait.filter(|_| should_sleep && deferred) {
+                    retry_at = Some(at);
+                } else if should_sleep && deferred {
+                    state.lock().unwrap().set_trigger("Deferred sleep");
+                    match spawn_blocking(|| sleep_logic(Kill::No)).await {
+                        Ok(Ok(_)) => {
+                            state.lock().unwrap().deferred_sleep = false;
+                            idle.reset();
+                            events::emit(
+                                EventKind::Enforcement,
+                                "Monitor: The last blocking process exited, GPU disabled",
+                            );
+                        }
+                        // Still the login screen: wait for a session change,
+                        // backing off in case none comes.
+                        Ok(Err(e @ Error::BlockedByProcesses { .. }))
+                            if !display_manager::greeters(e.processes()).is_empty() =>
+                        {
+                            let backoff = greeter_retry
+                                .map_or(GREETER_BACKOFF_MIN, |(_, backoff)| {
+                                    (backoff * 2).min(GREETER_BACKOFF_MAX)
+                                });
+                            greeter_retry = Some((Instant::now() + backoff, backoff));
+                            retry_at = Some(Instant::now() + backoff);
+                        }
+                        // Still in use, or busy: look again shortly.
+                        Ok(Err(Error::BlockedByProcesses { .. } | Error::Busy(_))) => {
+                            greeter_retry = None;
+                            retry_at = Some(Instant::now() + RETRY);
+                            if sleep_idle_holders(conn.as_ref(), &state, &mut idle).await {
+                                state.lock().unwrap().deferred_sleep = false;
+                            }
+                        }
+                        Ok(Err(e)) => {
+                            retry_at = Some(Instant::now() + RETRY);
+                            events::emit(
+                                EventKind::Failure,
+                                format!("Monitor: Deferred sleep failed: {}", e),
+                            );
+                            metrics::record_enforcement_failure();
+                            stats::record_failure();
+                        }
+                        Err(_) => retry_at = Some(Instant::now() + RETRY),
+                    }
+                } else if should_sleep && server.enabled {
+                    // Leave the GPU up between jobs and never kill one; sleep
+                    // once it has gone unused for `idle_after`.
+                    let in_use = spawn_blocking(|| {
+                        PciDevice::find_nvidia_gpu()
+                            .and_then(|gpu| {
+                                system::get_processes_using_nvidia(&gpu.get_device_nodes())
+                            })
+                            .map(|procs| !procs.is_empty())
+                            .unwrap_or(true)
+                    })
+                    .await
+                    .unwrap_or(true);
+                    let idle_after = Duration::from_secs(server.idle_after);
+                    if in_use {
+                        idle_since = None;
+                        retry_at = Some(now + SERVER_POLL);
+                        // Held, but perhaps by jobs that only keep a context open.
+                        sleep_idle_holders(conn.as_ref(), &state, &mut idle).await;
+                    } else if idle_since.get_or_insert(now).elapsed() < idle_after {
+                        retry_at = Some((idle_since.unwrap() + idle_after).min(now + SERVER_POLL));
+                    } else {
+                        state.lock().unwrap().set_trigger(format!(
+                            "Server: unused for {}",
+                            format_duration(server.idle_after)
+                        ));
+                        match spawn_blocking(|| sleep_logic(Kill::No)).await {
+                            Ok(Ok(_)) => {
+                                idle_since = None;
+                                events::emit(
+                                    EventKind::Enforcement,
+                                    format!(
+                                        "Monitor: GPU unused for {}, disabled",
+                                        format_duration(server.idle_after)
+                                    ),
+                                );
+                            }
+                            // A job just started, or a transition is running.
+                            Ok(Err(Error::BlockedByProcesses { .. } | Error::Busy(_))) => {
+                                idle_since = None;
+                                retry_at = Some(now + SERVER_POLL);
+                            }
+                            Ok(Err(e)) => {
+                                retry_at = Some(Instant::now() + RETRY);
+                                events::emit(
+                                    EventKind::Failure,
+                                    format!("Monitor: Failed to disable idle GPU: {}", e),
+                                );
+                                metrics::record_enforcement_failure();
+                                stats::record_failure();
+                            }
+                            Err(_) => retry_at = Some(Instant::now() + RETRY),
+                        }
+                    }
+                } else if should_sleep {
+                    events::emit(EventKind::Enforcement, "Monitor: GPU detected in high power state while Integrated mode is active. Attempting to disable...");
+                    state.lock().unwrap().set_trigger("Integrated enforcement");
+                    stats::record_enforcement_retry();
+                    let result = spawn_blocking(|| sleep_logic(Kill::Auto)).await;
+                    if !matches!(result, Ok(Ok(_))) {
+                        retry_at = Some(Instant::now() + RETRY);
+                    }
+                    match result {
+                        Ok(Ok(_)) => events::emit(EventKind::Enforcement, "Monitor: GPU disabled"),
+                        // Retrying can't help while the login screen holds it.
+                        Ok(Err(e @ Error::BlockedByProcesses { .. }))
+                            if !display_manager::greeters(e.processes()).is_empty() =>
+                        {
+                            handle_greeter(conn.as_ref(), &state, &e).await;
+                            greeter_retry = Some((now + GREETER_BACKOFF_MIN, GREETER_BACKOFF_MIN));
+                            retry_at = Some(now + GREETER_BACKOFF_MIN);
+                        }
+                        // The kill policy keeps them alive; sleep once they exit.
+                        Ok(Err(e @ Error::BlockedByProcesses { .. }))
+                            if system::kill_policy() != KillPolicy::Always =>
+                        {
+                            notify_sleep_blocked(conn.as_ref(), &state, &e);
+                            state.lock().unwrap().deferred_sleep = true;
+                            let names: Vec<String> =
+                                e.processes().iter().map(|p| p.to_string()).collect();
+                            events::emit(
+                                EventKind::Policy,
+                                format!(
+                                    "Monitor: not killing {} (kill policy: {}), sleeping once they exit",
+                                    names.join(", "),
+                                    system::kill_policy()
+                                ),
+                            );
+                        }
+                        Ok(Err(e)) if !matches!(e, Error::Busy(_)) => {
+                            events::emit(
+                                EventKind::Failure,
+                                format!("Monitor: Failed to disable GPU: {}", e),
+                            );
+                            notify_sleep_blocked(conn.as_ref(), &state, &e);
+                            metrics::record_enforcement_failure();
+                            stats::record_failure();
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Mode::Standard | Mode::Vfio => {}
+        }
+    }
+}
+
+/// Integrated enforcement found the login screen holding the GPU. Restarts
+/// the display manager around a sleep if configured and nobody is logged in;
+/// otherwise explains what to do and sleeps once the login screen lets go.
+async fn handle_greeter(conn: Option<&Connection>, state: &SharedState, error: &Error) {
+    let restart = state.lock().unwrap().restart_display_manager;
+    let logged_in = match conn {
+        Some(conn) => logind::user_logged_in_graphically(conn)
+            .await
+            .unwrap_or(true),
+        None => true,
+    };
+    if restart && !logged_in {
+        events::emit(
+            EventKind::Policy,
+            "Monitor: the login screen holds the GPU, restarting the display manager around a sleep",
+        );
+        state.lock().unwrap().set_trigger("Display manager restart");
+        let result = spawn_blocking(|| {
+            display_manager::restart_around(|| {
+                // The login screen takes a moment to exit after its manager.
+                let mut result = sleep_logic(Kill::No);
+                for _ in 0..GREETER_EXIT_TRIES {
+                    if !matches!(result, Err(Error::BlockedByProcesses { .. })) {
+                        break;
+                    }
+                    std::thread::sleep(Duration::from_secs(1));
+                    result = sleep_logic(Kill::No);
+                }
+                result
+            })
+        })
+        .await
+        .unwrap_or_else(|e| Err(Error::Failed(format!("Internal error: {}", e))));
+        match result {
+            Ok(_) => {
+                events::emit(
+                    EventKind::Enforcement,
+                    "Monitor: GPU disabled, display manager restarted on the iGPU",
+                );
+                return;
+            }
+            Err(e) => events::emit(
+                EventKind::Failure,
+                format!("Monitor: Restarting the display manager didn't help: {}", e),
+            ),
+        }
+    }
+    notify_sleep_blocked(conn, state, error);
+    state.lock().unwrap().deferred_sleep = true;
+    events::emit(EventKind::Policy, format!("Monitor: {}", error.message()));
+}
+
+/// Tells the users of graphical sessions why the GPU stays awake: the first
+/// time enforcement fails, and at most once per `[notifications] interval`.
+fn notify_sleep_blocked(conn: Option<&Connection>, state: &SharedState, error: &Error) {
+    let Some(conn) = conn.cloned() else {
+        return;
+    };
+    let requester = {
+        let mut state = state.lock().unwrap();
+        if !state.sleep_blocked.should_send() {
+            return;
+        }
+        state.requester
+    };
+    // Processes holding the GPU, or else why the sleep failed.
+    let reason = match error {
+        Error::BlockedByProcesses { processes, .. } => {
+            let names: Vec<String> = processes.iter().map(|p| p.to_string()).collect();
+            Ok(names.join(", "))
+        }
+        e => Err(e.to_string()),
+    };
+    tokio::spawn(async move {
+        notifications::send(&conn, requester, |t| {
+            let body = match &reason {
+                Ok(names) => tr!(t => "notify-stays-awake-blocked", processes = names.as_str()),
+                Err(e) => tr!(t => "notify-stays-awake-failed", error = e.as_str()),
+            };
+            (tr!(t => "notify-stays-awake"), body)
+        })
+        .await;
+    });
+}
+
+/// Tells the users on the GPU's seat that a sleep barely lowered the draw.
+fn notify_residual_power(conn: Option<&Connection>, state: &SharedState, measured: SleepDraw) {
+    let Some(conn) = conn.cloned() else {
+        return;
+    };
+    let requester = state.lock().unwrap().requester;
+    let before = format!("{:.1}", measured.before);
+    let after = format!("{:.1}", measured.after);
+    tokio::spawn(async move {
+        notifications::send(&conn, requester, |t| {
+            (
+                tr!(t => "notify-residual-power"),
+                tr!(t => "notify-residual-power-body", before = before.as_str(), after = after.as_str()),
+            )
+        })
+        .await;
+    });
+}
+
+/// Samples the awake GPU's utilization when due and, once the processes
+/// holding it have idled past `[idle] after`, sleeps it through the kill
+/// policy, which decides whether they may be ended. When it keeps them, tells
+/// the user once per idle streak. Returns whether the GPU slept.
+async fn sleep_idle_holders(
+    conn: Option<&Connection>,
+    state: &SharedState,
+    idle: &mut IdleTracker,
+) -> bool {
+    if idle.due() {
+        let usage = spawn_blocking(|| PciDevice::find_nvidia_gpu().ok()?.nvidia_smi_usage())
+            .await
+            .ok()
+            .flatten();
+        idle.record(usage);
+    }
+    if !idle.is_idle() {
+        return false;
+    }
+    let description = idle.describe();
+    state
+        .lock()
+        .unwrap()
+        .set_trigger(format!("Idle: {}", description));
+    match spawn_blocking(|| sleep_logic(Kill::Auto)).await {
+        Ok(Ok(_)) => {
+            idle.reset();
+            events::emit(
+                EventKind::Enforcement,
+                format!("Monitor: {}, disabled", description),
+            );
+            true
+        }
+        Ok(Err(Error::BlockedByProcesses { processes, .. })) if idle.announce() => {
+            let names: Vec<String> = processes.iter().map(|p| p.to_string()).collect();
+            events::emit(
+                EventKind::Policy,
+                format!(
+                    "Monitor: {}, but the kill policy keeps {} holding it",
+                    description,
+                    names.join(", ")
+                ),
+            );
+            let (enabled, requester) = {
+                let state = state.lock().unwrap();
+                (state.sleep_blocked.is_enabled(), state.requester)
+            };
+            if let Some(conn) = conn.cloned().filter(|_| enabled) {
+                let processes = names.join(", ");
+                tokio::spawn(async move {
+                    notifications::send(&conn, requester, |t| {
+                        (
+                            tr!(t => "notify-idles-awake"),
+                            tr!(
+                                t => "notify-idles-awake-body",
+                                description = description.as_str(),
+                                processes = processes.as_str()
+                            ),
+                        )
+                    })
+                    .await;
+                });
+            }
+            false
+        }
+        _ => false,
     }
 }
 
-async fn monitor_loop() {
-    let mut interval = tokio::time::interval(std::time::Duration::from_secs(2));
-
-    let mut last_charging = system::get_charging_status();
-    let mut stable_since = tokio::time::Instant::now();
+async fn current_context(profiles: &Profiles) -> PowerContext {
+    let profiles = profiles.clone();
+    spawn_blocking(move || profiles.current())
+        .await
+        .unwrap_or(PowerContext::Ac)
+}
 
+/// Feeds battery readings to the savings estimate. Runs on its own timer
+/// because the estimate averages samples taken at a fixed interval. A sleep
+/// that saved less than `[sleep] min_saving` is logged and, with
+/// `notify_residual`, reported to the user.
+async fn sample_savings(state: SharedState, conn: Option<Connection>, notify_residual: bool) {
+    let mut interval = tokio::time::interval(Duration::from_secs(SAVINGS_SAMPLE_SECS));
+    // Battery samples added to the discharge report since it was last saved.
+    let mut unsaved: u64 = 0;
+    let mut was_asleep = false;
     loop {
         interval.tick().await;
+        let draw = sysfs::battery_power_draw().await;
+        let (measured, woke) = {
+            let mut state = state.lock().unwrap();
+            let asleep = state.power_state.is_asleep();
+            let woke = was_asleep && !asleep;
+            was_asleep = asleep;
+            if let Some(watts) = draw {
+                state
+                    .discharge
+                    .add(asleep, watts, SAVINGS_SAMPLE_SECS as f64);
+            }
+            (state.savings.sample(asleep, draw), woke)
+        };
+        if woke {
+            let _ = spawn_blocking(SleepDraw::clear_residual).await;
+        }
+        if let Some(measured) = measured {
+            let watts = measured.saved();
+            let residual = measured.is_residual(system::sleep_config().min_saving);
+            if watts > 0.0 {
+                eventlog::info(format!("Power savings: {}", savings::describe(watts)));
+                let _ = spawn_blocking(move || savings::save(watts)).await;
+            }
+            if residual {
+                eventlog::warn(format!(
+                    "The GPU reports {} but may still draw power: {}",
+                    state.lock().unwrap().power_state,
+                    measured.describe()
+                ));
+            }
+            let _ = spawn_blocking(move || {
+                let saved = if residual {
+                    measured.save_residual()
+                } else {
+                    SleepDraw::clear_residual()
+                };
+                if let Err(e) = saved {
+                    eprintln!("Failed to save the residual power check: {}", e);
+                }
+            })
+            .await;
+            if residual && notify_residual {
+                notify_residual_power(conn.as_ref(), &state, measured);
+            }
+        }
+        unsaved += u64::from(draw.is_some());
+        if unsaved >= DISCHARGE_SAVE_SAMPLES {
+            unsaved = 0;
+            let report = state.lock().unwrap().discharge.clone();
+            let _ = spawn_blocking(move || {
+                if let Err(e) = report.save() {
+                    eprintln!("Failed to save the discharge report: {}", e);
+                }
+            })
+            .await;
+        }
+    }
+}
 
-        let mode = spawn_blocking(|| load_mode())
-            .await
-            .unwrap_or(Ok(Mode::Standard))
-            .unwrap_or(Mode::Standard);
+/// Records the GPU's kernel faults as they're logged and, with `recover`,
+/// power-cycles it after a fatal one.
+async fn watch_faults(state: SharedState, recover: bool) {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        let result = faults::follow_kmsg(|message| {
+            if message.contains("NVRM: Xid")
+                || message.contains("fallen off the bus")
+                || message.contains("PCIe Bus Error")
+            {
+                let _ = tx.send(message.to_string());
+            }
+        });
+        if let Err(e) = result {
+            eventlog::warn(format!("Not watching the kernel log for GPU faults: {}", e));
+        }
+    });
 
-        match mode {
-            Mode::Optimized => {
-                let current_charging = spawn_blocking(|| system::get_charging_status())
-                    .await
-                    .unwrap_or(true);
+    // The GPU may be gone from the bus by the time its fault is read.
+    let mut address = PciDevice::find_nvidia_gpu().ok().map(|gpu| gpu.address);
+    let mut last_seen: HashMap<String, u64> = HashMap::new();
+    let mut last_recovery: Option<u64> = None;
+    while let Some(message) = rx.recv().await {
+        if let Ok(Ok(gpu)) = spawn_blocking(PciDevice::find_nvidia_gpu).await {
+            address = Some(gpu.address);
+        }
+        let Some(kind) = address
+            .as_ref()
+            .and_then(|address| faults::classify(&message, address))
+        else {
+            continue;
+        };
+        let now = unix_now();
+        let key = kind.to_string();
+        if !kind.is_fatal()
+            && last_seen
+                .get(&key)
+                .is_some_and(|at| now.saturating_sub(*at) < FAULT_REPEAT_SECS)
+        {
+            continue;
+        }
+        last_seen.insert(key.clone(), now);
 
-                if current_charging != last_charging {
-                    println!(
-                        "Monitor: Power state changed to {}. Debouncing...",
-                        if current_charging {
-                            "Charging"
-                        } else {
-                            "Unplugged"
-                        }
-                    );
-                    last_charging = current_charging;
-                    stable_since = tokio::time::Instant::now();
-                } else if stable_since.elapsed().as_secs() >= 2 {
-                    if current_charging {
-                        let _ = spawn_blocking(|| wake_logic()).await;
-                    } else {
-                        let _ = spawn_blocking(|| sleep_logic(false)).await;
-                    }
-                }
+        events::emit(EventKind::Failure, format!("Kernel: {}: {}", kind, message));
+        // Attributes a power state change that follows, e.g. to NotFound.
+        state
+            .lock()
+            .unwrap()
+            .set_trigger(format!("kernel: {}", kind));
+        let record = FaultRecord {
+            timestamp: now,
+            kind: key,
+            message,
+        };
+        let _ = spawn_blocking(move || {
+            if let Err(e) = faults::append(&record) {
+                eprintln!("Failed to record the kernel fault: {}", e);
             }
-            Mode::Integrated => {
-                let should_sleep = spawn_blocking(|| match PciDevice::find_nvidia_gpu() {
-                    Ok(gpu) => {
-                        let state = gpu.get_power_state();
-                        state == "D0" || state == "Unknown"
-                    }
-                    Err(_) => false,
-                })
-                .await
-                .unwrap_or(false);
+        })
+        .await;
 
-                if should_sleep {
-                    println!("Monitor: GPU detected in high power state while Integrated mode is active. Attempting to disable...");
-                    let _ = spawn_blocking(|| sleep_logic(true)).await;
-                }
+        if !recover || !kind.is_fatal() {
+            continue;
+        }
+        if last_recovery.is_some_and(|at| now.saturating_sub(at) < RECOVERY_COOLDOWN_SECS) {
+            eventlog::warn(format!(
+                "Not recovering from {} again within {}",
+                kind,
+                format_duration(RECOVERY_COOLDOWN_SECS)
+            ));
+            continue;
+        }
+        {
+            let state = state.lock().unwrap();
+            if state.hands_off.is_some() || !state.conflicts.is_empty() {
+                continue;
             }
-            Mode::Standard => {}
+        }
+        last_recovery = Some(now);
+        state
+            .lock()
+            .unwrap()
+            .set_trigger(format!("recovery after {}", kind));
+        match spawn_blocking(recover_logic).await {
+            Ok(Ok(msg)) => events::emit(EventKind::Enforcement, format!("Recovery: {}", msg)),
+            Ok(Err(e)) => events::emit(
+                EventKind::Failure,
+                format!("Recovery after {} failed: {}", kind, e),
+            ),
+            Err(_) => {}
         }
     }
 }
 
-pub async fn run() -> Result<()> {
-    println!("Starting NvSleepify D-Bus daemon...");
-
-    // Wait for user login
-    println!("Waiting for user login...");
+async fn watch_conflicts(state: SharedState, acknowledged: Vec<String>) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(CONFLICT_CHECK_SECS));
+    interval.tick().await;
     loop {
-        let logged_in = spawn_blocking(|| system::is_user_logged_in())
-            .await
-            .unwrap_or(false);
-        if logged_in {
-            break;
+        interval.tick().await;
+        let acknowledged = acknowledged.clone();
+        let Ok(found) = spawn_blocking(move || conflicts::unacknowledged(&acknowledged)).await
+        else {
+            continue;
+        };
+        let previous = std::mem::replace(&mut state.lock().unwrap().conflicts, found.clone());
+        if found == previous {
+            continue;
+        }
+        if found.is_empty() {
+            events::emit(
+                EventKind::Policy,
+                "No conflicting GPU switcher is running anymore, resuming enforcement",
+            );
+        } else {
+            events::emit(EventKind::Policy, conflicts::describe(&found));
+        }
+    }
+}
+
+/// Startup choices from `nvsleepifyd`'s command line.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RunOptions {
+    /// Don't touch the GPU at startup: no journal recovery, no restore, and
+    /// no enforcement until a mode is set.
+    pub no_restore: bool,
+    /// Serve `org.nvsleepify.Debug` for injecting fake charger, process and
+    /// power state readings.
+    pub simulate: bool,
+}
+
+pub async fn run(options: RunOptions) -> Result<()> {
+    eventlog::info("Starting NvSleepify D-Bus daemon...");
+    if helper::is_remote() {
+        eventlog::info("Running unprivileged, privileged operations go through nvsleepify-helper");
+    }
+    if options.simulate {
+        simulate::enable();
+        eventlog::warn(format!(
+            "--simulate: fake readings can be injected on {}",
+            simulate::DEBUG_PATH
+        ));
+    }
+    let config = spawn_blocking(DaemonConfig::load).await??;
+    system::apply_config(&config);
+    access::set_group(config.access.group.clone());
+    let profiles = Profiles::from_config(&config.profiles).unwrap_or_else(|e| {
+        eventlog::error(format!("Ignoring [profiles]: {}", e));
+        Profiles::default()
+    });
+
+    // Undo a sleep or wake cut short by a crash before anything else touches the GPU
+    if options.no_restore {
+        eventlog::warn("--no-restore: leaving the GPU as it is until a mode is set");
+    } else if let Ok(Some(message)) = spawn_blocking(transition::recover).await {
+        events::emit(EventKind::Policy, message);
+    }
+
+    let state = SharedState::default();
+    {
+        let mut state = state.lock().unwrap();
+        let recent = history::load_recent(100);
+        state.last_external_wake = recent
+            .iter()
+            .rev()
+            .find(|record| {
+                record.to == PowerState::D0 && record.trigger.starts_with(EXTERNAL_TRIGGER)
+            })
+            .map(|record| (record.trigger.clone(), record.timestamp));
+        state.last_transition = recent.last().cloned();
+        state.savings = SavingsTracker::load();
+        state.discharge = DischargeReport::load();
+        state.profiles = profiles.clone();
+        state.hands_off = options
+            .no_restore
+            .then(|| "started with --no-restore".to_string());
+        state.sleep_blocked = notifications::Throttle::new(&config.notifications);
+        state.restart_display_manager = config.display_manager.restart;
+    }
+    let _ = spawn_blocking(stats::load).await;
+
+    // Claim the bus name right away: D-Bus activation and Type=dbus wait for
+    // it, and the login wait below can take indefinitely at boot.
+    let conn = match connect(state.clone()).await {
+        Ok(conn) => {
+            eventlog::info("Daemon listening on system bus: org.nvsleepify.Service");
+            Some(conn)
         }
-        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+        // Without a bus the socket is the only way in.
+        Err(e) if config.socket.path.is_some() => {
+            eventlog::warn(format!("No system bus ({}), serving the socket only", e));
+            None
+        }
+        Err(e) => return Err(e),
+    };
+
+    // Optional JSON-RPC socket, for clients without D-Bus
+    if config.socket.path.is_some() {
+        let (socket, state, conn) = (config.socket.clone(), state.clone(), conn.clone());
+        tokio::spawn(async move {
+            if let Err(e) = rpc::serve(socket, state, conn).await {
+                eventlog::error(format!("Socket failed: {}", e));
+            }
+        });
+    }
+
+    // Wait for user login; servers have nobody logging in.
+    if !options.no_restore && !config.server.enabled {
+        eventlog::info("Waiting for user login...");
+        logind::wait_for_user_login().await;
+        eventlog::info("User logged in detected.");
     }
-    println!("User logged in detected.");
 
     // Restore state on startup
-    println!("Restoring previous state...");
-    let delay = spawn_blocking(|| load_delay())
-        .await
-        .unwrap_or(Ok(0))
-        .unwrap_or(0);
+    let delay = if options.no_restore {
+        0
+    } else {
+        eventlog::info("Restoring previous state...");
+        read_delay().await
+    };
     if delay > 0 {
-        println!("Waiting {} seconds before restoring state...", delay);
+        eventlog::info(format!(
+            "Waiting {} seconds before restoring state...",
+            delay
+        ));
         tokio::time::sleep(tokio::time::Duration::from_secs(delay as u64)).await;
     }
 
-    let _ = spawn_blocking(|| match restore_logic() {
-        Ok(_) => println!("State restore successful"),
-        Err(e) => eprintln!("State restore failed: {}", e),
-    })
-    .await;
+    // Probed while the GPU is still on the bus, before the restore may
+    // power it off.
+    let _ = spawn_blocking(platform::probe).await;
+
+    // Sampled before restoring so the monitor records what the restore changed.
+    let initial_power_state = sysfs::power_state().await;
+    state.lock().unwrap().set_trigger("Restore on startup");
+
+    let acknowledged = config.conflicts.acknowledged.clone();
+    let found = {
+        let acknowledged = acknowledged.clone();
+        spawn_blocking(move || conflicts::unacknowledged(&acknowledged))
+            .await
+            .unwrap_or_default()
+    };
+    if options.no_restore {
+        state.lock().unwrap().conflicts = found;
+    } else if found.is_empty() {
+        let reconcile = config.restore.reconcile;
+        let deferred = spawn_blocking(move || match restore_logic(&profiles, reconcile) {
+            Ok(deferred) => {
+                eventlog::info("State restore successful");
+                deferred
+            }
+            Err(e) => {
+                eventlog::error(format!("State restore failed: {}", e));
+                false
+            }
+        })
+        .await
+        .unwrap_or(false);
+        state.lock().unwrap().deferred_sleep = deferred;
+    } else {
+        eventlog::error(format!(
+            "Not restoring state. {}",
+            conflicts::describe(&found)
+        ));
+        state.lock().unwrap().conflicts = found;
+    }
 
     // Start background monitoring
-    tokio::spawn(monitor_loop());
+    state.lock().unwrap().power_state = initial_power_state;
+    tokio::spawn(monitor_loop(
+        conn.clone(),
+        state.clone(),
+        initial_power_state,
+    ));
+    tokio::spawn(sample_savings(
+        state.clone(),
+        conn.clone(),
+        config.notifications.residual_power,
+    ));
 
-    // Setup D-Bus connection
-    let _conn = ConnectionBuilder::system()?
-        .name("org.nvsleepify.Service")?
-        .serve_at("/org/nvsleepify/Manager", NvSleepifyManager)?
-        .build()
-        .await?;
+    if config.faults.watch {
+        tokio::spawn(watch_faults(state.clone(), config.faults.recover));
+    }
 
-    println!("Daemon listening on system bus: org.nvsleepify.Service");
+    // Persist lifetime statistics
+    tokio::spawn(stats::save_loop());
 
-    // Keep running indefinitely (the connection will handle incoming messages)
-    std::future::pending::<()>().await;
-    Ok(())
-}
+    // Pause or resume enforcement as other switchers come and go
+    tokio::spawn(watch_conflicts(state.clone(), acknowledged));
+
+    if let Some(conn) = &conn {
+        // Forward event log entries and events to D-Bus subscribers
+        tokio::spawn(forward_log_entries(conn.clone()));
+        tokio::spawn(forward_events(conn.clone()));
 
-fn save_mode(mode: Mode) -> Result<()> {
-    let path = std::path::Path::new(MODE_FILE);
-    if let Some(parent) = path.parent() {
-        if !parent.exists() {
-            std::fs::create_dir_all(parent)?;
+        // Keep a sleeping GPU safe across hibernation
+        tokio::spawn(watch_hibernation(conn.clone(), state.clone()));
+
+        // Release the wake locks of applications that exit
+        tokio::spawn(watch_lease_owners(conn.clone(), state.clone()));
+
+        // Hold wake locks for the configured units while they run
+        for unit in &config.wake_locks.units {
+            tokio::spawn(watch_unit(conn.clone(), state.clone(), unit.clone()));
+        }
+
+        // Keep the applet interface's properties current
+        tokio::spawn(applet::publish_loop(conn.clone()));
+
+        // Optional supergfxd-compatible interface for existing frontends
+        if config.compat.supergfxd {
+            tokio::spawn(supergfx::run(conn.clone(), state.clone()));
         }
     }
-    let content = match mode {
-        Mode::Standard => "standard",
-        Mode::Integrated => "integrated",
-        Mode::Optimized => "optimized",
-    };
-    std::fs::write(path, content)?;
-    Ok(())
-}
 
-fn load_mode() -> Result<Mode> {
-    let path = std::path::Path::new(MODE_FILE);
-    if !path.exists() {
-        return Ok(Mode::Standard);
+    // Optional Prometheus metrics export
+    if let Some(addr) = config.metrics.listen {
+        eventlog::info(format!("Serving metrics on http://{}/metrics", addr));
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(addr).await {
+                eventlog::error(format!("Metrics endpoint failed: {}", e));
+            }
+        });
+    }
+    if let Some(path) = config.metrics.textfile {
+        tokio::spawn(metrics::write_textfile_loop(path));
     }
-    let content = std::fs::read_to_string(path)?;
-    Mode::from_str(content.trim()).map_err(|e| anyhow::anyhow!(e))
+
+    // Keep running indefinitely (the connection will handle incoming messages)
+    std::future::pending::<()>().await;
+    Ok(())
 }
 
-fn save_delay(seconds: u32) -> Result<()> {
-    let path = std::path::Path::new(DELAY_FILE);
-    if let Some(parent) = path.parent() {
-        if !parent.exists() {
-            std::fs::create_dir_all(parent)?;
-        }
+async fn connect(state: SharedState) -> Result<Connection> {
+    let mut builder = ConnectionBuilder::system()?
+        .name("org.nvsleepify.Service")?
+        .serve_at(
+            OBJECT_PATH,
+            NvSleepifyManager {
+                state: state.clone(),
+            },
+        )?
+        .serve_at(APPLET_PATH, NvSleepifyApplet::new(state))?;
+    if simulate::is_enabled() {
+        builder = builder.serve_at(simulate::DEBUG_PATH, NvSleepifyDebug)?;
     }
-    std::fs::write(path, seconds.to_string())?;
-    Ok(())
+    Ok(builder.build().await?)
 }
 
-fn load_delay() -> Result<u32> {
-    let path = std::path::Path::new(DELAY_FILE);
-    if !path.exists() {
-        return Ok(0);
+pub(crate) fn current_power_state() -> PowerState {
+    match PciDevice::find_nvidia_gpu() {
+        Ok(gpu) => gpu.get_power_state(),
+        Err(_) => PowerState::NotFound,
     }
-    let content = std::fs::read_to_string(path)?;
-    content
-        .trim()
-        .parse::<u32>()
-        .map_err(|e| anyhow::anyhow!(e))
 }
 
-fn info_logic() -> (String, String, Vec<(String, String)>) {
-    let mode = load_mode().unwrap_or(Mode::Standard);
-    let mode_str = mode.to_string();
+pub fn info_logic() -> StatusReport {
+    let mode = load_mode().unwrap_or(Mode::Standard).to_string();
 
     match PciDevice::find_nvidia_gpu() {
         Ok(gpu) => {
             let nodes = gpu.get_device_nodes();
-            let power_state = gpu.get_power_state();
-            let procs = system::get_processes_using_nvidia(&nodes).unwrap_or_default();
-            (mode_str, power_state, procs)
+            StatusReport {
+                mode,
+                power_state: gpu.get_power_state(),
+                processes: system::get_processes_using_nvidia(&nodes).unwrap_or_default(),
+                pending_sleep: false,
+            }
         }
-        Err(_) => (mode_str, "NotFound".to_string(), vec![]),
+        Err(_) => StatusReport {
+            mode,
+            power_state: PowerState::NotFound,
+            ..Default::default()
+        },
     }
 }
 
-fn status_logic() -> String {
+pub fn devices_logic() -> Vec<DeviceInfo> {
+    let managed = PciDevice::find_nvidia_gpu().ok().map(|gpu| gpu.address);
+    PciDevice::find_display_devices()
+        .into_iter()
+        .map(|device| {
+            let (vendor_id, device_id) = device.ids();
+            let name = pci::device_name(&vendor_id, &device_id)
+                .unwrap_or_else(|| format!("PCI device {}:{}", vendor_id, device_id));
+            DeviceInfo {
+                name,
+                power_state: device.get_power_state(),
+                driver: device.driver().unwrap_or_default(),
+                functions: device
+                    .slot_functions()
+                    .into_iter()
+                    .map(|function| function.address)
+                    .filter(|address| *address != device.address)
+                    .collect(),
+                managed: managed.as_ref() == Some(&device.address),
+                address: device.address,
+                vendor_id,
+                device_id,
+            }
+        })
+        .collect()
+}
+
+pub fn status_logic() -> String {
     let mut output = String::new();
     let mode = load_mode().unwrap_or(Mode::Standard);
     writeln!(output, "Current Mode: {}", mode).unwrap();
+    // Named so docks can be told apart in [profiles].
+    let sources = profiles::online_sources();
+    if sources.is_empty() {
+        writeln!(output, "Power Source: Battery").unwrap();
+    } else {
+        writeln!(output, "Power Source: {}", sources.join(", ")).unwrap();
+    }
+    if let Some(mux) = platform::mux() {
+        match platform::pending_mux() {
+            Some(pending) => {
+                writeln!(output, "Display MUX: {} ({} after a reboot)", mux, pending).unwrap()
+            }
+            None => writeln!(output, "Display MUX: {}", mux).unwrap(),
+        }
+    }
 
     match PciDevice::find_nvidia_gpu() {
         Ok(gpu) => {
@@ -242,16 +2528,42 @@ fn status_logic() -> String {
                 writeln!(output, "  Device Nodes: None (Driver unbound or card off)").unwrap();
             }
 
+            if let Some(driver) = gpu.driver() {
+                writeln!(output, "  Driver:      {}", driver).unwrap();
+            }
+            if system::gpu_driver() == Driver::Nouveau {
+                writeln!(output, "  Modules:     {}", Driver::Nouveau).unwrap();
+            } else if let Some(info) = driver::detect() {
+                writeln!(output, "  Modules:     {}", info).unwrap();
+            }
+
             let state = gpu.get_power_state();
             writeln!(output, "  Power State: {}", state).unwrap();
+            // Only read while awake; the sensor goes away once the card is powered down.
+            if state == PowerState::D0 {
+                if let Some(temp) = gpu.hwmon_sensors().temperature {
+                    writeln!(output, "  Temperature: {:.0} °C", temp).unwrap();
+                }
+            }
+
+            let displaying = display::check(&gpu);
+            if !displaying.is_empty() {
+                let reasons: Vec<String> = displaying.iter().map(|e| e.to_string()).collect();
+                writeln!(
+                    output,
+                    "  Display:     Drives the session ({})",
+                    reasons.join("; ")
+                )
+                .unwrap();
+            }
 
             let procs = system::get_processes_using_nvidia(&nodes).unwrap_or_default();
             if !procs.is_empty() {
                 writeln!(output, "  Status: Active (In Use)").unwrap();
                 writeln!(output, "  Blocking Processes: {}", procs.len()).unwrap();
-            } else if state == "D3cold" {
+            } else if state == PowerState::D3cold {
                 writeln!(output, "  Status: Off / D3cold").unwrap();
-            } else if state.contains("D3") {
+            } else if state.is_suspended() {
                 writeln!(output, "  Status: Suspended").unwrap();
             } else {
                 writeln!(output, "  Status: Idle / D0").unwrap();
@@ -265,127 +2577,659 @@ fn status_logic() -> String {
             .unwrap();
         }
     }
+
+    if let Some(last) = history::load_recent(1).pop() {
+        let power_state = current_power_state();
+        if let Some(time) =
+            history::time_in_state(power_state, (last.to, last.timestamp), unix_now())
+        {
+            writeln!(output, "Time in State: {}", time).unwrap();
+        }
+    }
+
+    if let Some(watts) = SavingsTracker::load().estimate() {
+        writeln!(output, "Power Savings: {}", savings::describe(watts)).unwrap();
+    }
+    if let Some(fault) = faults::load_recent(1).pop() {
+        let ago = format_duration(unix_now().saturating_sub(fault.timestamp));
+        writeln!(
+            output,
+            "Last Kernel Fault: {} {} ago ({})",
+            fault.kind, ago, fault.message
+        )
+        .unwrap();
+    }
+    if let Some(measured) = SleepDraw::load_residual() {
+        writeln!(
+            output,
+            "Residual Power: the last sleep saved only {:.1} W ({}); the GPU may not have \
+             powered down",
+            measured.saved(),
+            measured.describe()
+        )
+        .unwrap();
+    }
+
+    let acknowledged = DaemonConfig::load()
+        .map(|c| c.conflicts.acknowledged)
+        .unwrap_or_default();
+    let found = conflicts::detect();
+    if !found.is_empty() {
+        writeln!(output, "Conflicting GPU Switchers:").unwrap();
+        for conflict in &found {
+            let note = if acknowledged.iter().any(|a| a == conflict.tool) {
+                "acknowledged"
+            } else {
+                "enforcement paused"
+            };
+            writeln!(
+                output,
+                "  {}: {} ({})",
+                conflict.tool, conflict.evidence, note
+            )
+            .unwrap();
+        }
+    }
     output
 }
 
-fn set_mode_logic(mode_str: &str) -> (bool, String, Vec<(String, String)>) {
-    let mode = match Mode::from_str(mode_str) {
-        Ok(m) => m,
-        Err(e) => return (false, format!("Invalid mode: {}", e), vec![]),
-    };
+/// `kill_procs`: the user agreed to killing the processes holding the GPU.
+/// `force` skips the display check for this transition only.
+pub fn set_mode_logic(
+    mode_str: &str,
+    kill_procs: bool,
+    force: bool,
+) -> Result<TransitionResult, Error> {
+    let mode = parse_mode(mode_str)?;
+    // Taken before saving, so a rejected request doesn't change the mode.
+    let transition = begin_transition()?;
+    set_mode_locked(&transition, mode, kill_procs, force)
+}
+
+fn parse_mode(mode_str: &str) -> Result<Mode, Error> {
+    Mode::from_str(mode_str).map_err(|e| Error::InvalidArgs(format!("Invalid mode: {}", e)))
+}
+
+/// Claims the transition slot, or fails as busy.
+fn begin_transition() -> Result<transition::TransitionGuard, Error> {
+    transition::try_begin().ok_or_else(|| Error::Busy(transition::BUSY.to_string()))
+}
 
-    if let Err(e) = save_mode(mode) {
-        return (false, format!("Failed to save mode: {}", e), vec![]);
+/// set_mode_logic once the caller holds the transition slot.
+fn set_mode_locked(
+    _transition: &transition::TransitionGuard,
+    mode: Mode,
+    kill_procs: bool,
+    force: bool,
+) -> Result<TransitionResult, Error> {
+    if mode == Mode::Integrated && platform::mux() == Some(Mux::Discrete) {
+        if let Some(message) = switch_mux()? {
+            save_mode(mode).map_err(|e| Error::from(e.context("Failed to save mode")))?;
+            return Ok(TransitionResult {
+                mode: mode.to_string(),
+                power_state: current_power_state(),
+                message,
+            });
+        }
+    }
+    // Refused before saving, so the next boot doesn't black out the screen either.
+    if matches!(mode, Mode::Integrated | Mode::Vfio) {
+        check_display(force)?;
     }
 
-    match mode {
-        Mode::Standard => {
-            let (success, msg) = wake_logic();
-            (success, msg, vec![])
+    save_mode(mode).map_err(|e| Error::from(e.context("Failed to save mode")))?;
+
+    let xorg_note = match xorg::sync(mode) {
+        Ok(true) => " Xorg configuration updated; it takes effect at the next login.",
+        Ok(false) => "",
+        Err(e) => {
+            eventlog::error(format!("Failed to update Xorg configuration: {}", e));
+            ""
         }
-        Mode::Integrated => sleep_logic(true),
+    };
+
+    let kill = if kill_procs {
+        Kill::Requested
+    } else {
+        Kill::Auto
+    };
+    let msg = match mode {
+        Mode::Standard => wake_sequence()?,
+        Mode::Integrated => sleep_sequence(kill, force)?,
+        Mode::Vfio => vfio_sequence(kill, force)?,
         Mode::Optimized => {
             if system::get_charging_status() {
-                let (success, msg) = wake_logic();
-                (success, msg, vec![])
+                wake_sequence()?
             } else {
-                sleep_logic(false)
+                sleep_sequence(Kill::No, force)?
             }
         }
-    }
+    };
+    Ok(TransitionResult {
+        mode: mode.to_string(),
+        power_state: current_power_state(),
+        message: format!("{}{}", msg, xorg_note),
+    })
 }
 
-fn sleep_logic(kill_procs: bool) -> (bool, String, Vec<(String, String)>) {
-    let gpu = match PciDevice::find_nvidia_gpu() {
-        Ok(g) => g,
-        Err(_) => {
-            return (
+/// Integrated mode, but blocking processes are waited for instead of killed.
+/// Returns whether the sleep is still pending.
+pub fn deferred_sleep_logic() -> Result<(TransitionResult, bool), Error> {
+    let Some(_transition) = transition::try_begin() else {
+        return Err(Error::Busy(transition::BUSY.to_string()));
+    };
+    check_display(false)?;
+    save_mode(Mode::Integrated).map_err(|e| Error::from(e.context("Failed to save mode")))?;
+    if let Err(e) = xorg::sync(Mode::Integrated) {
+        eventlog::error(format!("Failed to update Xorg configuration: {}", e));
+    }
+    let (message, pending) = match sleep_sequence(Kill::No, false) {
+        Ok(msg) => (msg, false),
+        Err(Error::BlockedByProcesses { processes, .. }) => {
+            let names: Vec<String> = processes.iter().map(|p| p.to_string()).collect();
+            (
+                format!("Sleeping once these exit: {}", names.join(", ")),
                 true,
-                "Nvidia GPU not found (already off?)".to_string(),
-                vec![],
             )
         }
+        Err(e) => return Err(e),
+    };
+    Ok((
+        TransitionResult {
+            mode: Mode::Integrated.to_string(),
+            power_state: current_power_state(),
+            message,
+        },
+        pending,
+    ))
+}
+
+pub fn kill_logic(pids: &[u32]) -> Result<String, Error> {
+    let Ok(gpu) = PciDevice::find_nvidia_gpu() else {
+        return Ok("Nvidia GPU not found (already off?)".to_string());
     };
 
     let nodes = gpu.get_device_nodes();
-    match system::get_processes_using_nvidia(&nodes) {
-        Ok(procs) if !procs.is_empty() => {
-            if !kill_procs {
-                println!("Sleep blocked by processes (soft-sleep): {:?}", procs);
-                return (false, "Blocking processes found".to_string(), procs);
-            }
-            if let Err(e) = system::kill_processes(&procs) {
-                return (false, format!("Failed to kill processes: {}", e), vec![]);
-            }
-            std::thread::sleep(std::time::Duration::from_millis(500));
+    let procs = system::get_processes_using_nvidia(&nodes)
+        .map_err(|e| Error::Failed(format!("Failed checking processes: {}", e)))?;
+    let targets: ProcessList = procs
+        .into_iter()
+        .filter(|proc| {
+            pids.is_empty() || proc.pid.parse::<u32>().is_ok_and(|pid| pids.contains(&pid))
+        })
+        .collect();
+    if targets.is_empty() {
+        return Ok("No matching processes using the GPU".to_string());
+    }
+    kill_targets(&nodes, targets)
+}
+
+/// Runs one step of the sleep or wake sequence, with the checks the full
+/// sequence makes before it.
+pub fn run_step_logic(step: ManualStep) -> Result<String, Error> {
+    let Some(_transition) = transition::try_begin() else {
+        return Err(Error::Busy(transition::BUSY.to_string()));
+    };
+    let gpu = PciDevice::find_nvidia_gpu().ok();
+    if step.takes_gpu_down() {
+        if let Some(gpu) = &gpu {
+            check_display(false)?;
+            clear_processes(gpu, Kill::No)?;
+        }
+    }
+    if step == ManualStep::PowerOff {
+        if let Some(driver) = gpu.as_ref().and_then(|gpu| gpu.driver()) {
+            return Err(Error::Failed(format!(
+                "{} is still bound to the GPU; run `nvsleepify pci unbind` first",
+                driver
+            )));
         }
-        Err(e) => return (false, format!("Failed checking processes: {}", e), vec![]),
-        _ => {}
     }
+    let step = Step::from(step);
+    step.apply(gpu.as_ref())?;
+    Ok(format!("Done: {}", step.describe()))
+}
 
-    if let Err(e) = system::stop_services() {
-        return (false, format!("Failed to stop services: {}", e), vec![]);
+/// Most sleep/wake cycles one `Bench` runs.
+pub const MAX_BENCH_CYCLES: u32 = 50;
+
+/// Powers the GPU off and back on `cycles` times, timing each step, with
+/// the checks a sleep makes before it. Processes holding the GPU are killed
+/// as far as the kill policy allows. The GPU has to start awake and ends
+/// awake.
+pub fn bench_logic(cycles: u32) -> Result<Vec<StepTimings>, Error> {
+    if !(1..=MAX_BENCH_CYCLES).contains(&cycles) {
+        return Err(Error::InvalidArgs(format!(
+            "Cycles must be between 1 and {}",
+            MAX_BENCH_CYCLES
+        )));
     }
-    if let Err(e) = system::unload_modules() {
-        return (false, format!("Failed to unload modules: {}", e), vec![]);
+    let Some(_transition) = transition::try_begin() else {
+        return Err(Error::Busy(transition::BUSY.to_string()));
+    };
+    let Ok(mut gpu) = PciDevice::find_nvidia_gpu() else {
+        return Err(Error::InvalidArgs(
+            "The GPU is off the bus; wake it before benchmarking".to_string(),
+        ));
+    };
+    check_display(false)?;
+    let _inhibitor = inhibit::delay_sleep("Benchmarking the Nvidia GPU's power cycle");
+
+    let mut timings: Vec<StepTimings> = Vec::new();
+    let mut record = |step: &str, duration: Duration| match timings
+        .iter_mut()
+        .find(|timing| timing.step == step)
+    {
+        Some(timing) => timing.seconds.push(duration.as_secs_f64()),
+        None => timings.push(StepTimings {
+            step: step.to_string(),
+            seconds: vec![duration.as_secs_f64()],
+        }),
+    };
+    for cycle in 1..=cycles {
+        let started = Instant::now();
+        clear_processes(&gpu, Kill::Requested)?;
+        record("kill processes", started.elapsed());
+        for (step, duration) in transition::run_timed(&transition::SLEEP, Some(&gpu))? {
+            record(step.describe(), duration);
+        }
+        let woken = PciDevice::find_nvidia_gpu().ok();
+        for (step, duration) in transition::run_timed(&transition::WAKE, woken.as_ref())? {
+            record(step.describe(), duration);
+        }
+        gpu = PciDevice::find_nvidia_gpu().map_err(|_| {
+            Error::Failed(format!("The GPU didn't come back after cycle {}", cycle))
+        })?;
     }
-    if let Err(e) = gpu.unbind_driver() {
-        return (false, format!("Failed to unbind driver: {}", e), vec![]);
+    Ok(timings)
+}
+
+/// Kills one process chosen by the user. `name` is the name it was listed
+/// under; a different one means the PID was reused and nothing is killed.
+pub fn kill_one_logic(pid: u32, name: &str) -> Result<String, Error> {
+    let Ok(gpu) = PciDevice::find_nvidia_gpu() else {
+        return Ok("Nvidia GPU not found (already off?)".to_string());
+    };
+
+    let nodes = gpu.get_device_nodes();
+    let procs = system::get_processes_using_nvidia(&nodes)
+        .map_err(|e| Error::Failed(format!("Failed checking processes: {}", e)))?;
+    let Some(target) = procs.into_iter().find(|proc| proc.pid == pid.to_string()) else {
+        return Err(Error::InvalidArgs(format!(
+            "PID {} is not using the GPU (already exited?)",
+            pid
+        )));
+    };
+    if target.name != name {
+        return Err(Error::InvalidArgs(format!(
+            "PID {} is now {}, not {}; not killing it",
+            pid, target.name, name
+        )));
     }
-    if let Err(e) = gpu.set_slot_power(false) {
-        return (false, format!("Failed to power off slot: {}", e), vec![]);
+    kill_targets(&nodes, vec![target])
+}
+
+/// Kills `targets` and checks that they let go of the GPU's `nodes`.
+fn kill_targets(nodes: &[String], targets: ProcessList) -> Result<String, Error> {
+    system::kill_processes(&targets)
+        .map_err(|e| Error::Failed(format!("Failed to kill processes: {}", e)))?;
+
+    let remaining: ProcessList = system::get_processes_using_nvidia(nodes)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|proc| targets.iter().any(|target| target.pid == proc.pid))
+        .collect();
+    if remaining.is_empty() {
+        Ok(format!("Killed {} process(es)", targets.len()))
+    } else {
+        Err(Error::BlockedByProcesses {
+            message: "Some processes are still using the GPU".to_string(),
+            processes: remaining,
+        })
     }
+}
 
-    (true, "Success".to_string(), vec![])
+fn sleep_logic(kill: Kill) -> Result<String, Error> {
+    let Some(_transition) = transition::try_begin() else {
+        return Err(Error::Busy(transition::BUSY.to_string()));
+    };
+    sleep_sequence(kill, false)
 }
 
-fn wake_logic() -> (bool, String) {
-    use std::fs;
-    let slots_dir = std::path::Path::new("/sys/bus/pci/slots");
-    if slots_dir.exists() {
-        if let Ok(entries) = fs::read_dir(slots_dir) {
-            for entry in entries.flatten() {
-                let power_path = entry.path().join("power");
-                if power_path.exists() {
-                    let content = fs::read_to_string(&power_path).unwrap_or_default();
-                    if content.trim() == "0" {
-                        let _ = fs::write(&power_path, "1");
-                    }
-                }
-            }
+/// Wakes the GPU without changing the saved mode.
+pub fn wake_logic() -> Result<String, Error> {
+    let Some(_transition) = transition::try_begin() else {
+        return Err(Error::Busy(transition::BUSY.to_string()));
+    };
+    wake_sequence()
+}
+
+fn vfio_logic() -> Result<String, Error> {
+    let Some(_transition) = transition::try_begin() else {
+        return Err(Error::Busy(transition::BUSY.to_string()));
+    };
+    vfio_sequence(Kill::Auto, false)
+}
+
+/// Power-cycles the GPU after a fatal fault: down as for a sleep, then back
+/// up unless the saved mode wants it asleep. A GPU handed to vfio-pci is
+/// left to the VM.
+fn recover_logic() -> Result<String, Error> {
+    let Some(_transition) = transition::try_begin() else {
+        return Err(Error::Busy(transition::BUSY.to_string()));
+    };
+    let mode = load_mode().unwrap_or(Mode::Standard);
+    let awake = match mode {
+        Mode::Vfio => return Ok("Left alone in VFIO mode".to_string()),
+        Mode::Integrated => false,
+        Mode::Standard => true,
+        Mode::Optimized => system::get_charging_status(),
+    };
+    sleep_sequence(Kill::Requested, false)?;
+    if !awake {
+        return Ok(format!("GPU powered down ({} mode)", mode));
+    }
+    wake_sequence()?;
+    Ok("GPU power-cycled".to_string())
+}
+
+/// Puts a powered-off GPU back on the bus before a hibernation image is
+/// written; the resume kernel finds it there, and the restored one must not
+/// believe it is gone. The nvidia driver stays off it. Returns whether the GPU
+/// had to be powered up.
+fn hibernate_logic() -> Result<bool, Error> {
+    // logind only waits InhibitDelayMaxSec (5s by default) for us, so a
+    // running transition gets a moment to finish, not more.
+    let deadline = std::time::Instant::now() + HIBERNATE_WAIT;
+    let _transition = loop {
+        if let Some(guard) = transition::try_begin() {
+            break guard;
+        }
+        if std::time::Instant::now() >= deadline {
+            return Err(Error::Busy(transition::BUSY.to_string()));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    };
+    if PciDevice::find_nvidia_gpu().is_ok() {
+        return Ok(false);
+    }
+    transition::run(&[Step::PowerOnSlots, Step::Rescan], None)?;
+    Ok(true)
+}
+
+/// Fails when the session is displayed through the Nvidia GPU, which
+/// powering it down or handing it to vfio-pci would black out. With `force`
+/// (a forced mode change) it only logs what it found.
+fn check_display(force: bool) -> Result<(), Error> {
+    let Ok(gpu) = PciDevice::find_nvidia_gpu() else {
+        return Ok(());
+    };
+    let evidence = display::check(&gpu);
+    if evidence.is_empty() {
+        return Ok(());
+    }
+    if force {
+        let reasons: Vec<String> = evidence.iter().map(|e| e.to_string()).collect();
+        eventlog::warn(format!(
+            "Powering the GPU down despite the display check (forced): {}",
+            reasons.join("; ")
+        ));
+        return Ok(());
+    }
+    Err(Error::DrivesDisplay(display::advice(&evidence)))
+}
+
+/// With the display MUX wired to the dGPU, switches it to hybrid for the
+/// next boot if `[platform] switch_mux` allows, or finds it already
+/// switched. Returns what to tell the user then; None leaves the refusal to
+/// `check_display`.
+fn switch_mux() -> Result<Option<String>, Error> {
+    if platform::pending_mux() != Some(Mux::Hybrid) {
+        if !system::switch_mux() {
+            return Ok(None);
         }
+        platform::pin_mux();
+        helper::run(helper::Request::SwitchMux { mux: Mux::Hybrid })
+            .map_err(|e| Error::from(e.context("Failed to switch the display MUX")))?;
+        eventlog::info("Switched the display MUX to hybrid for the next boot");
     }
+    Ok(Some(
+        "The display MUX switches to hybrid at the next boot. Reboot to finish entering \
+         Integrated mode; the GPU goes to sleep then."
+            .to_string(),
+    ))
+}
+
+/// Whether a sleep may kill the processes holding the GPU, before the kill
+/// policy has its say.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kill {
+    /// Soft sleep: fail listing them.
+    No,
+    /// The mode calls for it, but nobody asked (monitor, restore, plain SetMode).
+    Auto,
+    /// The user agreed to it (`kill_procs`).
+    Requested,
+}
 
-    let _ = PciDevice::rescan();
-    std::thread::sleep(std::time::Duration::from_secs(1));
+impl Kill {
+    fn allowed(self, policy: KillPolicy) -> bool {
+        match (self, policy) {
+            (Kill::No, _) | (_, KillPolicy::Never) => false,
+            (Kill::Requested, _) => true,
+            (Kill::Auto, policy) => policy == KillPolicy::Always,
+        }
+    }
+}
 
-    if let Err(e) = system::load_modules() {
-        return (false, format!("Failed to load modules: {}", e));
+/// Kills the processes holding the GPU if `kill` and the kill policy allow
+/// it, and otherwise fails listing them.
+fn clear_processes(gpu: &PciDevice, kill: Kill) -> Result<(), Error> {
+    let nodes = gpu.get_device_nodes();
+    let procs = system::get_processes_using_nvidia(&nodes)
+        .map_err(|e| Error::Failed(format!("Failed checking processes: {}", e)))?;
+    let greeters = display_manager::greeters(&procs);
+    if !greeters.is_empty() {
+        // The display manager would only start its login screen again.
+        return Err(Error::BlockedByProcesses {
+            message: display_manager::advice(&greeters),
+            processes: procs,
+        });
+    }
+    if !procs.is_empty() {
+        let policy = system::kill_policy();
+        if !kill.allowed(policy) {
+            eventlog::warn(format!(
+                "Sleep blocked by processes (soft-sleep): {:?}",
+                procs
+            ));
+            let message = match (kill, policy) {
+                (Kill::No, _) => "Blocking processes found".to_string(),
+                (_, KillPolicy::Never) => {
+                    "Blocking processes found; the kill policy is \"never\"".to_string()
+                }
+                _ => "Blocking processes found; the kill policy is \"ask\", confirm killing them"
+                    .to_string(),
+            };
+            return Err(Error::BlockedByProcesses {
+                message,
+                processes: procs,
+            });
+        }
+        system::kill_processes(&procs)
+            .map_err(|e| Error::Failed(format!("Failed to kill processes: {}", e)))?;
+        std::thread::sleep(std::time::Duration::from_millis(500));
     }
+    Ok(())
+}
 
-    if let Err(e) = system::start_services() {
-        return (false, format!("Failed to start services: {}", e));
+/// Callers must hold the transition guard. `force` skips the display check.
+fn sleep_sequence(kill: Kill, force: bool) -> Result<String, Error> {
+    let Ok(gpu) = PciDevice::find_nvidia_gpu() else {
+        return Ok("Nvidia GPU not found (already off?)".to_string());
+    };
+    check_display(force)?;
+    clear_processes(&gpu, kill)?;
+
+    // Suspending between unbind and slot power-off can wedge the GPU.
+    let _inhibitor = inhibit::delay_sleep("Powering down the Nvidia GPU");
+    let config = system::sleep_config();
+    let timeout = Duration::from_secs(config.timeout);
+    for (i, stage) in config.strategy.iter().enumerate() {
+        let (steps, done): (&[Step], &str) = match stage {
+            SleepStage::RuntimeSuspend => (
+                &transition::RUNTIME_SUSPEND,
+                "Runtime suspended, driver still loaded",
+            ),
+            SleepStage::Unbind => (&transition::UNBIND, "Runtime suspended without a driver"),
+            SleepStage::PowerOff => {
+                transition::run(&transition::SLEEP, Some(&gpu))?;
+                verify::after_sleep().map_err(Error::PowerDownFailed)?;
+                return Ok("Success".to_string());
+            }
+        };
+        transition::run(steps, Some(&gpu))?;
+        if verify::reaches_d3cold(&gpu, timeout) {
+            return Ok(done.to_string());
+        }
+        let reason = format!(
+            "GPU {} didn't reach D3cold within {} of {}",
+            gpu.address,
+            format_duration(config.timeout),
+            stage
+        );
+        if i + 1 == config.strategy.len() {
+            return Err(Error::PowerDownFailed(reason));
+        }
+        eventlog::warn(format!("{}, escalating", reason));
     }
+    Ok("Success".to_string())
+}
 
-    (true, "Success".to_string())
+/// Callers must hold the transition guard. `force` skips the display check.
+fn vfio_sequence(kill: Kill, force: bool) -> Result<String, Error> {
+    let gpu = match PciDevice::find_nvidia_gpu() {
+        Ok(gpu) => gpu,
+        Err(_) => {
+            // Off the bus: power it up, but keep the nvidia driver off it.
+            let _inhibitor = inhibit::delay_sleep("Powering up the Nvidia GPU");
+            transition::run(&[Step::PowerOnSlots, Step::Rescan], None)?;
+            PciDevice::find_nvidia_gpu().map_err(|e| Error::Failed(e.to_string()))?
+        }
+    };
+    check_display(force)?;
+    clear_processes(&gpu, kill)?;
+    transition::run(&transition::VFIO, Some(&gpu))?;
+    let addresses: Vec<String> = gpu
+        .slot_functions()
+        .iter()
+        .map(|f| f.address.to_string())
+        .collect();
+    Ok(format!("Handed {} to vfio-pci", addresses.join(", ")))
 }
 
-fn restore_logic() -> Result<()> {
-    let mode = load_mode().unwrap_or(Mode::Standard);
+/// Callers must hold the transition guard.
+fn wake_sequence() -> Result<String, Error> {
+    let gpu = PciDevice::find_nvidia_gpu().ok();
+    // Only worth holding when the GPU is actually coming back; in Optimized
+    // mode on AC this runs on every monitor tick.
+    let _inhibitor = gpu
+        .is_none()
+        .then(|| inhibit::delay_sleep("Powering up the Nvidia GPU"))
+        .flatten();
+    transition::run(&transition::WAKE, gpu.as_ref())?;
+    Ok("Success".to_string())
+}
+
+/// Applies the saved mode, or the profile's for the power context we start in.
+/// `reconcile` decides what happens when that mode has the GPU off the nvidia
+/// driver but processes are using it. Returns whether the sleep waits for
+/// them to exit.
+fn restore_logic(profiles: &Profiles, reconcile: Reconcile) -> Result<bool> {
+    let saved = load_mode().unwrap_or(Mode::Standard);
+    let mode = match profiles.mode_for(&profiles.current()) {
+        Some(mode) if mode != saved => {
+            save_mode(mode)?;
+            if let Err(e) = xorg::sync(mode) {
+                eventlog::error(format!("Failed to update Xorg configuration: {}", e));
+            }
+            mode
+        }
+        _ => saved,
+    };
+    if matches!(mode, Mode::Integrated | Mode::Vfio) && reconcile != Reconcile::PreferState {
+        let users = PciDevice::find_nvidia_gpu()
+            .ok()
+            .and_then(|gpu| system::get_processes_using_nvidia(&gpu.get_device_nodes()).ok())
+            .unwrap_or_default();
+        if !users.is_empty() {
+            let names: Vec<String> = users.iter().map(|p| p.to_string()).collect();
+            if let Some(deferred) = reconcile_logic(mode, reconcile, &names.join(", "))? {
+                return Ok(deferred);
+            }
+        }
+    }
     match mode {
         Mode::Standard => {
-            wake_logic();
+            let _ = wake_logic();
         }
         Mode::Integrated => {
-            sleep_logic(true);
+            let _ = sleep_logic(Kill::Auto);
+        }
+        Mode::Vfio => {
+            let _ = vfio_logic();
         }
         Mode::Optimized => {
             if system::get_charging_status() {
-                wake_logic();
+                let _ = wake_logic();
             } else {
-                sleep_logic(false);
+                let _ = sleep_logic(Kill::No);
             }
         }
     }
-    Ok(())
+    Ok(false)
+}
+
+/// Startup found `users` on a GPU that `mode` has off the nvidia driver.
+/// None leaves it to the regular restore.
+fn reconcile_logic(mode: Mode, reconcile: Reconcile, users: &str) -> Result<Option<bool>> {
+    match reconcile {
+        Reconcile::PreferHardware => {
+            save_mode(Mode::Standard)?;
+            if let Err(e) = xorg::sync(Mode::Standard) {
+                eventlog::error(format!("Failed to update Xorg configuration: {}", e));
+            }
+            events::emit(
+                EventKind::Policy,
+                format!(
+                    "Startup: the GPU is in use by {} although {} mode has it off; \
+                     switched to Standard mode instead",
+                    users, mode
+                ),
+            );
+            let _ = wake_logic();
+            Ok(Some(false))
+        }
+        Reconcile::AskLater => {
+            events::emit(
+                EventKind::Policy,
+                format!(
+                    "Startup: the GPU is in use by {} although {} mode has it off; \
+                     not killing them. Run `nvsleepify set {}` to enforce it now",
+                    users,
+                    mode,
+                    mode.to_string().to_lowercase()
+                ),
+            );
+            // Vfio isn't enforced by the monitor, so it stays as found.
+            if mode != Mode::Integrated {
+                return Ok(Some(false));
+            }
+            Ok(Some(matches!(
+                sleep_logic(Kill::No),
+                Err(Error::BlockedByProcesses { .. })
+            )))
+        }
+        Reconcile::PreferState => Ok(None),
+    }
 }
@@ -0,0 +1,189 @@
+use anyhow::{anyhow, Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Desktop action id added to patched entries.
+const ACTION_ID: &str = "nvsleepify-dgpu";
+const ACTION_NAME: &str = "Launch on dGPU (nvsleepify)";
+/// Marks entries nvsleepify wrote from scratch, which are deleted on removal
+/// rather than edited back.
+const GENERATED_KEY: &str = "X-Nvsleepify-Generated";
+
+/// XDG data directories, the user's own first.
+pub fn data_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = user_data_dir().into_iter().collect();
+    let system_dirs = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".into());
+    dirs.extend(system_dirs.split(':').map(PathBuf::from));
+    dirs
+}
+
+fn user_data_dir() -> Option<PathBuf> {
+    match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) => Some(dir.into()),
+        None => std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")),
+    }
+}
+
+fn user_applications_dir() -> Result<PathBuf> {
+    user_data_dir()
+        .map(|dir| dir.join("applications"))
+        .ok_or_else(|| anyhow!("Neither XDG_DATA_HOME nor HOME is set"))
+}
+
+/// Finds the .desktop file for `app`, given either a path or a desktop file
+/// id such as "steam" or "org.gnome.Totem.desktop".
+pub fn find_entry(app: &str) -> Result<PathBuf> {
+    if app.contains('/') {
+        let path = PathBuf::from(app);
+        return if path.exists() {
+            Ok(path)
+        } else {
+            Err(anyhow!("{} does not exist", app))
+        };
+    }
+    let file_name = if app.ends_with(".desktop") {
+        app.to_string()
+    } else {
+        format!("{}.desktop", app)
+    };
+    data_dirs()
+        .into_iter()
+        .map(|dir| dir.join("applications").join(&file_name))
+        .find(|path| path.exists())
+        .ok_or_else(|| anyhow!("No desktop entry named {} found", file_name))
+}
+
+/// Adds the dGPU launch action to `entry` and writes the result to the user's
+/// applications directory, where it takes precedence over the system copy.
+/// Returns the written path.
+pub fn add_action(entry: &Path) -> Result<PathBuf> {
+    let content = std::fs::read_to_string(entry)
+        .with_context(|| format!("Failed to read {}", entry.display()))?;
+    let target = user_applications_dir()?.join(
+        entry
+            .file_name()
+            .ok_or_else(|| anyhow!("{} is not a file", entry.display()))?,
+    );
+    let marker = format!("{}=true", GENERATED_KEY);
+    let generated = target != entry || content.lines().any(|line| line == marker);
+
+    let mut lines: Vec<String> = strip_action(&content)
+        .lines()
+        .filter(|line| !line.starts_with(GENERATED_KEY))
+        .map(str::to_string)
+        .collect();
+    let (start, end) = main_group(&lines)
+        .ok_or_else(|| anyhow!("{} has no [Desktop Entry] group", entry.display()))?;
+    let exec = lines[start..end]
+        .iter()
+        .find_map(|line| line.strip_prefix("Exec="))
+        .ok_or_else(|| anyhow!("{} has no Exec line", entry.display()))?
+        .to_string();
+
+    match lines[start..end]
+        .iter()
+        .position(|line| line.starts_with("Actions="))
+    {
+        Some(i) => {
+            let line = &mut lines[start + i];
+            if !line.ends_with(';') {
+                line.push(';');
+            }
+            line.push_str(ACTION_ID);
+            line.push(';');
+        }
+        None => lines.insert(end, format!("Actions={};", ACTION_ID)),
+    }
+    if generated {
+        // Right after the group header, so it stays inside [Desktop Entry].
+        lines.insert(start, marker);
+    }
+
+    let mut out = lines.join("\n");
+    out.push_str(&format!(
+        "\n\n[Desktop Action {}]\nName={}\nExec=nvsleepify run {}\n",
+        ACTION_ID, ACTION_NAME, exec
+    ));
+
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&target, out)
+        .with_context(|| format!("Failed to write {}", target.display()))?;
+    Ok(target)
+}
+
+/// Undoes `add_action` for `app`. Entries nvsleepify generated are deleted so
+/// the system copy shows again; entries the user already had are edited.
+/// Returns the affected path, or None if `app` had no dGPU action.
+pub fn remove_action(app: &str) -> Result<Option<PathBuf>> {
+    let entry = find_entry(app)?;
+    let content = std::fs::read_to_string(&entry)
+        .with_context(|| format!("Failed to read {}", entry.display()))?;
+    if !content.contains(&format!("[Desktop Action {}]", ACTION_ID)) {
+        return Ok(None);
+    }
+    if content
+        .lines()
+        .any(|line| line == format!("{}=true", GENERATED_KEY))
+    {
+        std::fs::remove_file(&entry)
+            .with_context(|| format!("Failed to remove {}", entry.display()))?;
+    } else {
+        std::fs::write(&entry, strip_action(&content))
+            .with_context(|| format!("Failed to write {}", entry.display()))?;
+    }
+    Ok(Some(entry))
+}
+
+/// Line range of the [Desktop Entry] group's keys.
+fn main_group(lines: &[String]) -> Option<(usize, usize)> {
+    let header = lines.iter().position(|line| line == "[Desktop Entry]")?;
+    let end = lines[header + 1..]
+        .iter()
+        .position(|line| line.starts_with('['))
+        .map(|i| header + 1 + i)
+        .unwrap_or(lines.len());
+    // Keep trailing blank lines outside the group.
+    let end = (header + 1..end)
+        .rev()
+        .find(|&i| !lines[i].trim().is_empty())
+        .map(|i| i + 1)
+        .unwrap_or(header + 1);
+    Some((header + 1, end))
+}
+
+/// Removes the dGPU action group and its id from the Actions key.
+fn strip_action(content: &str) -> String {
+    let group = format!("[Desktop Action {}]", ACTION_ID);
+    let mut out: Vec<String> = Vec::new();
+    let mut skipping = false;
+    for line in content.lines() {
+        if line.starts_with('[') {
+            skipping = line == group;
+        }
+        if skipping {
+            continue;
+        }
+        match line.strip_prefix("Actions=") {
+            Some(actions) => {
+                let kept: Vec<&str> = actions
+                    .split(';')
+                    .filter(|a| !a.is_empty() && *a != ACTION_ID)
+                    .collect();
+                if !kept.is_empty() {
+                    out.push(format!("Actions={};", kept.join(";")));
+                }
+            }
+            None => out.push(line.to_string()),
+        }
+    }
+    while out.last().is_some_and(|line| line.trim().is_empty()) {
+        out.pop();
+    }
+    let mut out = out.join("\n");
+    out.push('\n');
+    out
+}
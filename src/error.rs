@@ -0,0 +1,181 @@
+use crate::protocol::{ProcessInfo, ProcessList};
+use crate::system::CommandTimeout;
+use crate::transition::{Step, TransitionError};
+use std::fmt;
+use zbus::names::ErrorName;
+use zbus::{DBusError, MessageBuilder, MessageHeader};
+
+pub const PREFIX: &str = "org.nvsleepify.Error";
+
+/// Why a mode change, sleep, wake or kill didn't happen.
+///
+/// Sent over D-Bus as `org.nvsleepify.Error.<Variant>` with a `(sa(ss))`
+/// body: the description, then the processes involved (empty unless the
+/// error is about processes). Clients branch on the name instead of parsing
+/// the description.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// Processes hold the GPU and weren't (or couldn't be) killed.
+    BlockedByProcesses {
+        message: String,
+        processes: ProcessList,
+    },
+    /// Another transition is running.
+    Busy(String),
+    InvalidArgs(String),
+    PermissionDenied(String),
+    /// The GPU's hotplug slot can't be found or switched (no acpiphp/pciehp).
+    SlotPowerUnavailable(String),
+    /// The nvidia modules are held by other modules or processes.
+    ModulesInUse(String),
+    /// An external command was killed after running into the command timeout.
+    Timeout(String),
+    /// The steps succeeded but the GPU didn't end up powered down.
+    PowerDownFailed(String),
+    /// The session is displayed through the GPU, so it has to stay on.
+    DrivesDisplay(String),
+    Failed(String),
+}
+
+impl Error {
+    /// The full D-Bus error name, e.g. `org.nvsleepify.Error.Busy`.
+    pub fn error_name(&self) -> &'static str {
+        match self {
+            Error::BlockedByProcesses { .. } => "org.nvsleepify.Error.BlockedByProcesses",
+            Error::Busy(_) => "org.nvsleepify.Error.Busy",
+            Error::InvalidArgs(_) => "org.nvsleepify.Error.InvalidArgs",
+            Error::PermissionDenied(_) => "org.nvsleepify.Error.PermissionDenied",
+            Error::SlotPowerUnavailable(_) => "org.nvsleepify.Error.SlotPowerUnavailable",
+            Error::ModulesInUse(_) => "org.nvsleepify.Error.ModulesInUse",
+            Error::Timeout(_) => "org.nvsleepify.Error.Timeout",
+            Error::PowerDownFailed(_) => "org.nvsleepify.Error.PowerDownFailed",
+            Error::DrivesDisplay(_) => "org.nvsleepify.Error.DrivesDisplay",
+            Error::Failed(_) => "org.nvsleepify.Error.Failed",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Error::BlockedByProcesses { message, .. }
+            | Error::Busy(message)
+            | Error::InvalidArgs(message)
+            | Error::PermissionDenied(message)
+            | Error::SlotPowerUnavailable(message)
+            | Error::ModulesInUse(message)
+            | Error::Timeout(message)
+            | Error::PowerDownFailed(message)
+            | Error::DrivesDisplay(message)
+            | Error::Failed(message) => message,
+        }
+    }
+
+    pub fn processes(&self) -> &[ProcessInfo] {
+        match self {
+            Error::BlockedByProcesses { processes, .. } => processes,
+            _ => &[],
+        }
+    }
+
+    pub(crate) fn from_parts(name: &str, message: String, processes: ProcessList) -> Self {
+        match name {
+            "BlockedByProcesses" => Error::BlockedByProcesses { message, processes },
+            "Busy" => Error::Busy(message),
+            "InvalidArgs" => Error::InvalidArgs(message),
+            "PermissionDenied" => Error::PermissionDenied(message),
+            "SlotPowerUnavailable" => Error::SlotPowerUnavailable(message),
+            "ModulesInUse" => Error::ModulesInUse(message),
+            "Timeout" => Error::Timeout(message),
+            "PowerDownFailed" => Error::PowerDownFailed(message),
+            "DrivesDisplay" => Error::DrivesDisplay(message),
+            _ => Error::Failed(message),
+        }
+    }
+
+    /// The `(success, message, processes)` shape of the older methods.
+    pub fn reply(result: Result<String, Error>) -> (bool, String, ProcessList) {
+        match result {
+            Ok(msg) => (true, msg, vec![]),
+            Err(e) => {
+                let processes = e.processes().to_vec();
+                (false, e.to_string(), processes)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl DBusError for Error {
+    fn create_reply(&self, call: &MessageHeader<'_>) -> zbus::Result<zbus::Message> {
+        MessageBuilder::error(call, self.error_name())?.build(&(self.message(), self.processes()))
+    }
+
+    fn name(&self) -> ErrorName<'_> {
+        ErrorName::from_static_str_unchecked(self.error_name())
+    }
+
+    fn description(&self) -> Option<&str> {
+        Some(self.message())
+    }
+}
+
+/// Turns a method call failure back into the daemon's error. Errors that
+/// aren't the daemon's (no reply, access denied by the bus) become `Failed`,
+/// except for the bus refusing the call, which is `PermissionDenied`.
+impl From<zbus::Error> for Error {
+    fn from(e: zbus::Error) -> Self {
+        if let zbus::Error::MethodError(name, description, reply) = &e {
+            let message = description.clone().unwrap_or_default();
+            if let Some(kind) = name.strip_prefix(PREFIX).and_then(|n| n.strip_prefix('.')) {
+                let processes = reply
+                    .body::<(String, ProcessList)>()
+                    .map(|(_, processes)| processes)
+                    .unwrap_or_default();
+                return Error::from_parts(kind, message, processes);
+            }
+            if name.as_str() == "org.freedesktop.DBus.Error.AccessDenied" {
+                return Error::PermissionDenied(message);
+            }
+        }
+        Error::Failed(e.to_string())
+    }
+}
+
+/// Sorts an error from a transition step into the kind a client can act on.
+fn classify(step: Option<Step>, error: &anyhow::Error, message: String) -> Error {
+    if error.chain().any(|cause| cause.is::<CommandTimeout>()) {
+        return Error::Timeout(message);
+    }
+    let permission_denied = error.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::PermissionDenied)
+    });
+    if permission_denied {
+        return Error::PermissionDenied(message);
+    }
+    match step {
+        Some(Step::UnloadModules) => Error::ModulesInUse(message),
+        Some(Step::PowerOffSlot) => Error::SlotPowerUnavailable(message),
+        _ => Error::Failed(message),
+    }
+}
+
+impl From<TransitionError> for Error {
+    fn from(e: TransitionError) -> Self {
+        classify(Some(e.failed), &e.error, e.to_string())
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        let message = format!("{:#}", e);
+        classify(None, &e, message)
+    }
+}
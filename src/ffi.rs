@@ -0,0 +1,221 @@
+use crate::client::Client;
+use crate::error::Error;
+use futures_util::StreamExt;
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::sync::OnceLock;
+use tokio::runtime::Runtime;
+use tokio::task::JoinHandle;
+
+// Return codes, mirrored in include/nvsleepify.h.
+const OK: c_int = 0;
+const ERROR_FAILED: c_int = 1;
+const ERROR_BUSY: c_int = 2;
+const ERROR_BLOCKED_BY_PROCESSES: c_int = 3;
+const ERROR_INVALID_ARGS: c_int = 4;
+const ERROR_PERMISSION_DENIED: c_int = 5;
+const ERROR_SLOT_POWER_UNAVAILABLE: c_int = 6;
+const ERROR_MODULES_IN_USE: c_int = 7;
+const ERROR_TIMEOUT: c_int = 8;
+const ERROR_POWER_DOWN_FAILED: c_int = 9;
+const ERROR_DRIVES_DISPLAY: c_int = 10;
+
+/// Called with the new mode, the GPU's power state and the caller's pointer.
+/// The strings are only valid during the call.
+pub type StateCallback =
+    extern "C" fn(mode: *const c_char, power_state: *const c_char, user_data: *mut c_void);
+
+/// A running `StateChanged` subscription, owned by the C caller.
+pub struct Subscription {
+    task: JoinHandle<()>,
+}
+
+/// Wraps the caller's pointer so the subscription task can carry it; what it
+/// points to is the caller's business, as with any C callback.
+struct UserData(*mut c_void);
+
+// SAFETY: the pointer is only handed back to the caller's callback.
+unsafe impl Send for UserData {}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// C callers have no async runtime; every call runs on this one.
+fn runtime() -> Option<&'static Runtime> {
+    static RUNTIME: OnceLock<Option<Runtime>> = OnceLock::new();
+    RUNTIME
+        .get_or_init(|| {
+            tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(1)
+                .enable_all()
+                .build()
+                .ok()
+        })
+        .as_ref()
+}
+
+fn to_c_string(s: &str) -> *mut c_char {
+    CString::new(s.replace('\0', ""))
+        .map(CString::into_raw)
+        .unwrap_or(std::ptr::null_mut())
+}
+
+fn set_last_error(message: &str) {
+    LAST_ERROR.with(|e| *e.borrow_mut() = CString::new(message.replace('\0', "")).ok());
+}
+
+fn error_code(error: &Error) -> c_int {
+    set_last_error(error.message());
+    match error {
+        Error::BlockedByProcesses { .. } => ERROR_BLOCKED_BY_PROCESSES,
+        Error::Busy(_) => ERROR_BUSY,
+        Error::InvalidArgs(_) => ERROR_INVALID_ARGS,
+        Error::PermissionDenied(_) => ERROR_PERMISSION_DENIED,
+        Error::SlotPowerUnavailable(_) => ERROR_SLOT_POWER_UNAVAILABLE,
+        Error::ModulesInUse(_) => ERROR_MODULES_IN_USE,
+        Error::Timeout(_) => ERROR_TIMEOUT,
+        Error::PowerDownFailed(_) => ERROR_POWER_DOWN_FAILED,
+        Error::DrivesDisplay(_) => ERROR_DRIVES_DISPLAY,
+        Error::Failed(_) => ERROR_FAILED,
+    }
+}
+
+/// Runs a client call to completion on the shared runtime. Blocking inside
+/// a runtime (e.g. from a subscription callback) would panic, so that's an error.
+fn call<T>(f: impl std::future::Future<Output = Result<T, Error>>) -> Result<T, Error> {
+    if tokio::runtime::Handle::try_current().is_ok() {
+        return Err(Error::Failed(
+            "nvsleepify can't be called from a subscription callback".into(),
+        ));
+    }
+    let runtime = runtime().ok_or_else(|| Error::Failed("Failed to start a runtime".into()))?;
+    runtime.block_on(f)
+}
+
+/// Writes `value` to `out` unless the caller passed NULL.
+///
+/// # Safety
+/// `out` must be NULL or valid for a pointer write.
+unsafe fn store(out: *mut *mut c_char, value: &str) {
+    if !out.is_null() {
+        *out = to_c_string(value);
+    }
+}
+
+/// The saved mode and the GPU's power state, as newly allocated strings to be
+/// released with `nvsleepify_free_string`. Returns 0 or an error code.
+///
+/// # Safety
+/// `mode` and `power_state` must each be NULL or valid for a pointer write.
+#[no_mangle]
+pub unsafe extern "C" fn nvsleepify_get_status(
+    mode: *mut *mut c_char,
+    power_state: *mut *mut c_char,
+) -> c_int {
+    match call(async { Client::connect().await?.info().await }) {
+        Ok(report) => {
+            store(mode, &report.mode);
+            store(power_state, &report.power_state.to_string());
+            OK
+        }
+        Err(e) => error_code(&e),
+    }
+}
+
+/// Switches to `mode` ("standard", "integrated", "optimized" or "vfio") and
+/// waits for the transition. `message`, if not NULL, receives the daemon's
+/// summary. Returns 0 or an error code.
+///
+/// # Safety
+/// `mode` must be a NUL-terminated string; `message` must be NULL or valid
+/// for a pointer write.
+#[no_mangle]
+pub unsafe extern "C" fn nvsleepify_set_mode(
+    mode: *const c_char,
+    message: *mut *mut c_char,
+) -> c_int {
+    if mode.is_null() {
+        return error_code(&Error::InvalidArgs("No mode given".into()));
+    }
+    let mode = match CStr::from_ptr(mode).to_str().map(str::parse) {
+        Ok(Ok(mode)) => mode,
+        _ => return error_code(&Error::InvalidArgs("Unknown mode".into())),
+    };
+    match call(async { Client::connect().await?.set_mode(mode).await }) {
+        Ok(result) => {
+            store(message, &result.message);
+            OK
+        }
+        Err(e) => error_code(&e),
+    }
+}
+
+/// Calls `callback` from a background thread whenever the mode or the GPU's
+/// power state changes, until `nvsleepify_unsubscribe`. Returns NULL on error.
+///
+/// # Safety
+/// `user_data` is passed to `callback` as is and must stay valid, and safe
+/// to use from another thread, until the subscription is dropped.
+#[no_mangle]
+pub unsafe extern "C" fn nvsleepify_subscribe(
+    callback: StateCallback,
+    user_data: *mut c_void,
+) -> *mut Subscription {
+    let stream = match call(async { Client::connect().await?.state_changes().await }) {
+        Ok(stream) => stream,
+        Err(e) => {
+            error_code(&e);
+            return std::ptr::null_mut();
+        }
+    };
+    let Some(runtime) = runtime() else {
+        return std::ptr::null_mut();
+    };
+    let user_data = UserData(user_data);
+    let task = runtime.spawn(async move {
+        let user_data = user_data;
+        let mut stream = stream;
+        while let Some(change) = stream.next().await {
+            let mode = CString::new(change.mode.to_string()).unwrap_or_default();
+            let power_state = CString::new(change.power_state.to_string()).unwrap_or_default();
+            callback(mode.as_ptr(), power_state.as_ptr(), user_data.0);
+        }
+    });
+    Box::into_raw(Box::new(Subscription { task }))
+}
+
+/// Stops a subscription; the callback isn't called after this returns,
+/// unless it is running right now on the background thread.
+///
+/// # Safety
+/// `subscription` must be NULL or come from `nvsleepify_subscribe`, and not
+/// be used again.
+#[no_mangle]
+pub unsafe extern "C" fn nvsleepify_unsubscribe(subscription: *mut Subscription) {
+    if !subscription.is_null() {
+        Box::from_raw(subscription).task.abort();
+    }
+}
+
+/// The description of the last error on this thread, or NULL. Release it
+/// with `nvsleepify_free_string`.
+#[no_mangle]
+pub extern "C" fn nvsleepify_last_error() -> *mut c_char {
+    LAST_ERROR.with(|e| {
+        e.borrow()
+            .as_ref()
+            .map_or(std::ptr::null_mut(), |message| message.clone().into_raw())
+    })
+}
+
+/// Releases a string returned by this library.
+///
+/// # Safety
+/// `s` must be NULL or a string from this library not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn nvsleepify_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
@@ -0,0 +1,167 @@
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource};
+use std::path::Path;
+use std::sync::OnceLock;
+use unic_langid::LanguageIdentifier;
+
+pub use fluent_bundle::FluentValue;
+
+/// The English messages, built in. Every other language falls back to them
+/// for messages its translation lacks.
+const ENGLISH: &str = include_str!("../locales/en/nvsleepify.ftl");
+
+/// Where `make install` and distro packages put translations, as
+/// `<language>/nvsleepify.ftl`.
+const LOCALE_DIRS: [&str; 2] = [
+    "/usr/local/share/nvsleepify/locales",
+    "/usr/share/nvsleepify/locales",
+];
+const RESOURCE_NAME: &str = "nvsleepify.ftl";
+
+/// Messages in our own environment's languages.
+static TRANSLATOR: OnceLock<Translator> = OnceLock::new();
+
+/// The message `id` in the user's language, with `args` filled in. With
+/// `translator =>` first, in that translator's languages instead.
+///
+/// ```
+/// use nvsleepify::tr;
+/// assert_eq!(tr!("notify-mode-changed", mode = "Integrated"), "Mode changed to Integrated");
+/// ```
+#[macro_export]
+macro_rules! tr {
+    ($id:literal) => {
+        $crate::i18n::tr($id, &[])
+    };
+    ($id:literal, $($name:ident = $value:expr),+ $(,)?) => {
+        $crate::i18n::tr(
+            $id,
+            &[$((stringify!($name), $crate::i18n::FluentValue::from($value))),+],
+        )
+    };
+    ($translator:expr => $id:literal) => {
+        $translator.tr($id, &[])
+    };
+    ($translator:expr => $id:literal, $($name:ident = $value:expr),+ $(,)?) => {
+        $translator.tr(
+            $id,
+            &[$((stringify!($name), $crate::i18n::FluentValue::from($value))),+],
+        )
+    };
+}
+
+/// The messages in one list of languages, in order of preference, English
+/// last.
+pub struct Translator {
+    bundles: Vec<FluentBundle<FluentResource>>,
+}
+
+impl Translator {
+    /// For the languages an environment asks for (see `requested_languages`),
+    /// with `var` looking up its variables.
+    pub fn from_env(var: impl Fn(&str) -> Option<String>) -> Self {
+        Translator {
+            bundles: load(languages_from(var)),
+        }
+    }
+
+    /// Formats message `id` from the first bundle that has it. Unknown IDs
+    /// come back as they are, so a missing message shows up without breaking
+    /// output.
+    pub fn tr(&self, id: &str, args: &[(&str, FluentValue)]) -> String {
+        let mut fluent_args = FluentArgs::new();
+        for (name, value) in args {
+            fluent_args.set(*name, value.clone());
+        }
+        for bundle in &self.bundles {
+            let Some(pattern) = bundle.get_message(id).and_then(|msg| msg.value()) else {
+                continue;
+            };
+            let mut errors = Vec::new();
+            return bundle
+                .format_pattern(pattern, Some(&fluent_args), &mut errors)
+                .into_owned();
+        }
+        id.to_string()
+    }
+}
+
+/// Formats message `id` in our own environment's languages.
+pub fn tr(id: &str, args: &[(&str, FluentValue)]) -> String {
+    TRANSLATOR
+        .get_or_init(|| Translator::from_env(|var| std::env::var(var).ok()))
+        .tr(id, args)
+}
+
+/// The languages messages are wanted in, most preferred first, going by the
+/// environment the way gettext does: LC_ALL, LC_MESSAGES or LANG pick the
+/// locale, and LANGUAGE may list more languages unless that locale is "C".
+pub fn requested_languages() -> Vec<LanguageIdentifier> {
+    languages_from(|var| std::env::var(var).ok())
+}
+
+fn languages_from(var: impl Fn(&str) -> Option<String>) -> Vec<LanguageIdentifier> {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .find_map(|name| var(name).filter(|value| !value.is_empty()));
+    let Some(locale) = locale.as_deref().and_then(parse_locale) else {
+        return Vec::new();
+    };
+    let mut languages: Vec<LanguageIdentifier> = var("LANGUAGE")
+        .unwrap_or_default()
+        .split(':')
+        .filter_map(parse_locale)
+        .collect();
+    languages.push(locale);
+    languages
+}
+
+/// e.g. "pt_BR.UTF-8" as pt-BR. None for the "C" and "POSIX" locales.
+fn parse_locale(locale: &str) -> Option<LanguageIdentifier> {
+    let name = locale.split(['.', '@']).next()?;
+    if name.is_empty() || name == "C" || name == "POSIX" {
+        return None;
+    }
+    name.replace('_', "-").parse().ok()
+}
+
+/// The installed translation for `language`, trying e.g. "pt-BR" before "pt".
+fn find_translation(language: &LanguageIdentifier) -> Option<String> {
+    let names = [language.to_string(), language.language.to_string()];
+    LOCALE_DIRS.iter().find_map(|dir| {
+        names.iter().find_map(|name| {
+            std::fs::read_to_string(Path::new(dir).join(name).join(RESOURCE_NAME)).ok()
+        })
+    })
+}
+
+fn bundle(language: LanguageIdentifier, source: String) -> FluentBundle<FluentResource> {
+    // A translation with syntax errors still has its valid messages used.
+    let resource = FluentResource::try_new(source).unwrap_or_else(|(resource, errors)| {
+        eprintln!(
+            "Ignoring {} malformed message(s) in the {} translation",
+            errors.len(),
+            language
+        );
+        resource
+    });
+    let mut bundle = FluentBundle::new_concurrent(vec![language]);
+    // Terminals and notification servers would show the isolation marks.
+    bundle.set_use_isolating(false);
+    let _ = bundle.add_resource(resource);
+    bundle
+}
+
+fn load(languages: Vec<LanguageIdentifier>) -> Vec<FluentBundle<FluentResource>> {
+    let english: LanguageIdentifier = "en".parse().unwrap();
+    let mut bundles: Vec<_> = languages
+        .into_iter()
+        .filter(|language| language.language != english.language)
+        .filter_map(|language| {
+            let source = find_translation(&language)?;
+            Some(bundle(language, source))
+        })
+        .collect();
+    bundles.push(bundle(english, ENGLISH.to_string()));
+    bundles
+}
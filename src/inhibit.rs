@@ -0,0 +1,30 @@
+use zbus::zvariant::OwnedFd;
+
+/// A logind "delay" inhibitor. While held, logind postpones system suspend
+/// (up to its InhibitDelayMaxSec), so the machine doesn't go to sleep halfway
+/// through powering the GPU down or up. Released when dropped.
+pub struct InhibitorLock {
+    _fd: OwnedFd,
+}
+
+/// Takes a sleep delay lock. Failure isn't fatal to the transition, so it is
+/// only logged (e.g. no logind on the system).
+pub fn delay_sleep(why: &str) -> Option<InhibitorLock> {
+    let result = zbus::blocking::Connection::system().and_then(|conn| {
+        conn.call_method(
+            Some("org.freedesktop.login1"),
+            "/org/freedesktop/login1",
+            Some("org.freedesktop.login1.Manager"),
+            "Inhibit",
+            &("sleep", "nvsleepify", why, "delay"),
+        )?
+        .body::<OwnedFd>()
+    });
+    match result {
+        Ok(fd) => Some(InhibitorLock { _fd: fd }),
+        Err(e) => {
+            eprintln!("Could not take a logind inhibitor lock: {}", e);
+            None
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use anyhow::{anyhow, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Where the shipped files name the binaries; rewritten to wherever
+/// `nvsleepify install` runs from.
+const SHIPPED_BIN_DIR: &str = "/usr/local/bin";
+
+/// Every file `nvsleepify install` puts in place, with its contents as shipped.
+const FILES: [(&str, &str); 6] = [
+    (
+        "/etc/systemd/system/nvsleepifyd.service",
+        include_str!("../nvsleepifyd.service"),
+    ),
+    (
+        "/etc/systemd/system/nvsleepify-helper.socket",
+        include_str!("../nvsleepify-helper.socket"),
+    ),
+    (
+        "/etc/systemd/system/nvsleepify-helper.service",
+        include_str!("../nvsleepify-helper.service"),
+    ),
+    (
+        "/etc/dbus-1/system.d/org.nvsleepify.conf",
+        include_str!("../org.nvsleepify.conf"),
+    ),
+    (
+        "/usr/share/dbus-1/system-services/org.nvsleepify.Service.service",
+        include_str!("../org.nvsleepify.Service.service"),
+    ),
+    (TRAY_AUTOSTART, include_str!("../nvsleepify-tray.desktop")),
+];
+
+const TRAY_AUTOSTART: &str = "/etc/xdg/autostart/nvsleepify-tray.desktop";
+
+/// Left behind by the README's unprivileged setup; removed on uninstall.
+const UNPRIVILEGED_DROP_IN: &str = "/etc/systemd/system/nvsleepifyd.service.d/unprivileged.conf";
+
+const DAEMON_UNIT: &str = "nvsleepifyd.service";
+const HELPER_UNITS: [&str; 2] = ["nvsleepify-helper.socket", "nvsleepify-helper.service"];
+
+/// Directories holding nvsleepify's configuration and state, removed by
+/// `uninstall --purge`.
+pub const DATA_DIRS: [&str; 2] = ["/etc/nvsleepify", "/var/lib/nvsleepify"];
+
+/// The directory the running binary is in, which the installed files point at.
+pub fn bin_dir() -> Result<PathBuf> {
+    let exe = std::env::current_exe().context("Can't find the nvsleepify binary")?;
+    exe.parent()
+        .map(Path::to_path_buf)
+        .ok_or_else(|| anyhow!("{} has no parent directory", exe.display()))
+}
+
+fn contents(shipped: &str, bin_dir: &Path) -> String {
+    let bin_dir = bin_dir.display().to_string();
+    shipped.replace(SHIPPED_BIN_DIR, &bin_dir).replace(
+        "Exec=nvsleepify-tray",
+        &format!("Exec={}/nvsleepify-tray", bin_dir),
+    )
+}
+
+/// Writes the units, D-Bus policy and activation file and the tray autostart
+/// entry, the latter only if the tray was built. Returns the paths written.
+pub fn write_files(bin_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut written = Vec::new();
+    for (path, shipped) in FILES {
+        if path == TRAY_AUTOSTART && !bin_dir.join("nvsleepify-tray").exists() {
+            continue;
+        }
+        let path = Path::new(path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, contents(shipped, bin_dir))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        written.push(path.to_path_buf());
+    }
+    Ok(written)
+}
+
+/// Removes what `write_files` wrote, plus the unprivileged drop-in.
+/// Returns the paths that were there.
+pub fn remove_files() -> Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    for path in FILES
+        .iter()
+        .map(|(path, _)| *path)
+        .chain([UNPRIVILEGED_DROP_IN])
+    {
+        match std::fs::remove_file(path) {
+            Ok(()) => removed.push(PathBuf::from(path)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("Failed to remove {}", path)),
+        }
+    }
+    let _ = std::fs::remove_dir(Path::new(UNPRIVILEGED_DROP_IN).parent().unwrap());
+    Ok(removed)
+}
+
+/// Runs `systemctl` with `args`, failing on a non-zero exit.
+pub fn systemctl(args: &[&str]) -> Result<()> {
+    let status = Command::new("systemctl")
+        .args(args)
+        .status()
+        .context("Failed to run systemctl")?;
+    if !status.success() {
+        return Err(anyhow!("systemctl {} failed", args.join(" ")));
+    }
+    Ok(())
+}
+
+/// Picks up changed units and D-Bus policy.
+pub fn reload() {
+    let _ = systemctl(&["daemon-reload"]);
+    // dbus-broker and dbus-daemon both reload their policy on this.
+    let _ = systemctl(&["reload", "dbus.service"]);
+}
+
+pub fn enable_daemon() -> Result<()> {
+    systemctl(&["enable", "--now", DAEMON_UNIT])
+}
+
+/// Stops and disables the daemon and the helper, ignoring units that are
+/// already gone.
+pub fn disable_units() {
+    let _ = systemctl(&["disable", "--now", DAEMON_UNIT]);
+    let _ = systemctl(&["disable", "--now", HELPER_UNITS[0], HELPER_UNITS[1]]);
+}
+
+/// Removes nvsleepify's configuration and state. Returns the directories
+/// that were there.
+pub fn purge() -> Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    for dir in DATA_DIRS {
+        match std::fs::remove_dir_all(dir) {
+            Ok(()) => removed.push(PathBuf::from(dir)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("Failed to remove {}", dir)),
+        }
+    }
+    Ok(removed)
+}
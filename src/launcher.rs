@@ -0,0 +1,63 @@
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Wrapper script `nvsleepify launcher --install` writes to ~/.local/bin.
+const WRAPPER_NAME: &str = "nvsleepify-game";
+
+/// Game launchers whose per-game command can go through `nvsleepify run`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Launcher {
+    Steam,
+    Lutris,
+}
+
+/// Where the wrapper goes: ~/.local/bin, which is on most users' PATH.
+pub fn wrapper_path() -> Result<PathBuf> {
+    std::env::var_os("HOME")
+        .map(|home| Path::new(&home).join(".local/bin").join(WRAPPER_NAME))
+        .ok_or_else(|| anyhow!("HOME is not set"))
+}
+
+/// Writes a script that runs its arguments through `nvsleepify run`, calling
+/// `nvsleepify` by its full path since launchers often run games with a
+/// reduced PATH. Returns the script's path.
+pub fn install_wrapper(nvsleepify: &Path) -> Result<PathBuf> {
+    let path = wrapper_path()?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let script = format!(
+        "#!/bin/sh\n\
+         # Written by `nvsleepify launcher --install`: wakes the Nvidia GPU for the\n\
+         # game and lets it sleep again once the game exits.\n\
+         exec {} run \"$@\"\n",
+        nvsleepify.display()
+    );
+    std::fs::write(&path, script).with_context(|| format!("Failed to write {}", path.display()))?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+    Ok(path)
+}
+
+/// How to set up `launcher` so games start through `command`, e.g.
+/// "nvsleepify run".
+pub fn instructions(launcher: Launcher, command: &str) -> String {
+    match launcher {
+        Launcher::Steam => format!(
+            "In Steam, open the game's Properties > General and set Launch Options to:\n\n    \
+             {} %command%\n\n\
+             Variables the game already sets (e.g. DXVK_HUD=1) go before it, arguments \
+             after %command%.",
+            command
+        ),
+        Launcher::Lutris => format!(
+            "In Lutris, open Configure > System options (or Preferences > Global options \
+             for every game) and set Command prefix to:\n\n    \
+             {}\n\n\
+             or add this to the game's configuration file:\n\n    \
+             system:\n      prefix_command: {}",
+            command, command
+        ),
+    }
+}
@@ -1,5 +1,30 @@
+pub mod access;
+pub mod applet;
+pub mod cli;
 pub mod client;
 pub mod daemon;
-pub mod pci;
-pub mod protocol;
-pub mod system;
+pub mod desktop;
+pub mod error;
+pub mod ffi;
+pub mod i18n;
+pub mod inhibit;
+pub mod install;
+pub mod launcher;
+pub mod logind;
+pub mod metrics;
+pub mod migrate;
+pub mod notifications;
+pub mod report;
+pub mod rpc;
+pub mod simulate;
+pub mod supergfx;
+pub mod triggers;
+pub mod units;
+
+// The sysfs, PCI, transition, policy and protocol logic lives in
+// nvsleepify-core; these keep its modules at their old paths.
+pub use nvsleepify_core::{
+    backend, config, conflicts, display, display_manager, driver, eventlog, events, faults, helper,
+    hibernate, history, idle, leases, output, pci, platform, profiles, protocol, savings, state,
+    stats, sysfs, system, transition, verify, xorg,
+};
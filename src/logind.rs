@@ -0,0 +1,166 @@
+use crate::pci::{PciDevice, DEFAULT_SEAT};
+use crate::system;
+use futures_util::future::ready;
+use futures_util::{Stream, StreamExt};
+use zbus::zvariant::OwnedObjectPath;
+use zbus::{dbus_proxy, Connection};
+
+/// (session_id, uid, user_name, seat_id, session_object_path)
+type SessionTuple = (String, u32, String, String, OwnedObjectPath);
+
+#[dbus_proxy(
+    interface = "org.freedesktop.login1.Manager",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1"
+)]
+trait LoginManager {
+    /// Returns: [(uid, user_name, user_object_path)]
+    fn list_users(&self) -> zbus::Result<Vec<(u32, String, OwnedObjectPath)>>;
+
+    fn list_sessions(&self) -> zbus::Result<Vec<SessionTuple>>;
+
+    /// Sent with `start` set before suspend or hibernation, and unset after
+    /// resume.
+    #[dbus_proxy(signal)]
+    fn prepare_for_sleep(&self, start: bool) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn session_new(&self, session_id: String, object_path: OwnedObjectPath) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn session_removed(&self, session_id: String, object_path: OwnedObjectPath)
+        -> zbus::Result<()>;
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.login1.Session",
+    default_service = "org.freedesktop.login1"
+)]
+trait LoginSession {
+    /// "x11", "wayland", "mir", "tty" or "unspecified".
+    #[dbus_proxy(property, name = "Type")]
+    fn session_type(&self) -> zbus::Result<String>;
+
+    #[dbus_proxy(property)]
+    fn active(&self) -> zbus::Result<bool>;
+}
+
+/// Active graphical sessions as (uid, seat_id); the seat is empty for
+/// sessions without one.
+async fn graphical_sessions(conn: &Connection) -> zbus::Result<Vec<(u32, String)>> {
+    let manager = LoginManagerProxy::new(conn).await?;
+    let mut sessions = Vec::new();
+    for (_, uid, _, seat, path) in manager.list_sessions().await? {
+        let session = LoginSessionProxy::builder(conn).path(path)?.build().await?;
+        let graphical = matches!(
+            session.session_type().await.as_deref(),
+            Ok("x11" | "wayland" | "mir")
+        );
+        if graphical && session.active().await.unwrap_or(false) {
+            sessions.push((uid, seat));
+        }
+    }
+    Ok(sessions)
+}
+
+/// Whether a regular user has an active graphical session, as opposed to
+/// only login screens.
+pub async fn user_logged_in_graphically(conn: &Connection) -> zbus::Result<bool> {
+    Ok(graphical_sessions(conn)
+        .await?
+        .iter()
+        .any(|(uid, _)| is_human_uid(*uid)))
+}
+
+/// The seat the Nvidia GPU is assigned to; "seat0" unless a multi-seat setup
+/// moved it.
+pub fn gpu_seat() -> String {
+    PciDevice::find_nvidia_gpu()
+        .map(|gpu| gpu.seat())
+        .unwrap_or_else(|_| DEFAULT_SEAT.to_string())
+}
+
+/// Who to tell about the GPU, each once: users with an active graphical
+/// session on the GPU's seat, and `requester` (who last set the mode) if
+/// they have one on another seat.
+pub async fn gpu_session_uids(conn: &Connection, requester: Option<u32>) -> zbus::Result<Vec<u32>> {
+    let seat = tokio::task::spawn_blocking(gpu_seat)
+        .await
+        .unwrap_or_else(|_| DEFAULT_SEAT.to_string());
+    let mut uids = Vec::new();
+    for (uid, session_seat) in graphical_sessions(conn).await? {
+        if (session_seat == seat || Some(uid) == requester) && !uids.contains(&uid) {
+            uids.push(uid);
+        }
+    }
+    Ok(uids)
+}
+
+/// Whether this process runs in a session on the GPU's seat, going by
+/// `XDG_SEAT`. Without it (no logind, or a session without a seat) we can't
+/// tell and assume so.
+pub fn on_gpu_seat() -> bool {
+    std::env::var("XDG_SEAT").map_or(true, |seat| seat == gpu_seat())
+}
+
+/// logind's PrepareForSleep signals: true as the system goes to sleep, false
+/// once it has resumed. Take a delay inhibitor to get time to react.
+pub async fn sleep_signals(conn: &Connection) -> zbus::Result<impl Stream<Item = bool> + Unpin> {
+    Ok(LoginManagerProxy::new(conn)
+        .await?
+        .receive_prepare_for_sleep()
+        .await?
+        .filter_map(|signal| ready(signal.args().ok().map(|args| args.start))))
+}
+
+/// An item whenever logind adds or removes a session, e.g. a user logging in
+/// from the login screen.
+pub async fn session_changes(conn: &Connection) -> zbus::Result<impl Stream<Item = ()> + Unpin> {
+    let manager = LoginManagerProxy::new(conn).await?;
+    let new = manager.receive_session_new().await?.map(|_| ());
+    let removed = manager.receive_session_removed().await?.map(|_| ());
+    Ok(futures_util::stream::select(new, removed))
+}
+
+/// Regular (non-system) users; matches the UID range useradd allocates from.
+fn is_human_uid(uid: u32) -> bool {
+    (1000..65534).contains(&uid)
+}
+
+async fn any_user_logged_in(manager: &LoginManagerProxy<'_>) -> zbus::Result<bool> {
+    Ok(manager
+        .list_users()
+        .await?
+        .iter()
+        .any(|(uid, _, _)| is_human_uid(*uid)))
+}
+
+/// Returns once a regular user has a session, waking on logind's SessionNew
+/// signal. Falls back to polling when logind isn't reachable over D-Bus.
+pub async fn wait_for_user_login() {
+    match wait_via_dbus().await {
+        Ok(()) => return,
+        Err(e) => eprintln!("logind unavailable ({}), polling for logins instead", e),
+    }
+    while !tokio::task::spawn_blocking(system::is_user_logged_in)
+        .await
+        .unwrap_or(false)
+    {
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    }
+}
+
+async fn wait_via_dbus() -> zbus::Result<()> {
+    let conn = Connection::system().await?;
+    let manager = LoginManagerProxy::new(&conn).await?;
+    // Subscribe before the first check so a login in between isn't missed.
+    let mut sessions = manager.receive_session_new().await?;
+    while !any_user_logged_in(&manager).await? {
+        if sessions.next().await.is_none() {
+            return Err(zbus::Error::Failure(
+                "logind signal stream ended".to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
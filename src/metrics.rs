@@ -0,0 +1,196 @@
+use crate::eventlog;
+use crate::protocol::{Mode, PowerState};
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// How often the textfile output is rewritten.
+const TEXTFILE_INTERVAL_SECS: u64 = 15;
+/// Pause after a failed accept, so running out of file descriptors doesn't
+/// turn into a busy loop.
+const ACCEPT_RETRY: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Counters and gauges exported in Prometheus format. Counters start from zero
+/// whenever the daemon starts, as Prometheus expects.
+#[derive(Debug, Default)]
+struct Metrics {
+    mode: Option<Mode>,
+    power_state: Option<PowerState>,
+    /// Transitions by the power state they ended in.
+    transitions: BTreeMap<String, u64>,
+    enforcement_failures: u64,
+    asleep_secs: f64,
+    asleep_since: Option<Instant>,
+}
+
+fn metrics() -> &'static Mutex<Metrics> {
+    static METRICS: OnceLock<Mutex<Metrics>> = OnceLock::new();
+    METRICS.get_or_init(|| Mutex::new(Metrics::default()))
+}
+
+/// Records the current mode and power state; called on every monitor tick.
+pub fn observe(mode: Mode, power_state: PowerState) {
+    let mut m = metrics().lock().unwrap();
+    m.mode = Some(mode);
+    m.power_state = Some(power_state);
+    let asleep = power_state.is_asleep();
+    match (asleep, m.asleep_since) {
+        (true, None) => m.asleep_since = Some(Instant::now()),
+        (false, Some(since)) => {
+            m.asleep_secs += since.elapsed().as_secs_f64();
+            m.asleep_since = None;
+        }
+        _ => {}
+    }
+}
+
+pub fn record_transition(to: PowerState) {
+    *metrics()
+        .lock()
+        .unwrap()
+        .transitions
+        .entry(to.to_string())
+        .or_default() += 1;
+}
+
+/// A failed attempt by the monitor to put the GPU into the mode's state.
+pub fn record_enforcement_failure() {
+    metrics().lock().unwrap().enforcement_failures += 1;
+}
+
+pub fn render() -> String {
+    let m = metrics().lock().unwrap();
+    let mut out = String::new();
+
+    let _ = writeln!(out, "# HELP nvsleepify_mode Currently selected mode.");
+    let _ = writeln!(out, "# TYPE nvsleepify_mode gauge");
+    for mode in [
+        Mode::Standard,
+        Mode::Integrated,
+        Mode::Optimized,
+        Mode::Vfio,
+    ] {
+        let value = u8::from(m.mode == Some(mode));
+        let _ = writeln!(out, "nvsleepify_mode{{mode=\"{}\"}} {}", mode, value);
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP nvsleepify_power_state Current GPU power state."
+    );
+    let _ = writeln!(out, "# TYPE nvsleepify_power_state gauge");
+    if let Some(power_state) = m.power_state {
+        let _ = writeln!(out, "nvsleepify_power_state{{state=\"{}\"}} 1", power_state);
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP nvsleepify_gpu_asleep Whether the GPU is powered down."
+    );
+    let _ = writeln!(out, "# TYPE nvsleepify_gpu_asleep gauge");
+    let _ = writeln!(
+        out,
+        "nvsleepify_gpu_asleep {}",
+        u8::from(m.asleep_since.is_some())
+    );
+
+    let _ = writeln!(
+        out,
+        "# HELP nvsleepify_transitions_total Observed power state changes, by new state."
+    );
+    let _ = writeln!(out, "# TYPE nvsleepify_transitions_total counter");
+    for (to, count) in &m.transitions {
+        let _ = writeln!(
+            out,
+            "nvsleepify_transitions_total{{to=\"{}\"}} {}",
+            to, count
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP nvsleepify_enforcement_failures_total Failed attempts to apply the mode's power state."
+    );
+    let _ = writeln!(out, "# TYPE nvsleepify_enforcement_failures_total counter");
+    let _ = writeln!(
+        out,
+        "nvsleepify_enforcement_failures_total {}",
+        m.enforcement_failures
+    );
+
+    let asleep = m.asleep_secs
+        + m.asleep_since
+            .map(|since| since.elapsed().as_secs_f64())
+            .unwrap_or(0.0);
+    let _ = writeln!(
+        out,
+        "# HELP nvsleepify_asleep_seconds_total Time the GPU spent powered down."
+    );
+    let _ = writeln!(out, "# TYPE nvsleepify_asleep_seconds_total counter");
+    let _ = writeln!(out, "nvsleepify_asleep_seconds_total {:.0}", asleep);
+
+    out
+}
+
+/// Serves the metrics over plain HTTP. Any GET is answered; the request path
+/// isn't checked, so both / and /metrics work. Only failing to bind returns;
+/// a failed accept (out of file descriptors, an aborted connection) is
+/// logged and the next one tried after a pause.
+pub async fn serve(addr: String) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    loop {
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                eventlog::warn(format!(
+                    "Metrics endpoint failed to accept a connection: {}",
+                    e
+                ));
+                tokio::time::sleep(ACCEPT_RETRY).await;
+                continue;
+            }
+        };
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            let Ok(n) = stream.read(&mut buf).await else {
+                return;
+            };
+            let response = if buf[..n].starts_with(b"GET ") {
+                let body = render();
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+            } else {
+                "HTTP/1.1 405 Method Not Allowed\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_string()
+            };
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+/// Periodically writes the metrics to `path` for node_exporter's textfile
+/// collector. Written via a temporary file so the collector never sees a
+/// partial file.
+pub async fn write_textfile_loop(path: PathBuf) {
+    let mut interval =
+        tokio::time::interval(std::time::Duration::from_secs(TEXTFILE_INTERVAL_SECS));
+    let tmp = path.with_extension("prom.tmp");
+    loop {
+        interval.tick().await;
+        let body = render();
+        let result = tokio::fs::write(&tmp, body).await;
+        if let Err(e) = match result {
+            Ok(()) => tokio::fs::rename(&tmp, &path).await,
+            Err(e) => Err(e),
+        } {
+            eprintln!("Failed to write metrics to {}: {}", path.display(), e);
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use crate::protocol::Mode;
+use anyhow::{anyhow, Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Suffix for files moved aside by `nvsleepify migrate`. Restoring one is a rename.
+pub const BACKUP_SUFFIX: &str = ".nvsleepify-bak";
+
+/// Something another GPU switching tool left on the system.
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub tool: &'static str,
+    pub path: PathBuf,
+    pub description: String,
+    /// Changes how the GPU or its driver behaves (blacklists, udev rules that
+    /// remove the device, Xorg configs pinned to the dGPU) and would fight nvsleepify.
+    pub conflicting: bool,
+    /// The nvsleepify mode closest to what the tool was configured for.
+    pub suggested_mode: Option<Mode>,
+}
+
+/// Services of the tools above that keep re-applying their configuration.
+pub const TOOL_SERVICES: [(&str, &str); 2] = [
+    ("supergfxctl", "supergfxd.service"),
+    ("optimus-manager", "optimus-manager.service"),
+];
+
+/// Maps another tool's mode name onto ours.
+fn map_mode(name: &str) -> Option<Mode> {
+    match name.trim().to_lowercase().as_str() {
+        "integrated" => Some(Mode::Integrated),
+        // dGPU available, with or without runtime power management
+        "hybrid" | "nvidia" | "dedicated" | "asusmuxdgpu" => Some(Mode::Standard),
+        "auto" => Some(Mode::Optimized),
+        "vfio" => Some(Mode::Vfio),
+        _ => None,
+    }
+}
+
+/// Static files: (tool, path, description, conflicting, suggested mode).
+/// Files only identified by a marker in their content are checked separately.
+const KNOWN_FILES: [(&str, &str, &str, bool, Option<Mode>); 7] = [
+    (
+        "envycontrol",
+        "/etc/modprobe.d/blacklist-nvidia.conf",
+        "Nvidia driver blacklist (integrated mode)",
+        true,
+        Some(Mode::Integrated),
+    ),
+    (
+        "envycontrol",
+        "/lib/udev/rules.d/50-remove-nvidia.rules",
+        "udev rule removing the GPU from the PCI bus (integrated mode)",
+        true,
+        Some(Mode::Integrated),
+    ),
+    (
+        "envycontrol",
+        "/lib/udev/rules.d/80-nvidia-pm.rules",
+        "runtime power management udev rule (hybrid mode)",
+        false,
+        Some(Mode::Standard),
+    ),
+    (
+        "envycontrol",
+        "/etc/lightdm/nvidia.sh",
+        "LightDM display setup script (nvidia mode)",
+        true,
+        Some(Mode::Standard),
+    ),
+    (
+        "supergfxctl",
+        "/etc/modprobe.d/supergfxd.conf",
+        "Nvidia driver blacklist",
+        true,
+        None,
+    ),
+    (
+        "optimus-manager",
+        "/etc/X11/xorg.conf.d/10-optimus-manager.conf",
+        "generated Xorg configuration",
+        true,
+        None,
+    ),
+    (
+        "optimus-manager",
+        "/etc/modprobe.d/optimus-manager.conf",
+        "generated kernel module options",
+        true,
+        None,
+    ),
+];
+
+const ENVYCONTROL_XORG: &str = "/etc/X11/xorg.conf";
+const SUPERGFXD_CONFIG: &str = "/etc/supergfxd.conf";
+const OPTIMUS_MANAGER_CONFIG: &str = "/etc/optimus-manager/optimus-manager.conf";
+
+/// Looks for configuration left by envycontrol, supergfxctl and optimus-manager.
+pub fn scan() -> Vec<Finding> {
+    let mut findings: Vec<Finding> = KNOWN_FILES
+        .iter()
+        .filter(|(_, path, ..)| Path::new(path).exists())
+        .map(
+            |&(tool, path, description, conflicting, suggested_mode)| Finding {
+                tool,
+                path: PathBuf::from(path),
+                description: description.to_string(),
+                conflicting,
+                suggested_mode,
+            },
+        )
+        .collect();
+
+    findings.extend(CONFIG_FILES.iter().filter_map(|path| {
+        let content = std::fs::read_to_string(path).ok()?;
+        parse_config(path, &content)
+    }));
+
+    findings
+}
+
+/// Files whose content tells what the tool was set up for.
+const CONFIG_FILES: [&str; 3] = [ENVYCONTROL_XORG, SUPERGFXD_CONFIG, OPTIMUS_MANAGER_CONFIG];
+
+/// Reads one of `CONFIG_FILES`. None for other paths, and for an xorg.conf
+/// envycontrol didn't write.
+fn parse_config(path: &str, content: &str) -> Option<Finding> {
+    match path {
+        // xorg.conf is shared, so only claim it when envycontrol wrote it.
+        ENVYCONTROL_XORG => content.contains("EnvyControl").then(|| Finding {
+            tool: "envycontrol",
+            path: PathBuf::from(path),
+            description: "Xorg configuration pinned to the Nvidia GPU (nvidia mode)".into(),
+            conflicting: true,
+            suggested_mode: Some(Mode::Standard),
+        }),
+        SUPERGFXD_CONFIG => {
+            let mode = serde_json::from_str::<serde_json::Value>(content)
+                .ok()
+                .and_then(|config| config.get("mode")?.as_str().map(str::to_string));
+            Some(Finding {
+                tool: "supergfxctl",
+                path: PathBuf::from(path),
+                description: format!(
+                    "supergfxd configuration (mode: {})",
+                    mode.as_deref().unwrap_or("unknown")
+                ),
+                conflicting: false,
+                suggested_mode: mode.as_deref().and_then(map_mode),
+            })
+        }
+        OPTIMUS_MANAGER_CONFIG => {
+            let mode = content.lines().find_map(|line| {
+                let (key, value) = line.split_once('=')?;
+                (key.trim() == "startup_mode").then(|| value.trim().to_string())
+            });
+            Some(Finding {
+                tool: "optimus-manager",
+                path: PathBuf::from(path),
+                description: format!(
+                    "optimus-manager configuration (startup mode: {})",
+                    mode.as_deref().unwrap_or("unknown")
+                ),
+                conflicting: false,
+                suggested_mode: mode.as_deref().and_then(map_mode),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// The mode to switch to, going by the first finding that implies one.
+pub fn suggested_mode(findings: &[Finding]) -> Option<Mode> {
+    findings.iter().find_map(|f| f.suggested_mode)
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(BACKUP_SUFFIX);
+    PathBuf::from(backup)
+}
+
+/// Moves a conflicting file aside instead of deleting it.
+pub fn back_up(path: &Path) -> Result<PathBuf> {
+    let backup = backup_path(path);
+    std::fs::rename(path, &backup)
+        .with_context(|| format!("Failed to move {} aside", path.display()))?;
+    Ok(backup)
+}
+
+/// Files `back_up` moved aside that are still there, as (original, backup).
+pub fn backups() -> Vec<(PathBuf, PathBuf)> {
+    KNOWN_FILES
+        .iter()
+        .map(|(_, path, ..)| *path)
+        .chain([ENVYCONTROL_XORG])
+        .map(|path| (PathBuf::from(path), backup_path(Path::new(path))))
+        .filter(|(_, backup)| backup.exists())
+        .collect()
+}
+
+/// Moves a backup back into place, unless something took its place since.
+pub fn restore((path, backup): &(PathBuf, PathBuf)) -> Result<()> {
+    if path.exists() {
+        return Err(anyhow!(
+            "{} exists, leaving {} alone",
+            path.display(),
+            backup.display()
+        ));
+    }
+    std::fs::rename(backup, path).with_context(|| format!("Failed to restore {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn envycontrol_xorg_is_claimed_by_its_marker() {
+        let xorg = r#"# Automatically generated by EnvyControl
+
+Section "ServerLayout"
+    Identifier "layout"
+    Screen 0 "nvidia"
+    Inactive "intel"
+EndSection
+"#;
+        let finding = parse_config(ENVYCONTROL_XORG, xorg).unwrap();
+        assert_eq!(finding.tool, "envycontrol");
+        assert!(finding.conflicting);
+        assert_eq!(finding.suggested_mode, Some(Mode::Standard));
+
+        // Somebody else's xorg.conf is left alone.
+        let own = "Section \"Device\"\n    Identifier \"Card0\"\n    Driver \"modesetting\"\nEndSection\n";
+        assert!(parse_config(ENVYCONTROL_XORG, own).is_none());
+    }
+
+    #[test]
+    fn supergfxd_mode_is_read() {
+        let config = r#"{
+  "mode": "Integrated",
+  "vfio_enable": false,
+  "vfio_save": false,
+  "always_reboot": false,
+  "no_logind": false,
+  "logout_timeout_s": 180,
+  "hotplug_type": "None"
+}"#;
+        let finding = parse_config(SUPERGFXD_CONFIG, config).unwrap();
+        assert_eq!(finding.tool, "supergfxctl");
+        assert!(!finding.conflicting);
+        assert_eq!(
+            finding.description,
+            "supergfxd configuration (mode: Integrated)"
+        );
+        assert_eq!(finding.suggested_mode, Some(Mode::Integrated));
+
+        let finding = parse_config(SUPERGFXD_CONFIG, r#"{"mode": "AsusMuxDgpu"}"#).unwrap();
+        assert_eq!(finding.suggested_mode, Some(Mode::Standard));
+    }
+
+    #[test]
+    fn optimus_manager_startup_mode_is_read() {
+        let config = "[optimus]
+switching=none
+pci_power_control=no
+startup_mode = hybrid
+startup_auto_battery_mode=integrated
+
+[intel]
+driver=modesetting
+";
+        let finding = parse_config(OPTIMUS_MANAGER_CONFIG, config).unwrap();
+        assert_eq!(finding.tool, "optimus-manager");
+        assert_eq!(
+            finding.description,
+            "optimus-manager configuration (startup mode: hybrid)"
+        );
+        assert_eq!(finding.suggested_mode, Some(Mode::Standard));
+
+        let finding = parse_config(OPTIMUS_MANAGER_CONFIG, "startup_mode=auto\n").unwrap();
+        assert_eq!(finding.suggested_mode, Some(Mode::Optimized));
+    }
+
+    #[test]
+    fn unreadable_configs_are_reported_without_a_mode() {
+        for path in [SUPERGFXD_CONFIG, OPTIMUS_MANAGER_CONFIG] {
+            let finding = parse_config(path, "not a config").unwrap();
+            assert!(finding.description.ends_with("unknown)"), "{}", path);
+            assert_eq!(finding.suggested_mode, None, "{}", path);
+        }
+        let finding = parse_config(SUPERGFXD_CONFIG, r#"{"mode": "Compute"}"#).unwrap();
+        assert_eq!(finding.suggested_mode, None);
+    }
+
+    #[test]
+    fn unrecognized_files_are_ignored() {
+        assert!(parse_config("/etc/prime-discrete", "on\n").is_none());
+        assert!(parse_config("/etc/modprobe.d/nvidia.conf", "startup_mode=hybrid\n").is_none());
+    }
+}
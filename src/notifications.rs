@@ -0,0 +1,155 @@
+use crate::config::NotificationsConfig;
+use crate::i18n::Translator;
+use crate::logind;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use zbus::zvariant::Value;
+use zbus::{dbus_proxy, Connection, ConnectionBuilder};
+
+#[dbus_proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, &Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.systemd1.Manager",
+    default_service = "org.freedesktop.systemd1",
+    default_path = "/org/freedesktop/systemd1"
+)]
+trait UserManager {
+    /// What the user's services start with, as "NAME=value". Desktops
+    /// import the session's locale into it.
+    #[dbus_proxy(property)]
+    fn environment(&self) -> zbus::Result<Vec<String>>;
+}
+
+/// Limits "sleep blocked" notifications to the first failure of a streak, and
+/// to one per `interval` across streaks.
+#[derive(Debug, Default)]
+pub struct Throttle {
+    enabled: bool,
+    interval: Duration,
+    /// Already notified about the current streak of failures.
+    notified: bool,
+    last_sent: Option<Instant>,
+}
+
+impl Throttle {
+    pub fn new(config: &NotificationsConfig) -> Self {
+        Self {
+            enabled: config.sleep_blocked,
+            interval: Duration::from_secs(config.interval),
+            notified: false,
+            last_sent: None,
+        }
+    }
+
+    /// Whether to notify about this failure; if so, counts it as sent.
+    pub fn should_send(&mut self) -> bool {
+        if !self.enabled || self.notified {
+            return false;
+        }
+        if self
+            .last_sent
+            .is_some_and(|at| at.elapsed() < self.interval)
+        {
+            return false;
+        }
+        self.notified = true;
+        self.last_sent = Some(Instant::now());
+        true
+    }
+
+    /// Whether `[notifications] sleep_blocked` is on.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// The GPU slept, so the next failure starts a new streak.
+    pub fn reset(&mut self) {
+        self.notified = false;
+    }
+}
+
+/// Shows a notification in the active graphical sessions on the GPU's seat,
+/// and in `requester`'s, through each user's session bus. Sessions without a
+/// notification server are skipped. `message` gives the summary and body in
+/// each user's language.
+pub async fn send<F>(system: &Connection, requester: Option<u32>, message: F)
+where
+    F: Fn(&Translator) -> (String, String) + Sync,
+{
+    let uids = match logind::gpu_session_uids(system, requester).await {
+        Ok(uids) => uids,
+        Err(e) => {
+            eprintln!("Can't list graphical sessions for notifications: {}", e);
+            return;
+        }
+    };
+    for uid in uids {
+        if let Err(e) = send_to(uid, &message).await {
+            eprintln!("Failed to notify UID {}: {}", uid, e);
+        }
+    }
+}
+
+async fn send_to<F>(uid: u32, message: &F) -> zbus::Result<()>
+where
+    F: Fn(&Translator) -> (String, String),
+{
+    // The session bus lets root in alongside its owner.
+    let conn = ConnectionBuilder::address(format!("unix:path=/run/user/{}/bus", uid).as_str())?
+        .build()
+        .await?;
+    let (summary, body) = match user_environment(&conn).await {
+        Some(environment) => message(&Translator::from_env(|var| {
+            let prefix = format!("{}=", var);
+            environment
+                .iter()
+                .find_map(|entry| entry.strip_prefix(&prefix))
+                .map(str::to_string)
+        })),
+        // No user manager: the daemon's own language is the best guess.
+        None => message(&Translator::from_env(|var| std::env::var(var).ok())),
+    };
+    let urgency = Value::U8(1);
+    NotificationsProxy::new(&conn)
+        .await?
+        .notify(
+            "nvsleepify",
+            0,
+            "nvsleepify-gpu-active",
+            &summary,
+            &body,
+            &[],
+            HashMap::from([("urgency", &urgency)]),
+            -1,
+        )
+        .await?;
+    Ok(())
+}
+
+/// The environment of the user's systemd instance on their session bus.
+async fn user_environment(conn: &Connection) -> Option<Vec<String>> {
+    UserManagerProxy::new(conn)
+        .await
+        .ok()?
+        .environment()
+        .await
+        .ok()
+}
@@ -0,0 +1,508 @@
+use crate::client::{is_daemon_running, Client};
+use crate::config;
+use crate::daemon;
+use crate::error::Error;
+use crate::eventlog::{format_timestamp, unix_now};
+use crate::pci::PciDevice;
+use crate::platform;
+use crate::system;
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tokio::task::spawn_blocking;
+use zbus::Connection;
+
+const STATE_DIR: &str = "/var/lib/nvsleepify";
+const SLOTS_DIR: &str = "/sys/bus/pci/slots";
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+const DMI_DIR: &str = "/sys/class/dmi/id";
+/// DMI fields that name the model and firmware; the serials and UUID are left out.
+const DMI_FILES: [&str; 7] = [
+    "sys_vendor",
+    "product_name",
+    "product_version",
+    "product_family",
+    "board_name",
+    "bios_version",
+    "bios_date",
+];
+
+/// Device attributes that aren't copied: reading `config` wakes a suspended
+/// GPU, the BARs and the ROM are large binaries, and the rest are write-only
+/// triggers or the serial number.
+const SKIPPED_FILES: [&str; 11] = [
+    "config",
+    "rom",
+    "vpd",
+    "remove",
+    "rescan",
+    "reset",
+    "new_id",
+    "remove_id",
+    "bind",
+    "unbind",
+    "serial_number",
+];
+/// Subdirectories of a PCI device that are copied, with their contents.
+const DEVICE_SUBDIRS: [&str; 3] = ["power", "drm", "link"];
+/// How deep the copy descends, enough for drm/card1/card1-eDP-1/status.
+const MAX_DEPTH: usize = 3;
+/// Files larger than this aren't sysfs attributes and are skipped.
+const MAX_FILE_SIZE: u64 = 1 << 20;
+/// Lines kept of the daemon's journal.
+const JOURNAL_LINES: &str = "2000";
+/// Kernel messages worth keeping besides the ones naming the GPU.
+const KERNEL_KEYWORDS: [&str; 8] = [
+    "NVRM", "nvidia", "nouveau", "pciehp", "AER", "vfio", "acpi", "D3cold",
+];
+
+/// Replaces what identifies the machine or its users in the collected files.
+struct Scrubber {
+    rules: Vec<(Regex, String)>,
+}
+
+impl Scrubber {
+    fn new() -> Self {
+        let mut rules = vec![
+            (Regex::new(r"/home/[^/\s]+").unwrap(), "/home/<user>".into()),
+            (
+                Regex::new(r"GPU-[0-9a-fA-F]{8}(-[0-9a-fA-F]{4}){3}-[0-9a-fA-F]{12}").unwrap(),
+                "GPU-<uuid>".into(),
+            ),
+            // Secrets in configuration files, e.g. `token = "..."`.
+            (
+                Regex::new(r#"(?im)^(\s*[\w.-]*(token|password|passwd|secret)[\w.-]*\s*=\s*).*$"#)
+                    .unwrap(),
+                r#"${1}"<scrubbed>""#.into(),
+            ),
+        ];
+        if let Some(hostname) = std::fs::read_to_string("/proc/sys/kernel/hostname")
+            .ok()
+            .map(|name| name.trim().to_string())
+            .filter(|name| name.len() > 1)
+        {
+            rules.push((word(&hostname), "<hostname>".into()));
+        }
+        for user in local_users() {
+            rules.push((word(&user), "<user>".into()));
+        }
+        Self { rules }
+    }
+
+    fn scrub(&self, text: &str) -> String {
+        self.rules
+            .iter()
+            .fold(text.to_string(), |text, (pattern, replacement)| {
+                pattern
+                    .replace_all(&text, replacement.as_str())
+                    .into_owned()
+            })
+    }
+}
+
+fn word(literal: &str) -> Regex {
+    Regex::new(&format!(r"\b{}\b", regex::escape(literal))).unwrap()
+}
+
+/// Login names of regular users (UID 1000 and up) from /etc/passwd.
+fn local_users() -> Vec<String> {
+    std::fs::read_to_string("/etc/passwd")
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            let uid: u32 = fields.get(2)?.parse().ok()?;
+            let name = fields.first()?;
+            ((1000..65534).contains(&uid) && name.len() > 1).then(|| name.to_string())
+        })
+        .collect()
+}
+
+/// The staging directory of a report, tarred up at the end.
+struct Bundle {
+    dir: PathBuf,
+    scrubber: Scrubber,
+    /// What couldn't be collected, written to errors.txt.
+    errors: Vec<String>,
+}
+
+impl Bundle {
+    fn write(&mut self, name: &str, content: &str) {
+        let path = self.dir.join(name);
+        if let Err(e) = write_file(&path, self.scrubber.scrub(content).as_bytes()) {
+            self.errors.push(format!("{}: {}", name, e));
+        }
+    }
+
+    /// Copies `source` to `target`, relative to the bundle, scrubbing text.
+    fn copy(&mut self, source: &Path, target: &Path) {
+        let content = match std::fs::metadata(source) {
+            Ok(meta) if meta.len() > MAX_FILE_SIZE => return,
+            Ok(_) => std::fs::read(source),
+            Err(e) => Err(e),
+        };
+        // Unreadable attributes (write-only, root-only, or erroring while the
+        // device sleeps) are common in sysfs and not worth reporting.
+        let Ok(content) = content else {
+            return;
+        };
+        let content = match String::from_utf8(content) {
+            Ok(text) => self.scrubber.scrub(&text).into_bytes(),
+            Err(e) => e.into_bytes(),
+        };
+        if let Err(e) = write_file(&self.dir.join(target), &content) {
+            self.errors.push(format!("{}: {}", target.display(), e));
+        }
+    }
+
+    /// Copies the regular files in `source` and, `depth` levels down, its
+    /// subdirectories, skipping symlinks and nested PCI devices. `subdirs`
+    /// limits the first level.
+    fn copy_dir(&mut self, source: &Path, target: &Path, depth: usize, subdirs: Option<&[&str]>) {
+        let Ok(entries) = std::fs::read_dir(source) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_file() {
+                if !SKIPPED_FILES.contains(&name.as_str()) && !name.starts_with("resource") {
+                    self.copy(&entry.path(), &target.join(&name));
+                }
+            } else if file_type.is_dir()
+                && depth > 0
+                && !is_pci_address(&name)
+                && subdirs.is_none_or(|allowed| allowed.contains(&name.as_str()))
+            {
+                self.copy_dir(&entry.path(), &target.join(&name), depth - 1, None);
+            }
+        }
+    }
+
+    fn run(&mut self, name: &str, program: &str, args: &[&str]) -> Option<String> {
+        match system::output_with_timeout(Command::new(program).args(args)) {
+            Ok(output) if output.status.success() => {
+                Some(String::from_utf8_lossy(&output.stdout).into_owned())
+            }
+            Ok(output) => {
+                self.errors.push(format!(
+                    "{}: `{} {}` failed: {}",
+                    name,
+                    program,
+                    args.join(" "),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+                None
+            }
+            Err(e) => {
+                self.errors.push(format!("{}: {}", name, e));
+                None
+            }
+        }
+    }
+}
+
+fn write_file(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, content)
+}
+
+fn is_pci_address(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    bytes.len() == 12 && bytes[4] == b':' && bytes[7] == b':' && bytes[10] == b'.'
+}
+
+/// The PCI devices worth a copy: the display controllers, the Nvidia GPU's
+/// other functions, and the bridges above them, by their path under
+/// /sys/devices.
+fn pci_devices() -> BTreeSet<PathBuf> {
+    let mut devices: Vec<PciDevice> = PciDevice::find_display_devices();
+    if let Ok(gpu) = PciDevice::find_nvidia_gpu() {
+        devices.extend(gpu.slot_functions());
+    }
+    let mut paths = BTreeSet::new();
+    for device in devices {
+        let Ok(path) = std::fs::canonicalize(&device.path) else {
+            continue;
+        };
+        paths.extend(
+            path.ancestors()
+                .filter(|dir| {
+                    dir.file_name()
+                        .is_some_and(|name| is_pci_address(&name.to_string_lossy()))
+                })
+                .map(Path::to_path_buf),
+        );
+    }
+    paths
+}
+
+/// A copy of the GPU's part of /sys, laid out so `--sysfs-root` can read it:
+/// the devices under sys/devices with sys/bus/pci/devices linking to them,
+/// as on the real system.
+fn snapshot_sysfs(bundle: &mut Bundle) {
+    for path in pci_devices() {
+        let Ok(relative) = path.strip_prefix("/") else {
+            continue;
+        };
+        bundle.copy_dir(&path, relative, MAX_DEPTH, Some(&DEVICE_SUBDIRS));
+        // Only the driver's name is read from the link, so it may dangle.
+        if let Ok(driver) = std::fs::read_link(path.join("driver")) {
+            let _ = std::os::unix::fs::symlink(driver, bundle.dir.join(relative).join("driver"));
+        }
+        let Some(address) = path.file_name() else {
+            continue;
+        };
+        let link = bundle.dir.join("sys/bus/pci/devices").join(address);
+        let target = Path::new("../../..").join(relative.strip_prefix("sys").unwrap_or(relative));
+        if let Err(e) = std::fs::create_dir_all(link.parent().unwrap())
+            .and_then(|()| std::os::unix::fs::symlink(target, &link))
+        {
+            bundle.errors.push(format!("{}: {}", link.display(), e));
+        }
+    }
+
+    for dir in [SLOTS_DIR, POWER_SUPPLY_DIR] {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let target = Path::new(&dir[1..]).join(entry.file_name());
+            bundle.copy_dir(&entry.path(), &target, 0, None);
+        }
+    }
+    for name in DMI_FILES {
+        let source = Path::new(DMI_DIR).join(name);
+        bundle.copy(&source, &Path::new(&DMI_DIR[1..]).join(name));
+    }
+}
+
+fn system_info() -> String {
+    let read = |path: &str| {
+        std::fs::read_to_string(path)
+            .map(|content| content.trim().to_string())
+            .unwrap_or_else(|_| "unknown".to_string())
+    };
+    let os = std::fs::read_to_string("/etc/os-release")
+        .unwrap_or_default()
+        .lines()
+        .find_map(|line| line.strip_prefix("PRETTY_NAME="))
+        .map(|name| name.trim_matches('"').to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    format!(
+        "nvsleepify: {}\nKernel: {}\nOS: {}\nCommand line: {}\nNvidia driver: {}\nGenerated: {}\nRun as root: {}\n",
+        env!("CARGO_PKG_VERSION"),
+        read("/proc/sys/kernel/osrelease"),
+        os,
+        read("/proc/cmdline"),
+        read("/proc/driver/nvidia/version"),
+        format_timestamp(unix_now()),
+        unsafe { libc::geteuid() } == 0,
+    )
+}
+
+/// Kernel messages of this boot about the GPU, PCI power and the drivers.
+fn kernel_log(bundle: &mut Bundle) {
+    let Some(log) = bundle.run(
+        "kernel.txt",
+        "journalctl",
+        &["-k", "-b", "--no-pager", "-o", "short-iso"],
+    ) else {
+        return;
+    };
+    let slot = PciDevice::find_nvidia_gpu()
+        .ok()
+        .map(|gpu| gpu.address.slot().to_string());
+    let lines: Vec<&str> = log
+        .lines()
+        .filter(|line| {
+            slot.as_deref().is_some_and(|slot| line.contains(slot))
+                || KERNEL_KEYWORDS.iter().any(|keyword| line.contains(keyword))
+        })
+        .collect();
+    bundle.write("kernel.txt", &(lines.join("\n") + "\n"));
+}
+
+fn json(value: &impl serde::Serialize) -> Result<String> {
+    Ok(serde_json::to_string_pretty(value)?)
+}
+
+/// What the daemon reports, or the same computed locally while it isn't
+/// running; the event and message history only exist in the daemon.
+async fn daemon_reports() -> Vec<(&'static str, Result<String>)> {
+    let client = match Connection::system().await {
+        Ok(connection) if is_daemon_running(&connection).await => {
+            Client::with_connection(&connection).await.ok()
+        }
+        _ => None,
+    };
+    let Some(client) = client else {
+        let local = spawn_blocking(|| {
+            vec![
+                ("status.txt", Ok(daemon::status_logic())),
+                ("devices.json", json(&daemon::devices_logic())),
+                ("capabilities.json", json(&platform::probe())),
+                ("events.txt", Err(anyhow!("nvsleepifyd is not running"))),
+            ]
+        })
+        .await;
+        return local.unwrap_or_default();
+    };
+
+    let mut reports = vec![(
+        "status.txt",
+        client.status().await.map_err(anyhow::Error::from),
+    )];
+    reports.push((
+        "devices.json",
+        match client.devices().await {
+            Ok(devices) => json(&devices),
+            Err(e) => Err(e.into()),
+        },
+    ));
+    reports.push((
+        "capabilities.json",
+        match client.capabilities().await {
+            Ok(capabilities) => json(&capabilities),
+            Err(e) => Err(e.into()),
+        },
+    ));
+    reports.push((
+        "explain.txt",
+        client
+            .proxy()
+            .explain()
+            .await
+            .map_err(|e| Error::from(e).into()),
+    ));
+    reports.push((
+        "events.txt",
+        client.events().await.map_err(Into::into).map(|events| {
+            events
+                .iter()
+                .map(|e| {
+                    format!(
+                        "{} [{}] {}\n",
+                        format_timestamp(e.timestamp),
+                        e.kind,
+                        e.message
+                    )
+                })
+                .collect()
+        }),
+    ));
+    reports.push((
+        "logs.txt",
+        client.logs().await.map_err(Into::into).map(|logs| {
+            logs.iter()
+                .map(|l| {
+                    format!(
+                        "{} [{}] {}\n",
+                        format_timestamp(l.timestamp),
+                        l.level,
+                        l.message
+                    )
+                })
+                .collect()
+        }),
+    ));
+    reports
+}
+
+/// Everything but the daemon's reports, gathered into `bundle`.
+fn collect(bundle: &mut Bundle) {
+    bundle.write("system.txt", &system_info());
+    snapshot_sysfs(bundle);
+
+    match std::fs::read_to_string("/proc/modules") {
+        Ok(modules) => bundle.write("modules.txt", &modules),
+        Err(e) => bundle.errors.push(format!("modules.txt: {}", e)),
+    }
+    if let Some(journal) = bundle.run(
+        "journal.txt",
+        "journalctl",
+        &[
+            "-b",
+            "--no-pager",
+            "-o",
+            "short-iso",
+            "-n",
+            JOURNAL_LINES,
+            "-u",
+            "nvsleepifyd.service",
+            "-u",
+            "nvsleepify-helper.service",
+        ],
+    ) {
+        bundle.write("journal.txt", &journal);
+    }
+    kernel_log(bundle);
+
+    let config_path = config::daemon_config_path();
+    if config_path.exists() {
+        bundle.copy(config_path, Path::new("nvsleepifyd.toml"));
+    }
+    match std::fs::read_dir(STATE_DIR) {
+        Ok(entries) => {
+            for entry in entries.flatten() {
+                if entry.file_type().is_ok_and(|t| t.is_file()) {
+                    bundle.copy(&entry.path(), &Path::new("state").join(entry.file_name()));
+                }
+            }
+        }
+        Err(e) => bundle.errors.push(format!("{}: {}", STATE_DIR, e)),
+    }
+}
+
+/// Writes a tarball with what a bug report needs, scrubbed of host and user
+/// names, serials and secrets, to `output` or
+/// ./nvsleepify-report-<timestamp>.tar.gz. Returns its path.
+pub async fn generate(output: Option<PathBuf>) -> Result<PathBuf> {
+    let name = format!("nvsleepify-report-{}", unix_now());
+    let output = output.unwrap_or_else(|| PathBuf::from(format!("{}.tar.gz", name)));
+    let staging = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+    let dir = staging.join(&name);
+    std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let reports = daemon_reports().await;
+    spawn_blocking(move || {
+        let mut bundle = Bundle {
+            dir,
+            scrubber: Scrubber::new(),
+            errors: Vec::new(),
+        };
+        for (file, report) in reports {
+            match report {
+                Ok(content) => bundle.write(file, &content),
+                Err(e) => bundle.errors.push(format!("{}: {}", file, e)),
+            }
+        }
+        collect(&mut bundle);
+        if !bundle.errors.is_empty() {
+            let errors = bundle.errors.join("\n") + "\n";
+            bundle.write("errors.txt", &errors);
+        }
+
+        let status = system::status_with_timeout(
+            Command::new("tar")
+                .arg("-czf")
+                .arg(&output)
+                .arg("-C")
+                .arg(&staging)
+                .arg(&name),
+        );
+        let _ = std::fs::remove_dir_all(&staging);
+        match status {
+            Ok(status) if status.success() => Ok(output),
+            Ok(status) => Err(anyhow!("tar failed ({})", status)),
+            Err(e) => Err(e.context("Failed to run tar")),
+        }
+    })
+    .await?
+}
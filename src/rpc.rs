@@ -0,0 +1,550 @@
+use crate::access;
+use crate::config::SocketConfig;
+use crate::daemon::{self, SharedState};
+use crate::error::{Error, PREFIX};
+use crate::eventlog;
+use crate::events;
+use crate::faults;
+use crate::history;
+use crate::platform;
+use crate::protocol::ProcessList;
+use crate::state::{read_delay, write_delay};
+use crate::system;
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::task::spawn_blocking;
+use zbus::{Connection, SignalContext};
+
+/// Where `nvsleepify --socket` connects without a path.
+pub const DEFAULT_SOCKET_PATH: &str = "/run/nvsleepify.sock";
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// A daemon error; `data` carries its D-Bus name and the processes involved.
+const DAEMON_ERROR: i64 = -32000;
+
+/// Pause after a failed accept, so running out of file descriptors doesn't
+/// turn into a busy loop.
+const ACCEPT_RETRY: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// The Manager methods available on the socket, named as on D-Bus and taking
+/// named parameters, e.g.
+/// `{"jsonrpc":"2.0","id":1,"method":"SetMode","params":{"mode":"integrated"}}`.
+/// Results are the D-Bus replies as JSON objects.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "method", content = "params")]
+pub enum Call {
+    Status,
+    Info,
+    Explain,
+    GetDevices,
+    GetCapabilities,
+    SetMode {
+        mode: String,
+        /// As in SetModeWithOptions.
+        #[serde(default)]
+        kill_procs: bool,
+        /// As in SetModeForced.
+        #[serde(default)]
+        force: bool,
+    },
+    SleepWhenIdle,
+    WakeFor {
+        seconds: u32,
+    },
+    CancelWake,
+    KillProcesses {
+        pids: Vec<u32>,
+    },
+    KillProcess {
+        pid: u32,
+        name: String,
+    },
+    RunStep {
+        step: String,
+    },
+    Bench {
+        cycles: u32,
+    },
+    SetRestoreDelay {
+        seconds: u32,
+    },
+    RestoreDelay,
+    GetHistory {
+        limit: u32,
+    },
+    GetFaults {
+        limit: u32,
+    },
+    GetEvents,
+    GetLogs,
+    KillPolicy,
+}
+
+impl Call {
+    /// The method names above, to tell an unknown method from bad parameters.
+    const METHODS: &'static [&'static str] = &[
+        "Status",
+        "Info",
+        "Explain",
+        "GetDevices",
+        "GetCapabilities",
+        "SetMode",
+        "SleepWhenIdle",
+        "WakeFor",
+        "CancelWake",
+        "KillProcesses",
+        "KillProcess",
+        "RunStep",
+        "Bench",
+        "SetRestoreDelay",
+        "RestoreDelay",
+        "GetHistory",
+        "GetFaults",
+        "GetEvents",
+        "GetLogs",
+        "KillPolicy",
+    ];
+
+    /// Methods that go through `[access]`, as on D-Bus.
+    fn changes_state(&self) -> bool {
+        matches!(
+            self,
+            Call::SetMode { .. }
+                | Call::SleepWhenIdle
+                | Call::WakeFor { .. }
+                | Call::CancelWake
+                | Call::KillProcesses { .. }
+                | Call::KillProcess { .. }
+                | Call::RunStep { .. }
+                | Call::Bench { .. }
+                | Call::SetRestoreDelay { .. }
+        )
+    }
+}
+
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Response {
+    jsonrpc: String,
+    id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data: Option<ErrorData>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ErrorData {
+    /// e.g. "org.nvsleepify.Error.Busy"
+    name: String,
+    processes: ProcessList,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+}
+
+impl From<Error> for RpcError {
+    fn from(e: Error) -> Self {
+        Self {
+            code: DAEMON_ERROR,
+            message: e.message().to_string(),
+            data: Some(ErrorData {
+                name: e.error_name().to_string(),
+                processes: e.processes().to_vec(),
+            }),
+        }
+    }
+}
+
+impl From<RpcError> for Error {
+    fn from(e: RpcError) -> Self {
+        match e.data {
+            Some(data) => Error::from_parts(
+                data.name
+                    .strip_prefix(PREFIX)
+                    .and_then(|n| n.strip_prefix('.'))
+                    .unwrap_or_default(),
+                e.message,
+                data.processes,
+            ),
+            None if e.code == INVALID_PARAMS => Error::InvalidArgs(e.message),
+            None => Error::Failed(e.message),
+        }
+    }
+}
+
+/// Binds the configured socket with its permissions and answers requests on
+/// it. `conn` is the daemon's bus connection, if it has one, so changes made
+/// here still reach D-Bus listeners. Only failing to bind returns; a failed
+/// accept is logged and the next one tried after a pause.
+pub(crate) async fn serve(
+    config: SocketConfig,
+    state: SharedState,
+    conn: Option<Connection>,
+) -> Result<()> {
+    let Some(path) = config.path else {
+        return Ok(());
+    };
+    let listener = bind(&path, config.mode, config.group.as_deref())?;
+    eventlog::info(format!("Listening for JSON-RPC on {}", path.display()));
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                eventlog::warn(format!(
+                    "JSON-RPC socket failed to accept a connection: {}",
+                    e
+                ));
+                tokio::time::sleep(ACCEPT_RETRY).await;
+                continue;
+            }
+        };
+        tokio::spawn(handle(stream, state.clone(), conn.clone()));
+    }
+}
+
+/// Binds `path` so that nobody can connect before it has its group and
+/// mode: the socket is created in a directory only root can enter, given its
+/// permissions there and then renamed into place.
+fn bind(path: &Path, mode: u32, group: Option<&str>) -> Result<UnixListener> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let name = path
+        .file_name()
+        .with_context(|| format!("Socket path {} has no file name", path.display()))?;
+    std::fs::create_dir_all(parent)?;
+    let mut staging_name = std::ffi::OsString::from(".");
+    staging_name.push(name);
+    staging_name.push(".bind");
+    let staging = parent.join(staging_name);
+    let _ = std::fs::remove_dir_all(&staging);
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&staging)
+        .with_context(|| format!("Failed to create {}", staging.display()))?;
+    let result = bind_staged(&staging.join(name), path, mode, group);
+    let _ = std::fs::remove_dir_all(&staging);
+    result
+}
+
+fn bind_staged(staged: &Path, path: &Path, mode: u32, group: Option<&str>) -> Result<UnixListener> {
+    let listener =
+        UnixListener::bind(staged).with_context(|| format!("Failed to bind {}", path.display()))?;
+    if let Some(group) = group {
+        let (gid, _) = access::lookup_group(group)
+            .with_context(|| format!("Socket group '{}' doesn't exist", group))?;
+        std::os::unix::fs::chown(staged, None, Some(gid))?;
+    }
+    std::fs::set_permissions(staged, std::fs::Permissions::from_mode(mode))?;
+    let _ = std::fs::remove_file(path);
+    std::fs::rename(staged, path)
+        .with_context(|| format!("Failed to move the socket to {}", path.display()))?;
+    Ok(listener)
+}
+
+async fn handle(stream: UnixStream, state: SharedState, conn: Option<Connection>) {
+    let uid = stream.peer_cred().ok().map(|cred| cred.uid());
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = respond(&line, uid, &state, conn.as_ref()).await;
+        let Ok(mut reply) = serde_json::to_string(&response) else {
+            return;
+        };
+        reply.push('\n');
+        if write.write_all(reply.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn respond(
+    line: &str,
+    uid: Option<u32>,
+    state: &SharedState,
+    conn: Option<&Connection>,
+) -> Response {
+    let (id, result) = match serde_json::from_str::<Value>(line) {
+        Err(e) => (
+            Value::Null,
+            Err(RpcError::new(PARSE_ERROR, format!("Parse error: {}", e))),
+        ),
+        Ok(value) => match serde_json::from_value::<Request>(value) {
+            Err(e) => (
+                Value::Null,
+                Err(RpcError::new(
+                    INVALID_REQUEST,
+                    format!("Invalid request: {}", e),
+                )),
+            ),
+            Ok(request) => {
+                let result = call(request.method, request.params, uid, state, conn).await;
+                (request.id, result)
+            }
+        },
+    };
+    let (result, error) = match result {
+        Ok(value) => (Some(value), None),
+        Err(e) => (None, Some(e)),
+    };
+    Response {
+        jsonrpc: "2.0".to_string(),
+        id,
+        result,
+        error,
+    }
+}
+
+async fn call(
+    method: String,
+    params: Value,
+    uid: Option<u32>,
+    state: &SharedState,
+    conn: Option<&Connection>,
+) -> Result<Value, RpcError> {
+    if !Call::METHODS.contains(&method.as_str()) {
+        return Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method {}", method),
+        ));
+    }
+    let call: Call = serde_json::from_value(json!({ "method": method, "params": params }))
+        .map_err(|e| RpcError::new(INVALID_PARAMS, format!("Invalid params: {}", e)))?;
+    if call.changes_state() {
+        let uid = uid.ok_or_else(|| Error::PermissionDenied("Can't identify the caller".into()))?;
+        access::authorize_uid(uid, None).await?;
+    }
+    let ctxt = conn.and_then(|conn| SignalContext::new(conn, daemon::OBJECT_PATH).ok());
+    let ctxt = ctxt.as_ref();
+    Ok(match call {
+        Call::Status => json!(daemon::request_status(state).await),
+        Call::Info => json!(daemon::request_info(state).await),
+        Call::Explain => json!(daemon::request_explain(state).await),
+        Call::GetDevices => json!(spawn_blocking(daemon::devices_logic)
+            .await
+            .unwrap_or_default()),
+        Call::GetCapabilities => json!(spawn_blocking(platform::probe).await.unwrap_or_default()),
+        Call::SetMode {
+            mode,
+            kill_procs,
+            force,
+        } => {
+            json!(daemon::request_mode(state, ctxt, uid, mode, kill_procs, force).await?)
+        }
+        Call::SleepWhenIdle => json!(daemon::request_sleep_when_idle(state, ctxt).await?),
+        Call::WakeFor { seconds } => json!(daemon::request_wake_for(state, ctxt, seconds).await?),
+        Call::CancelWake => json!(daemon::request_cancel_wake(state, ctxt).await?),
+        Call::KillProcesses { pids } => json!(daemon::request_kill(pids).await?),
+        Call::KillProcess { pid, name } => json!(daemon::request_kill_one(pid, name).await?),
+        Call::RunStep { step } => json!(daemon::request_step(state, step).await?),
+        Call::Bench { cycles } => json!(daemon::request_bench(state, cycles).await?),
+        Call::SetRestoreDelay { seconds } => {
+            write_delay(seconds)
+                .await
+                .map_err(|e| Error::from(e.context("Failed to set delay")))?;
+            json!(format!("Restore delay set to {} seconds", seconds))
+        }
+        Call::RestoreDelay => json!(read_delay().await),
+        Call::GetHistory { limit } => {
+            json!(spawn_blocking(move || history::load_recent(limit as usize))
+                .await
+                .unwrap_or_default()
+                .iter()
+                .map(|r| r.to_tuple())
+                .collect::<Vec<_>>())
+        }
+        Call::GetFaults { limit } => {
+            json!(spawn_blocking(move || faults::load_recent(limit as usize))
+                .await
+                .unwrap_or_default()
+                .iter()
+                .map(|r| r.to_tuple())
+                .collect::<Vec<_>>())
+        }
+        Call::GetEvents => json!(events::recent()
+            .iter()
+            .map(|e| e.to_tuple())
+            .collect::<Vec<_>>()),
+        Call::GetLogs => json!(eventlog::entries()
+            .iter()
+            .map(|e| e.to_tuple())
+            .collect::<Vec<_>>()),
+        Call::KillPolicy => json!(system::kill_policy().to_string()),
+    })
+}
+
+/// Calls the daemon over its socket instead of D-Bus (`nvsleepify --socket`).
+/// Each call is a connection of its own.
+#[derive(Clone, Debug)]
+pub struct SocketClient {
+    path: PathBuf,
+}
+
+impl SocketClient {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub async fn call<T: DeserializeOwned>(&self, call: Call) -> Result<T, Error> {
+        let stream = UnixStream::connect(&self.path).await.map_err(|e| {
+            Error::Failed(format!(
+                "Can't reach nvsleepifyd at {}: {}",
+                self.path.display(),
+                e
+            ))
+        })?;
+        let mut request = serde_json::to_value(&call).map_err(|e| Error::Failed(e.to_string()))?;
+        request["jsonrpc"] = json!("2.0");
+        request["id"] = json!(1);
+        let mut line = request.to_string();
+        line.push('\n');
+        let (read, mut write) = stream.into_split();
+        write
+            .write_all(line.as_bytes())
+            .await
+            .map_err(|e| Error::Failed(e.to_string()))?;
+        let reply = BufReader::new(read)
+            .lines()
+            .next_line()
+            .await
+            .map_err(|e| Error::Failed(e.to_string()))?
+            .ok_or_else(|| Error::Failed("nvsleepifyd closed the connection".to_string()))?;
+        let response: Response = serde_json::from_str(&reply)
+            .map_err(|e| Error::Failed(format!("Malformed reply: {}", e)))?;
+        if let Some(error) = response.error {
+            return Err(error.into());
+        }
+        serde_json::from_value(response.result.unwrap_or(Value::Null))
+            .map_err(|e| Error::Failed(format!("Malformed reply: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn bind_moves_socket_into_place_with_its_mode() {
+        let dir = std::env::temp_dir().join(format!("nvsleepify-rpc-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("nvsleepify.sock");
+        // A stale socket from an earlier run is replaced.
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&path, "").unwrap();
+
+        let _listener = bind(&path, 0o660, None).unwrap();
+
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o660);
+        assert!(!dir.join(".nvsleepify.sock.bind").exists());
+        UnixStream::connect(&path).await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn methods_lists_every_call() {
+        let calls = [
+            Call::Status,
+            Call::Info,
+            Call::Explain,
+            Call::GetDevices,
+            Call::GetCapabilities,
+            Call::SetMode {
+                mode: "integrated".into(),
+                kill_procs: false,
+                force: false,
+            },
+            Call::SleepWhenIdle,
+            Call::WakeFor { seconds: 60 },
+            Call::CancelWake,
+            Call::KillProcesses { pids: vec![1] },
+            Call::KillProcess {
+                pid: 1,
+                name: "init".into(),
+            },
+            Call::RunStep {
+                step: "Rescan".into(),
+            },
+            Call::Bench { cycles: 1 },
+            Call::SetRestoreDelay { seconds: 5 },
+            Call::RestoreDelay,
+            Call::GetHistory { limit: 10 },
+            Call::GetFaults { limit: 10 },
+            Call::GetEvents,
+            Call::GetLogs,
+            Call::KillPolicy,
+        ];
+        let names: Vec<String> = calls
+            .iter()
+            .map(|call| {
+                serde_json::to_value(call).unwrap()["method"]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(names, Call::METHODS);
+    }
+
+    #[tokio::test]
+    async fn unknown_methods_and_bad_params_get_their_own_codes() {
+        let state = SharedState::default();
+        let cases = [
+            (
+                r#"{"jsonrpc":"2.0","id":1,"method":"Reboot"}"#,
+                METHOD_NOT_FOUND,
+            ),
+            (
+                r#"{"jsonrpc":"2.0","id":1,"method":"WakeFor","params":{}}"#,
+                INVALID_PARAMS,
+            ),
+            (
+                r#"{"jsonrpc":"2.0","id":1,"method":"WakeFor","params":{"seconds":"soon"}}"#,
+                INVALID_PARAMS,
+            ),
+            (r#"{"jsonrpc":"2.0","id":1"#, PARSE_ERROR),
+            (r#"{"jsonrpc":"2.0","id":1}"#, INVALID_REQUEST),
+        ];
+        for (line, code) in cases {
+            let response = respond(line, Some(0), &state, None).await;
+            assert_eq!(response.error.map(|e| e.code), Some(code), "{}", line);
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use crate::access;
+use crate::error::Error;
+use crate::eventlog;
+use crate::protocol::{PowerState, ProcessList};
+use nvsleepify_core::simulate::{reset, set_charging, set_power_state, set_processes};
+use zbus::{dbus_interface, Connection, MessageHeader};
+
+pub use nvsleepify_core::simulate::{charging, enable, is_enabled, kill, power_state, processes};
+
+/// Object path of the debug interface, only served with `nvsleepifyd --simulate`.
+pub const DEBUG_PATH: &str = "/org/nvsleepify/Debug";
+
+/// `org.nvsleepify.Debug`: injects fake charger, process and power state
+/// readings so policies and frontends can be tried out without touching the
+/// laptop. The daemon acts on them as on real ones.
+pub struct NvSleepifyDebug;
+
+#[dbus_interface(name = "org.nvsleepify.Debug")]
+impl NvSleepifyDebug {
+    /// Pretend the charger is plugged in (true) or unplugged (false).
+    async fn set_charger(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: MessageHeader<'_>,
+        online: bool,
+    ) -> Result<(), Error> {
+        access::authorize(conn, &header).await?;
+        set_charging(online);
+        eventlog::info(format!(
+            "Simulating charger {}",
+            if online { "plugged in" } else { "unplugged" }
+        ));
+        Ok(())
+    }
+
+    /// Pretend these processes hold the GPU, as (name, pid). Killing them
+    /// only removes them from the list; an empty list means none.
+    async fn set_processes(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: MessageHeader<'_>,
+        processes: ProcessList,
+    ) -> Result<(), Error> {
+        access::authorize(conn, &header).await?;
+        eventlog::info(format!(
+            "Simulating {} blocking process(es)",
+            processes.len()
+        ));
+        set_processes(processes);
+        Ok(())
+    }
+
+    /// Pretend the GPU is in this PCI power state, e.g. "D0", "D3cold" or
+    /// "NotFound".
+    async fn set_power_state(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: MessageHeader<'_>,
+        power_state: PowerState,
+    ) -> Result<(), Error> {
+        access::authorize(conn, &header).await?;
+        eventlog::info(format!("Simulating power state {}", power_state));
+        set_power_state(power_state);
+        Ok(())
+    }
+
+    /// Drop every injected reading and go back to the hardware's.
+    async fn reset(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: MessageHeader<'_>,
+    ) -> Result<(), Error> {
+        access::authorize(conn, &header).await?;
+        reset();
+        eventlog::info("Simulation reset, reading the hardware again");
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+use crate::access;
+use crate::daemon::{self, SharedState};
+use crate::eventlog;
+use crate::protocol::{Mode, PowerState};
+use crate::state;
+use tokio::task::spawn_blocking;
+use zbus::{dbus_interface, Connection, MessageHeader, SignalContext};
+
+/// Bus name, object path and interface of supergfxd, which asusctl's ROG
+/// Control Center and several Plasma widgets talk to.
+pub const SUPERGFX_SERVICE: &str = "org.supergfxctl.Daemon";
+pub const SUPERGFX_PATH: &str = "/org/supergfxctl/Gfx";
+/// The supergfxctl release whose D-Bus API is emulated. Frontends compare
+/// against it, so it is not nvsleepify's own version.
+const SUPERGFX_API_VERSION: &str = "5.2.1";
+/// supergfxd has no change signal for power, so frontends rely on
+/// NotifyGfxStatus; this is how often it is checked for.
+const POLL_SECS: u64 = 2;
+
+// GfxMode values from supergfxctl.
+const GFX_HYBRID: u32 = 0;
+const GFX_INTEGRATED: u32 = 1;
+const GFX_VFIO: u32 = 3;
+const GFX_NONE: u32 = 6;
+
+// GfxPower values from supergfxctl.
+const POWER_ACTIVE: u32 = 0;
+const POWER_SUSPENDED: u32 = 1;
+const POWER_OFF: u32 = 2;
+const POWER_UNKNOWN: u32 = 5;
+
+// UserActionRequired values from supergfxctl. Mode changes never need a
+// logout or reboot here.
+const ACTION_NOTHING: u32 = 4;
+
+/// Hybrid covers both modes that keep the dGPU usable; Optimized has no
+/// supergfxd counterpart.
+fn gfx_mode(mode: Mode) -> u32 {
+    match mode {
+        Mode::Standard | Mode::Optimized => GFX_HYBRID,
+        Mode::Integrated => GFX_INTEGRATED,
+        Mode::Vfio => GFX_VFIO,
+    }
+}
+
+fn gfx_power(power_state: PowerState) -> u32 {
+    match power_state {
+        PowerState::D0 => POWER_ACTIVE,
+        PowerState::D3hot | PowerState::D3cold => POWER_SUSPENDED,
+        PowerState::NotFound => POWER_OFF,
+        _ => POWER_UNKNOWN,
+    }
+}
+
+fn sample() -> (u32, u32) {
+    let mode = state::load_mode().unwrap_or(Mode::Standard);
+    (gfx_mode(mode), gfx_power(daemon::current_power_state()))
+}
+
+/// Opt-in `org.supergfxctl.Daemon` compatibility interface, translating
+/// supergfxd's graphics modes onto nvsleepify's.
+pub(crate) struct SupergfxShim {
+    state: SharedState,
+}
+
+impl SupergfxShim {
+    pub(crate) fn new(state: SharedState) -> Self {
+        Self { state }
+    }
+}
+
+#[dbus_interface(name = "org.supergfxctl.Daemon")]
+impl SupergfxShim {
+    async fn version(&self) -> String {
+        SUPERGFX_API_VERSION.to_string()
+    }
+
+    async fn mode(&self) -> zbus::fdo::Result<u32> {
+        let (mode, _) = spawn_blocking(sample)
+            .await
+            .map_err(|e| zbus::fdo::Error::Failed(e.to_string()))?;
+        Ok(mode)
+    }
+
+    async fn supported(&self) -> Vec<u32> {
+        vec![GFX_HYBRID, GFX_INTEGRATED, GFX_VFIO]
+    }
+
+    async fn vendor(&self) -> String {
+        "Nvidia".to_string()
+    }
+
+    async fn power_status(&self) -> zbus::fdo::Result<u32> {
+        let (_, power) = spawn_blocking(sample)
+            .await
+            .map_err(|e| zbus::fdo::Error::Failed(e.to_string()))?;
+        Ok(power)
+    }
+
+    /// Returns the user action required, which is always "nothing".
+    async fn set_mode(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: MessageHeader<'_>,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        mode: u32,
+    ) -> zbus::fdo::Result<u32> {
+        if let Err(e) = access::authorize(conn, &header).await {
+            return Err(zbus::fdo::Error::AccessDenied(e.to_string()));
+        }
+        let target = match mode {
+            GFX_HYBRID => Mode::Standard,
+            GFX_INTEGRATED => Mode::Integrated,
+            GFX_VFIO => Mode::Vfio,
+            other => {
+                return Err(zbus::fdo::Error::InvalidArgs(format!(
+                    "Unsupported graphics mode {}",
+                    other
+                )))
+            }
+        };
+        let manager_ctxt = SignalContext::new(conn, daemon::OBJECT_PATH)?;
+        let requester = access::caller_uid(conn, &header).await;
+        if let Err(e) = daemon::request_mode(
+            &self.state,
+            Some(&manager_ctxt),
+            requester,
+            target.to_string(),
+            false,
+            false,
+        )
+        .await
+        {
+            return Err(zbus::fdo::Error::Failed(e.to_string()));
+        }
+        let _ = Self::notify_gfx(&ctxt, mode).await;
+        let _ = Self::notify_action(&ctxt, ACTION_NOTHING).await;
+        Ok(ACTION_NOTHING)
+    }
+
+    /// Mode changes apply immediately, so nothing is ever pending.
+    async fn pending_mode(&self) -> u32 {
+        GFX_NONE
+    }
+
+    async fn pending_user_action(&self) -> u32 {
+        ACTION_NOTHING
+    }
+
+    #[dbus_interface(signal)]
+    async fn notify_gfx(ctxt: &SignalContext<'_>, mode: u32) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn notify_action(ctxt: &SignalContext<'_>, action: u32) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn notify_gfx_status(ctxt: &SignalContext<'_>, status: u32) -> zbus::Result<()>;
+}
+
+/// Claims supergfxd's bus name and keeps its signals in step with mode and
+/// power changes made through any interface.
+pub(crate) async fn run(conn: Connection, state: SharedState) {
+    if let Err(e) = conn
+        .object_server()
+        .at(SUPERGFX_PATH, SupergfxShim::new(state))
+        .await
+    {
+        eventlog::error(format!("Failed to serve supergfxd interface: {}", e));
+        return;
+    }
+    if let Err(e) = conn.request_name(SUPERGFX_SERVICE).await {
+        eventlog::error(format!(
+            "Failed to claim {} (is supergfxd running?): {}",
+            SUPERGFX_SERVICE, e
+        ));
+        return;
+    }
+    eventlog::info(format!(
+        "supergfxd compatibility enabled: {}",
+        SUPERGFX_SERVICE
+    ));
+
+    let Ok(ctxt) = SignalContext::new(&conn, SUPERGFX_PATH) else {
+        return;
+    };
+    let mut last = None;
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(POLL_SECS));
+    loop {
+        interval.tick().await;
+        let Ok((mode, power)) = spawn_blocking(sample).await else {
+            continue;
+        };
+        if let Some((last_mode, last_power)) = last {
+            if mode != last_mode {
+                let _ = SupergfxShim::notify_gfx(&ctxt, mode).await;
+            }
+            if power != last_power {
+                let _ = SupergfxShim::notify_gfx_status(&ctxt, power).await;
+            }
+        }
+        last = Some((mode, power));
+    }
+}
@@ -0,0 +1,219 @@
+use colored::*;
+use std::ffi::CString;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use tokio::io::unix::AsyncFd;
+
+/// Nvidia's PCI vendor ID as it appears in the `PCI_ID` uevent key.
+const NVIDIA_PCI_ID_PREFIX: &str = "10DE:";
+
+/// Something that may have changed what the monitor should do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trigger {
+    /// The Nvidia GPU was added, removed, bound or unbound ("add", "bind", ...).
+    Gpu(String),
+    /// A charger or battery changed state.
+    PowerSupply,
+    /// The saved mode was rewritten.
+    ModeFile,
+}
+
+/// The event sources the monitor waits on instead of polling: kernel uevents
+/// for the GPU and power supplies, and inotify on the mode file. Sources that
+/// can't be opened stay silent; callers fall back to polling.
+pub struct Triggers {
+    uevents: Option<AsyncFd<OwnedFd>>,
+    mode_file: Option<(AsyncFd<OwnedFd>, CString)>,
+}
+
+impl Triggers {
+    pub fn open(mode_file: &Path) -> Self {
+        let uevents = open_uevent_socket()
+            .and_then(AsyncFd::new)
+            .map_err(|e| {
+                eprintln!(
+                    "{} Can't listen for kernel uevents, polling instead: {}",
+                    "WARN:".yellow(),
+                    e
+                )
+            })
+            .ok();
+        let mode_file = watch_file(mode_file)
+            .map_err(|e| {
+                eprintln!(
+                    "{} Can't watch {:?} for changes: {}",
+                    "WARN:".yellow(),
+                    mode_file,
+                    e
+                )
+            })
+            .ok();
+        Self { uevents, mode_file }
+    }
+
+    /// Whether GPU and charger changes arrive as events. Without uevents the
+    /// monitor has to poll for them.
+    pub fn is_event_driven(&self) -> bool {
+        self.uevents.is_some()
+    }
+
+    /// Waits for the next relevant event. Never returns if no source is open.
+    pub async fn next(&mut self) -> Trigger {
+        tokio::select! {
+            Some(trigger) = next_uevent(self.uevents.as_ref()) => trigger,
+            Some(trigger) = next_file_event(self.mode_file.as_ref()) => trigger,
+            else => std::future::pending().await,
+        }
+    }
+}
+
+fn open_uevent_socket() -> io::Result<OwnedFd> {
+    // SAFETY: plain socket(2)/bind(2) calls; the fd is owned right away.
+    unsafe {
+        let fd = libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            libc::NETLINK_KOBJECT_UEVENT,
+        );
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = OwnedFd::from_raw_fd(fd);
+        let mut addr: libc::sockaddr_nl = std::mem::zeroed();
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        // Group 1 carries the kernel's own messages (udevd rebroadcasts on 2).
+        addr.nl_groups = 1;
+        if libc::bind(
+            fd.as_raw_fd(),
+            &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        ) < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(fd)
+    }
+}
+
+/// Watches the directory rather than the file, so replacing or recreating
+/// the file is still seen. Returns the fd and the file name to match.
+fn watch_file(path: &Path) -> io::Result<(AsyncFd<OwnedFd>, CString)> {
+    let dir = path.parent().unwrap_or(Path::new("/"));
+    std::fs::create_dir_all(dir)?;
+    let name = path.file_name().unwrap_or_default();
+    let name = CString::new(name.as_bytes())?;
+    let dir = CString::new(dir.as_os_str().as_bytes())?;
+    // SAFETY: inotify_init1(2)/inotify_add_watch(2) with a valid C string.
+    unsafe {
+        let fd = libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = OwnedFd::from_raw_fd(fd);
+        if libc::inotify_add_watch(
+            fd.as_raw_fd(),
+            dir.as_ptr(),
+            libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO,
+        ) < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((AsyncFd::new(fd)?, name))
+    }
+}
+
+/// Reads one datagram (or inotify batch) from a non-blocking fd once it is readable.
+async fn read_ready(fd: &AsyncFd<OwnedFd>, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        let mut guard = fd.readable().await?;
+        let result = guard.try_io(|inner| {
+            // SAFETY: `buf` is valid for `buf.len()` bytes.
+            let n = unsafe {
+                libc::read(
+                    inner.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                )
+            };
+            if n < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(n as usize)
+            }
+        });
+        match result {
+            Ok(result) => return result,
+            Err(_would_block) => continue,
+        }
+    }
+}
+
+/// The next uevent about the Nvidia GPU or a power supply. `None` only if
+/// there's no socket, which keeps that `select!` branch disabled.
+async fn next_uevent(fd: Option<&AsyncFd<OwnedFd>>) -> Option<Trigger> {
+    let fd = fd?;
+    let mut buf = vec![0u8; 8192];
+    loop {
+        let Ok(n) = read_ready(fd, &mut buf).await else {
+            continue;
+        };
+        if let Some(trigger) = parse_uevent(&buf[..n]) {
+            return Some(trigger);
+        }
+    }
+}
+
+/// Kernel uevents are "action@devpath" followed by KEY=VALUE fields, all
+/// NUL-separated.
+fn parse_uevent(msg: &[u8]) -> Option<Trigger> {
+    let mut action = None;
+    let mut subsystem = None;
+    let mut pci_id = None;
+    for field in msg.split(|b| *b == 0).skip(1) {
+        let field = std::str::from_utf8(field).ok()?;
+        if let Some((key, value)) = field.split_once('=') {
+            match key {
+                "ACTION" => action = Some(value),
+                "SUBSYSTEM" => subsystem = Some(value),
+                "PCI_ID" => pci_id = Some(value),
+                _ => {}
+            }
+        }
+    }
+    match subsystem? {
+        "power_supply" => Some(Trigger::PowerSupply),
+        "pci" if pci_id?.to_uppercase().starts_with(NVIDIA_PCI_ID_PREFIX) => {
+            Some(Trigger::Gpu(action?.to_string()))
+        }
+        _ => None,
+    }
+}
+
+async fn next_file_event(watch: Option<&(AsyncFd<OwnedFd>, CString)>) -> Option<Trigger> {
+    let (fd, name) = watch?;
+    let mut buf = vec![0u8; 4096];
+    let header = std::mem::size_of::<libc::inotify_event>();
+    loop {
+        let Ok(n) = read_ready(fd, &mut buf).await else {
+            continue;
+        };
+        let mut offset = 0;
+        let mut matched = false;
+        while offset + header <= n {
+            // SAFETY: the kernel writes whole events; read_unaligned copes with
+            // the byte buffer's alignment.
+            let event: libc::inotify_event =
+                unsafe { std::ptr::read_unaligned(buf[offset..].as_ptr() as *const _) };
+            let name_start = offset + header;
+            let name_end = (name_start + event.len as usize).min(n);
+            let event_name = buf[name_start..name_end].split(|b| *b == 0).next();
+            matched |= event_name == Some(name.as_bytes());
+            offset = name_end;
+        }
+        if matched {
+            return Some(Trigger::ModeFile);
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use futures_util::{Stream, StreamExt};
+use zbus::zvariant::OwnedObjectPath;
+use zbus::{dbus_proxy, Connection};
+
+#[dbus_proxy(
+    interface = "org.freedesktop.systemd1.Manager",
+    default_service = "org.freedesktop.systemd1",
+    default_path = "/org/freedesktop/systemd1"
+)]
+trait SystemdManager {
+    /// Loads the unit if needed, so units that aren't running yet can be
+    /// watched too.
+    fn load_unit(&self, name: &str) -> zbus::Result<OwnedObjectPath>;
+
+    /// systemd only sends unit property changes while a client is subscribed.
+    fn subscribe(&self) -> zbus::Result<()>;
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.systemd1.Unit",
+    default_service = "org.freedesktop.systemd1"
+)]
+trait SystemdUnit {
+    /// "active", "reloading", "inactive", "failed", "activating" or
+    /// "deactivating".
+    #[dbus_proxy(property)]
+    fn active_state(&self) -> zbus::Result<String>;
+}
+
+/// Lease owner for the wake lock a unit holds. Clients' locks are keyed by
+/// their unique bus names, which start with ':', so no client can release
+/// a unit's.
+pub fn lease_owner(unit: &str) -> String {
+    format!("systemd:{}", unit)
+}
+
+/// Whether a unit in `active_state` counts as running: starting and
+/// reloading ones do, stopping ones don't.
+pub fn is_running(active_state: &str) -> bool {
+    matches!(active_state, "active" | "activating" | "reloading")
+}
+
+/// Whether `unit` runs now, then each time that changes.
+pub async fn running_states(
+    conn: &Connection,
+    unit: &str,
+) -> zbus::Result<impl Stream<Item = bool>> {
+    let manager = SystemdManagerProxy::new(conn).await?;
+    // Already subscribed by another client is fine.
+    let _ = manager.subscribe().await;
+    let path = manager.load_unit(unit).await?;
+    let proxy = SystemdUnitProxy::builder(conn).path(path)?.build().await?;
+    let initial = is_running(&proxy.active_state().await?);
+    let changes = proxy
+        .receive_active_state_changed()
+        .await
+        .filter_map(|change| async move { change.get().await.ok() })
+        .map(|state| is_running(&state));
+    Ok(futures_util::stream::once(async move { initial }).chain(changes))
+}